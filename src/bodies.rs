@@ -0,0 +1,187 @@
+//! ボディの選択と、選択した2つのボディの結合 (ブーリアン演算)
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+use bevy::window::PrimaryWindow;
+use bevy_egui::{egui, EguiContexts};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::brep::Brep;
+use crate::csg::BooleanOp;
+use crate::features::{FeatureKind, FeatureTree};
+use crate::history::UndoHistory;
+use crate::Body;
+
+/// ボディの通常の色
+const BODY_COLOR: Color = Color::rgb(0.7, 0.7, 0.7);
+/// 選択中のボディの色
+const SELECTED_BODY_COLOR: Color = Color::rgb(0.4, 0.6, 1.0);
+/// クリックとみなすカーソルの移動量 (ピクセル)。これより動いた場合はカメラ操作とみなす
+const CLICK_SLOP: f32 = 4.0;
+
+/// 選択中のボディ。演算の順序に使うため選択した順に並べる
+#[derive(Resource, Default)]
+pub struct BodySelection {
+    pub entities: Vec<Entity>,
+    pub message: String,
+}
+
+/// 選択中の2つのボディを演算するイベント
+#[derive(Event)]
+pub struct CombineEvent(pub BooleanOp);
+
+/// クリックしたボディを選択するシステム。Shiftキーで選択を追加・解除する
+pub fn body_picking_system(
+    mut contexts: EguiContexts,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    q_bodies: Query<(Entity, &Handle<Mesh>, &GlobalTransform, &ViewVisibility), With<Body>>,
+    meshes: Res<Assets<Mesh>>,
+    mut selection: ResMut<BodySelection>,
+    mut press_position: Local<Option<Vec2>>,
+) {
+    let window = q_window.single();
+    if mouse_buttons.just_pressed(MouseButton::Left) && !contexts.ctx_mut().is_pointer_over_area() {
+        *press_position = window.cursor_position();
+    }
+    if !mouse_buttons.just_released(MouseButton::Left) {
+        return;
+    }
+    // 左ボタンはカメラの回転にも使うので、ほとんど動かさずに離した時だけ選択する
+    let (Some(pressed), Some(cursor)) = (press_position.take(), window.cursor_position()) else {
+        return;
+    };
+    if pressed.distance(cursor) > CLICK_SLOP {
+        return;
+    }
+    let (camera, camera_transform) = q_camera.single();
+    let Some(ray) = camera.viewport_to_world(camera_transform, cursor) else {
+        return;
+    };
+
+    let hit = q_bodies
+        .iter()
+        .filter(|(_, _, _, visibility)| visibility.get())
+        .filter_map(|(entity, handle, transform, _)| {
+            let distance = ray_mesh_distance(ray, meshes.get(handle)?, transform)?;
+            Some((entity, distance))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(entity, _)| entity);
+
+    selection.entities.retain(|&entity| q_bodies.contains(entity));
+    selection.message.clear();
+    if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        if let Some(entity) = hit {
+            if let Some(i) = selection.entities.iter().position(|&e| e == entity) {
+                selection.entities.remove(i);
+            } else {
+                selection.entities.push(entity);
+            }
+        }
+    } else {
+        selection.entities = hit.into_iter().collect();
+    }
+}
+
+/// レイとメッシュの最も近い交点までの距離 (Möller–Trumbore法)
+fn ray_mesh_distance(ray: Ray3d, mesh: &Mesh, transform: &GlobalTransform) -> Option<f32> {
+    let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+        return None;
+    };
+    let positions: Vec<Vec3> = positions.iter().map(|p| transform.transform_point(Vec3::from_array(*p))).collect();
+    let indices: Vec<usize> = match mesh.indices() {
+        Some(indices) => indices.iter().collect(),
+        None => (0..positions.len()).collect(),
+    };
+    let direction = *ray.direction;
+    indices
+        .chunks_exact(3)
+        .filter_map(|tri| {
+            let (a, b, c) = (positions[tri[0]], positions[tri[1]], positions[tri[2]]);
+            let (e1, e2) = (b - a, c - a);
+            let p = direction.cross(e2);
+            let det = e1.dot(p);
+            if det.abs() < 1e-8 {
+                return None;
+            }
+            let s = ray.origin - a;
+            let u = s.dot(p) / det;
+            let q = s.cross(e1);
+            let v = direction.dot(q) / det;
+            let t = e2.dot(q) / det;
+            (u >= 0.0 && v >= 0.0 && u + v <= 1.0 && t > 0.0).then_some(t)
+        })
+        .min_by(|a, b| a.total_cmp(b))
+}
+
+/// 選択中のボディの色を変えるシステム
+pub fn highlight_bodies_system(
+    selection: Res<BodySelection>,
+    q_bodies: Query<(Entity, &Handle<StandardMaterial>), With<Body>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if !selection.is_changed() {
+        return;
+    }
+    for (entity, handle) in q_bodies.iter() {
+        if let Some(material) = materials.get_mut(handle) {
+            material.base_color =
+                if selection.entities.contains(&entity) { SELECTED_BODY_COLOR } else { BODY_COLOR };
+        }
+    }
+}
+
+/// 選択中の2つのボディの演算を結合フィーチャーとして履歴に追加するシステム。差は先に選択したボディから後のボディを引く
+pub fn combine_system(
+    mut events: EventReader<CombineEvent>,
+    mut selection: ResMut<BodySelection>,
+    mut tree: ResMut<FeatureTree>,
+    mut history: ResMut<UndoHistory>,
+    q_bodies: Query<(&Body, &Brep)>,
+) {
+    for CombineEvent(op) in events.read() {
+        let [a, b] = selection.entities[..] else {
+            selection.message = "ボディを2つ選択してください".to_string();
+            continue;
+        };
+        let (Ok((body_a, brep_a)), Ok((body_b, brep_b))) = (q_bodies.get(a), q_bodies.get(b)) else {
+            continue;
+        };
+        if !brep_a.is_closed() || !brep_b.is_closed() {
+            selection.message = "閉じていないボディは結合できません".to_string();
+            continue;
+        }
+        let name = tree.next_name("結合");
+        tree.insert(name, FeatureKind::Combine { target: body_a.body, tool: body_b.body, op: *op });
+        history.record(format!("{}で結合", op.label()));
+        selection.message = format!("{}で結合しました", op.label());
+    }
+}
+
+/// ボディの結合操作のUI
+#[derive(SystemParam)]
+pub struct CombinePanel<'w> {
+    events: EventWriter<'w, CombineEvent>,
+    selection: Res<'w, BodySelection>,
+}
+
+impl CombinePanel<'_> {
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        ui.label("ボディの結合");
+        ui.label(format!("選択中のボディ: {} (Shift+クリックで追加)", self.selection.entities.len()));
+        ui.horizontal(|ui| {
+            for op in BooleanOp::ALL {
+                if ui.add_enabled(self.selection.entities.len() == 2, egui::Button::new(op.label())).clicked() {
+                    self.events.send(CombineEvent(op));
+                }
+            }
+        });
+        if !self.selection.message.is_empty() {
+            ui.label(&self.selection.message);
+        }
+    }
+}
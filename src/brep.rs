@@ -0,0 +1,1263 @@
+//! 境界表現 (B-rep) によるソリッドの位相と形状
+//!
+//! ソリッドはシェルの集まりで、シェルは面、面は外周と穴のループ、ループは
+//! ハーフエッジの環で構成される。面は平面・円柱・回転面・押し出し面・線織面、エッジは直線・円・楕円・スプラインの形状を持ち、
+//! 描画用の三角形メッシュは必要になった時に生成する。
+
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+
+use crate::geometry::{signed_area, BSpline, Curve2d, SketchFrame};
+use crate::mesh_builder::{merge_holes, triangulate, MeshBuffers};
+
+/// 円エッジの分割数 (一周あたり)
+const SEGMENTS_PER_TURN: f32 = 64.0;
+/// 同じ頂点とみなす距離
+const WELD_DISTANCE: f32 = 1e-5;
+/// 回転軸上にあるとみなす距離
+const AXIS_TOLERANCE: f32 = 1e-4;
+/// ブーリアン演算の結果の点が元の面の上にあるとみなす距離
+const SURFACE_TOLERANCE: f32 = 1e-4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VertexId(usize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EdgeId(usize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HalfEdgeId(usize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LoopId(usize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FaceId(usize);
+
+/// 面の形状
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Surface {
+    Plane { origin: Vec3, normal: Vec3 },
+    /// `origin` を通る `axis` 方向の軸を持つ円柱
+    Cylinder { origin: Vec3, axis: Vec3, radius: f32 },
+    /// 面の最初のエッジ (母線) を `origin` を通る `axis` まわりに `angle` (ラジアン) だけ回転した面
+    Revolution { origin: Vec3, axis: Vec3, angle: f32 },
+    /// 面の最初のエッジ (母線) を `direction` 方向に平行移動した面。法線は母線をたどる向きの接線 × `direction`
+    Extrusion { direction: Vec3 },
+    /// 面の最初のエッジと3番目のエッジ (逆向き) の同じ位置の点を直線で結んだ線織面。
+    /// 法線は最初のエッジをたどる向きの接線 × 3番目のエッジへ向かう向き
+    Ruled,
+}
+
+impl Surface {
+    /// 法線が `normal` の平らな多角形 `points` が面の上にあるか。回転面・押し出し面・線織面はエッジがないと決まらないので常に `false`
+    fn contains_polygon(&self, points: &[Vec3], normal: Vec3) -> bool {
+        match *self {
+            Surface::Plane { origin, normal: plane_normal } => {
+                plane_normal.dot(normal).abs() > 1.0 - SURFACE_TOLERANCE
+                    && points.iter().all(|&p| (p - origin).dot(plane_normal).abs() < SURFACE_TOLERANCE)
+            }
+            // 円柱を横切る平面上の三角形も頂点はすべて円柱の上にありうるので、法線が半径方向を向くものに限る
+            Surface::Cylinder { origin, axis, radius } => {
+                let center = points.iter().sum::<Vec3>() / points.len() as f32;
+                self.normal_at(center).dot(normal).abs() > 0.99
+                    && points.iter().all(|&p| {
+                        let d = p - origin;
+                        ((d - axis * d.dot(axis)).length() - radius).abs() < SURFACE_TOLERANCE
+                    })
+            }
+            Surface::Revolution { .. } | Surface::Extrusion { .. } | Surface::Ruled => false,
+        }
+    }
+
+    /// 形状としての法線 (面の向きは考慮しない)。回転面の法線は母線の向きで決まるので、軸から離れる向きで近似する。
+    /// 押し出し面と線織面の法線は点だけでは決まらないので0を返す
+    pub fn normal_at(&self, p: Vec3) -> Vec3 {
+        match *self {
+            Surface::Plane { normal, .. } => normal,
+            Surface::Cylinder { origin, axis, .. } | Surface::Revolution { origin, axis, .. } => {
+                let d = p - origin;
+                (d - axis * d.dot(axis)).normalize_or_zero()
+            }
+            Surface::Extrusion { .. } | Surface::Ruled => Vec3::ZERO,
+        }
+    }
+}
+
+/// エッジの形状
+#[derive(Debug, Clone, PartialEq)]
+pub enum EdgeCurve {
+    Line,
+    /// `normal` まわりに反時計回りに進む円 (円弧)
+    Circle { center: Vec3, normal: Vec3, radius: f32 },
+    /// 始点から終点へ向かうスプライン
+    Spline(BSpline<Vec3>),
+    /// `center + major` から始まり `major` → `minor` の向きに一周する楕円
+    Ellipse { center: Vec3, major: Vec3, minor: Vec3 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vertex {
+    pub point: Vec3,
+}
+
+/// 始点から終点へ向かうエッジ。始点と終点が同じ円エッジは一周する
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub curve: EdgeCurve,
+    pub vertices: [VertexId; 2],
+}
+
+/// ループの中でエッジを一方向にたどる辺
+#[derive(Debug, Clone, PartialEq)]
+pub struct HalfEdge {
+    pub edge: EdgeId,
+    /// エッジと同じ向きにたどるか
+    pub forward: bool,
+    pub next: HalfEdgeId,
+    /// 隣の面で同じエッジを逆向きにたどるハーフエッジ
+    pub twin: Option<HalfEdgeId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    pub first: HalfEdgeId,
+}
+
+/// 面。外周ループは外向きの法線から見て反時計回り、穴のループは時計回り
+#[derive(Debug, Clone, PartialEq)]
+pub struct Face {
+    pub surface: Surface,
+    /// 外向きの法線が形状の法線と同じ向きか
+    pub sense: bool,
+    pub outer: LoopId,
+    pub inner: Vec<LoopId>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Shell {
+    pub faces: Vec<FaceId>,
+}
+
+/// 1つのソリッドの境界表現
+#[derive(Component, Debug, Clone, Default, PartialEq)]
+pub struct Brep {
+    pub vertices: Vec<Vertex>,
+    pub edges: Vec<Edge>,
+    pub half_edges: Vec<HalfEdge>,
+    pub loops: Vec<Loop>,
+    pub faces: Vec<Face>,
+    pub shells: Vec<Shell>,
+}
+
+impl Brep {
+    fn add_vertex(&mut self, point: Vec3) -> VertexId {
+        self.vertices.push(Vertex { point });
+        VertexId(self.vertices.len() - 1)
+    }
+
+    fn add_edge(&mut self, curve: EdgeCurve, start: VertexId, end: VertexId) -> EdgeId {
+        self.edges.push(Edge { curve, vertices: [start, end] });
+        EdgeId(self.edges.len() - 1)
+    }
+
+    /// (エッジ, 順方向か) の列からループを作る
+    fn add_loop(&mut self, half_edges: &[(EdgeId, bool)]) -> LoopId {
+        let base = self.half_edges.len();
+        for (i, &(edge, forward)) in half_edges.iter().enumerate() {
+            let next = HalfEdgeId(base + (i + 1) % half_edges.len());
+            self.half_edges.push(HalfEdge { edge, forward, next, twin: None });
+        }
+        self.loops.push(Loop { first: HalfEdgeId(base) });
+        LoopId(self.loops.len() - 1)
+    }
+
+    fn add_face(&mut self, surface: Surface, sense: bool, outer: &[(EdgeId, bool)], inner: &[Vec<(EdgeId, bool)>]) {
+        let outer = self.add_loop(outer);
+        let inner = inner.iter().map(|half_edges| self.add_loop(half_edges)).collect();
+        self.faces.push(Face { surface, sense, outer, inner });
+        if self.shells.is_empty() {
+            self.shells.push(Shell::default());
+        }
+        self.shells[0].faces.push(FaceId(self.faces.len() - 1));
+    }
+
+    /// 同じエッジを逆向きにたどるハーフエッジ同士を対にする
+    fn link_twins(&mut self) {
+        let mut by_edge: HashMap<EdgeId, Vec<HalfEdgeId>> = HashMap::new();
+        for (i, half_edge) in self.half_edges.iter().enumerate() {
+            by_edge.entry(half_edge.edge).or_default().push(HalfEdgeId(i));
+        }
+        for pair in by_edge.values() {
+            if let [a, b] = pair[..] {
+                if self.half_edges[a.0].forward != self.half_edges[b.0].forward {
+                    self.half_edges[a.0].twin = Some(b);
+                    self.half_edges[b.0].twin = Some(a);
+                }
+            }
+        }
+    }
+
+    /// すべてのハーフエッジに対があるか (閉じたソリッドか)
+    pub fn is_closed(&self) -> bool {
+        !self.half_edges.is_empty() && self.half_edges.iter().all(|half_edge| half_edge.twin.is_some())
+    }
+
+    /// ループを構成するハーフエッジ
+    pub fn loop_half_edges(&self, loop_id: LoopId) -> Vec<HalfEdgeId> {
+        let first = self.loops[loop_id.0].first;
+        let mut result = vec![first];
+        let mut current = self.half_edges[first.0].next;
+        while current != first && result.len() <= self.half_edges.len() {
+            result.push(current);
+            current = self.half_edges[current.0].next;
+        }
+        result
+    }
+
+    /// エッジを折れ線で近似する。始点と終点を含む
+    pub fn edge_polyline(&self, edge_id: EdgeId) -> Vec<Vec3> {
+        let edge = &self.edges[edge_id.0];
+        let (start, end) = (self.vertices[edge.vertices[0].0].point, self.vertices[edge.vertices[1].0].point);
+        match edge.curve {
+            EdgeCurve::Line => vec![start, end],
+            EdgeCurve::Circle { center, normal, radius } => {
+                let x_axis = (start - center).normalize_or_zero();
+                let y_axis = normal.cross(x_axis);
+                let sweep = self.edge_sweep(edge_id);
+                let segments = arc_segments(sweep);
+                let mut points: Vec<Vec3> = (0..segments)
+                    .map(|i| {
+                        let angle = sweep * i as f32 / segments as f32;
+                        center + (x_axis * angle.cos() + y_axis * angle.sin()) * radius
+                    })
+                    .collect();
+                points.push(end);
+                points
+            }
+            EdgeCurve::Spline(ref spline) => spline.tessellate(),
+            EdgeCurve::Ellipse { center, major, minor } => {
+                let segments = SEGMENTS_PER_TURN as usize;
+                (0..=segments)
+                    .map(|i| {
+                        let angle = TAU * i as f32 / segments as f32;
+                        center + major * angle.cos() + minor * angle.sin()
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// 円エッジの中心角。始点と終点が同じ場合は一周
+    fn edge_sweep(&self, edge_id: EdgeId) -> f32 {
+        let edge = &self.edges[edge_id.0];
+        let EdgeCurve::Circle { center, normal, .. } = edge.curve else {
+            return 0.0;
+        };
+        let (start, end) = (self.vertices[edge.vertices[0].0].point, self.vertices[edge.vertices[1].0].point);
+        let x_axis = (start - center).normalize_or_zero();
+        let y_axis = normal.cross(x_axis);
+        let d = end - center;
+        let sweep = d.dot(y_axis).atan2(d.dot(x_axis)).rem_euclid(TAU);
+        if sweep < 1e-4 {
+            TAU
+        } else {
+            sweep
+        }
+    }
+
+    /// ハーフエッジをたどる向きの折れ線
+    fn half_edge_polyline(&self, id: HalfEdgeId) -> Vec<Vec3> {
+        let half_edge = &self.half_edges[id.0];
+        let mut points = self.edge_polyline(half_edge.edge);
+        if !half_edge.forward {
+            points.reverse();
+        }
+        points
+    }
+
+    /// ループを閉じた折れ線で近似する (始点の重複なし)
+    fn loop_polyline(&self, loop_id: LoopId) -> Vec<Vec3> {
+        let mut points = Vec::new();
+        for half_edge in self.loop_half_edges(loop_id) {
+            let polyline = self.half_edge_polyline(half_edge);
+            points.extend_from_slice(&polyline[..polyline.len() - 1]);
+        }
+        points
+    }
+
+    /// 描画用の三角形メッシュを生成する
+    pub fn tessellate(&self) -> Mesh {
+        let mut buffers = MeshBuffers::default();
+        for face_id in self.shells.iter().flat_map(|shell| &shell.faces) {
+            let face = &self.faces[face_id.0];
+            let sign = if face.sense { 1.0 } else { -1.0 };
+            let loop_half_edges = self.loop_half_edges(face.outer);
+            match face.surface {
+                // 押し出しや掃引の側面は [下辺, 縦辺, 上辺, 縦辺] のループなので、下辺と上辺の間を帯状に分割する
+                Surface::Cylinder { .. } | Surface::Extrusion { .. } | Surface::Ruled
+                    if loop_half_edges.len() == 4 && face.inner.is_empty() =>
+                {
+                    let bottom = self.half_edge_polyline(loop_half_edges[0]);
+                    let mut top = self.half_edge_polyline(loop_half_edges[2]);
+                    top.reverse();
+                    // 下辺と上辺の同じ番号の点は押し出し方向に並ぶので、法線は下辺の点で求める
+                    let normals: Vec<Vec3> = match face.surface {
+                        Surface::Extrusion { direction } => (0..bottom.len())
+                            .map(|k| {
+                                let tangent = bottom[(k + 1).min(bottom.len() - 1)] - bottom[k.saturating_sub(1)];
+                                tangent.cross(direction).normalize_or_zero() * sign
+                            })
+                            .collect(),
+                        Surface::Ruled => (0..bottom.len().min(top.len()))
+                            .map(|k| {
+                                let tangent = bottom[(k + 1).min(bottom.len() - 1)] - bottom[k.saturating_sub(1)];
+                                tangent.cross(top[k] - bottom[k]).normalize_or_zero() * sign
+                            })
+                            .collect(),
+                        _ => bottom.iter().map(|&p| face.surface.normal_at(p) * sign).collect(),
+                    };
+                    for j in 0..bottom.len().min(top.len()) - 1 {
+                        let (a, b, c, d) = (bottom[j], bottom[j + 1], top[j + 1], top[j]);
+                        let (na, nb) = (normals[j], normals[j + 1]);
+                        buffers.smooth_triangle([a, b, c], [na, nb, nb]);
+                        buffers.smooth_triangle([a, c, d], [na, nb, na]);
+                    }
+                }
+                // 回転面は母線を回転方向の円エッジと同じ分割数で回転させて格子状に分割する
+                Surface::Revolution { origin, axis, angle: sweep } => {
+                    let profile = self.half_edge_polyline(loop_half_edges[0]);
+                    // 母線の回転方向。母線はすべて軸の同じ側にある
+                    let Some(direction) = profile
+                        .iter()
+                        .map(|&p| axis.cross(p - origin))
+                        .max_by(|a, b| a.length_squared().total_cmp(&b.length_squared()))
+                        .and_then(|d| d.try_normalize())
+                    else {
+                        continue;
+                    };
+                    let normals: Vec<Vec3> = (0..profile.len())
+                        .map(|k| {
+                            let tangent = profile[(k + 1).min(profile.len() - 1)] - profile[k.saturating_sub(1)];
+                            tangent.cross(direction).normalize_or_zero() * sign
+                        })
+                        .collect();
+                    let segments = arc_segments(sweep);
+                    let rotation = |i: usize| Quat::from_axis_angle(axis, sweep * i as f32 / segments as f32);
+                    for i in 0..segments {
+                        let (r0, r1) = (rotation(i), rotation(i + 1));
+                        for k in 0..profile.len() - 1 {
+                            let a = origin + r0 * (profile[k] - origin);
+                            let b = origin + r0 * (profile[k + 1] - origin);
+                            let c = origin + r1 * (profile[k + 1] - origin);
+                            let d = origin + r1 * (profile[k] - origin);
+                            let (na, nb) = (r0 * normals[k], r0 * normals[k + 1]);
+                            let (nc, nd) = (r1 * normals[k + 1], r1 * normals[k]);
+                            // 軸上の点では三角形がつぶれるので省く
+                            if (b - a).cross(c - a).length_squared() > 1e-12 {
+                                buffers.smooth_triangle([a, b, c], [na, nb, nc]);
+                            }
+                            if (c - a).cross(d - a).length_squared() > 1e-12 {
+                                buffers.smooth_triangle([a, c, d], [na, nc, nd]);
+                            }
+                        }
+                    }
+                }
+                // ブーリアン演算の結果の円柱上の三角形は頂点の法線で滑らかに塗る
+                Surface::Cylinder { .. } if loop_half_edges.len() == 3 && face.inner.is_empty() => {
+                    let [a, b, c] = [0, 1, 2].map(|k| self.half_edge_polyline(loop_half_edges[k])[0]);
+                    buffers.smooth_triangle([a, b, c], [a, b, c].map(|p| face.surface.normal_at(p) * sign));
+                }
+                _ => {
+                    let outer = self.loop_polyline(face.outer);
+                    let holes: Vec<Vec<Vec3>> = face.inner.iter().map(|&l| self.loop_polyline(l)).collect();
+                    let normal = match face.surface {
+                        Surface::Plane { normal, .. } => normal * sign,
+                        Surface::Cylinder { .. }
+                        | Surface::Revolution { .. }
+                        | Surface::Extrusion { .. }
+                        | Surface::Ruled => newell_normal(&outer),
+                    };
+                    let frame = plane_frame(outer[0], normal);
+                    let outer_2d: Vec<Vec2> = outer.iter().map(|p| frame.to_local(*p)).collect();
+                    let holes_2d: Vec<Vec<Vec2>> =
+                        holes.iter().map(|hole| hole.iter().map(|p| frame.to_local(*p)).collect()).collect();
+                    let cap = merge_holes(&outer_2d, &holes_2d);
+                    for [i, j, k] in triangulate(&cap) {
+                        let (a, b, c) = (frame.to_world(cap[i]), frame.to_world(cap[j]), frame.to_world(cap[k]));
+                        buffers.triangle(a, b, c, normal);
+                    }
+                }
+            }
+        }
+        buffers.build()
+    }
+
+    /// 外周と穴の曲線からなるスケッチ上のプロファイルを押し出したソリッドを作る
+    pub fn extrude(outer: &[Curve2d], holes: &[Vec<Curve2d>], frame: &SketchFrame, distance: f32) -> Brep {
+        let normal = frame.u.cross(frame.v);
+        // 負の方向への押し出しは、押し出した先の面から正の方向へ押し出したものとして作る
+        let (base, height) = if distance < 0.0 {
+            (SketchFrame { origin: frame.origin + normal * distance, ..*frame }, -distance)
+        } else {
+            (*frame, distance)
+        };
+        let offset = normal * height;
+
+        let mut brep = Brep::default();
+        let mut bottom_loops = Vec::new();
+        let mut top_loops = Vec::new();
+        for (curves, ccw) in oriented_loops(outer, holes) {
+            let n = curves.len();
+            let bottom_vertices: Vec<VertexId> =
+                curves.iter().map(|curve| brep.add_vertex(base.to_world(curve.start()))).collect();
+            let top_vertices: Vec<VertexId> =
+                curves.iter().map(|curve| brep.add_vertex(base.to_world(curve.start()) + offset)).collect();
+            let verticals: Vec<EdgeId> =
+                (0..n).map(|i| brep.add_edge(EdgeCurve::Line, bottom_vertices[i], top_vertices[i])).collect();
+
+            let mut bottom_loop = Vec::new();
+            let mut top_loop = Vec::new();
+            for (i, curve) in curves.iter().enumerate() {
+                let j = (i + 1) % n;
+                // 円・円弧のエッジは常に法線まわり反時計回りに作り、たどる向きで時計回りを表す
+                let (edge_curve, forward, surface) = match *curve {
+                    Curve2d::Line { a, b } => {
+                        let edge = b - a;
+                        let outward = (base.u * edge.y - base.v * edge.x).normalize_or_zero();
+                        (EdgeCurve::Line, true, Surface::Plane { origin: base.to_world(a), normal: outward })
+                    }
+                    Curve2d::Circle { center, radius } => {
+                        let center = base.to_world(center);
+                        (
+                            EdgeCurve::Circle { center, normal, radius },
+                            ccw,
+                            Surface::Cylinder { origin: center, axis: normal, radius },
+                        )
+                    }
+                    Curve2d::Arc { center, radius, sweep, .. } => {
+                        let center = base.to_world(center);
+                        (
+                            EdgeCurve::Circle { center, normal, radius },
+                            sweep > 0.0,
+                            Surface::Cylinder { origin: center, axis: normal, radius },
+                        )
+                    }
+                    Curve2d::Spline(ref spline) => (
+                        EdgeCurve::Spline(spline.map(|p| base.to_world(p))),
+                        true,
+                        Surface::Extrusion { direction: normal },
+                    ),
+                    Curve2d::Ellipse { center, major, minor } => (
+                        EdgeCurve::Ellipse {
+                            center: base.to_world(center),
+                            major: base.to_world(major) - base.origin,
+                            minor: base.to_world(minor) - base.origin,
+                        },
+                        true,
+                        Surface::Extrusion { direction: normal },
+                    ),
+                };
+                let (start, end) = if forward { (i, j) } else { (j, i) };
+                let top_curve = match edge_curve {
+                    EdgeCurve::Line => EdgeCurve::Line,
+                    EdgeCurve::Circle { center, normal, radius } => {
+                        EdgeCurve::Circle { center: center + offset, normal, radius }
+                    }
+                    EdgeCurve::Spline(ref spline) => EdgeCurve::Spline(spline.map(|p| p + offset)),
+                    EdgeCurve::Ellipse { center, major, minor } => EdgeCurve::Ellipse { center: center + offset, major, minor },
+                };
+                let bottom = brep.add_edge(edge_curve, bottom_vertices[start], bottom_vertices[end]);
+                let top = brep.add_edge(top_curve, top_vertices[start], top_vertices[end]);
+
+                // 側面: 下辺 → 縦辺 (上へ) → 上辺 (逆向き) → 縦辺 (下へ)
+                let side = [(bottom, forward), (verticals[j], true), (top, !forward), (verticals[i], false)];
+                // 円柱の穴の内面や、へこんだ側の円弧の面は外向きの法線が軸に向かう
+                let sense = !matches!(surface, Surface::Cylinder { .. }) || forward;
+                brep.add_face(surface, sense, &side, &[]);
+                bottom_loop.push((bottom, !forward));
+                top_loop.push((top, forward));
+            }
+            bottom_loop.reverse();
+            bottom_loops.push(bottom_loop);
+            top_loops.push(top_loop);
+        }
+
+        if !bottom_loops.is_empty() {
+            let bottom = Surface::Plane { origin: base.origin, normal: -normal };
+            brep.add_face(bottom, true, &bottom_loops[0], &bottom_loops[1..]);
+            let top = Surface::Plane { origin: base.origin + offset, normal };
+            brep.add_face(top, true, &top_loops[0], &top_loops[1..]);
+        }
+        brep.link_twins();
+        brep
+    }
+
+    /// スケッチ上のプロファイルを、スケッチ上の直線 `axis_a`→`axis_b` を軸に `angle` (ラジアン) だけ回転したソリッドを作る。
+    /// プロファイルは軸の片側にある必要がある。回転の向きはプロファイルのある側から見て反時計回りになる
+    pub fn revolve(
+        outer: &[Curve2d],
+        holes: &[Vec<Curve2d>],
+        frame: &SketchFrame,
+        (axis_a, axis_b): (Vec2, Vec2),
+        angle: f32,
+    ) -> Result<Brep, String> {
+        let plane_normal = frame.u.cross(frame.v);
+        let Some(axis_2d) = (axis_b - axis_a).try_normalize() else {
+            return Err("回転軸の長さが0です".to_string());
+        };
+        // 軸からの符号付き距離でプロファイルが片側にあるかを調べる
+        let distance = |p: Vec2| axis_2d.perp_dot(p - axis_a);
+        let points: Vec<Vec2> =
+            std::iter::once(outer).chain(holes.iter().map(Vec::as_slice)).flatten().flat_map(Curve2d::tessellate).collect();
+        let farthest =
+            points.iter().map(|&p| distance(p)).max_by(|a, b| a.abs().total_cmp(&b.abs())).unwrap_or(0.0);
+        if farthest.abs() < AXIS_TOLERANCE {
+            return Err("プロファイルが回転軸上にあります".to_string());
+        }
+        if points.iter().any(|&p| distance(p) * farthest.signum() < -AXIS_TOLERANCE) {
+            return Err("プロファイルが回転軸をまたいでいます".to_string());
+        }
+        // プロファイル側への向き s に対して軸 × s が平面の法線になるよう軸の向きを選ぶと、
+        // 回転方向が押し出しの方向と一致し、押し出しと同じ向きでループを組める
+        let side = frame.to_world(axis_a + axis_2d.perp() * farthest.signum()) - frame.to_world(axis_a);
+        let origin = frame.to_world(axis_a);
+        let axis = side.cross(plane_normal).normalize();
+        let full = angle >= TAU - 1e-4;
+        let angle = angle.min(TAU);
+        let rotation = Quat::from_axis_angle(axis, angle);
+        let rotate = |p: Vec3| origin + rotation * (p - origin);
+        let on_axis = |p: Vec2| distance(p).abs() < AXIS_TOLERANCE;
+        let axis_point = |p: Vec3| origin + axis * (p - origin).dot(axis);
+
+        let mut brep = Brep::default();
+        let mut start_loops = Vec::new();
+        let mut end_loops = Vec::new();
+        for (curves, ccw) in oriented_loops(outer, holes) {
+            let n = curves.len();
+            let starts: Vec<Vec2> = curves.iter().map(Curve2d::start).collect();
+            let start_vertices: Vec<VertexId> = starts.iter().map(|&p| brep.add_vertex(frame.to_world(p))).collect();
+            // 回転しても動かない軸上の点と一周する場合は、回転後の頂点を共有する
+            let end_vertices: Vec<VertexId> = (0..n)
+                .map(|i| {
+                    if full || on_axis(starts[i]) {
+                        start_vertices[i]
+                    } else {
+                        brep.add_vertex(rotate(frame.to_world(starts[i])))
+                    }
+                })
+                .collect();
+            // 各頂点が描く円弧。軸上の頂点にはない
+            let arcs: Vec<Option<EdgeId>> = (0..n)
+                .map(|i| {
+                    if on_axis(starts[i]) {
+                        return None;
+                    }
+                    let point = frame.to_world(starts[i]);
+                    let center = axis_point(point);
+                    let curve = EdgeCurve::Circle { center, normal: axis, radius: point.distance(center) };
+                    Some(brep.add_edge(curve, start_vertices[i], end_vertices[i]))
+                })
+                .collect();
+
+            let mut start_loop = Vec::new();
+            let mut end_loop = Vec::new();
+            for (i, curve) in curves.iter().enumerate() {
+                let j = (i + 1) % n;
+                let (edge_curve, forward) = match *curve {
+                    Curve2d::Line { .. } => (EdgeCurve::Line, true),
+                    Curve2d::Circle { center, radius } => {
+                        (EdgeCurve::Circle { center: frame.to_world(center), normal: plane_normal, radius }, ccw)
+                    }
+                    Curve2d::Arc { center, radius, sweep, .. } => (
+                        EdgeCurve::Circle { center: frame.to_world(center), normal: plane_normal, radius },
+                        sweep > 0.0,
+                    ),
+                    Curve2d::Spline(ref spline) => (EdgeCurve::Spline(spline.map(|p| frame.to_world(p))), true),
+                    Curve2d::Ellipse { center, major, minor } => (
+                        EdgeCurve::Ellipse {
+                            center: frame.to_world(center),
+                            major: frame.to_world(major) - frame.origin,
+                            minor: frame.to_world(minor) - frame.origin,
+                        },
+                        true,
+                    ),
+                };
+                let (start, end) = if forward { (i, j) } else { (j, i) };
+                let rotated_curve = match edge_curve {
+                    EdgeCurve::Line => EdgeCurve::Line,
+                    EdgeCurve::Circle { center, normal, radius } => {
+                        EdgeCurve::Circle { center: rotate(center), normal: rotation * normal, radius }
+                    }
+                    EdgeCurve::Spline(ref spline) => EdgeCurve::Spline(spline.map(rotate)),
+                    EdgeCurve::Ellipse { center, major, minor } => {
+                        EdgeCurve::Ellipse { center: rotate(center), major: rotation * major, minor: rotation * minor }
+                    }
+                };
+                let first = brep.add_edge(edge_curve, start_vertices[start], start_vertices[end]);
+                // 軸上にある直線は回転しても動かないので、回転後のエッジも同じになる
+                let lies_on_axis = matches!(curve, Curve2d::Line { a, b } if on_axis(*a) && on_axis(*b));
+                let last = if full || lies_on_axis {
+                    first
+                } else {
+                    brep.add_edge(rotated_curve, end_vertices[start], end_vertices[end])
+                };
+
+                // 側面: 母線 → 終点の円弧 → 回転後の母線 (逆向き) → 始点の円弧 (逆向き)
+                if !lies_on_axis {
+                    let mut side = vec![(first, forward)];
+                    side.extend(arcs[j].map(|arc| (arc, true)));
+                    side.push((last, !forward));
+                    side.extend(arcs[i].map(|arc| (arc, false)));
+                    brep.add_face(Surface::Revolution { origin, axis, angle }, true, &side, &[]);
+                }
+                start_loop.push((first, !forward));
+                end_loop.push((last, forward));
+            }
+            start_loop.reverse();
+            start_loops.push(start_loop);
+            end_loops.push(end_loop);
+        }
+
+        // 一周しない場合は両端を平面でふさぐ
+        if !full && !start_loops.is_empty() {
+            let start = Surface::Plane { origin: frame.origin, normal: -plane_normal };
+            brep.add_face(start, true, &start_loops[0], &start_loops[1..]);
+            let end = Surface::Plane { origin: rotate(frame.origin), normal: rotation * plane_normal };
+            brep.add_face(end, true, &end_loops[0], &end_loops[1..]);
+        }
+        brep.link_twins();
+        Ok(brep)
+    }
+
+    /// スケッチ上のプロファイルを、同じスケッチ上の折れ線 `path` に沿って掃引したソリッドを作る。
+    /// プロファイルはパスの始点を通りパスに垂直な向きに立てて使う。`follow_path` が偽の場合は
+    /// 始点での向きのまま平行移動する。`twist` (ラジアン) はパスの終点までにプロファイルを回す角度。
+    /// 側面は断面の曲線ごと、パスの区間ごとの線織面になる
+    pub fn sweep(
+        outer: &[Curve2d],
+        holes: &[Vec<Curve2d>],
+        frame: &SketchFrame,
+        path: &[Vec2],
+        follow_path: bool,
+        twist: f32,
+    ) -> Result<Brep, String> {
+        let mut path = path.to_vec();
+        path.dedup_by(|a, b| a.distance(*b) < WELD_DISTANCE);
+        let tangents: Vec<Vec2> = path.windows(2).map(|w| (w[1] - w[0]).normalize()).collect();
+        if tangents.is_empty() {
+            return Err("パスの長さが0です".to_string());
+        }
+        let mut lengths = vec![0.0];
+        for w in path.windows(2) {
+            lengths.push(lengths[lengths.len() - 1] + w[0].distance(w[1]));
+        }
+        let total = lengths[lengths.len() - 1];
+
+        // プロファイルを始点の法線方向 a と、パスの進行方向を平面の法線方向に立てた b の座標で表す
+        let (start, t0) = (path[0], tangents[0]);
+        let n0 = t0.perp();
+        let to_profile = |q: Vec2| {
+            let d = q - start;
+            Vec2::new(d.dot(n0), d.dot(t0))
+        };
+        // 円は向きを持たないので楕円に置き換え、穴の円も時計回りにそろえられるようにする
+        let as_ellipse = |curve: &Curve2d| match *curve {
+            Curve2d::Circle { center, radius } => {
+                Curve2d::Ellipse { center, major: Vec2::X * radius, minor: Vec2::Y * radius }
+            }
+            ref curve => curve.clone(),
+        };
+        let outer: Vec<Curve2d> = outer.iter().map(as_ellipse).collect();
+        let holes: Vec<Vec<Curve2d>> = holes.iter().map(|hole| hole.iter().map(as_ellipse).collect()).collect();
+        // 座標の取り方で向きが反転するので、断面の上で外周が反時計回りになるのが曲線の向きかどうかを求めておく
+        let loops: Vec<(Vec<Curve2d>, bool)> = oriented_loops(&outer, &holes)
+            .into_iter()
+            .map(|(curves, ccw)| {
+                let polygon: Vec<Vec2> = curves.iter().flat_map(Curve2d::tessellate).map(to_profile).collect();
+                let forward = (signed_area(&polygon) > 0.0) == ccw;
+                (curves, forward)
+            })
+            .collect();
+
+        // 各折れ点での断面の配置。パスに沿う場合は折れ角の二等分面に置き、断面が細らないよう法線方向に伸ばす。
+        // ねじる場合は側面が大きくよじれないよう、区間の途中にも断面を置く
+        let up = frame.u.cross(frame.v);
+        let mut stations = Vec::new();
+        for k in 0..path.len() {
+            let incoming = tangents[k.saturating_sub(1)];
+            let outgoing = tangents[k.min(tangents.len() - 1)];
+            let (normal, stretch) = if follow_path {
+                let bisector = (incoming + outgoing).normalize_or_zero();
+                let cos = bisector.dot(outgoing);
+                if cos < 0.1 {
+                    return Err("パスの折れ角が鋭すぎます".to_string());
+                }
+                (bisector.perp(), 1.0 / cos)
+            } else {
+                if outgoing.dot(t0).abs() < 0.1 {
+                    return Err("断面と平行に進む区間がパスにあります".to_string());
+                }
+                (n0, 1.0)
+            };
+            stations.push((path[k], normal * stretch, Mat2::from_angle(twist * lengths[k] / total)));
+            if k + 1 == path.len() {
+                break;
+            }
+            let steps = arc_segments(twist.abs() * (lengths[k + 1] - lengths[k]) / total);
+            for step in 1..steps {
+                let t = step as f32 / steps as f32;
+                let normal = if follow_path { outgoing.perp() } else { n0 };
+                let length = lengths[k] + (lengths[k + 1] - lengths[k]) * t;
+                stations.push((path[k].lerp(path[k + 1], t), normal, Mat2::from_angle(twist * length / total)));
+            }
+        }
+        let place = |station: &(Vec2, Vec2, Mat2), p: Vec2| {
+            let (origin, normal, rotation) = *station;
+            let p = rotation * p;
+            frame.to_world(origin + normal * p.x) + up * p.y
+        };
+
+        // 配置はアフィン変換なので、直線・楕円・スプラインはそのまま写せる。円弧は伸ばすと楕円弧になるのでスプラインで近似する
+        let place_curve = |station: &(Vec2, Vec2, Mat2), curve: &Curve2d| {
+            let point = |q: Vec2| place(station, to_profile(q));
+            let edge_curve = match *curve {
+                Curve2d::Line { .. } => EdgeCurve::Line,
+                Curve2d::Spline(ref spline) => EdgeCurve::Spline(spline.map(point)),
+                Curve2d::Ellipse { center, major, minor } => EdgeCurve::Ellipse {
+                    center: point(center),
+                    major: point(center + major) - point(center),
+                    minor: point(center + minor) - point(center),
+                },
+                Curve2d::Circle { .. } | Curve2d::Arc { .. } => BSpline::interpolate(&curve.tessellate())
+                    .map_or(EdgeCurve::Line, |spline| EdgeCurve::Spline(spline.map(point))),
+            };
+            (point(curve.start()), edge_curve)
+        };
+        let sections: Vec<Vec<Vec<(Vec3, EdgeCurve)>>> = stations
+            .iter()
+            .map(|station| {
+                loops
+                    .iter()
+                    .map(|(curves, _)| curves.iter().map(|curve| place_curve(station, curve)).collect())
+                    .collect()
+            })
+            .collect();
+        let forward: Vec<bool> = loops.iter().map(|(_, forward)| *forward).collect();
+        // 始点の断面は進行方向と逆を向く。パスに沿わない場合はどの断面も始点の断面と平行になる
+        let direction = |v: Vec2| frame.to_world(v) - frame.origin;
+        let end = if follow_path { tangents[tangents.len() - 1] } else { t0 };
+        Ok(Brep::ruled(&sections, &forward, (-direction(t0), direction(end))))
+    }
+
+    /// 平行な平面上の断面を順につないだソリッドを作る。断面は (外周, 平面の高さ, 開始位置) で、
+    /// 開始位置は外周の始点からの周長の割合。頂点数の違う断面は、すべての断面の頂点の位置で
+    /// 周長を分割し直してつなぐ。`auto_align` が真なら、ねじれが最小になるよう各断面の始点を
+    /// 前の断面に合わせてから開始位置を足す。側面はどの断面でも曲線の境目になる位置で分けた線織面になる
+    pub fn loft(sections: &[(Vec<Curve2d>, f32, f32)], frame: &SketchFrame, auto_align: bool) -> Result<Brep, String> {
+        if sections.len() < 2 {
+            return Err("断面が2つ以上必要です".to_string());
+        }
+        let mut rings: Vec<(Ring, f32, Vec<f32>)> = Vec::new();
+        for (curves, height, _) in sections {
+            let mut polygon: Vec<Vec2> = curves
+                .iter()
+                .flat_map(|curve| {
+                    let points = curve.tessellate();
+                    points[..points.len() - 1].to_vec()
+                })
+                .collect();
+            polygon.dedup_by(|a, b| a.distance(*b) < WELD_DISTANCE);
+            if signed_area(&polygon) < 0.0 {
+                polygon.reverse();
+            }
+            if polygon.len() < 3 {
+                return Err("断面が閉じていません".to_string());
+            }
+            let ring = Ring::new(polygon);
+            // 曲線の境目の周長の割合
+            let breaks = curves
+                .iter()
+                .filter_map(|curve| {
+                    let start = curve.start();
+                    ring.points.iter().position(|p| p.distance(start) < WELD_DISTANCE).map(|i| ring.fractions[i])
+                })
+                .collect();
+            rings.push((ring, *height, breaks));
+        }
+        let ascending = rings[1].1 > rings[0].1;
+        if rings.windows(2).any(|w| (w[1].1 > w[0].1) != ascending || (w[1].1 - w[0].1).abs() < WELD_DISTANCE) {
+            return Err("断面の高さは順に増えるか減る必要があります".to_string());
+        }
+
+        // 各断面の始点 (周長の割合)
+        let mut starts = Vec::with_capacity(rings.len());
+        for (k, (ring, _, _)) in rings.iter().enumerate() {
+            let aligned = match k {
+                0 => 0.0,
+                _ if auto_align => ring.best_start(&rings[k - 1].0, starts[k - 1]),
+                _ => 0.0,
+            };
+            starts.push(aligned + sections[k].2);
+        }
+        // すべての断面の頂点の位置で分割し直すので、どの断面の角も残る
+        let mut params: Vec<f32> = rings
+            .iter()
+            .zip(&starts)
+            .flat_map(|((ring, _, _), start)| ring.fractions.iter().map(move |f| (f - start).rem_euclid(1.0)))
+            .collect();
+        params.sort_by(f32::total_cmp);
+        params.dedup_by(|a, b| *a - *b < 1e-5);
+        if params.last().is_some_and(|last| 1.0 - last < 1e-5) {
+            params.pop();
+        }
+        // 周長の割合が同じかどうか (一周の境目をまたいでも比べられるように)
+        let same = |a: f32, b: f32| {
+            let d = (a - b).rem_euclid(1.0);
+            d.min(1.0 - d) < 1e-5
+        };
+        // 側面を分ける位置。いずれかの断面で曲線の境目になる位置で分けるので、側面の辺はどの断面でも1つの曲線の上にある
+        let n = params.len();
+        let mut breaks: Vec<usize> = (0..n)
+            .filter(|&i| {
+                rings
+                    .iter()
+                    .zip(&starts)
+                    .any(|((_, _, breaks), start)| breaks.iter().any(|f| same(f - start, params[i])))
+            })
+            .collect();
+        if breaks.is_empty() {
+            breaks.push(0);
+        }
+        let up = frame.u.cross(frame.v);
+        let sections: Vec<Vec<Vec<(Vec3, EdgeCurve)>>> = rings
+            .iter()
+            .zip(&starts)
+            .map(|((ring, height, _), start)| {
+                let place = |p: Vec2| frame.to_world(p) + up * *height;
+                let curves = (0..breaks.len())
+                    .map(|b| {
+                        // 次の境目までの点。境目が1つなら一周する
+                        let (first, last) = (breaks[b], breaks[(b + 1) % breaks.len()]);
+                        let steps = match (last + n - first) % n {
+                            0 => n,
+                            steps => steps,
+                        };
+                        let points: Vec<Vec2> =
+                            (0..=steps).map(|i| ring.point_at(start + params[(first + i) % n])).collect();
+                        let curve = match points.len() {
+                            2 => EdgeCurve::Line,
+                            _ => BSpline::interpolate(&points)
+                                .map_or(EdgeCurve::Line, |spline| EdgeCurve::Spline(spline.map(place))),
+                        };
+                        (place(points[0]), curve)
+                    })
+                    .collect();
+                vec![curves]
+            })
+            .collect();
+        // 反時計回りの外周を高い方へつなぐと側面が外を向く
+        let normal = if ascending { up } else { -up };
+        Ok(Brep::ruled(&sections, &[ascending], (-normal, normal)))
+    }
+
+    /// 断面を順に線織面の側面でつないだソリッドを作る。`sections` は断面ごと、ループごとの (曲線の始点, 曲線) の列で、
+    /// どの断面も同じ数のループと曲線を持つ。`forward` はループごとの、曲線の向きにたどると次の断面へ向かう側面が
+    /// 外を向くか。最初と最後の断面は外向きの法線が `normals` の平面でふさぎ、最初のループを外周とする
+    fn ruled(sections: &[Vec<Vec<(Vec3, EdgeCurve)>>], forward: &[bool], normals: (Vec3, Vec3)) -> Brep {
+        let mut brep = Brep::default();
+        let mut vertices: Vec<Vec<Vec<VertexId>>> = Vec::new();
+        let mut edges: Vec<Vec<Vec<EdgeId>>> = Vec::new();
+        for loops in sections {
+            let mut section_vertices = Vec::new();
+            let mut section_edges = Vec::new();
+            for curves in loops {
+                let ids: Vec<VertexId> = curves.iter().map(|(point, _)| brep.add_vertex(*point)).collect();
+                let n = ids.len();
+                let loop_edges: Vec<EdgeId> = curves
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (_, curve))| brep.add_edge(curve.clone(), ids[i], ids[(i + 1) % n]))
+                    .collect();
+                section_edges.push(loop_edges);
+                section_vertices.push(ids);
+            }
+            vertices.push(section_vertices);
+            edges.push(section_edges);
+        }
+
+        // 側面: 前の断面の辺 → 縦辺 (次の断面へ) → 次の断面の辺 (逆向き) → 縦辺 (戻る)
+        for k in 0..sections.len() - 1 {
+            for (l, &forward) in forward.iter().enumerate() {
+                let n = vertices[k][l].len();
+                let rails: Vec<EdgeId> =
+                    (0..n).map(|i| brep.add_edge(EdgeCurve::Line, vertices[k][l][i], vertices[k + 1][l][i])).collect();
+                for (i, (&bottom, &top)) in edges[k][l].iter().zip(&edges[k + 1][l]).enumerate() {
+                    let j = (i + 1) % n;
+                    let (up, down) = if forward { (j, i) } else { (i, j) };
+                    let side = [(bottom, forward), (rails[up], true), (top, !forward), (rails[down], false)];
+                    brep.add_face(Surface::Ruled, true, &side, &[]);
+                }
+            }
+        }
+
+        let last = sections.len() - 1;
+        let cap_loops = |k: usize, reverse: bool| -> Vec<Vec<(EdgeId, bool)>> {
+            edges[k]
+                .iter()
+                .zip(forward)
+                .map(|(ids, &forward)| {
+                    let mut half_edges: Vec<(EdgeId, bool)> = ids.iter().map(|&id| (id, forward != reverse)).collect();
+                    if reverse {
+                        half_edges.reverse();
+                    }
+                    half_edges
+                })
+                .collect()
+        };
+        let (start, end) = (cap_loops(0, true), cap_loops(last, false));
+        let start_surface = Surface::Plane { origin: sections[0][0][0].0, normal: normals.0 };
+        brep.add_face(start_surface, true, &start[0], &start[1..]);
+        let end_surface = Surface::Plane { origin: sections[last][0][0].0, normal: normals.1 };
+        brep.add_face(end_surface, true, &end[0], &end[1..]);
+        brep.link_twins();
+        brep
+    }
+
+    /// スケッチの直線を押し出した、厚みのない1枚の面を作る
+    pub fn extrude_sheet(a: Vec2, b: Vec2, frame: &SketchFrame, distance: f32) -> Brep {
+        let offset = frame.u.cross(frame.v) * distance;
+        let (a, b) = (frame.to_world(a), frame.to_world(b));
+        let mut brep = Brep::default();
+        let corners = [a, b, b + offset, a + offset].map(|p| brep.add_vertex(p));
+        let edges: Vec<(EdgeId, bool)> =
+            (0..4).map(|i| (brep.add_edge(EdgeCurve::Line, corners[i], corners[(i + 1) % 4]), true)).collect();
+        let normal = (b - a).cross(offset).normalize_or_zero();
+        brep.add_face(Surface::Plane { origin: a, normal }, true, &edges, &[]);
+        brep
+    }
+
+    /// 三角形メッシュから、三角形ごとに平面の面を持つB-repを作る。
+    /// 解析的な形状を持たないブーリアン演算の結果などに使う
+    pub fn from_mesh(mesh: &Mesh) -> Brep {
+        let mut brep = Brep::default();
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+            return brep;
+        };
+        let indices: Vec<usize> = match mesh.indices() {
+            Some(indices) => indices.iter().collect(),
+            None => (0..positions.len()).collect(),
+        };
+
+        let mut vertex_lookup: HashMap<[i64; 3], VertexId> = HashMap::new();
+        let mut edge_lookup: HashMap<(VertexId, VertexId), EdgeId> = HashMap::new();
+        for tri in indices.chunks_exact(3) {
+            let points = [0, 1, 2].map(|k| Vec3::from_array(positions[tri[k]]));
+            let Some(normal) = (points[1] - points[0]).cross(points[2] - points[0]).try_normalize() else {
+                continue;
+            };
+            let ids = points.map(|p| {
+                let key = (p / WELD_DISTANCE).round().as_i64vec3().to_array();
+                *vertex_lookup.entry(key).or_insert_with(|| brep.add_vertex(p))
+            });
+            if ids[0] == ids[1] || ids[1] == ids[2] || ids[2] == ids[0] {
+                continue;
+            }
+            let half_edges: Vec<(EdgeId, bool)> = (0..3)
+                .map(|k| {
+                    let (a, b) = (ids[k], ids[(k + 1) % 3]);
+                    if let Some(&edge) = edge_lookup.get(&(b, a)) {
+                        (edge, false)
+                    } else {
+                        let edge = brep.add_edge(EdgeCurve::Line, a, b);
+                        edge_lookup.insert((a, b), edge);
+                        (edge, true)
+                    }
+                })
+                .collect();
+            brep.add_face(Surface::Plane { origin: points[0], normal }, true, &half_edges, &[]);
+        }
+        brep.link_twins();
+        brep
+    }
+
+    /// ブーリアン演算の結果のメッシュから `from_mesh` と同じく三角形ごとの面を持つB-repを作り、
+    /// 演算前のソリッド `sources` の平面や円柱の上にある三角形にはその形状を引き継ぐ。
+    /// エッジは直線のままで、元の面も三角形に分かれる
+    pub fn from_boolean(mesh: &Mesh, sources: &[&Brep]) -> Brep {
+        let mut brep = Brep::from_mesh(mesh);
+        let surfaces: Vec<Surface> = sources
+            .iter()
+            .flat_map(|source| source.faces.iter().map(|face| face.surface))
+            .filter(|surface| matches!(surface, Surface::Plane { .. } | Surface::Cylinder { .. }))
+            .collect();
+        for i in 0..brep.faces.len() {
+            let points = brep.loop_polyline(brep.faces[i].outer);
+            let Surface::Plane { normal, .. } = brep.faces[i].surface else {
+                continue;
+            };
+            let Some(surface) = surfaces.iter().find(|surface| surface.contains_polygon(&points, normal)) else {
+                continue;
+            };
+            let center = points.iter().sum::<Vec3>() / points.len() as f32;
+            brep.faces[i].surface = *surface;
+            brep.faces[i].sense = surface.normal_at(center).dot(normal) > 0.0;
+        }
+        brep
+    }
+}
+
+/// 外周を反時計回り、穴を時計回りにそろえたループと、反時計回りかどうか。空のループは除く
+fn oriented_loops(outer: &[Curve2d], holes: &[Vec<Curve2d>]) -> Vec<(Vec<Curve2d>, bool)> {
+    std::iter::once((outer, true))
+        .chain(holes.iter().map(|hole| (hole.as_slice(), false)))
+        .filter(|(curves, _)| !curves.is_empty())
+        .map(|(curves, ccw)| {
+            let points: Vec<Vec2> = curves.iter().flat_map(|curve| curve.tessellate()).collect();
+            if (signed_area(&points) > 0.0) == ccw {
+                (curves.to_vec(), ccw)
+            } else {
+                (curves.iter().rev().map(|curve| curve.reversed()).collect(), ccw)
+            }
+        })
+        .collect()
+}
+
+/// ロフトの断面の外周。周長の割合で位置を求める
+struct Ring {
+    points: Vec<Vec2>,
+    /// 各頂点の、始点からの周長の割合
+    fractions: Vec<f32>,
+}
+
+impl Ring {
+    fn new(points: Vec<Vec2>) -> Self {
+        let n = points.len();
+        let mut lengths = vec![0.0];
+        for i in 0..n {
+            lengths.push(lengths[i] + points[i].distance(points[(i + 1) % n]));
+        }
+        let perimeter = lengths[n];
+        let fractions = lengths[..n].iter().map(|length| length / perimeter).collect();
+        Self { points, fractions }
+    }
+
+    fn point_at(&self, t: f32) -> Vec2 {
+        let t = t.rem_euclid(1.0);
+        let n = self.points.len();
+        let i = self.fractions.partition_point(|f| *f <= t) - 1;
+        let end = if i + 1 < n { self.fractions[i + 1] } else { 1.0 };
+        let s = (t - self.fractions[i]) / (end - self.fractions[i]);
+        self.points[i].lerp(self.points[(i + 1) % n], s)
+    }
+
+    fn center(&self) -> Vec2 {
+        self.points.iter().sum::<Vec2>() / self.points.len() as f32
+    }
+
+    /// 始点を `previous` の `previous_start` に合わせた時に対応点の距離が最小になる始点
+    fn best_start(&self, previous: &Ring, previous_start: f32) -> f32 {
+        const SAMPLES: usize = 64;
+        let offset = self.center() - previous.center();
+        let targets: Vec<Vec2> = (0..SAMPLES)
+            .map(|i| previous.point_at(previous_start + i as f32 / SAMPLES as f32) + offset)
+            .collect();
+        let cost = |start: f32| -> f32 {
+            targets
+                .iter()
+                .enumerate()
+                .map(|(i, target)| self.point_at(start + i as f32 / SAMPLES as f32).distance_squared(*target))
+                .sum()
+        };
+        (0..360)
+            .map(|i| i as f32 / 360.0)
+            .chain(self.fractions.iter().copied())
+            .min_by(|a, b| cost(*a).total_cmp(&cost(*b)))
+            .unwrap_or(0.0)
+    }
+}
+
+/// 中心角 `sweep` の円弧の分割数
+fn arc_segments(sweep: f32) -> usize {
+    ((sweep / TAU) * SEGMENTS_PER_TURN).ceil().max(1.0) as usize
+}
+
+/// 多角形の法線 (Newellの方法)
+fn newell_normal(points: &[Vec3]) -> Vec3 {
+    let n = points.len();
+    (0..n).map(|i| points[i].cross(points[(i + 1) % n])).sum::<Vec3>().normalize_or_zero()
+}
+
+/// 法線方向から見て反時計回りが正になる平面の座標系
+fn plane_frame(origin: Vec3, normal: Vec3) -> SketchFrame {
+    let u = normal.any_orthonormal_vector();
+    SketchFrame { origin, u, v: normal.cross(u) }
+}
+
+/// B-repが変わったボディの描画用メッシュを作り直すシステム
+pub fn tessellate_bodies_system(
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut q_bodies: Query<(&Brep, &mut Handle<Mesh>), Changed<Brep>>,
+) {
+    for (brep, mut handle) in q_bodies.iter_mut() {
+        *handle = meshes.add(brep.tessellate());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csg::{self, BooleanOp};
+
+    fn rectangle(min: Vec2, max: Vec2) -> Vec<Curve2d> {
+        let corners = [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)];
+        (0..4).map(|i| Curve2d::Line { a: corners[i], b: corners[(i + 1) % 4] }).collect()
+    }
+
+    /// 三角形メッシュの符号付き体積
+    fn volume(mesh: &Mesh) -> f32 {
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+            return 0.0;
+        };
+        let indices: Vec<usize> = mesh.indices().unwrap().iter().collect();
+        indices
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = [t[0], t[1], t[2]].map(|i| Vec3::from_array(positions[i]));
+                a.dot(b.cross(c)) / 6.0
+            })
+            .sum()
+    }
+
+    #[test]
+    fn extrudes_rectangle_with_hole() {
+        let frame = SketchFrame::default();
+        let outer = rectangle(Vec2::ZERO, Vec2::splat(4.0));
+        let hole = rectangle(Vec2::ONE, Vec2::splat(3.0));
+        let brep = Brep::extrude(&outer, &[hole], &frame, 2.0);
+        assert!(brep.is_closed());
+        // 上下の面と、外周と穴の側面
+        assert_eq!(brep.faces.len(), 10);
+        assert_eq!(brep.faces.iter().filter(|face| face.inner.len() == 1).count(), 2);
+        assert!((volume(&brep.tessellate()) - 24.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn revolves_rectangle_fully_and_partially() {
+        let frame = SketchFrame::default();
+        let axis = (Vec2::ZERO, Vec2::Y);
+        // 軸から1離れた 1×1 の四角形を一周させると、内径1・外径2・高さ1の筒になる
+        let outer = rectangle(Vec2::new(1.0, 0.0), Vec2::new(2.0, 1.0));
+        let brep = Brep::revolve(&outer, &[], &frame, axis, TAU).unwrap();
+        assert!(brep.is_closed());
+        assert_eq!(brep.faces.len(), 4);
+        let expected = std::f32::consts::PI * 3.0;
+        assert!((volume(&brep.tessellate()) - expected).abs() < expected * 0.01);
+
+        // 90度だけ回すと、始めと終わりの断面に端面ができる
+        let brep = Brep::revolve(&outer, &[], &frame, axis, TAU / 4.0).unwrap();
+        assert!(brep.is_closed());
+        assert_eq!(brep.faces.len(), 6);
+        assert_eq!(brep.faces.iter().filter(|face| matches!(face.surface, Surface::Plane { .. })).count(), 2);
+        assert!((volume(&brep.tessellate()) - expected / 4.0).abs() < expected * 0.01);
+    }
+
+    #[test]
+    fn revolves_profile_touching_axis_without_holes() {
+        let frame = SketchFrame::default();
+        let axis = (Vec2::ZERO, Vec2::Y);
+        // 軸上の辺は面にならず、残りの辺だけで円柱を閉じる
+        let outer = rectangle(Vec2::ZERO, Vec2::ONE);
+        let brep = Brep::revolve(&outer, &[], &frame, axis, TAU).unwrap();
+        assert!(brep.is_closed());
+        assert_eq!(brep.faces.len(), 3);
+        let expected = std::f32::consts::PI;
+        assert!((volume(&brep.tessellate()) - expected).abs() < expected * 0.01);
+
+        // 半周でも、軸上の辺を共有する2つの端面で閉じる
+        let brep = Brep::revolve(&outer, &[], &frame, axis, TAU / 2.0).unwrap();
+        assert!(brep.is_closed());
+        assert!((volume(&brep.tessellate()) - expected / 2.0).abs() < expected * 0.01);
+
+        // 頂点が軸上にある三角形は円錐になり、頂点で円弧が縮退しても穴が開かない
+        let corners = [Vec2::ZERO, Vec2::X, Vec2::Y];
+        let outer: Vec<Curve2d> = (0..3).map(|i| Curve2d::Line { a: corners[i], b: corners[(i + 1) % 3] }).collect();
+        let brep = Brep::revolve(&outer, &[], &frame, axis, TAU).unwrap();
+        assert!(brep.is_closed());
+        assert!((volume(&brep.tessellate()) - expected / 3.0).abs() < expected * 0.01);
+    }
+
+    #[test]
+    fn sweeps_profile_with_ruled_sides() {
+        let frame = SketchFrame::default();
+        // パスの始点に立てた 2×2 の断面を長さ5だけ掃引する
+        let outer = rectangle(Vec2::new(-1.0, 0.0), Vec2::new(1.0, 2.0));
+        let path = [Vec2::ZERO, Vec2::new(0.0, 5.0)];
+        let brep = Brep::sweep(&outer, &[], &frame, &path, true, 0.0).unwrap();
+        assert!(brep.is_closed());
+        assert_eq!(brep.faces.len(), 6);
+        assert_eq!(brep.faces.iter().filter(|face| face.surface == Surface::Ruled).count(), 4);
+        assert!((volume(&brep.tessellate()) - 20.0).abs() < 1e-3);
+
+        // 円の断面は1枚の側面になり、穴の円は内側を向く
+        let outer = [Curve2d::Circle { center: Vec2::new(0.0, 2.0), radius: 2.0 }];
+        let hole = vec![Curve2d::Circle { center: Vec2::new(0.0, 2.0), radius: 1.0 }];
+        let brep = Brep::sweep(&outer, &[hole], &frame, &path, true, 0.0).unwrap();
+        assert!(brep.is_closed());
+        assert_eq!(brep.faces.len(), 4);
+        let expected = std::f32::consts::PI * 3.0 * 5.0;
+        assert!((volume(&brep.tessellate()) - expected).abs() < expected * 0.01);
+    }
+
+    #[test]
+    fn lofts_sections_with_ruled_sides() {
+        let frame = SketchFrame::default();
+        let sections = [
+            (rectangle(Vec2::splat(-1.0), Vec2::splat(1.0)), 0.0, 0.0),
+            (rectangle(Vec2::splat(-0.5), Vec2::splat(0.5)), 2.0, 0.0),
+        ];
+        let brep = Brep::loft(&sections, &frame, false).unwrap();
+        assert!(brep.is_closed());
+        // 角ごとに分かれた4枚の側面と両端の面。角錐台の体積は h (A1 + A2 + √(A1 A2)) / 3
+        assert_eq!(brep.faces.len(), 6);
+        assert!((volume(&brep.tessellate()) - 14.0 / 3.0).abs() < 1e-3);
+
+        // 四角形から円へ。円の始点も境目になる
+        let circle = vec![Curve2d::Circle { center: Vec2::ZERO, radius: 1.0 }];
+        let sections = [(rectangle(Vec2::splat(-1.0), Vec2::splat(1.0)), 0.0, 0.0), (circle, -2.0, 0.0)];
+        let brep = Brep::loft(&sections, &frame, true).unwrap();
+        assert!(brep.is_closed());
+        assert_eq!(brep.faces.iter().filter(|face| face.surface == Surface::Ruled).count(), 5);
+        assert!(volume(&brep.tessellate()) > 0.0);
+    }
+
+    #[test]
+    fn boolean_result_keeps_cylinder_and_plane_faces() {
+        let frame = SketchFrame::default();
+        let cylinder = Brep::extrude(&[Curve2d::Circle { center: Vec2::ZERO, radius: 1.0 }], &[], &frame, 2.0);
+        let block = Brep::extrude(&rectangle(Vec2::new(0.0, -2.0), Vec2::new(2.0, 2.0)), &[], &frame, 1.0);
+        let mesh = csg::boolean(
+            (&cylinder.tessellate(), &Transform::IDENTITY),
+            (&block.tessellate(), &Transform::IDENTITY),
+            BooleanOp::Difference,
+        )
+        .unwrap();
+        let brep = Brep::from_boolean(&mesh, &[&cylinder, &block]);
+        assert!(brep.is_closed());
+
+        let cylinder_faces: Vec<&Face> =
+            brep.faces.iter().filter(|face| matches!(face.surface, Surface::Cylinder { .. })).collect();
+        assert!(!cylinder_faces.is_empty());
+        // 円柱の面は外向き
+        for face in cylinder_faces {
+            let points = brep.loop_polyline(face.outer);
+            let normal = (points[1] - points[0]).cross(points[2] - points[0]);
+            let center = points.iter().sum::<Vec3>() / 3.0;
+            assert!(face.sense && face.surface.normal_at(center).dot(normal) > 0.0);
+        }
+        // 切り口はブロックの側面 (x = 0) の平面を裏向きに引き継ぐ
+        assert!(brep.faces.iter().any(|face| matches!(
+            face.surface,
+            Surface::Plane { normal, .. } if normal.x < -0.99
+        ) && !face.sense));
+    }
+}
@@ -0,0 +1,914 @@
+//! スケッチの幾何拘束
+//!
+//! 拘束はスケッチの子エンティティとして保持し、編集中のスケッチのジオメトリや
+//! 拘束が変更されるたびに `solver` で解き直してジオメトリへ書き戻す。
+
+use std::collections::HashMap;
+
+use bevy::ecs::system::SystemParam;
+use bevy::math::DVec2;
+use bevy::prelude::*;
+use bevy_egui::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::dimensions::{self, SketchDimension};
+use crate::geometry::SketchFrame;
+use crate::history::UndoHistory;
+use crate::solver::{EquationStatus, PointExpr, SolverSystem};
+use crate::{ActiveSketch, Selected, SketchArc, SketchCircle, SketchLine, SketchRectangle};
+
+/// スケッチエンティティ上の特徴点の種類
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PointKind {
+    Start,
+    End,
+    Center,
+    /// 四角形の角 (0: p1, 2: p2。1と3は辺の向きに沿った残りの角)
+    Corner(u8),
+}
+
+/// スケッチエンティティ上の点の参照
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PointRef<E = Entity> {
+    pub entity: E,
+    pub kind: PointKind,
+}
+
+/// 拘束の種類と対象。ファイル保存時は `E` をスケッチ内のインデックスに置き換える
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ConstraintKind<E = Entity> {
+    Coincident(PointRef<E>, PointRef<E>),
+    Horizontal(E),
+    Vertical(E),
+    Parallel(E, E),
+    Perpendicular(E, E),
+    /// 直線と円・円弧、または円・円弧同士の接線
+    Tangent(E, E),
+    /// 直線同士の長さ、または円同士の半径が等しい
+    Equal(E, E),
+    /// 点が直線の中点にある
+    Midpoint(PointRef<E>, E),
+    Concentric(E, E),
+    /// 点をスケッチ座標上の位置に固定する
+    Fix(PointRef<E>, [f32; 2]),
+}
+
+impl<E: Copy> ConstraintKind<E> {
+    /// 拘束が参照しているエンティティ
+    pub fn entities(&self) -> Vec<E> {
+        match self {
+            ConstraintKind::Coincident(a, b) => vec![a.entity, b.entity],
+            ConstraintKind::Horizontal(e) | ConstraintKind::Vertical(e) => vec![*e],
+            ConstraintKind::Parallel(a, b)
+            | ConstraintKind::Perpendicular(a, b)
+            | ConstraintKind::Tangent(a, b)
+            | ConstraintKind::Equal(a, b)
+            | ConstraintKind::Concentric(a, b) => vec![*a, *b],
+            ConstraintKind::Midpoint(p, line) => vec![p.entity, *line],
+            ConstraintKind::Fix(p, _) => vec![p.entity],
+        }
+    }
+
+    /// 参照先のエンティティを置き換える。1つでも置き換えられなければ `None`
+    pub fn map<T: Copy>(&self, mut f: impl FnMut(E) -> Option<T>) -> Option<ConstraintKind<T>> {
+        let mut point = |p: &PointRef<E>| Some(PointRef { entity: f(p.entity)?, kind: p.kind });
+        Some(match self {
+            ConstraintKind::Coincident(a, b) => {
+                let a = point(a)?;
+                ConstraintKind::Coincident(a, point(b)?)
+            }
+            ConstraintKind::Midpoint(p, line) => {
+                let p = point(p)?;
+                ConstraintKind::Midpoint(p, f(*line)?)
+            }
+            ConstraintKind::Fix(p, target) => ConstraintKind::Fix(point(p)?, *target),
+            ConstraintKind::Horizontal(e) => ConstraintKind::Horizontal(f(*e)?),
+            ConstraintKind::Vertical(e) => ConstraintKind::Vertical(f(*e)?),
+            ConstraintKind::Parallel(a, b) => ConstraintKind::Parallel(f(*a)?, f(*b)?),
+            ConstraintKind::Perpendicular(a, b) => ConstraintKind::Perpendicular(f(*a)?, f(*b)?),
+            ConstraintKind::Tangent(a, b) => ConstraintKind::Tangent(f(*a)?, f(*b)?),
+            ConstraintKind::Equal(a, b) => ConstraintKind::Equal(f(*a)?, f(*b)?),
+            ConstraintKind::Concentric(a, b) => ConstraintKind::Concentric(f(*a)?, f(*b)?),
+        })
+    }
+
+    /// 点の参照を置き換える。エンティティ全体への参照はそのまま残す。1つでも置き換えられなければ `None`
+    pub fn map_points(&self, mut f: impl FnMut(PointRef<E>) -> Option<PointRef<E>>) -> Option<Self> {
+        Some(match self {
+            ConstraintKind::Coincident(a, b) => {
+                let a = f(*a)?;
+                ConstraintKind::Coincident(a, f(*b)?)
+            }
+            ConstraintKind::Midpoint(p, line) => ConstraintKind::Midpoint(f(*p)?, *line),
+            ConstraintKind::Fix(p, target) => ConstraintKind::Fix(f(*p)?, *target),
+            other => other.clone(),
+        })
+    }
+
+    pub fn constraint_type(&self) -> ConstraintType {
+        match self {
+            ConstraintKind::Coincident(..) => ConstraintType::Coincident,
+            ConstraintKind::Horizontal(..) => ConstraintType::Horizontal,
+            ConstraintKind::Vertical(..) => ConstraintType::Vertical,
+            ConstraintKind::Parallel(..) => ConstraintType::Parallel,
+            ConstraintKind::Perpendicular(..) => ConstraintType::Perpendicular,
+            ConstraintKind::Tangent(..) => ConstraintType::Tangent,
+            ConstraintKind::Equal(..) => ConstraintType::Equal,
+            ConstraintKind::Midpoint(..) => ConstraintType::Midpoint,
+            ConstraintKind::Concentric(..) => ConstraintType::Concentric,
+            ConstraintKind::Fix(..) => ConstraintType::Fix,
+        }
+    }
+}
+
+/// UIから作成できる拘束の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintType {
+    Coincident,
+    Horizontal,
+    Vertical,
+    Parallel,
+    Perpendicular,
+    Tangent,
+    Equal,
+    Midpoint,
+    Concentric,
+    Fix,
+}
+
+impl ConstraintType {
+    pub const ALL: [ConstraintType; 10] = [
+        ConstraintType::Coincident,
+        ConstraintType::Horizontal,
+        ConstraintType::Vertical,
+        ConstraintType::Parallel,
+        ConstraintType::Perpendicular,
+        ConstraintType::Tangent,
+        ConstraintType::Equal,
+        ConstraintType::Midpoint,
+        ConstraintType::Concentric,
+        ConstraintType::Fix,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ConstraintType::Coincident => "一致",
+            ConstraintType::Horizontal => "水平",
+            ConstraintType::Vertical => "垂直",
+            ConstraintType::Parallel => "平行",
+            ConstraintType::Perpendicular => "直交",
+            ConstraintType::Tangent => "正接",
+            ConstraintType::Equal => "等しい",
+            ConstraintType::Midpoint => "中点",
+            ConstraintType::Concentric => "同心",
+            ConstraintType::Fix => "固定",
+        }
+    }
+}
+
+/// スケッチの幾何拘束。所属するスケッチの子エンティティとして生成する
+#[derive(Component, Debug, Clone)]
+pub struct SketchConstraint(pub ConstraintKind);
+
+/// 選択中のエンティティに拘束を追加するイベント
+#[derive(Event)]
+pub struct AddConstraintEvent(pub ConstraintType);
+
+/// 拘束1つ分の解析結果 (後のものほど深刻)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum ConstraintStatus {
+    #[default]
+    Satisfied,
+    Redundant,
+    Conflicting,
+}
+
+impl ConstraintStatus {
+    pub fn color(&self) -> Color {
+        match self {
+            ConstraintStatus::Satisfied => Color::GREEN,
+            ConstraintStatus::Redundant => Color::YELLOW,
+            ConstraintStatus::Conflicting => Color::RED,
+        }
+    }
+}
+
+/// 編集中スケッチの最後の求解結果
+#[derive(Resource, Default)]
+pub struct SolverReport {
+    /// 残りの自由度
+    pub dof: usize,
+    pub converged: bool,
+    pub statuses: HashMap<Entity, ConstraintStatus>,
+    /// 拘束の追加に失敗した時などのメッセージ
+    pub message: String,
+}
+
+/// スケッチエンティティの形状を読み取るクエリ
+pub type GeometryQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Option<&'static SketchLine>,
+        Option<&'static SketchCircle>,
+        Option<&'static SketchRectangle>,
+        Option<&'static SketchArc>,
+    ),
+>;
+
+/// ソルバー上でのスケッチエンティティの変数
+#[derive(Debug, Clone, Copy)]
+pub enum EntityVars {
+    Line { p1: PointExpr, p2: PointExpr },
+    Circle { center: PointExpr, radius: usize },
+    /// 四隅の点。対角の `corners[0]` と `corners[2]` 以外は傾きを保つ方程式で決まる
+    Rectangle { corners: [PointExpr; 4] },
+    /// 中心・半径と両端の点。両端は中心から半径の距離にあるよう方程式で保つ
+    Arc { center: PointExpr, radius: usize, start: PointExpr, end: PointExpr },
+}
+
+impl EntityVars {
+    pub fn point(&self, kind: PointKind) -> Option<PointExpr> {
+        match (*self, kind) {
+            (EntityVars::Line { p1, .. }, PointKind::Start) => Some(p1),
+            (EntityVars::Line { p2, .. }, PointKind::End) => Some(p2),
+            (EntityVars::Circle { center, .. }, PointKind::Center) => Some(center),
+            (EntityVars::Rectangle { corners }, PointKind::Corner(i)) => corners.get(i as usize).copied(),
+            (EntityVars::Arc { start, .. }, PointKind::Start) => Some(start),
+            (EntityVars::Arc { end, .. }, PointKind::End) => Some(end),
+            (EntityVars::Arc { center, .. }, PointKind::Center) => Some(center),
+            _ => None,
+        }
+    }
+
+    pub fn line(&self) -> Option<(PointExpr, PointExpr)> {
+        match *self {
+            EntityVars::Line { p1, p2 } => Some((p1, p2)),
+            _ => None,
+        }
+    }
+
+    /// 円か円弧の中心と半径
+    pub fn circle(&self) -> Option<(PointExpr, usize)> {
+        match *self {
+            EntityVars::Circle { center, radius } | EntityVars::Arc { center, radius, .. } => Some((center, radius)),
+            _ => None,
+        }
+    }
+}
+
+/// エンティティの特徴点をすべて列挙する
+pub fn entity_points(
+    entity: Entity,
+    line: Option<&SketchLine>,
+    circle: Option<&SketchCircle>,
+    rect: Option<&SketchRectangle>,
+    arc: Option<&SketchArc>,
+) -> Vec<(PointRef, Vec3)> {
+    let point = |kind| PointRef { entity, kind };
+    if let Some(line) = line {
+        vec![(point(PointKind::Start), line.p1), (point(PointKind::End), line.p2)]
+    } else if let Some(circle) = circle {
+        vec![(point(PointKind::Center), circle.center)]
+    } else if let Some(rect) = rect {
+        rect.corners()
+            .into_iter()
+            .enumerate()
+            .map(|(i, corner)| (point(PointKind::Corner(i as u8)), corner))
+            .collect()
+    } else if let Some(arc) = arc {
+        vec![(point(PointKind::Start), arc.start), (point(PointKind::End), arc.end), (point(PointKind::Center), arc.center)]
+    } else {
+        Vec::new()
+    }
+}
+
+/// 2つの特徴点の一覧から、最も近い点の組を返す
+pub fn closest_points(a: &[(PointRef, Vec3)], b: &[(PointRef, Vec3)]) -> Option<(PointRef, PointRef)> {
+    let mut best: Option<(PointRef, PointRef, f32)> = None;
+    for &(pa, va) in a {
+        for &(pb, vb) in b {
+            let d = va.distance(vb);
+            if best.is_none_or(|(.., best_d)| d < best_d) {
+                best = Some((pa, pb, d));
+            }
+        }
+    }
+    best.map(|(pa, pb, _)| (pa, pb))
+}
+
+/// 拘束を残差方程式としてソルバーに登録する。登録した方程式のインデックスを返す
+pub fn add_equations(system: &mut SolverSystem, vars: &HashMap<Entity, EntityVars>, kind: &ConstraintKind) -> Vec<usize> {
+    let point = |p: &PointRef| vars.get(&p.entity).and_then(|v| v.point(p.kind));
+    let line = |e: &Entity| vars.get(e).and_then(|v| v.line());
+    let circle = |e: &Entity| vars.get(e).and_then(|v| v.circle());
+
+    let mut equations = Vec::new();
+    match kind {
+        ConstraintKind::Coincident(a, b) => {
+            if let (Some(a), Some(b)) = (point(a), point(b)) {
+                equations.push(system.add_equation(move |p| p[a.x] - p[b.x]));
+                equations.push(system.add_equation(move |p| p[a.y] - p[b.y]));
+            }
+        }
+        ConstraintKind::Horizontal(e) => {
+            if let Some((a, b)) = line(e) {
+                equations.push(system.add_equation(move |p| p[a.y] - p[b.y]));
+            }
+        }
+        ConstraintKind::Vertical(e) => {
+            if let Some((a, b)) = line(e) {
+                equations.push(system.add_equation(move |p| p[a.x] - p[b.x]));
+            }
+        }
+        ConstraintKind::Parallel(e1, e2) | ConstraintKind::Perpendicular(e1, e2) => {
+            if let (Some((a1, b1)), Some((a2, b2))) = (line(e1), line(e2)) {
+                let parallel = matches!(kind, ConstraintKind::Parallel(..));
+                equations.push(system.add_equation(move |p| {
+                    let d1 = b1.eval(p) - a1.eval(p);
+                    let d2 = b2.eval(p) - a2.eval(p);
+                    let scale = (d1.length() * d2.length()).max(1e-12);
+                    if parallel { d1.perp_dot(d2) / scale } else { d1.dot(d2) / scale }
+                }));
+            }
+        }
+        ConstraintKind::Tangent(e1, e2) => match (line(e1), circle(e1), line(e2), circle(e2)) {
+            (Some((a, b)), _, _, Some((c, r))) | (_, Some((c, r)), Some((a, b)), _) => {
+                equations.push(system.add_equation(move |p| {
+                    distance_to_line(c.eval(p), a.eval(p), b.eval(p)) - p[r]
+                }));
+            }
+            (_, Some((c1, r1)), _, Some((c2, r2))) => {
+                equations.push(system.add_equation(move |p| {
+                    let d = c1.eval(p).distance(c2.eval(p));
+                    // 一方がもう一方を内包していれば内接、そうでなければ外接とみなす
+                    if d < p[r1].max(p[r2]) { d - (p[r1] - p[r2]).abs() } else { d - (p[r1] + p[r2]) }
+                }));
+            }
+            _ => {}
+        },
+        ConstraintKind::Equal(e1, e2) => match (line(e1), circle(e1), line(e2), circle(e2)) {
+            (Some((a1, b1)), _, Some((a2, b2)), _) => {
+                equations.push(system.add_equation(move |p| {
+                    a1.eval(p).distance(b1.eval(p)) - a2.eval(p).distance(b2.eval(p))
+                }));
+            }
+            (_, Some((_, r1)), _, Some((_, r2))) => {
+                equations.push(system.add_equation(move |p| p[r1] - p[r2]));
+            }
+            _ => {}
+        },
+        ConstraintKind::Midpoint(pt, e) => {
+            if let (Some(m), Some((a, b))) = (point(pt), line(e)) {
+                equations.push(system.add_equation(move |p| p[m.x] - (p[a.x] + p[b.x]) / 2.0));
+                equations.push(system.add_equation(move |p| p[m.y] - (p[a.y] + p[b.y]) / 2.0));
+            }
+        }
+        ConstraintKind::Concentric(e1, e2) => {
+            if let (Some((c1, _)), Some((c2, _))) = (circle(e1), circle(e2)) {
+                equations.push(system.add_equation(move |p| p[c1.x] - p[c2.x]));
+                equations.push(system.add_equation(move |p| p[c1.y] - p[c2.y]));
+            }
+        }
+        ConstraintKind::Fix(pt, target) => {
+            if let Some(m) = point(pt) {
+                let (tx, ty) = (target[0] as f64, target[1] as f64);
+                equations.push(system.add_equation(move |p| p[m.x] - tx));
+                equations.push(system.add_equation(move |p| p[m.y] - ty));
+            }
+        }
+    }
+    equations
+}
+
+/// 点から無限直線までの距離
+fn distance_to_line(p: DVec2, a: DVec2, b: DVec2) -> f64 {
+    let d = b - a;
+    d.perp_dot(p - a).abs() / d.length().max(1e-12)
+}
+
+/// 四角形の対角でない2つの角を、対角の2点を辺の向きに射影した位置に固定する方程式を追加し、その数を返す
+fn add_rectangle_equations(system: &mut SolverSystem, frame: &SketchFrame, angle: f32, corners: [PointExpr; 4]) -> usize {
+    let rotation = Quat::from_rotation_y(angle);
+    let axis = |v: Vec3| to_dvec(frame.to_local(rotation * v) - frame.to_local(Vec3::ZERO));
+    let [p1, _, p2, _] = corners;
+    for (corner, direction) in [(corners[1], axis(Vec3::Z)), (corners[3], axis(Vec3::X))] {
+        let expected = move |p: &[f64]| p1.eval(p) + direction * (p2.eval(p) - p1.eval(p)).dot(direction);
+        system.add_equation(move |p| p[corner.x] - expected(p).x);
+        system.add_equation(move |p| p[corner.y] - expected(p).y);
+    }
+    4
+}
+
+/// 円弧の両端を中心から半径の距離に保つ方程式を追加し、その数を返す
+fn add_arc_equations(system: &mut SolverSystem, center: PointExpr, radius: usize, start: PointExpr, end: PointExpr) -> usize {
+    for point in [start, end] {
+        system.add_equation(move |p| point.eval(p).distance(center.eval(p)) - p[radius]);
+    }
+    2
+}
+
+fn to_dvec(p: Vec2) -> DVec2 {
+    DVec2::new(p.x as f64, p.y as f64)
+}
+
+/// 求めた点をワールド座標に戻し、十分に動いた場合だけ書き戻す
+fn write_back(target: &mut Vec3, frame: &SketchFrame, params: &[f64], expr: PointExpr) -> bool {
+    let p = expr.eval(params);
+    let new = frame.to_world(Vec2::new(p.x as f32, p.y as f32));
+    if target.distance(new) > 1e-6 {
+        *target = new;
+        true
+    } else {
+        false
+    }
+}
+
+/// 編集中のスケッチの拘束と寸法を解き、ジオメトリを更新するシステム
+pub fn solve_constraints_system(
+    active_sketch: Res<ActiveSketch>,
+    mut report: ResMut<SolverReport>,
+    mut removed: RemovedComponents<SketchConstraint>,
+    mut removed_dimensions: RemovedComponents<SketchDimension>,
+    q_constraints: Query<(Entity, Ref<SketchConstraint>, &Parent)>,
+    q_dimensions: Query<(Entity, Ref<SketchDimension>, &Parent)>,
+    mut q_lines: Query<(Entity, &mut SketchLine, &Parent)>,
+    mut q_circles: Query<(Entity, &mut SketchCircle, &Parent)>,
+    mut q_rectangles: Query<(Entity, &mut SketchRectangle, &Parent)>,
+    mut q_arcs: Query<(Entity, &mut SketchArc, &Parent)>,
+) {
+    let Some(sketch) = active_sketch.0 else {
+        return;
+    };
+    let in_sketch = |parent: &Parent| parent.get() == sketch;
+
+    // ジオメトリか拘束・寸法に変更があった時だけ解き直す
+    let mut dirty = active_sketch.is_changed() || removed.read().count() > 0;
+    dirty |= removed_dimensions.read().count() > 0;
+    dirty |= q_constraints.iter().any(|(_, c, parent)| in_sketch(parent) && c.is_changed());
+    dirty |= q_dimensions.iter().any(|(_, d, parent)| in_sketch(parent) && d.is_changed());
+    dirty |= q_lines.iter_mut().any(|(_, line, parent)| in_sketch(parent) && line.is_changed());
+    dirty |= q_circles.iter_mut().any(|(_, circle, parent)| in_sketch(parent) && circle.is_changed());
+    dirty |= q_rectangles.iter_mut().any(|(_, rect, parent)| in_sketch(parent) && rect.is_changed());
+    dirty |= q_arcs.iter_mut().any(|(_, arc, parent)| in_sketch(parent) && arc.is_changed());
+    if !dirty {
+        return;
+    }
+
+    let frame = SketchFrame::default();
+    let mut system = SolverSystem::default();
+    let mut vars = HashMap::new();
+    for (entity, line, parent) in q_lines.iter() {
+        if in_sketch(parent) {
+            let p1 = system.add_point(to_dvec(frame.to_local(line.p1)));
+            let p2 = system.add_point(to_dvec(frame.to_local(line.p2)));
+            vars.insert(entity, EntityVars::Line { p1, p2 });
+        }
+    }
+    for (entity, circle, parent) in q_circles.iter() {
+        if in_sketch(parent) {
+            let center = system.add_point(to_dvec(frame.to_local(circle.center)));
+            let radius = system.add_param(circle.radius as f64);
+            vars.insert(entity, EntityVars::Circle { center, radius });
+        }
+    }
+    // 四角形と円弧の形を保つ方程式。拘束の状態には含めない
+    let mut shape_equations = 0;
+    for (entity, rect, parent) in q_rectangles.iter() {
+        if in_sketch(parent) {
+            let corners = rect.corners().map(|corner| system.add_point(to_dvec(frame.to_local(corner))));
+            shape_equations += add_rectangle_equations(&mut system, &frame, rect.angle, corners);
+            vars.insert(entity, EntityVars::Rectangle { corners });
+        }
+    }
+    for (entity, arc, parent) in q_arcs.iter() {
+        if in_sketch(parent) {
+            let center = system.add_point(to_dvec(frame.to_local(arc.center)));
+            let radius = system.add_param(arc.start.distance(arc.center) as f64);
+            let start = system.add_point(to_dvec(frame.to_local(arc.start)));
+            let end = system.add_point(to_dvec(frame.to_local(arc.end)));
+            shape_equations += add_arc_equations(&mut system, center, radius, start, end);
+            vars.insert(entity, EntityVars::Arc { center, radius, start, end });
+        }
+    }
+
+    let mut owners = Vec::new();
+    for (entity, constraint, parent) in q_constraints.iter() {
+        if in_sketch(parent) {
+            for _ in add_equations(&mut system, &vars, &constraint.0) {
+                owners.push(entity);
+            }
+        }
+    }
+    for (entity, dimension, parent) in q_dimensions.iter() {
+        if in_sketch(parent) {
+            for _ in dimensions::add_equations(&mut system, &vars, &dimension) {
+                owners.push(entity);
+            }
+        }
+    }
+
+    let result = system.solve();
+    report.dof = result.dof;
+    report.converged = result.converged;
+    report.statuses.clear();
+    for (owner, status) in owners.iter().zip(&result.statuses[shape_equations..]) {
+        let status = match status {
+            EquationStatus::Satisfied => ConstraintStatus::Satisfied,
+            EquationStatus::Redundant => ConstraintStatus::Redundant,
+            EquationStatus::Conflicting => ConstraintStatus::Conflicting,
+        };
+        // 1つの拘束が複数の方程式を持つ場合は最も悪い状態を採用する
+        let entry = report.statuses.entry(*owner).or_default();
+        *entry = (*entry).max(status);
+    }
+    if !result.converged {
+        return;
+    }
+
+    let params = &system.params;
+    for (entity, mut line, _) in q_lines.iter_mut() {
+        if let Some(&EntityVars::Line { p1, p2 }) = vars.get(&entity) {
+            let mut new = (line.p1, line.p2);
+            if write_back(&mut new.0, &frame, params, p1) | write_back(&mut new.1, &frame, params, p2) {
+                (line.p1, line.p2) = new;
+            }
+        }
+    }
+    for (entity, mut circle, _) in q_circles.iter_mut() {
+        if let Some(&EntityVars::Circle { center, radius }) = vars.get(&entity) {
+            let mut new_center = circle.center;
+            let new_radius = params[radius] as f32;
+            if write_back(&mut new_center, &frame, params, center) | ((new_radius - circle.radius).abs() > 1e-6) {
+                circle.center = new_center;
+                circle.radius = new_radius;
+            }
+        }
+    }
+    for (entity, mut rect, _) in q_rectangles.iter_mut() {
+        if let Some(&EntityVars::Rectangle { corners: [p1, _, p2, _] }) = vars.get(&entity) {
+            let mut new = (rect.p1, rect.p2);
+            if write_back(&mut new.0, &frame, params, p1) | write_back(&mut new.1, &frame, params, p2) {
+                (rect.p1, rect.p2) = new;
+            }
+        }
+    }
+    for (entity, mut arc, _) in q_arcs.iter_mut() {
+        if let Some(&EntityVars::Arc { center, start, end, .. }) = vars.get(&entity) {
+            let mut new = (arc.center, arc.start, arc.end);
+            if write_back(&mut new.0, &frame, params, center)
+                | write_back(&mut new.1, &frame, params, start)
+                | write_back(&mut new.2, &frame, params, end)
+            {
+                (arc.center, arc.start, arc.end) = new;
+            }
+        }
+    }
+}
+
+/// 選択中のエンティティから拘束を作成するシステム
+pub fn add_constraint_system(
+    mut commands: Commands,
+    mut events: EventReader<AddConstraintEvent>,
+    mut report: ResMut<SolverReport>,
+    mut history: ResMut<UndoHistory>,
+    active_sketch: Res<ActiveSketch>,
+    q_selected: Query<
+        (Entity, Option<&SketchLine>, Option<&SketchCircle>, Option<&SketchRectangle>, Option<&SketchArc>, &Parent),
+        With<Selected>,
+    >,
+) {
+    let Some(sketch) = active_sketch.0 else {
+        events.clear();
+        return;
+    };
+    for AddConstraintEvent(constraint_type) in events.read() {
+        let selected: Vec<_> = q_selected.iter().filter(|(.., parent)| parent.get() == sketch).collect();
+        let lines: Vec<Entity> = selected.iter().filter(|s| s.1.is_some()).map(|s| s.0).collect();
+        // 円弧も円と同じく接線・等しい半径・同心の対象にする
+        let circles: Vec<Entity> = selected.iter().filter(|s| s.2.is_some() || s.4.is_some()).map(|s| s.0).collect();
+        let points = |&(entity, line, circle, rect, arc, _): &(Entity, _, _, _, _, &Parent)| {
+            entity_points(entity, line, circle, rect, arc)
+        };
+
+        let frame = SketchFrame::default();
+        let mut kinds = Vec::new();
+        match constraint_type {
+            ConstraintType::Coincident => {
+                // 2つのエンティティの特徴点のうち、最も近い組を一致させる
+                if let [a, b] = selected.as_slice() {
+                    if let Some((pa, pb)) = closest_points(&points(a), &points(b)) {
+                        kinds.push(ConstraintKind::Coincident(pa, pb));
+                    }
+                }
+            }
+            ConstraintType::Horizontal => kinds.extend(lines.iter().map(|&e| ConstraintKind::Horizontal(e))),
+            ConstraintType::Vertical => kinds.extend(lines.iter().map(|&e| ConstraintKind::Vertical(e))),
+            ConstraintType::Parallel => {
+                if let [a, b] = lines.as_slice() {
+                    kinds.push(ConstraintKind::Parallel(*a, *b));
+                }
+            }
+            ConstraintType::Perpendicular => {
+                if let [a, b] = lines.as_slice() {
+                    kinds.push(ConstraintKind::Perpendicular(*a, *b));
+                }
+            }
+            ConstraintType::Tangent => match (lines.as_slice(), circles.as_slice()) {
+                ([line], [circle]) => kinds.push(ConstraintKind::Tangent(*line, *circle)),
+                ([], [a, b]) => kinds.push(ConstraintKind::Tangent(*a, *b)),
+                _ => {}
+            },
+            ConstraintType::Equal => match (lines.as_slice(), circles.as_slice()) {
+                ([a, b], []) => kinds.push(ConstraintKind::Equal(*a, *b)),
+                ([], [a, b]) => kinds.push(ConstraintKind::Equal(*a, *b)),
+                _ => {}
+            },
+            ConstraintType::Midpoint => {
+                // 直線の中点に最も近い、もう一方のエンティティの特徴点を中点に置く
+                if let [a, b] = selected.as_slice() {
+                    for (line_sel, other) in [(a, b), (b, a)] {
+                        let Some(line) = line_sel.1 else {
+                            continue;
+                        };
+                        let mid = (line.p1 + line.p2) / 2.0;
+                        let nearest = points(other)
+                            .into_iter()
+                            .min_by(|x, y| x.1.distance(mid).total_cmp(&y.1.distance(mid)));
+                        if let Some((point, _)) = nearest {
+                            kinds.push(ConstraintKind::Midpoint(point, line_sel.0));
+                            break;
+                        }
+                    }
+                }
+            }
+            ConstraintType::Concentric => {
+                if let [a, b] = circles.as_slice() {
+                    kinds.push(ConstraintKind::Concentric(*a, *b));
+                }
+            }
+            ConstraintType::Fix => {
+                for s in &selected {
+                    for (point, position) in points(s) {
+                        kinds.push(ConstraintKind::Fix(point, frame.to_local(position).to_array()));
+                    }
+                }
+            }
+        }
+
+        if kinds.is_empty() {
+            report.message = format!("{}拘束を作成できる図形が選択されていません", constraint_type.label());
+            continue;
+        }
+        report.message.clear();
+        for kind in kinds {
+            commands.spawn(SketchConstraint(kind)).set_parent(sketch);
+        }
+        history.record(format!("{}拘束を追加", constraint_type.label()));
+    }
+}
+
+/// 拘束の状態をマーカーとして描画するシステム
+pub fn draw_constraint_gizmos(
+    mut gizmos: Gizmos,
+    active_sketch: Res<ActiveSketch>,
+    report: Res<SolverReport>,
+    q_constraints: Query<(Entity, &SketchConstraint, &Parent)>,
+    q_geometry: GeometryQuery,
+) {
+    let Some(sketch) = active_sketch.0 else {
+        return;
+    };
+    for (entity, constraint, parent) in q_constraints.iter() {
+        if parent.get() != sketch {
+            continue;
+        }
+        let color = report.statuses.get(&entity).copied().unwrap_or_default().color();
+        for target in constraint.0.entities() {
+            let Ok((line, circle, rect, arc)) = q_geometry.get(target) else {
+                continue;
+            };
+            let anchor = match (line, circle, rect, arc) {
+                (Some(line), ..) => (line.p1 + line.p2) / 2.0,
+                (_, Some(circle), ..) => circle.center,
+                (_, _, Some(rect), _) => (rect.p1 + rect.p2) / 2.0,
+                (.., Some(arc)) => arc.center,
+                _ => continue,
+            };
+            gizmos.circle(anchor, Direction3d::Y, 0.06, color);
+        }
+        if let ConstraintKind::Coincident(p, _) | ConstraintKind::Fix(p, _) = constraint.0 {
+            if let Ok((line, circle, rect, arc)) = q_geometry.get(p.entity) {
+                if let Some((_, position)) = entity_points(p.entity, line, circle, rect, arc)
+                    .into_iter()
+                    .find(|(point, _)| *point == p)
+                {
+                    gizmos.circle(position, Direction3d::Y, 0.1, color);
+                }
+            }
+        }
+    }
+}
+
+/// サイドパネルに表示する拘束の操作と一覧
+#[derive(SystemParam)]
+pub struct ConstraintPanel<'w, 's> {
+    commands: Commands<'w, 's>,
+    events: EventWriter<'w, AddConstraintEvent>,
+    report: Res<'w, SolverReport>,
+    q_constraints: Query<'w, 's, (Entity, &'static SketchConstraint, &'static Parent)>,
+}
+
+impl ConstraintPanel<'_, '_> {
+    pub fn show(&mut self, ui: &mut egui::Ui, sketch: Entity, history: &mut UndoHistory) {
+        ui.label("拘束");
+        ui.horizontal_wrapped(|ui| {
+            for constraint_type in ConstraintType::ALL {
+                if ui.button(constraint_type.label()).clicked() {
+                    self.events.send(AddConstraintEvent(constraint_type));
+                }
+            }
+        });
+        if !self.report.message.is_empty() {
+            ui.colored_label(egui::Color32::YELLOW, &self.report.message);
+        }
+
+        if self.report.dof == 0 && self.report.converged {
+            ui.label("完全拘束");
+        } else {
+            ui.label(format!("残りの自由度: {}", self.report.dof));
+        }
+        if !self.report.converged {
+            ui.colored_label(egui::Color32::RED, "拘束を満たす解が見つかりません");
+        }
+
+        egui::ScrollArea::vertical().id_source("constraint_list").max_height(150.0).show(ui, |ui| {
+            for (entity, constraint, parent) in self.q_constraints.iter() {
+                if parent.get() != sketch {
+                    continue;
+                }
+                let status = self.report.statuses.get(&entity).copied().unwrap_or_default();
+                let (color, suffix) = match status {
+                    ConstraintStatus::Satisfied => (egui::Color32::LIGHT_GREEN, ""),
+                    ConstraintStatus::Redundant => (egui::Color32::YELLOW, " (冗長)"),
+                    ConstraintStatus::Conflicting => (egui::Color32::RED, " (矛盾)"),
+                };
+                ui.horizontal(|ui| {
+                    ui.colored_label(color, format!("{}{}", constraint.0.constraint_type().label(), suffix));
+                    if ui.small_button("×").clicked() {
+                        self.commands.entity(entity).despawn_recursive();
+                        history.record(format!("{}拘束を削除", constraint.0.constraint_type().label()));
+                    }
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 直線を未知数に加える
+    fn add_line(system: &mut SolverSystem, vars: &mut HashMap<Entity, EntityVars>, index: u32, a: DVec2, b: DVec2) -> Entity {
+        let entity = Entity::from_raw(index);
+        let (p1, p2) = (system.add_point(a), system.add_point(b));
+        vars.insert(entity, EntityVars::Line { p1, p2 });
+        entity
+    }
+
+    fn start(entity: Entity) -> PointRef {
+        PointRef { entity, kind: PointKind::Start }
+    }
+
+    fn end(entity: Entity) -> PointRef {
+        PointRef { entity, kind: PointKind::End }
+    }
+
+    #[test]
+    fn closest_points_picks_nearest_pair() {
+        let (a, b) = (Entity::from_raw(1), Entity::from_raw(2));
+        let line_a = [(start(a), Vec3::ZERO), (end(a), Vec3::X)];
+        let line_b = [(start(b), Vec3::new(3.0, 0.0, 0.0)), (end(b), Vec3::new(1.1, 0.0, 0.0))];
+        assert_eq!(closest_points(&line_a, &line_b), Some((end(a), end(b))));
+        assert_eq!(closest_points(&line_a, &[]), None);
+    }
+
+    #[test]
+    fn solves_right_angle_corner() {
+        let mut system = SolverSystem::default();
+        let mut vars = HashMap::new();
+        let a = add_line(&mut system, &mut vars, 1, DVec2::new(0.0, 0.0), DVec2::new(1.0, 0.1));
+        let b = add_line(&mut system, &mut vars, 2, DVec2::new(1.1, 0.0), DVec2::new(1.3, 1.0));
+        for kind in [
+            ConstraintKind::Fix(start(a), [0.0, 0.0]),
+            ConstraintKind::Coincident(end(a), start(b)),
+            ConstraintKind::Horizontal(a),
+            ConstraintKind::Perpendicular(a, b),
+        ] {
+            add_equations(&mut system, &vars, &kind);
+        }
+        let result = system.solve();
+        assert!(result.converged);
+        // 2本の長さの分だけ自由度が残る
+        assert_eq!(result.dof, 2);
+        let (Some((a1, a2)), Some((b1, b2))) = (vars[&a].line(), vars[&b].line()) else {
+            unreachable!();
+        };
+        let params = &system.params;
+        assert!(a1.eval(params).length() < 1e-6);
+        assert!(a2.eval(params).distance(b1.eval(params)) < 1e-6);
+        assert!((a2.eval(params).y - a1.eval(params).y).abs() < 1e-6);
+        assert!((b2.eval(params).x - b1.eval(params).x).abs() < 1e-6);
+    }
+
+    #[test]
+    fn solves_line_circle_tangent() {
+        let mut system = SolverSystem::default();
+        let mut vars = HashMap::new();
+        let line = add_line(&mut system, &mut vars, 1, DVec2::new(-1.0, 0.0), DVec2::new(1.0, 0.0));
+        let circle = Entity::from_raw(2);
+        let center = system.add_point(DVec2::new(0.0, 0.7));
+        let radius = system.add_param(0.5);
+        vars.insert(circle, EntityVars::Circle { center, radius });
+        add_equations(&mut system, &vars, &ConstraintKind::Tangent(line, circle));
+        assert!(system.solve().converged);
+        let (a, b) = vars[&line].line().unwrap();
+        let params = &system.params;
+        assert!((distance_to_line(center.eval(params), a.eval(params), b.eval(params)) - params[radius]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn solves_fillet_arc_between_lines() {
+        let mut system = SolverSystem::default();
+        let mut vars = HashMap::new();
+        let a = add_line(&mut system, &mut vars, 1, DVec2::new(-2.0, 0.0), DVec2::new(-0.9, 0.1));
+        let b = add_line(&mut system, &mut vars, 2, DVec2::new(0.1, 1.1), DVec2::new(0.0, 2.0));
+        // 半径1の円弧を少しずらした位置から始める
+        let arc = Entity::from_raw(3);
+        let center = system.add_point(DVec2::new(-1.1, 1.2));
+        let radius = system.add_param(1.0);
+        let arc_start = system.add_point(DVec2::new(-1.0, 0.0));
+        let arc_end = system.add_point(DVec2::new(0.0, 1.0));
+        let shape_equations = add_arc_equations(&mut system, center, radius, arc_start, arc_end);
+        vars.insert(arc, EntityVars::Arc { center, radius, start: arc_start, end: arc_end });
+        for kind in [
+            ConstraintKind::Fix(start(a), [-2.0, 0.0]),
+            ConstraintKind::Horizontal(a),
+            ConstraintKind::Fix(end(b), [0.0, 2.0]),
+            ConstraintKind::Vertical(b),
+            ConstraintKind::Coincident(end(a), start(arc)),
+            ConstraintKind::Coincident(start(b), end(arc)),
+            ConstraintKind::Tangent(a, arc),
+            ConstraintKind::Tangent(b, arc),
+        ] {
+            add_equations(&mut system, &vars, &kind);
+        }
+        let result = system.solve();
+        assert!(result.converged);
+        assert!(result.statuses[shape_equations..].iter().all(|&status| status == EquationStatus::Satisfied));
+        // 半径の分だけ自由度が残る
+        assert_eq!(result.dof, 1);
+        let params = &system.params;
+        let (a1, a2) = vars[&a].line().unwrap();
+        let (b1, b2) = vars[&b].line().unwrap();
+        let c = center.eval(params);
+        assert!((distance_to_line(c, a1.eval(params), a2.eval(params)) - params[radius]).abs() < 1e-6);
+        assert!((distance_to_line(c, b1.eval(params), b2.eval(params)) - params[radius]).abs() < 1e-6);
+        assert!(a2.eval(params).distance(arc_start.eval(params)) < 1e-6);
+        assert!(b1.eval(params).distance(arc_end.eval(params)) < 1e-6);
+        for point in [arc_start, arc_end] {
+            assert!((point.eval(params).distance(c) - params[radius]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn flags_redundant_and_conflicting_constraints() {
+        let mut system = SolverSystem::default();
+        let mut vars = HashMap::new();
+        let a = add_line(&mut system, &mut vars, 1, DVec2::new(0.0, 0.0), DVec2::new(1.0, 0.2));
+        let b = add_line(&mut system, &mut vars, 2, DVec2::new(0.0, 1.0), DVec2::new(1.0, 1.3));
+        let mut equations = Vec::new();
+        for kind in [ConstraintKind::Horizontal(a), ConstraintKind::Horizontal(b), ConstraintKind::Parallel(a, b)] {
+            equations.extend(add_equations(&mut system, &vars, &kind));
+        }
+        let result = system.solve();
+        assert!(result.converged);
+        assert_eq!(result.statuses[equations[2]], EquationStatus::Redundant);
+
+        // 両端を固定した斜めの直線は水平にできない
+        let mut system = SolverSystem::default();
+        let mut vars = HashMap::new();
+        let a = add_line(&mut system, &mut vars, 1, DVec2::new(0.0, 0.0), DVec2::new(1.0, 0.2));
+        for kind in [
+            ConstraintKind::Fix(start(a), [0.0, 0.0]),
+            ConstraintKind::Fix(end(a), [1.0, 1.0]),
+            ConstraintKind::Horizontal(a),
+        ] {
+            add_equations(&mut system, &vars, &kind);
+        }
+        let result = system.solve();
+        assert!(!result.converged);
+        assert!(result.statuses.contains(&EquationStatus::Conflicting));
+    }
+}
@@ -0,0 +1,592 @@
+//! BSPツリーによるソリッドのブーリアン演算 (和・差・積)
+//!
+//! 各ソリッドを凸多角形の集合として持ち、相手のBSPツリーで多角形を切り分けて
+//! 内側・外側の部分を取捨選択する。入力のメッシュは閉じていることが前提。
+
+use std::collections::{HashMap, HashSet};
+
+use bevy::math::DVec3;
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+use serde::{Deserialize, Serialize};
+
+use crate::mesh_builder::MeshBuffers;
+
+/// 平面上とみなす距離
+const EPSILON: f64 = 1e-5;
+
+#[derive(Debug, Clone, Copy)]
+struct Plane {
+    normal: DVec3,
+    w: f64,
+}
+
+impl Plane {
+    fn from_points(a: DVec3, b: DVec3, c: DVec3) -> Option<Self> {
+        let normal = (b - a).cross(c - a).try_normalize()?;
+        Some(Self { normal, w: normal.dot(a) })
+    }
+
+    fn flip(&mut self) {
+        self.normal = -self.normal;
+        self.w = -self.w;
+    }
+
+    /// 多角形をこの平面で切り分け、前後・同一平面のいずれかに振り分ける
+    fn split_polygon(
+        &self,
+        polygon: Polygon,
+        coplanar_front: &mut Vec<Polygon>,
+        coplanar_back: &mut Vec<Polygon>,
+        front: &mut Vec<Polygon>,
+        back: &mut Vec<Polygon>,
+    ) {
+        const COPLANAR: u8 = 0;
+        const FRONT: u8 = 1;
+        const BACK: u8 = 2;
+        const SPANNING: u8 = 3;
+
+        let types: Vec<u8> = polygon
+            .vertices
+            .iter()
+            .map(|v| {
+                let t = self.normal.dot(*v) - self.w;
+                if t < -EPSILON {
+                    BACK
+                } else if t > EPSILON {
+                    FRONT
+                } else {
+                    COPLANAR
+                }
+            })
+            .collect();
+
+        match types.iter().fold(COPLANAR, |acc, t| acc | t) {
+            COPLANAR if self.normal.dot(polygon.plane.normal) > 0.0 => coplanar_front.push(polygon),
+            COPLANAR => coplanar_back.push(polygon),
+            FRONT => front.push(polygon),
+            BACK => back.push(polygon),
+            _ => {
+                let n = polygon.vertices.len();
+                let (mut f, mut b) = (Vec::new(), Vec::new());
+                for i in 0..n {
+                    let j = (i + 1) % n;
+                    let (ti, tj) = (types[i], types[j]);
+                    let (vi, vj) = (polygon.vertices[i], polygon.vertices[j]);
+                    if ti != BACK {
+                        f.push(vi);
+                    }
+                    if ti != FRONT {
+                        b.push(vi);
+                    }
+                    if ti | tj == SPANNING {
+                        let t = (self.w - self.normal.dot(vi)) / self.normal.dot(vj - vi);
+                        let v = vi.lerp(vj, t);
+                        f.push(v);
+                        b.push(v);
+                    }
+                }
+                if f.len() >= 3 {
+                    front.push(Polygon { vertices: f, plane: polygon.plane });
+                }
+                if b.len() >= 3 {
+                    back.push(Polygon { vertices: b, plane: polygon.plane });
+                }
+            }
+        }
+    }
+}
+
+/// 平面上の凸多角形
+#[derive(Debug, Clone)]
+struct Polygon {
+    vertices: Vec<DVec3>,
+    plane: Plane,
+}
+
+impl Polygon {
+    fn flip(&mut self) {
+        self.vertices.reverse();
+        self.plane.flip();
+    }
+}
+
+/// BSPツリーのノード。前側 (法線側) が外部、後側が内部
+#[derive(Default)]
+struct Node {
+    plane: Option<Plane>,
+    front: Option<Box<Node>>,
+    back: Option<Box<Node>>,
+    polygons: Vec<Polygon>,
+}
+
+impl Node {
+    fn new(polygons: Vec<Polygon>) -> Self {
+        let mut node = Node::default();
+        node.build(polygons);
+        node
+    }
+
+    /// 内部と外部を入れ替える
+    fn invert(&mut self) {
+        for polygon in &mut self.polygons {
+            polygon.flip();
+        }
+        if let Some(plane) = &mut self.plane {
+            plane.flip();
+        }
+        if let Some(front) = &mut self.front {
+            front.invert();
+        }
+        if let Some(back) = &mut self.back {
+            back.invert();
+        }
+        std::mem::swap(&mut self.front, &mut self.back);
+    }
+
+    /// このツリーの内部にある部分を取り除く
+    fn clip_polygons(&self, polygons: Vec<Polygon>) -> Vec<Polygon> {
+        let Some(plane) = self.plane else {
+            return polygons;
+        };
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+        let (mut coplanar_front, mut coplanar_back) = (Vec::new(), Vec::new());
+        for polygon in polygons {
+            plane.split_polygon(polygon, &mut coplanar_front, &mut coplanar_back, &mut front, &mut back);
+        }
+        front.append(&mut coplanar_front);
+        back.append(&mut coplanar_back);
+
+        let mut front = match &self.front {
+            Some(node) => node.clip_polygons(front),
+            None => front,
+        };
+        if let Some(node) = &self.back {
+            front.extend(node.clip_polygons(back));
+        }
+        front
+    }
+
+    /// このツリーの多角形のうち、`bsp` の内部にある部分を取り除く
+    fn clip_to(&mut self, bsp: &Node) {
+        self.polygons = bsp.clip_polygons(std::mem::take(&mut self.polygons));
+        if let Some(front) = &mut self.front {
+            front.clip_to(bsp);
+        }
+        if let Some(back) = &mut self.back {
+            back.clip_to(bsp);
+        }
+    }
+
+    fn all_polygons(&self) -> Vec<Polygon> {
+        let mut polygons = self.polygons.clone();
+        if let Some(front) = &self.front {
+            polygons.extend(front.all_polygons());
+        }
+        if let Some(back) = &self.back {
+            polygons.extend(back.all_polygons());
+        }
+        polygons
+    }
+
+    fn build(&mut self, polygons: Vec<Polygon>) {
+        let Some(first) = polygons.first() else {
+            return;
+        };
+        let plane = *self.plane.get_or_insert(first.plane);
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+        let (mut coplanar_front, mut coplanar_back) = (Vec::new(), Vec::new());
+        for polygon in polygons {
+            plane.split_polygon(polygon, &mut coplanar_front, &mut coplanar_back, &mut front, &mut back);
+        }
+        self.polygons.append(&mut coplanar_front);
+        self.polygons.append(&mut coplanar_back);
+        if !front.is_empty() {
+            self.front.get_or_insert_with(Default::default).build(front);
+        }
+        if !back.is_empty() {
+            self.back.get_or_insert_with(Default::default).build(back);
+        }
+    }
+}
+
+/// ソリッド同士の演算の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BooleanOp {
+    Union,
+    Difference,
+    Intersection,
+}
+
+impl BooleanOp {
+    pub const ALL: [BooleanOp; 3] = [BooleanOp::Union, BooleanOp::Difference, BooleanOp::Intersection];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BooleanOp::Union => "和",
+            BooleanOp::Difference => "差",
+            BooleanOp::Intersection => "積",
+        }
+    }
+}
+
+/// 2つの閉じた三角形メッシュを演算し、結果をワールド座標の閉じたメッシュで返す。
+/// 結果が空の場合は `None`
+pub fn boolean(a: (&Mesh, &Transform), b: (&Mesh, &Transform), op: BooleanOp) -> Option<Mesh> {
+    let (a, b) = (Solid::from_mesh(a.0, a.1), Solid::from_mesh(b.0, b.1));
+    let result = a.apply(&b, op);
+    (!result.is_empty()).then(|| result.to_mesh())
+}
+
+/// ブーリアン演算の対象となるソリッド
+#[derive(Debug, Clone, Default)]
+pub struct Solid {
+    polygons: Vec<Polygon>,
+}
+
+impl Solid {
+    /// 三角形メッシュをワールド座標のソリッドにする
+    pub fn from_mesh(mesh: &Mesh, transform: &Transform) -> Self {
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+            return Self::default();
+        };
+        let matrix = transform.compute_matrix();
+        let positions: Vec<DVec3> =
+            positions.iter().map(|p| matrix.transform_point3(Vec3::from_array(*p)).as_dvec3()).collect();
+        let indices: Vec<usize> = match mesh.indices() {
+            Some(indices) => indices.iter().collect(),
+            None => (0..positions.len()).collect(),
+        };
+        let polygons = indices
+            .chunks_exact(3)
+            .filter_map(|tri| {
+                let vertices = vec![positions[tri[0]], positions[tri[1]], positions[tri[2]]];
+                let plane = Plane::from_points(vertices[0], vertices[1], vertices[2])?;
+                Some(Polygon { vertices, plane })
+            })
+            .collect();
+        Self { polygons }
+    }
+
+    /// 面ごとに法線を持つ三角形メッシュにする。近い頂点を1つにまとめ、他の面の頂点が
+    /// 辺の途中にある (T字接合) 場合はその辺を分割して、隣り合う面の辺が一致するようにする
+    pub fn to_mesh(&self) -> Mesh {
+        let mut vertices: Vec<DVec3> = Vec::new();
+        let mut lookup: HashMap<[i64; 3], usize> = HashMap::new();
+        let rings: Vec<(Vec<usize>, Vec3)> = self
+            .polygons
+            .iter()
+            .filter_map(|polygon| {
+                let mut ring: Vec<usize> = polygon
+                    .vertices
+                    .iter()
+                    .map(|v| {
+                        let key = (*v / EPSILON).round().as_i64vec3().to_array();
+                        *lookup.entry(key).or_insert_with(|| {
+                            vertices.push(*v);
+                            vertices.len() - 1
+                        })
+                    })
+                    .collect();
+                ring.dedup();
+                while ring.len() > 1 && ring.first() == ring.last() {
+                    ring.pop();
+                }
+                (ring.len() >= 3).then(|| (ring, polygon.plane.normal.as_vec3()))
+            })
+            .collect();
+
+        let grid = VertexGrid::new(&vertices);
+        let mut buffers = MeshBuffers::default();
+        for (ring, normal) in rings {
+            let ring = split_t_junctions(&ring, &vertices, &grid);
+            let points: Vec<Vec3> = ring.iter().map(|&i| vertices[i].as_vec3()).collect();
+            // 辺の途中の頂点が扇の中心と一直線に並ぶと潰れた三角形になるので、その場合は重心から分割する
+            let degenerate = (1..points.len() - 1)
+                .any(|i| (points[i] - points[0]).cross(points[i + 1] - points[0]).length_squared() < 1e-12);
+            if degenerate {
+                let center = points.iter().sum::<Vec3>() / points.len() as f32;
+                for i in 0..points.len() {
+                    buffers.triangle(center, points[i], points[(i + 1) % points.len()], normal);
+                }
+            } else {
+                for i in 1..points.len() - 1 {
+                    buffers.triangle(points[0], points[i], points[i + 1], normal);
+                }
+            }
+        }
+        buffers.build()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.polygons.is_empty()
+    }
+
+    /// 軸平行な外接箱 (最小点, 最大点)
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        self.polygons.iter().flat_map(|p| &p.vertices).fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), v| (min.min(v.as_vec3()), max.max(v.as_vec3())),
+        )
+    }
+
+    /// 外接箱が重なるか
+    pub fn overlaps(&self, other: &Solid) -> bool {
+        let (a_min, a_max) = self.bounds();
+        let (b_min, b_max) = other.bounds();
+        a_min.cmple(b_max).all() && b_min.cmple(a_max).all()
+    }
+
+    pub fn apply(&self, other: &Solid, op: BooleanOp) -> Solid {
+        match op {
+            BooleanOp::Union => self.union(other),
+            BooleanOp::Difference => self.subtract(other),
+            BooleanOp::Intersection => self.intersect(other),
+        }
+    }
+
+    pub fn union(&self, other: &Solid) -> Solid {
+        let mut a = Node::new(self.polygons.clone());
+        let mut b = Node::new(other.polygons.clone());
+        a.clip_to(&b);
+        b.clip_to(&a);
+        b.invert();
+        b.clip_to(&a);
+        b.invert();
+        a.build(b.all_polygons());
+        Solid { polygons: a.all_polygons() }
+    }
+
+    pub fn subtract(&self, other: &Solid) -> Solid {
+        let mut a = Node::new(self.polygons.clone());
+        let mut b = Node::new(other.polygons.clone());
+        a.invert();
+        a.clip_to(&b);
+        b.clip_to(&a);
+        b.invert();
+        b.clip_to(&a);
+        b.invert();
+        a.build(b.all_polygons());
+        a.invert();
+        Solid { polygons: a.all_polygons() }
+    }
+
+    pub fn intersect(&self, other: &Solid) -> Solid {
+        let mut a = Node::new(self.polygons.clone());
+        let mut b = Node::new(other.polygons.clone());
+        a.invert();
+        b.clip_to(&a);
+        b.invert();
+        a.clip_to(&b);
+        b.clip_to(&a);
+        a.build(b.all_polygons());
+        a.invert();
+        Solid { polygons: a.all_polygons() }
+    }
+}
+
+/// 頂点を立方体の格子に振り分けた空間ハッシュ。辺の途中にある頂点の候補を近くの格子から探す
+struct VertexGrid {
+    cell: f64,
+    cells: HashMap<[i64; 3], Vec<usize>>,
+}
+
+impl VertexGrid {
+    /// 1つの格子に平均して1つ程度の頂点が入る大きさで振り分ける
+    fn new(vertices: &[DVec3]) -> Self {
+        let (min, max) = vertices
+            .iter()
+            .fold((DVec3::splat(f64::MAX), DVec3::splat(f64::MIN)), |(min, max), v| (min.min(*v), max.max(*v)));
+        let extent = (max - min).max_element().max(0.0);
+        let cell = (extent / (vertices.len() as f64).cbrt().max(1.0)).max(EPSILON * 4.0);
+        let mut grid = Self { cell, cells: HashMap::new() };
+        for (i, v) in vertices.iter().enumerate() {
+            grid.cells.entry(grid.key(*v)).or_default().push(i);
+        }
+        grid
+    }
+
+    fn key(&self, p: DVec3) -> [i64; 3] {
+        (p / self.cell).floor().as_i64vec3().to_array()
+    }
+
+    /// 線分 `a`-`b` の近くの格子にある頂点。格子の半分の間隔でたどった点の周囲の格子を調べるので、
+    /// 線分から `EPSILON` 以内の頂点はすべて含まれる
+    fn near_segment(&self, a: DVec3, b: DVec3) -> Vec<usize> {
+        let steps = (a.distance(b) / (self.cell / 2.0)).ceil().max(1.0) as usize;
+        let mut keys = HashSet::new();
+        for i in 0..=steps {
+            let [x, y, z] = self.key(a.lerp(b, i as f64 / steps as f64));
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        keys.insert([x + dx, y + dy, z + dz]);
+                    }
+                }
+            }
+        }
+        keys.iter().filter_map(|key| self.cells.get(key)).flatten().copied().collect()
+    }
+}
+
+/// 多角形の辺の途中にある頂点を辺に挿入する
+fn split_t_junctions(ring: &[usize], vertices: &[DVec3], grid: &VertexGrid) -> Vec<usize> {
+    let mut result = Vec::with_capacity(ring.len());
+    for i in 0..ring.len() {
+        let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+        result.push(a);
+        let (pa, pb) = (vertices[a], vertices[b]);
+        let ab = pb - pa;
+        let length_sq = ab.length_squared();
+        if length_sq == 0.0 {
+            continue;
+        }
+        let mut on_edge: Vec<(f64, usize)> = grid
+            .near_segment(pa, pb)
+            .into_iter()
+            .filter(|&k| k != a && k != b)
+            .filter_map(|k| {
+                let p = vertices[k];
+                let t = (p - pa).dot(ab) / length_sq;
+                let distance = (pa + ab * t).distance(p);
+                (t > 0.0 && t < 1.0 && distance < EPSILON).then_some((t, k))
+            })
+            .collect();
+        on_edge.sort_by(|x, y| x.0.total_cmp(&y.0).then(x.1.cmp(&y.1)));
+        result.extend(on_edge.into_iter().map(|(_, k)| k));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use bevy::math::DVec2;
+
+    use super::*;
+
+    /// 反時計回りの外形を `z0` から `z1` まで押し出した角柱
+    fn prism(outline: &[DVec2], z0: f64, z1: f64) -> Solid {
+        let polygon = |vertices: Vec<DVec3>| {
+            let plane = Plane::from_points(vertices[0], vertices[1], vertices[2]).unwrap();
+            Polygon { vertices, plane }
+        };
+        let n = outline.len();
+        let mut polygons = vec![
+            polygon(outline.iter().rev().map(|p| p.extend(z0)).collect()),
+            polygon(outline.iter().map(|p| p.extend(z1)).collect()),
+        ];
+        for i in 0..n {
+            let (a, b) = (outline[i], outline[(i + 1) % n]);
+            polygons.push(polygon(vec![a.extend(z0), b.extend(z0), b.extend(z1), a.extend(z1)]));
+        }
+        Solid { polygons }
+    }
+
+    fn cuboid(min: [f64; 3], max: [f64; 3]) -> Solid {
+        let outline = [DVec2::new(min[0], min[1]), DVec2::new(max[0], min[1]), DVec2::new(max[0], max[1]), DVec2::new(min[0], max[1])];
+        prism(&outline, min[2], max[2])
+    }
+
+    const SEGMENTS: usize = 32;
+
+    /// z軸に沿った円柱を近似した正多角柱
+    fn cylinder(center: DVec2, radius: f64, z0: f64, z1: f64) -> Solid {
+        let outline: Vec<DVec2> =
+            (0..SEGMENTS).map(|i| center + DVec2::from_angle(TAU * i as f64 / SEGMENTS as f64) * radius).collect();
+        prism(&outline, z0, z1)
+    }
+
+    /// 円柱を近似した正多角形の面積
+    fn cylinder_area(radius: f64) -> f64 {
+        SEGMENTS as f64 / 2.0 * radius * radius * (TAU / SEGMENTS as f64).sin()
+    }
+
+    fn triangles(mesh: &Mesh) -> Vec<[DVec3; 3]> {
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+            panic!("頂点がない");
+        };
+        let indices: Vec<usize> = mesh.indices().unwrap().iter().collect();
+        indices.chunks_exact(3).map(|tri| [0, 1, 2].map(|k| Vec3::from_array(positions[tri[k]]).as_dvec3())).collect()
+    }
+
+    /// すべての辺がちょうど2つの三角形に逆向きに共有されていることを確かめる
+    fn assert_watertight(triangles: &[[DVec3; 3]]) {
+        let key = |p: DVec3| (p / 1e-4).round().as_i64vec3().to_array();
+        let mut edges: HashMap<([i64; 3], [i64; 3]), i32> = HashMap::new();
+        for tri in triangles {
+            for i in 0..3 {
+                let (a, b) = (key(tri[i]), key(tri[(i + 1) % 3]));
+                if a == b {
+                    continue;
+                }
+                *edges.entry((a, b)).or_default() += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "辺 {a:?}-{b:?} が同じ向きに {count} 回使われている");
+            assert_eq!(edges.get(&(b, a)), Some(&1), "辺 {a:?}-{b:?} の反対側の三角形がない");
+        }
+    }
+
+    /// 原点から各三角形への四面体の符号付き体積の和
+    fn volume(triangles: &[[DVec3; 3]]) -> f64 {
+        triangles.iter().map(|[a, b, c]| a.dot(b.cross(*c)) / 6.0).sum()
+    }
+
+    fn check(solid: &Solid, expected_volume: f64, expected_bounds: ([f32; 3], [f32; 3])) {
+        let triangles = triangles(&solid.to_mesh());
+        assert_watertight(&triangles);
+        let volume = volume(&triangles);
+        assert!((volume - expected_volume).abs() < 1e-4, "体積 {volume} (期待値 {expected_volume})");
+        let (min, max) = solid.bounds();
+        let (expected_min, expected_max) = (Vec3::from_array(expected_bounds.0), Vec3::from_array(expected_bounds.1));
+        assert!(min.distance(expected_min) < 1e-4 && max.distance(expected_max) < 1e-4, "外接箱 {min} {max}");
+    }
+
+    #[test]
+    fn overlapping_cubes() {
+        let a = cuboid([0.0; 3], [1.0; 3]);
+        let b = cuboid([0.5; 3], [1.5; 3]);
+        check(&a.union(&b), 1.875, ([0.0; 3], [1.5; 3]));
+        check(&a.subtract(&b), 0.875, ([0.0; 3], [1.0; 3]));
+        check(&a.intersect(&b), 0.125, ([0.5; 3], [1.0; 3]));
+    }
+
+    #[test]
+    fn cubes_with_coplanar_faces() {
+        // 上下と奥行きの面が同一平面にある
+        let a = cuboid([0.0; 3], [1.0; 3]);
+        let b = cuboid([0.5, 0.0, 0.0], [1.5, 1.0, 1.0]);
+        check(&a.union(&b), 1.5, ([0.0; 3], [1.5, 1.0, 1.0]));
+        check(&a.subtract(&b), 0.5, ([0.0; 3], [0.5, 1.0, 1.0]));
+        check(&a.intersect(&b), 0.5, ([0.5, 0.0, 0.0], [1.0; 3]));
+        // 面で接する2つの立方体の和は1つの直方体になる
+        let c = cuboid([1.0, 0.0, 0.0], [2.0, 1.0, 1.0]);
+        check(&a.union(&c), 2.0, ([0.0; 3], [2.0, 1.0, 1.0]));
+    }
+
+    #[test]
+    fn cubes_with_shared_edge() {
+        // 角を切り欠く直方体の辺の1本が立方体の辺に重なる
+        let a = cuboid([0.0; 3], [1.0; 3]);
+        let b = cuboid([0.5, 0.5, 0.5], [1.0, 1.0, 1.5]);
+        check(&a.union(&b), 1.125, ([0.0; 3], [1.0, 1.0, 1.5]));
+        check(&a.subtract(&b), 0.875, ([0.0; 3], [1.0; 3]));
+        check(&a.intersect(&b), 0.125, ([0.5; 3], [1.0; 3]));
+    }
+
+    #[test]
+    fn cube_and_cylinder() {
+        let cube = cuboid([0.0; 3], [1.0; 3]);
+        let cylinder = cylinder(DVec2::splat(0.5), 0.3, -0.5, 1.5);
+        let area = cylinder_area(0.3);
+        check(&cube.union(&cylinder), 1.0 + area, ([0.0, 0.0, -0.5], [1.0, 1.0, 1.5]));
+        check(&cube.subtract(&cylinder), 1.0 - area, ([0.0; 3], [1.0; 3]));
+        check(&cube.intersect(&cylinder), area, ([0.2, 0.2, 0.0], [0.8, 0.8, 1.0]));
+        // 円柱の上面が立方体の上面と同一平面にある止まり穴
+        let blind = self::cylinder(DVec2::splat(0.5), 0.3, 0.5, 1.0);
+        check(&cube.subtract(&blind), 1.0 - area * 0.5, ([0.0; 3], [1.0; 3]));
+    }
+}
@@ -0,0 +1,650 @@
+//! スケッチの駆動寸法
+//!
+//! 寸法は拘束と同じくスケッチの子エンティティとして保持し、値を変更すると
+//! ソルバーがジオメトリを寸法に合わせて動かす。
+
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+
+use bevy::ecs::system::SystemParam;
+use bevy::math::DVec2;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use bevy_panorbit_camera::PanOrbitCamera;
+use serde::{Deserialize, Serialize};
+
+use crate::constraints::{closest_points, entity_points, ConstraintStatus, EntityVars, GeometryQuery, PointKind, PointRef, SolverReport};
+use crate::geometry::SketchFrame;
+use crate::history::UndoHistory;
+use crate::solver::SolverSystem;
+use crate::{ActiveSketch, Selected, SketchArc, SketchCircle, SketchLine, SketchRectangle};
+
+/// 寸法線を図形から離す距離
+const DIMENSION_OFFSET: f32 = 0.5;
+/// 矢印の長さ
+const ARROW_SIZE: f32 = 0.12;
+
+/// 寸法の種類と対象。ファイル保存時は `E` をスケッチ内のインデックスに置き換える
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DimensionKind<E = Entity> {
+    /// 2点間の距離
+    Distance(PointRef<E>, PointRef<E>),
+    HorizontalDistance(PointRef<E>, PointRef<E>),
+    VerticalDistance(PointRef<E>, PointRef<E>),
+    Radius(E),
+    Diameter(E),
+    /// 2直線のなす角 (度)
+    Angle(E, E),
+}
+
+impl<E: Copy> DimensionKind<E> {
+    /// 参照先のエンティティを置き換える。1つでも置き換えられなければ `None`
+    pub fn map<T: Copy>(&self, mut f: impl FnMut(E) -> Option<T>) -> Option<DimensionKind<T>> {
+        let mut point = |p: &PointRef<E>| Some(PointRef { entity: f(p.entity)?, kind: p.kind });
+        Some(match self {
+            DimensionKind::Distance(a, b) => {
+                let a = point(a)?;
+                DimensionKind::Distance(a, point(b)?)
+            }
+            DimensionKind::HorizontalDistance(a, b) => {
+                let a = point(a)?;
+                DimensionKind::HorizontalDistance(a, point(b)?)
+            }
+            DimensionKind::VerticalDistance(a, b) => {
+                let a = point(a)?;
+                DimensionKind::VerticalDistance(a, point(b)?)
+            }
+            DimensionKind::Radius(e) => DimensionKind::Radius(f(*e)?),
+            DimensionKind::Diameter(e) => DimensionKind::Diameter(f(*e)?),
+            DimensionKind::Angle(a, b) => DimensionKind::Angle(f(*a)?, f(*b)?),
+        })
+    }
+
+    /// 点の参照を置き換える。エンティティ全体への参照はそのまま残す。1つでも置き換えられなければ `None`
+    pub fn map_points(&self, mut f: impl FnMut(PointRef<E>) -> Option<PointRef<E>>) -> Option<Self> {
+        Some(match self {
+            DimensionKind::Distance(a, b) => {
+                let a = f(*a)?;
+                DimensionKind::Distance(a, f(*b)?)
+            }
+            DimensionKind::HorizontalDistance(a, b) => {
+                let a = f(*a)?;
+                DimensionKind::HorizontalDistance(a, f(*b)?)
+            }
+            DimensionKind::VerticalDistance(a, b) => {
+                let a = f(*a)?;
+                DimensionKind::VerticalDistance(a, f(*b)?)
+            }
+            other => other.clone(),
+        })
+    }
+
+    pub fn dimension_type(&self) -> DimensionType {
+        match self {
+            DimensionKind::Distance(..) => DimensionType::Distance,
+            DimensionKind::HorizontalDistance(..) => DimensionType::HorizontalDistance,
+            DimensionKind::VerticalDistance(..) => DimensionType::VerticalDistance,
+            DimensionKind::Radius(..) => DimensionType::Radius,
+            DimensionKind::Diameter(..) => DimensionType::Diameter,
+            DimensionKind::Angle(..) => DimensionType::Angle,
+        }
+    }
+}
+
+/// UIから作成できる寸法の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DimensionType {
+    Distance,
+    HorizontalDistance,
+    VerticalDistance,
+    Radius,
+    Diameter,
+    Angle,
+}
+
+impl DimensionType {
+    pub const ALL: [DimensionType; 6] = [
+        DimensionType::Distance,
+        DimensionType::HorizontalDistance,
+        DimensionType::VerticalDistance,
+        DimensionType::Radius,
+        DimensionType::Diameter,
+        DimensionType::Angle,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DimensionType::Distance => "距離",
+            DimensionType::HorizontalDistance => "水平距離",
+            DimensionType::VerticalDistance => "垂直距離",
+            DimensionType::Radius => "半径",
+            DimensionType::Diameter => "直径",
+            DimensionType::Angle => "角度",
+        }
+    }
+
+    fn prefix(&self) -> &'static str {
+        match self {
+            DimensionType::Radius => "R",
+            DimensionType::Diameter => "⌀",
+            _ => "",
+        }
+    }
+
+    fn suffix(&self) -> &'static str {
+        match self {
+            DimensionType::Angle => "°",
+            _ => "m",
+        }
+    }
+}
+
+/// スケッチの駆動寸法。所属するスケッチの子エンティティとして生成する
+#[derive(Component, Debug, Clone)]
+pub struct SketchDimension {
+    pub kind: DimensionKind,
+    /// 寸法値 (長さはm、角度は度)
+    pub value: f32,
+}
+
+/// 選択中のエンティティに寸法を追加するイベント
+#[derive(Event)]
+pub struct AddDimensionEvent(pub DimensionType);
+
+/// 寸法を残差方程式としてソルバーに登録する。登録した方程式のインデックスを返す
+pub fn add_equations(system: &mut SolverSystem, vars: &HashMap<Entity, EntityVars>, dimension: &SketchDimension) -> Vec<usize> {
+    let point = |p: &PointRef| vars.get(&p.entity).and_then(|v| v.point(p.kind));
+    let line = |e: &Entity| vars.get(e).and_then(|v| v.line());
+    let circle = |e: &Entity| vars.get(e).and_then(|v| v.circle());
+    let value = dimension.value as f64;
+
+    let mut equations = Vec::new();
+    match &dimension.kind {
+        DimensionKind::Distance(a, b) => {
+            if let (Some(a), Some(b)) = (point(a), point(b)) {
+                equations.push(system.add_equation(move |p| a.eval(p).distance(b.eval(p)) - value));
+            }
+        }
+        DimensionKind::HorizontalDistance(a, b) => {
+            if let (Some(a), Some(b)) = (point(a), point(b)) {
+                equations.push(system.add_equation(move |p| (p[b.x] - p[a.x]).abs() - value));
+            }
+        }
+        DimensionKind::VerticalDistance(a, b) => {
+            if let (Some(a), Some(b)) = (point(a), point(b)) {
+                equations.push(system.add_equation(move |p| (p[b.y] - p[a.y]).abs() - value));
+            }
+        }
+        DimensionKind::Radius(e) => {
+            if let Some((_, r)) = circle(e) {
+                equations.push(system.add_equation(move |p| p[r] - value));
+            }
+        }
+        DimensionKind::Diameter(e) => {
+            if let Some((_, r)) = circle(e) {
+                equations.push(system.add_equation(move |p| 2.0 * p[r] - value));
+            }
+        }
+        DimensionKind::Angle(e1, e2) => {
+            if let (Some((a1, b1)), Some((a2, b2))) = (line(e1), line(e2)) {
+                let target = value.to_radians();
+                equations.push(system.add_equation(move |p| {
+                    let d1 = b1.eval(p) - a1.eval(p);
+                    let d2 = b2.eval(p) - a2.eval(p);
+                    unsigned_angle(d1, d2) - target
+                }));
+            }
+        }
+    }
+    equations
+}
+
+/// 2つの方向ベクトルのなす角 (0〜π)
+fn unsigned_angle(d1: DVec2, d2: DVec2) -> f64 {
+    d1.perp_dot(d2).abs().atan2(d1.dot(d2))
+}
+
+/// 寸法を描画するための線分とラベル位置
+pub struct DimensionLayout {
+    pub lines: Vec<(Vec3, Vec3)>,
+    pub label: Vec3,
+}
+
+/// スケッチ座標上の寸法線と矢印を組み立てる
+struct LayoutBuilder {
+    frame: SketchFrame,
+    lines: Vec<(Vec3, Vec3)>,
+}
+
+impl LayoutBuilder {
+    fn line(&mut self, a: Vec2, b: Vec2) {
+        self.lines.push((self.frame.to_world(a), self.frame.to_world(b)));
+    }
+
+    /// `tip` に先端がある `dir` 方向を向いた矢印
+    fn arrow(&mut self, tip: Vec2, dir: Vec2) {
+        let dir = dir.normalize_or_zero();
+        let side = dir.perp() * ARROW_SIZE * 0.4;
+        self.line(tip, tip - dir * ARROW_SIZE + side);
+        self.line(tip, tip - dir * ARROW_SIZE - side);
+    }
+
+    /// `a` から `b` までの両矢印の寸法線
+    fn dimension_line(&mut self, a: Vec2, b: Vec2) {
+        self.line(a, b);
+        self.arrow(a, a - b);
+        self.arrow(b, b - a);
+    }
+
+    fn finish(self, label: Vec2) -> DimensionLayout {
+        let label = self.frame.to_world(label);
+        DimensionLayout { lines: self.lines, label }
+    }
+}
+
+/// 点の参照からワールド座標を求める
+fn resolve_point(q_geometry: &GeometryQuery, p: &PointRef) -> Option<Vec3> {
+    let (line, circle, rect, arc) = q_geometry.get(p.entity).ok()?;
+    entity_points(p.entity, line, circle, rect, arc)
+        .into_iter()
+        .find(|(point, _)| point == p)
+        .map(|(_, position)| position)
+}
+
+fn resolve_line(q_geometry: &GeometryQuery, entity: Entity) -> Option<(Vec3, Vec3)> {
+    let (line, ..) = q_geometry.get(entity).ok()?;
+    line.map(|line| (line.p1, line.p2))
+}
+
+/// 円または円弧の中心と半径
+fn resolve_circle(q_geometry: &GeometryQuery, entity: Entity) -> Option<(Vec3, f32)> {
+    let (_, circle, _, arc) = q_geometry.get(entity).ok()?;
+    circle
+        .map(|circle| (circle.center, circle.radius))
+        .or_else(|| arc.map(|arc| (arc.center, arc.center.distance(arc.start))))
+}
+
+/// 半径・直径の寸法線の向き。円弧なら円弧の中央を向け、円なら `default` のまま
+fn leader_direction(q_geometry: &GeometryQuery, entity: Entity, frame: &SketchFrame, default: Vec2) -> Vec2 {
+    let Ok((.., Some(arc))) = q_geometry.get(entity) else {
+        return default;
+    };
+    let center = frame.to_local(arc.center);
+    let (start, end) = (frame.to_local(arc.start) - center, frame.to_local(arc.end) - center);
+    let sweep = (end.to_angle() - start.to_angle()).rem_euclid(TAU);
+    Vec2::from_angle(sweep / 2.0).rotate(start).try_normalize().unwrap_or(default)
+}
+
+/// 現在のジオメトリから寸法の値を測る
+pub fn measure(kind: &DimensionKind, q_geometry: &GeometryQuery, frame: &SketchFrame) -> Option<f32> {
+    let local = |p: &PointRef| resolve_point(q_geometry, p).map(|p| frame.to_local(p));
+    Some(match kind {
+        DimensionKind::Distance(a, b) => local(a)?.distance(local(b)?),
+        DimensionKind::HorizontalDistance(a, b) => (local(b)?.x - local(a)?.x).abs(),
+        DimensionKind::VerticalDistance(a, b) => (local(b)?.y - local(a)?.y).abs(),
+        DimensionKind::Radius(e) => resolve_circle(q_geometry, *e)?.1,
+        DimensionKind::Diameter(e) => resolve_circle(q_geometry, *e)?.1 * 2.0,
+        DimensionKind::Angle(e1, e2) => {
+            let (a1, b1) = resolve_line(q_geometry, *e1)?;
+            let (a2, b2) = resolve_line(q_geometry, *e2)?;
+            let d1 = frame.to_local(b1) - frame.to_local(a1);
+            let d2 = frame.to_local(b2) - frame.to_local(a2);
+            d1.perp_dot(d2).abs().atan2(d1.dot(d2)).to_degrees()
+        }
+    })
+}
+
+/// 寸法線・補助線・ラベル位置を計算する
+pub fn layout(kind: &DimensionKind, q_geometry: &GeometryQuery, frame: &SketchFrame) -> Option<DimensionLayout> {
+    let local = |p: &PointRef| resolve_point(q_geometry, p).map(|p| frame.to_local(p));
+    let mut builder = LayoutBuilder { frame: *frame, lines: Vec::new() };
+    let label = match kind {
+        DimensionKind::Distance(a, b) => {
+            let (a, b) = (local(a)?, local(b)?);
+            let normal = (b - a).normalize_or_zero().perp();
+            let (da, db) = (a + normal * DIMENSION_OFFSET, b + normal * DIMENSION_OFFSET);
+            builder.line(a, da + normal * 0.1);
+            builder.line(b, db + normal * 0.1);
+            builder.dimension_line(da, db);
+            (da + db) / 2.0 + normal * 0.2
+        }
+        DimensionKind::HorizontalDistance(a, b) => {
+            let (a, b) = (local(a)?, local(b)?);
+            let y = a.y.max(b.y) + DIMENSION_OFFSET;
+            let (da, db) = (Vec2::new(a.x, y), Vec2::new(b.x, y));
+            builder.line(a, da + Vec2::Y * 0.1);
+            builder.line(b, db + Vec2::Y * 0.1);
+            builder.dimension_line(da, db);
+            (da + db) / 2.0 + Vec2::Y * 0.2
+        }
+        DimensionKind::VerticalDistance(a, b) => {
+            let (a, b) = (local(a)?, local(b)?);
+            let x = a.x.max(b.x) + DIMENSION_OFFSET;
+            let (da, db) = (Vec2::new(x, a.y), Vec2::new(x, b.y));
+            builder.line(a, da + Vec2::X * 0.1);
+            builder.line(b, db + Vec2::X * 0.1);
+            builder.dimension_line(da, db);
+            (da + db) / 2.0 + Vec2::X * 0.4
+        }
+        DimensionKind::Radius(e) => {
+            let (center, radius) = resolve_circle(q_geometry, *e)?;
+            let center = frame.to_local(center);
+            let dir = leader_direction(q_geometry, *e, frame, Vec2::new(1.0, 1.0).normalize());
+            let rim = center + dir * radius;
+            builder.line(center, rim);
+            builder.arrow(rim, dir);
+            center + dir * (radius + 0.3)
+        }
+        DimensionKind::Diameter(e) => {
+            let (center, radius) = resolve_circle(q_geometry, *e)?;
+            let center = frame.to_local(center);
+            let dir = leader_direction(q_geometry, *e, frame, Vec2::new(2.0, 1.0).normalize());
+            builder.dimension_line(center - dir * radius, center + dir * radius);
+            center + dir * (radius + 0.3)
+        }
+        DimensionKind::Angle(e1, e2) => {
+            let (a1, b1) = resolve_line(q_geometry, *e1)?;
+            let (a2, b2) = resolve_line(q_geometry, *e2)?;
+            let (a1, b1, a2, b2) = (frame.to_local(a1), frame.to_local(b1), frame.to_local(a2), frame.to_local(b2));
+            let (d1, d2) = ((b1 - a1).normalize_or_zero(), (b2 - a2).normalize_or_zero());
+            // 2直線の交点を中心に円弧を描く。平行なら描かない
+            let denom = d1.perp_dot(d2);
+            if denom.abs() < 1e-6 {
+                return None;
+            }
+            let t = (a2 - a1).perp_dot(d2) / denom;
+            let vertex = a1 + d1 * t;
+            let radius = 0.8;
+            let sweep = d1.angle_between(d2);
+            let steps = 16;
+            let arc: Vec<Vec2> = (0..=steps)
+                .map(|i| vertex + Vec2::from_angle(sweep * i as f32 / steps as f32).rotate(d1) * radius)
+                .collect();
+            for pair in arc.windows(2) {
+                builder.line(pair[0], pair[1]);
+            }
+            builder.line(vertex, vertex + d1 * (radius + 0.1));
+            builder.line(vertex, vertex + d2 * (radius + 0.1));
+            vertex + Vec2::from_angle(sweep / 2.0).rotate(d1) * (radius + 0.3)
+        }
+    };
+    Some(builder.finish(label))
+}
+
+/// 寸法の状態に応じた色
+pub fn dimension_color(status: ConstraintStatus) -> Color {
+    match status {
+        ConstraintStatus::Satisfied => Color::rgb(0.4, 0.8, 1.0),
+        other => other.color(),
+    }
+}
+
+/// 選択中のエンティティから寸法を作成するシステム
+pub fn add_dimension_system(
+    mut commands: Commands,
+    mut events: EventReader<AddDimensionEvent>,
+    mut report: ResMut<SolverReport>,
+    mut history: ResMut<UndoHistory>,
+    active_sketch: Res<ActiveSketch>,
+    q_selected: Query<
+        (Entity, Option<&SketchLine>, Option<&SketchCircle>, Option<&SketchRectangle>, Option<&SketchArc>, &Parent),
+        With<Selected>,
+    >,
+    q_geometry: GeometryQuery,
+) {
+    let Some(sketch) = active_sketch.0 else {
+        events.clear();
+        return;
+    };
+    let frame = SketchFrame::default();
+    for AddDimensionEvent(dimension_type) in events.read() {
+        let selected: Vec<_> = q_selected.iter().filter(|(.., parent)| parent.get() == sketch).collect();
+        let lines: Vec<Entity> = selected.iter().filter(|s| s.1.is_some()).map(|s| s.0).collect();
+        // 円弧も円と同じく半径・直径の対象にする
+        let circles: Vec<Entity> = selected.iter().filter(|s| s.2.is_some() || s.4.is_some()).map(|s| s.0).collect();
+
+        // 距離系の寸法は、直線1本ならその両端、2つの図形なら最も近い特徴点の組を対象にする
+        let point_pair = || -> Option<(PointRef, PointRef)> {
+            match selected.as_slice() {
+                [(entity, Some(_), ..)] => Some((
+                    PointRef { entity: *entity, kind: PointKind::Start },
+                    PointRef { entity: *entity, kind: PointKind::End },
+                )),
+                [a, b] => closest_points(
+                    &entity_points(a.0, a.1, a.2, a.3, a.4),
+                    &entity_points(b.0, b.1, b.2, b.3, b.4),
+                ),
+                _ => None,
+            }
+        };
+
+        let kind = match dimension_type {
+            DimensionType::Distance => point_pair().map(|(a, b)| DimensionKind::Distance(a, b)),
+            DimensionType::HorizontalDistance => point_pair().map(|(a, b)| DimensionKind::HorizontalDistance(a, b)),
+            DimensionType::VerticalDistance => point_pair().map(|(a, b)| DimensionKind::VerticalDistance(a, b)),
+            DimensionType::Radius => circles.first().map(|&e| DimensionKind::Radius(e)),
+            DimensionType::Diameter => circles.first().map(|&e| DimensionKind::Diameter(e)),
+            DimensionType::Angle => match lines.as_slice() {
+                [a, b] => Some(DimensionKind::Angle(*a, *b)),
+                _ => None,
+            },
+        };
+
+        // 作成時の値は現在の寸法にして、図形が動かないようにする
+        let Some((kind, value)) = kind.and_then(|kind| measure(&kind, &q_geometry, &frame).map(|value| (kind, value)))
+        else {
+            report.message = format!("{}寸法を作成できる図形が選択されていません", dimension_type.label());
+            continue;
+        };
+        report.message.clear();
+        commands.spawn(SketchDimension { kind, value }).set_parent(sketch);
+        history.record(format!("{}寸法を追加", dimension_type.label()));
+    }
+}
+
+/// 寸法値のラベルをビューポート上に表示し、その場で編集できるようにするシステム
+pub fn dimension_label_system(
+    mut contexts: EguiContexts,
+    active_sketch: Res<ActiveSketch>,
+    report: Res<SolverReport>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    mut q_dimensions: Query<(Entity, &mut SketchDimension, &Parent)>,
+    q_geometry: GeometryQuery,
+    mut history: ResMut<UndoHistory>,
+) {
+    let Some(sketch) = active_sketch.0 else {
+        return;
+    };
+    let (camera, camera_transform) = q_camera.single();
+    let frame = SketchFrame::default();
+    let ctx = contexts.ctx_mut();
+
+    for (entity, mut dimension, parent) in q_dimensions.iter_mut() {
+        if parent.get() != sketch {
+            continue;
+        }
+        let Some(layout) = layout(&dimension.kind, &q_geometry, &frame) else {
+            continue;
+        };
+        let Some(screen) = camera.world_to_viewport(camera_transform, layout.label) else {
+            continue;
+        };
+        let status = report.statuses.get(&entity).copied().unwrap_or_default();
+        let color = dimension_color(status).as_rgba_u8();
+        let dimension_type = dimension.kind.dimension_type();
+
+        egui::Area::new(egui::Id::new(("dimension_label", entity)))
+            .fixed_pos(egui::pos2(screen.x, screen.y))
+            .pivot(egui::Align2::CENTER_CENTER)
+            .show(ctx, |ui| {
+                ui.visuals_mut().override_text_color =
+                    Some(egui::Color32::from_rgb(color[0], color[1], color[2]));
+                let mut value = dimension.value;
+                let speed = if dimension_type == DimensionType::Angle { 0.5 } else { 0.01 };
+                let response = ui.add(
+                    egui::DragValue::new(&mut value)
+                        .speed(speed)
+                        .clamp_range(0.0..=f32::MAX)
+                        .prefix(dimension_type.prefix())
+                        .suffix(dimension_type.suffix()),
+                );
+                if response.changed() {
+                    dimension.value = value;
+                }
+                history.track_drag(&response, "寸法を変更");
+            });
+    }
+}
+
+/// サイドパネルに表示する寸法の操作と一覧
+#[derive(SystemParam)]
+pub struct DimensionPanel<'w, 's> {
+    commands: Commands<'w, 's>,
+    events: EventWriter<'w, AddDimensionEvent>,
+    report: Res<'w, SolverReport>,
+    q_dimensions: Query<'w, 's, (Entity, &'static mut SketchDimension, &'static Parent)>,
+}
+
+impl DimensionPanel<'_, '_> {
+    pub fn show(&mut self, ui: &mut egui::Ui, sketch: Entity, history: &mut UndoHistory) {
+        ui.label("寸法");
+        ui.horizontal_wrapped(|ui| {
+            for dimension_type in DimensionType::ALL {
+                if ui.button(dimension_type.label()).clicked() {
+                    self.events.send(AddDimensionEvent(dimension_type));
+                }
+            }
+        });
+
+        egui::ScrollArea::vertical().id_source("dimension_list").max_height(150.0).show(ui, |ui| {
+            for (entity, mut dimension, parent) in self.q_dimensions.iter_mut() {
+                if parent.get() != sketch {
+                    continue;
+                }
+                let status = self.report.statuses.get(&entity).copied().unwrap_or_default();
+                let dimension_type = dimension.kind.dimension_type();
+                ui.horizontal(|ui| {
+                    let text = match status {
+                        ConstraintStatus::Satisfied => egui::RichText::new(dimension_type.label()),
+                        ConstraintStatus::Redundant => {
+                            egui::RichText::new(format!("{} (冗長)", dimension_type.label())).color(egui::Color32::YELLOW)
+                        }
+                        ConstraintStatus::Conflicting => {
+                            egui::RichText::new(format!("{} (矛盾)", dimension_type.label())).color(egui::Color32::RED)
+                        }
+                    };
+                    ui.label(text);
+                    let mut value = dimension.value;
+                    let speed = if dimension_type == DimensionType::Angle { 0.5 } else { 0.01 };
+                    let response = ui.add(
+                        egui::DragValue::new(&mut value)
+                            .speed(speed)
+                            .clamp_range(0.0..=f32::MAX)
+                            .suffix(dimension_type.suffix()),
+                    );
+                    if response.changed() {
+                        dimension.value = value;
+                    }
+                    history.track_drag(&response, "寸法を変更");
+                    if ui.small_button("×").clicked() {
+                        self.commands.entity(entity).despawn_recursive();
+                        history.record(format!("{}寸法を削除", dimension_type.label()));
+                    }
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::constraints::{self, ConstraintKind};
+    use crate::solver::EquationStatus;
+
+    /// 直線を未知数に加える
+    fn add_line(system: &mut SolverSystem, vars: &mut HashMap<Entity, EntityVars>, index: u32, a: DVec2, b: DVec2) -> Entity {
+        let entity = Entity::from_raw(index);
+        let (p1, p2) = (system.add_point(a), system.add_point(b));
+        vars.insert(entity, EntityVars::Line { p1, p2 });
+        entity
+    }
+
+    fn start(entity: Entity) -> PointRef {
+        PointRef { entity, kind: PointKind::Start }
+    }
+
+    fn end(entity: Entity) -> PointRef {
+        PointRef { entity, kind: PointKind::End }
+    }
+
+    #[test]
+    fn changed_radius_and_distance_dimensions_resize_geometry() {
+        // 長さ1の直線と半径0.5の円から、寸法を別の値にして解き直す
+        for (length, diameter) in [(2.5, 3.0), (0.5, 0.4)] {
+            let mut system = SolverSystem::default();
+            let mut vars = HashMap::new();
+            let line = add_line(&mut system, &mut vars, 1, DVec2::ZERO, DVec2::new(1.0, 0.0));
+            let circle = Entity::from_raw(2);
+            let center = system.add_point(DVec2::new(3.0, 0.0));
+            let radius = system.add_param(0.5);
+            vars.insert(circle, EntityVars::Circle { center, radius });
+            constraints::add_equations(&mut system, &vars, &ConstraintKind::Fix(start(line), [0.0, 0.0]));
+            constraints::add_equations(&mut system, &vars, &ConstraintKind::Horizontal(line));
+            for dimension in [
+                SketchDimension { kind: DimensionKind::Distance(start(line), end(line)), value: length },
+                SketchDimension { kind: DimensionKind::Diameter(circle), value: diameter },
+            ] {
+                add_equations(&mut system, &vars, &dimension);
+            }
+            let result = system.solve();
+            assert!(result.converged);
+            // 円の中心の分だけ自由度が残る
+            assert_eq!(result.dof, 2);
+            let (a, b) = vars[&line].line().unwrap();
+            let params = &system.params;
+            assert!(a.eval(params).length() < 1e-6);
+            assert!(b.eval(params).distance(DVec2::new(length as f64, 0.0)) < 1e-6);
+            assert!((params[radius] - diameter as f64 / 2.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn flags_dimension_conflicting_with_constraints() {
+        let solve = |length: f32| {
+            let mut system = SolverSystem::default();
+            let mut vars = HashMap::new();
+            let line = add_line(&mut system, &mut vars, 1, DVec2::ZERO, DVec2::new(1.0, 0.2));
+            constraints::add_equations(&mut system, &vars, &ConstraintKind::Fix(start(line), [0.0, 0.0]));
+            constraints::add_equations(&mut system, &vars, &ConstraintKind::Fix(end(line), [1.0, 0.0]));
+            let dimension = SketchDimension { kind: DimensionKind::Distance(start(line), end(line)), value: length };
+            let equations = add_equations(&mut system, &vars, &dimension);
+            let result = system.solve();
+            (result.converged, result.statuses[equations[0]])
+        };
+        // 両端を固定した直線の長さと同じ寸法は冗長、違う寸法は矛盾になる
+        assert_eq!(solve(1.0), (true, EquationStatus::Redundant));
+        assert_eq!(solve(2.0), (false, EquationStatus::Conflicting));
+    }
+
+    #[test]
+    fn measures_current_geometry() {
+        let mut world = World::new();
+        let a = world.spawn(SketchLine { p1: Vec3::ZERO, p2: Vec3::new(3.0, 0.0, -4.0) }).id();
+        let b = world.spawn(SketchLine { p1: Vec3::ZERO, p2: Vec3::new(0.0, 0.0, -2.0) }).id();
+        let arc = SketchArc { center: Vec3::X, start: Vec3::new(2.5, 0.0, 0.0), end: Vec3::new(1.0, 0.0, -1.5) };
+        let arc = world.spawn(arc).id();
+        world.run_system_once(move |q_geometry: GeometryQuery| {
+            let frame = SketchFrame::default();
+            let measure = |kind| measure(&kind, &q_geometry, &frame).unwrap();
+            assert!((measure(DimensionKind::Distance(start(a), end(a))) - 5.0).abs() < 1e-5);
+            assert!((measure(DimensionKind::HorizontalDistance(start(a), end(a))) - 3.0).abs() < 1e-5);
+            assert!((measure(DimensionKind::VerticalDistance(start(a), end(a))) - 4.0).abs() < 1e-5);
+            assert!((measure(DimensionKind::Radius(arc)) - 1.5).abs() < 1e-5);
+            assert!((measure(DimensionKind::Diameter(arc)) - 3.0).abs() < 1e-5);
+            assert!((measure(DimensionKind::Angle(a, b)) - 3.0f32.atan2(4.0).to_degrees()).abs() < 1e-3);
+        });
+    }
+}
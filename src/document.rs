@@ -0,0 +1,1225 @@
+//! QuillCADネイティブのプロジェクトファイル (.qcad) の読み書き
+//!
+//! ファイルはRON形式で、先頭の `version` フィールドでスキーマを判別する。
+//! 古いバージョンのファイルは `migrate` で現在のスキーマへ変換してから読み込む。
+
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use bevy::render::{
+    mesh::{Indices, VertexAttributeValues},
+    render_asset::RenderAssetUsages,
+    render_resource::PrimitiveTopology,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::brep::Brep;
+use crate::constraints::{ConstraintKind, SketchConstraint};
+use crate::csg::BooleanOp;
+use crate::dimensions::{DimensionKind, SketchDimension};
+use crate::features::{
+    BodyRef, ExtrudeOperation, Feature, FeatureId, FeatureKind, FeatureTree, LoftSection, ProfileRef, SweepOrientation,
+};
+use crate::history::UndoHistory;
+use crate::pattern::{CopyTransform, SketchCopy};
+use crate::{
+    ActiveSketch, AppState, Construction, LoftSections, Sketch, SketchArc, SketchCircle, SketchEllipse, SketchLine, SketchPolygon, SketchRectangle,
+    SketchSlot, SketchSpline, SweepPath,
+};
+
+/// プロジェクトファイルの拡張子
+pub const FILE_EXTENSION: &str = "qcad";
+
+/// 現在のファイルスキーマのバージョン
+pub const CURRENT_VERSION: u32 = 3;
+
+/// プロジェクトファイルのルート
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProjectFile {
+    pub version: u32,
+    #[serde(default)]
+    pub sketches: Vec<SketchDocData>,
+    /// フィーチャーの履歴。ボディは読み込み時にここから再生成する
+    #[serde(default)]
+    pub features: Vec<FeatureData>,
+    /// ロールバックバーの位置。省略時は履歴の末尾
+    #[serde(default)]
+    pub rollback: Option<usize>,
+}
+
+/// 名前付きスケッチ1つ分のデータ
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SketchDocData {
+    pub name: String,
+    #[serde(default)]
+    pub plane: PlaneData,
+    #[serde(default)]
+    pub entities: Vec<SketchEntityData>,
+    /// 拘束。参照先は `entities` のインデックス
+    #[serde(default)]
+    pub constraints: Vec<ConstraintKind<usize>>,
+    /// 駆動寸法。参照先は `entities` のインデックス
+    #[serde(default)]
+    pub dimensions: Vec<DimensionData>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DimensionData {
+    pub kind: DimensionKind<usize>,
+    pub value: f32,
+}
+
+/// スケッチ平面 (原点と法線)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PlaneData {
+    pub origin: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+impl Default for PlaneData {
+    /// 現在のスケッチはすべてXZ平面上に描かれる
+    fn default() -> Self {
+        Self { origin: [0.0, 0.0, 0.0], normal: [0.0, 1.0, 0.0] }
+    }
+}
+
+impl PlaneData {
+    /// 原点を法線方向に測った位置。平行な平面の間の距離はこの差になる
+    pub fn offset(&self) -> f32 {
+        Vec3::from(self.origin).dot(Vec3::from(self.normal).normalize_or_zero())
+    }
+
+    /// 原点を法線方向に動かして、法線方向の位置を `offset` にする
+    pub fn set_offset(&mut self, offset: f32) {
+        let normal = Vec3::from(self.normal).normalize_or_zero();
+        self.origin = (Vec3::from(self.origin) + normal * (offset - self.offset())).to_array();
+    }
+}
+
+/// スケッチエンティティ1つ分のデータ
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SketchEntityData {
+    pub geometry: SketchGeometryData,
+    /// 押し出し済みで非表示になっているか
+    #[serde(default)]
+    pub hidden: bool,
+    /// 補助線か
+    #[serde(default)]
+    pub construction: bool,
+    /// ミラーやパターンで作ったコピーなら元の図形と変換
+    #[serde(default)]
+    pub copy: Option<CopyData>,
+}
+
+/// コピーの元の図形と変換。参照先は同じスケッチの `entities` のインデックス
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CopyData {
+    pub source: usize,
+    pub transform: CopyTransform<usize>,
+}
+
+/// スケッチの形状データ
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SketchGeometryData {
+    Line { p1: [f32; 3], p2: [f32; 3] },
+    Circle { center: [f32; 3], radius: f32 },
+    /// `angle` は辺をY軸まわりに回した角度 (ラジアン)
+    Rectangle {
+        p1: [f32; 3],
+        p2: [f32; 3],
+        #[serde(default)]
+        angle: f32,
+    },
+    /// 中心まわりに始点から終点まで反時計回りに回る円弧
+    Arc { center: [f32; 3], start: [f32; 3], end: [f32; 3] },
+    /// `fit` なら `points` を通過点、そうでなければ制御点とするスプライン。`knots` は制御点の場合のノットで、空なら等間隔
+    Spline {
+        points: Vec<[f32; 3]>,
+        fit: bool,
+        #[serde(default)]
+        knots: Vec<f32>,
+    },
+    /// `inscribed` なら `point` を頂点、そうでなければ辺の中点とする正多角形
+    Polygon { center: [f32; 3], point: [f32; 3], sides: u32, inscribed: bool },
+    Slot { a: [f32; 3], b: [f32; 3], radius: f32 },
+    /// `major` は長軸の端点
+    Ellipse { center: [f32; 3], major: [f32; 3], minor_radius: f32 },
+}
+
+/// フィーチャー1つ分のデータ
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FeatureData {
+    pub id: FeatureId,
+    pub name: String,
+    pub kind: FeatureKindData,
+    #[serde(default)]
+    pub suppressed: bool,
+}
+
+/// フィーチャーの種類とパラメータ。スケッチは `sketches` のインデックスで参照する
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum FeatureKindData {
+    Sketch { sketch: usize },
+    Extrude { sketch: usize, profiles: Vec<ProfileRefData>, distance: f32, operation: ExtrudeOperation },
+    /// 回転。`axis` はスケッチ内の直線のインデックス、角度は度
+    Revolve { sketch: usize, profiles: Vec<ProfileRefData>, axis: usize, angle: f32, operation: ExtrudeOperation },
+    /// スイープ。`path` は `path_sketch` (省略時は `sketch`) 内の直線・円弧・スプラインのインデックス、ねじれは度
+    Sweep {
+        sketch: usize,
+        profiles: Vec<ProfileRefData>,
+        path: Vec<usize>,
+        #[serde(default)]
+        path_sketch: Option<usize>,
+        orientation: SweepOrientation,
+        twist: f32,
+        operation: ExtrudeOperation,
+    },
+    Loft { sections: Vec<LoftSectionData>, auto_align: bool, operation: ExtrudeOperation },
+    /// ボディは作ったフィーチャーと、そのフィーチャーが作ったボディの中の番号で参照する
+    Combine {
+        target: FeatureId,
+        #[serde(default)]
+        target_index: usize,
+        tool: FeatureId,
+        #[serde(default)]
+        tool_index: usize,
+        op: BooleanOp,
+    },
+    /// 履歴を持たないボディ。ワールド座標の三角形メッシュで保存する
+    BaseBody { mesh: MeshData },
+}
+
+/// ロフトの断面
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoftSectionData {
+    pub sketch: usize,
+    pub profile: ProfileRefData,
+    pub start: f32,
+}
+
+/// 押し出すプロファイルの参照
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ProfileRefData {
+    /// 領域内の点 (スケッチ平面上の座標)
+    Region([f32; 2]),
+    /// スケッチ内のエンティティのインデックス
+    Entity(usize),
+}
+
+/// 三角形メッシュの頂点属性とインデックス
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MeshData {
+    pub positions: Vec<[f32; 3]>,
+    #[serde(default)]
+    pub normals: Vec<[f32; 3]>,
+    #[serde(default)]
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+/// バージョン判別のために先頭だけを読むためのヘッダ
+#[derive(Deserialize)]
+struct VersionHeader {
+    version: u32,
+}
+
+/// ファイル入出力のエラー
+#[derive(Debug)]
+pub enum DocumentError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocumentError::Io(e) => write!(f, "ファイルの入出力に失敗しました: {e}"),
+            DocumentError::Parse(e) => write!(f, "ファイルの解析に失敗しました: {e}"),
+            DocumentError::Serialize(e) => write!(f, "ファイルの書き出しに失敗しました: {e}"),
+            DocumentError::UnsupportedVersion(v) => write!(
+                f,
+                "このファイルは新しいQuillCADで保存されています (バージョン {v}, 対応は {CURRENT_VERSION} まで)"
+            ),
+        }
+    }
+}
+
+impl From<std::io::Error> for DocumentError {
+    fn from(e: std::io::Error) -> Self {
+        DocumentError::Io(e)
+    }
+}
+
+impl From<ron::error::SpannedError> for DocumentError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        DocumentError::Parse(e)
+    }
+}
+
+impl From<ron::Error> for DocumentError {
+    fn from(e: ron::Error) -> Self {
+        DocumentError::Serialize(e)
+    }
+}
+
+/// UIから要求されるファイル操作
+#[derive(Debug, Clone)]
+pub enum FileAction {
+    Save,
+    SaveAs(PathBuf),
+    Open(PathBuf),
+}
+
+/// 開いているドキュメントの状態
+#[derive(Resource)]
+pub struct DocumentState {
+    /// 現在のドキュメントの保存先
+    pub path: Option<PathBuf>,
+    /// UIのパス入力欄
+    pub path_input: String,
+    /// 最後のファイル操作の結果
+    pub status: String,
+    /// 次のフレームで処理するファイル操作
+    pub pending: Option<FileAction>,
+}
+
+impl Default for DocumentState {
+    fn default() -> Self {
+        Self {
+            path: None,
+            path_input: format!("untitled.{FILE_EXTENSION}"),
+            status: String::new(),
+            pending: None,
+        }
+    }
+}
+
+/// テキストをプロジェクトファイルとして解析する
+pub fn parse_project(text: &str) -> Result<ProjectFile, DocumentError> {
+    let header: VersionHeader = ron::from_str(text)?;
+    if header.version > CURRENT_VERSION {
+        return Err(DocumentError::UnsupportedVersion(header.version));
+    }
+    migrate(header.version, text)
+}
+
+/// 古いバージョンのファイルを現在のスキーマへ変換する
+///
+/// スキーマを変更する時は `CURRENT_VERSION` を上げ、旧バージョンの構造体のコピーを
+/// `v1` や `v2` のようなモジュールに固定したうえでここに変換を追加する。
+fn migrate(version: u32, text: &str) -> Result<ProjectFile, DocumentError> {
+    match version {
+        1 => Ok(v2::ProjectFile::from(ron::from_str::<v1::ProjectFile>(text)?).into()),
+        2 => Ok(ron::from_str::<v2::ProjectFile>(text)?.into()),
+        CURRENT_VERSION => Ok(ron::from_str(text)?),
+        other => Err(DocumentError::UnsupportedVersion(other)),
+    }
+}
+
+/// バージョン1: スケッチの区別がなく、すべてのエンティティが平坦に並んでいた
+///
+/// 現在のスキーマを変えても読み方が変わらないよう、当時の構造体をここに固定している。
+mod v1 {
+    use serde::Deserialize;
+
+    use super::v2::{self, BodyData};
+
+    #[derive(Deserialize)]
+    pub struct ProjectFile {
+        #[serde(default)]
+        pub sketch_entities: Vec<SketchEntityData>,
+        #[serde(default)]
+        pub bodies: Vec<BodyData>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SketchEntityData {
+        #[serde(default)]
+        pub plane: PlaneData,
+        pub geometry: SketchGeometryData,
+        #[serde(default)]
+        pub hidden: bool,
+    }
+
+    #[derive(Deserialize, Clone, Copy, PartialEq)]
+    pub struct PlaneData {
+        pub origin: [f32; 3],
+        pub normal: [f32; 3],
+    }
+
+    impl Default for PlaneData {
+        fn default() -> Self {
+            Self { origin: [0.0, 0.0, 0.0], normal: [0.0, 1.0, 0.0] }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub enum SketchGeometryData {
+        Line { p1: [f32; 3], p2: [f32; 3] },
+        Circle { center: [f32; 3], radius: f32 },
+        Rectangle { p1: [f32; 3], p2: [f32; 3] },
+    }
+
+    impl From<SketchGeometryData> for v2::SketchGeometryData {
+        fn from(old: SketchGeometryData) -> Self {
+            match old {
+                SketchGeometryData::Line { p1, p2 } => v2::SketchGeometryData::Line { p1, p2 },
+                SketchGeometryData::Circle { center, radius } => v2::SketchGeometryData::Circle { center, radius },
+                SketchGeometryData::Rectangle { p1, p2 } => v2::SketchGeometryData::Rectangle { p1, p2 },
+            }
+        }
+    }
+
+    impl From<ProjectFile> for v2::ProjectFile {
+        /// 平面ごとに1つのスケッチへまとめる
+        fn from(old: ProjectFile) -> Self {
+            let mut sketches: Vec<v2::SketchDocData> = Vec::new();
+            for entity in old.sketch_entities {
+                let plane = v2::PlaneData { origin: entity.plane.origin, normal: entity.plane.normal };
+                let data = v2::SketchEntityData { geometry: entity.geometry.into(), hidden: entity.hidden };
+                match sketches.iter_mut().find(|sketch| sketch.plane == plane) {
+                    Some(sketch) => sketch.entities.push(data),
+                    None => sketches.push(v2::SketchDocData {
+                        name: format!("スケッチ{}", sketches.len() + 1),
+                        plane,
+                        entities: vec![data],
+                        constraints: Vec::new(),
+                        dimensions: Vec::new(),
+                    }),
+                }
+            }
+            Self { sketches, bodies: old.bodies }
+        }
+    }
+}
+
+/// バージョン2: 履歴がなく、ボディのメッシュと変換をそのまま保存していた
+///
+/// 現在のスキーマを変えても読み方が変わらないよう、当時の構造体をここに固定している。
+mod v2 {
+    use bevy::prelude::*;
+    use serde::Deserialize;
+
+    use crate::constraints;
+    use crate::dimensions;
+    use crate::features::FeatureId;
+
+    #[derive(Deserialize)]
+    pub struct ProjectFile {
+        #[serde(default)]
+        pub sketches: Vec<SketchDocData>,
+        #[serde(default)]
+        pub bodies: Vec<BodyData>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SketchDocData {
+        pub name: String,
+        #[serde(default)]
+        pub plane: PlaneData,
+        #[serde(default)]
+        pub entities: Vec<SketchEntityData>,
+        #[serde(default)]
+        pub constraints: Vec<ConstraintKind>,
+        #[serde(default)]
+        pub dimensions: Vec<DimensionData>,
+    }
+
+    #[derive(Deserialize, Clone, Copy, PartialEq)]
+    pub struct PlaneData {
+        pub origin: [f32; 3],
+        pub normal: [f32; 3],
+    }
+
+    impl Default for PlaneData {
+        fn default() -> Self {
+            Self { origin: [0.0, 0.0, 0.0], normal: [0.0, 1.0, 0.0] }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct SketchEntityData {
+        pub geometry: SketchGeometryData,
+        #[serde(default)]
+        pub hidden: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub enum SketchGeometryData {
+        Line { p1: [f32; 3], p2: [f32; 3] },
+        Circle { center: [f32; 3], radius: f32 },
+        Rectangle { p1: [f32; 3], p2: [f32; 3] },
+    }
+
+    #[derive(Deserialize, Clone, Copy)]
+    pub enum PointKind {
+        Start,
+        End,
+        Center,
+        Corner(u8),
+    }
+
+    #[derive(Deserialize, Clone, Copy)]
+    pub struct PointRef {
+        pub entity: usize,
+        pub kind: PointKind,
+    }
+
+    #[derive(Deserialize)]
+    pub enum ConstraintKind {
+        Coincident(PointRef, PointRef),
+        Horizontal(usize),
+        Vertical(usize),
+        Parallel(usize, usize),
+        Perpendicular(usize, usize),
+        Tangent(usize, usize),
+        Equal(usize, usize),
+        Midpoint(PointRef, usize),
+        Concentric(usize, usize),
+        Fix(PointRef, [f32; 2]),
+    }
+
+    #[derive(Deserialize)]
+    pub struct DimensionData {
+        pub kind: DimensionKind,
+        pub value: f32,
+    }
+
+    #[derive(Deserialize)]
+    pub enum DimensionKind {
+        Distance(PointRef, PointRef),
+        HorizontalDistance(PointRef, PointRef),
+        VerticalDistance(PointRef, PointRef),
+        Radius(usize),
+        Diameter(usize),
+        Angle(usize, usize),
+    }
+
+    #[derive(Deserialize)]
+    pub struct BodyData {
+        #[allow(dead_code)]
+        pub extrude_distance: f32,
+        pub transform: TransformData,
+        pub mesh: MeshData,
+    }
+
+    #[derive(Deserialize)]
+    pub struct TransformData {
+        pub translation: [f32; 3],
+        pub rotation: [f32; 4],
+        pub scale: [f32; 3],
+    }
+
+    #[derive(Deserialize)]
+    pub struct MeshData {
+        pub positions: Vec<[f32; 3]>,
+        #[serde(default)]
+        pub normals: Vec<[f32; 3]>,
+        #[serde(default)]
+        pub uvs: Vec<[f32; 2]>,
+        pub indices: Vec<u32>,
+    }
+
+    impl From<PointRef> for constraints::PointRef<usize> {
+        fn from(old: PointRef) -> Self {
+            let kind = match old.kind {
+                PointKind::Start => constraints::PointKind::Start,
+                PointKind::End => constraints::PointKind::End,
+                PointKind::Center => constraints::PointKind::Center,
+                PointKind::Corner(i) => constraints::PointKind::Corner(i),
+            };
+            Self { entity: old.entity, kind }
+        }
+    }
+
+    impl From<ConstraintKind> for constraints::ConstraintKind<usize> {
+        fn from(old: ConstraintKind) -> Self {
+            use constraints::ConstraintKind as New;
+            match old {
+                ConstraintKind::Coincident(a, b) => New::Coincident(a.into(), b.into()),
+                ConstraintKind::Horizontal(e) => New::Horizontal(e),
+                ConstraintKind::Vertical(e) => New::Vertical(e),
+                ConstraintKind::Parallel(a, b) => New::Parallel(a, b),
+                ConstraintKind::Perpendicular(a, b) => New::Perpendicular(a, b),
+                ConstraintKind::Tangent(a, b) => New::Tangent(a, b),
+                ConstraintKind::Equal(a, b) => New::Equal(a, b),
+                ConstraintKind::Midpoint(p, line) => New::Midpoint(p.into(), line),
+                ConstraintKind::Concentric(a, b) => New::Concentric(a, b),
+                ConstraintKind::Fix(p, target) => New::Fix(p.into(), target),
+            }
+        }
+    }
+
+    impl From<DimensionKind> for dimensions::DimensionKind<usize> {
+        fn from(old: DimensionKind) -> Self {
+            use dimensions::DimensionKind as New;
+            match old {
+                DimensionKind::Distance(a, b) => New::Distance(a.into(), b.into()),
+                DimensionKind::HorizontalDistance(a, b) => New::HorizontalDistance(a.into(), b.into()),
+                DimensionKind::VerticalDistance(a, b) => New::VerticalDistance(a.into(), b.into()),
+                DimensionKind::Radius(e) => New::Radius(e),
+                DimensionKind::Diameter(e) => New::Diameter(e),
+                DimensionKind::Angle(a, b) => New::Angle(a, b),
+            }
+        }
+    }
+
+    impl From<SketchGeometryData> for super::SketchGeometryData {
+        fn from(old: SketchGeometryData) -> Self {
+            match old {
+                SketchGeometryData::Line { p1, p2 } => super::SketchGeometryData::Line { p1, p2 },
+                SketchGeometryData::Circle { center, radius } => super::SketchGeometryData::Circle { center, radius },
+                SketchGeometryData::Rectangle { p1, p2 } => super::SketchGeometryData::Rectangle { p1, p2, angle: 0.0 },
+            }
+        }
+    }
+
+    impl From<SketchDocData> for super::SketchDocData {
+        fn from(old: SketchDocData) -> Self {
+            Self {
+                name: old.name,
+                plane: super::PlaneData { origin: old.plane.origin, normal: old.plane.normal },
+                entities: old
+                    .entities
+                    .into_iter()
+                    .map(|entity| super::SketchEntityData {
+                        geometry: entity.geometry.into(),
+                        hidden: entity.hidden,
+                        construction: false,
+                        copy: None,
+                    })
+                    .collect(),
+                constraints: old.constraints.into_iter().map(Into::into).collect(),
+                dimensions: old
+                    .dimensions
+                    .into_iter()
+                    .map(|dimension| super::DimensionData { kind: dimension.kind.into(), value: dimension.value })
+                    .collect(),
+            }
+        }
+    }
+
+    impl From<ProjectFile> for super::ProjectFile {
+        /// スケッチごとにスケッチフィーチャーを作り、ボディは変換を焼き込んだ履歴なしのボディにする
+        fn from(old: ProjectFile) -> Self {
+            let sketches: Vec<super::SketchDocData> = old.sketches.into_iter().map(Into::into).collect();
+            let mut features: Vec<super::FeatureData> = sketches
+                .iter()
+                .enumerate()
+                .map(|(i, sketch)| super::FeatureData {
+                    id: FeatureId(i as u32),
+                    name: sketch.name.clone(),
+                    kind: super::FeatureKindData::Sketch { sketch: i },
+                    suppressed: false,
+                })
+                .collect();
+            for (i, body) in old.bodies.into_iter().enumerate() {
+                let transform = Transform {
+                    translation: Vec3::from_array(body.transform.translation),
+                    rotation: Quat::from_array(body.transform.rotation),
+                    scale: Vec3::from_array(body.transform.scale),
+                };
+                let MeshData { mut positions, mut normals, uvs, indices } = body.mesh;
+                for p in &mut positions {
+                    *p = transform.transform_point(Vec3::from_array(*p)).to_array();
+                }
+                for n in &mut normals {
+                    *n = (transform.rotation * Vec3::from_array(*n)).to_array();
+                }
+                features.push(super::FeatureData {
+                    id: FeatureId(features.len() as u32),
+                    name: format!("ボディ{}", i + 1),
+                    kind: super::FeatureKindData::BaseBody { mesh: super::MeshData { positions, normals, uvs, indices } },
+                    suppressed: false,
+                });
+            }
+            Self { version: super::CURRENT_VERSION, sketches, features, rollback: None }
+        }
+    }
+}
+
+/// プロジェクトファイルをテキストに変換する
+pub fn serialize_project(project: &ProjectFile) -> Result<String, DocumentError> {
+    Ok(ron::ser::to_string_pretty(project, ron::ser::PrettyConfig::default())?)
+}
+
+pub fn save_to_path(project: &ProjectFile, path: &Path) -> Result<(), DocumentError> {
+    fs::write(path, serialize_project(project)?)?;
+    Ok(())
+}
+
+pub fn load_from_path(path: &Path) -> Result<ProjectFile, DocumentError> {
+    parse_project(&fs::read_to_string(path)?)
+}
+
+/// 拡張子がなければ `.qcad` を付ける
+pub fn with_default_extension(path: PathBuf) -> PathBuf {
+    if path.extension().is_some() {
+        path
+    } else {
+        path.with_extension(FILE_EXTENSION)
+    }
+}
+
+/// プロジェクトファイルのスケッチと図形に対応するワールド上のエンティティ
+#[derive(Debug, Default)]
+pub struct DocumentEntities {
+    /// `sketches` の順のスケッチ
+    pub sketches: Vec<Entity>,
+    /// スケッチごとの、`entities` の順の図形
+    pub entities: Vec<Vec<Entity>>,
+}
+
+/// ワールド上のスケッチとボディをプロジェクトファイルに書き出す
+pub fn capture_document(world: &mut World) -> ProjectFile {
+    capture_document_entities(world).0
+}
+
+/// `capture_document` と同じく書き出し、書き出したスケッチと図形のエンティティも返す
+pub fn capture_document_entities(world: &mut World) -> (ProjectFile, DocumentEntities) {
+    let mut sketches = Vec::new();
+    let mut sketch_index = HashMap::new();
+    let mut document_entities = DocumentEntities::default();
+    let mut q_sketches = world.query::<(Entity, &Sketch)>();
+    for (entity, sketch) in q_sketches.iter(world) {
+        sketch_index.insert(entity, sketches.len());
+        document_entities.sketches.push(entity);
+        document_entities.entities.push(Vec::new());
+        sketches.push(SketchDocData {
+            name: sketch.name.clone(),
+            plane: sketch.plane,
+            entities: Vec::new(),
+            constraints: Vec::new(),
+            dimensions: Vec::new(),
+        });
+    }
+    // エンティティごとの (スケッチの位置, スケッチ内の位置)
+    let mut entity_index = HashMap::new();
+    // 親スケッチの位置を調べ、そのスケッチのエンティティ一覧に追加する
+    let mut push_entity = |entity: Entity, parent: Option<&Parent>, geometry: SketchGeometryData, visibility: Option<&Visibility>| {
+        if let Some(&index) = parent.and_then(|parent| sketch_index.get(&parent.get())) {
+            entity_index.insert(entity, (index, sketches[index].entities.len()));
+            document_entities.entities[index].push(entity);
+            let data = SketchEntityData { geometry, hidden: is_hidden(visibility), construction: false, copy: None };
+            sketches[index].entities.push(data);
+        }
+    };
+
+    let mut q_lines = world.query::<(Entity, &SketchLine, Option<&Parent>, Option<&Visibility>)>();
+    for (entity, line, parent, visibility) in q_lines.iter(world) {
+        let geometry = SketchGeometryData::Line { p1: line.p1.to_array(), p2: line.p2.to_array() };
+        push_entity(entity, parent, geometry, visibility);
+    }
+    let mut q_circles = world.query::<(Entity, &SketchCircle, Option<&Parent>, Option<&Visibility>)>();
+    for (entity, circle, parent, visibility) in q_circles.iter(world) {
+        let geometry = SketchGeometryData::Circle { center: circle.center.to_array(), radius: circle.radius };
+        push_entity(entity, parent, geometry, visibility);
+    }
+    let mut q_rectangles = world.query::<(Entity, &SketchRectangle, Option<&Parent>, Option<&Visibility>)>();
+    for (entity, rect, parent, visibility) in q_rectangles.iter(world) {
+        let geometry =
+            SketchGeometryData::Rectangle { p1: rect.p1.to_array(), p2: rect.p2.to_array(), angle: rect.angle };
+        push_entity(entity, parent, geometry, visibility);
+    }
+    let mut q_arcs = world.query::<(Entity, &SketchArc, Option<&Parent>, Option<&Visibility>)>();
+    for (entity, arc, parent, visibility) in q_arcs.iter(world) {
+        let geometry =
+            SketchGeometryData::Arc { center: arc.center.to_array(), start: arc.start.to_array(), end: arc.end.to_array() };
+        push_entity(entity, parent, geometry, visibility);
+    }
+    let mut q_splines = world.query::<(Entity, &SketchSpline, Option<&Parent>, Option<&Visibility>)>();
+    for (entity, spline, parent, visibility) in q_splines.iter(world) {
+        let points = spline.points.iter().map(|p| p.to_array()).collect();
+        let geometry = SketchGeometryData::Spline { points, fit: spline.fit, knots: spline.knots.clone() };
+        push_entity(entity, parent, geometry, visibility);
+    }
+    let mut q_polygons = world.query::<(Entity, &SketchPolygon, Option<&Parent>, Option<&Visibility>)>();
+    for (entity, polygon, parent, visibility) in q_polygons.iter(world) {
+        let geometry = SketchGeometryData::Polygon {
+            center: polygon.center.to_array(),
+            point: polygon.point.to_array(),
+            sides: polygon.sides,
+            inscribed: polygon.inscribed,
+        };
+        push_entity(entity, parent, geometry, visibility);
+    }
+    let mut q_slots = world.query::<(Entity, &SketchSlot, Option<&Parent>, Option<&Visibility>)>();
+    for (entity, slot, parent, visibility) in q_slots.iter(world) {
+        let geometry = SketchGeometryData::Slot { a: slot.a.to_array(), b: slot.b.to_array(), radius: slot.radius };
+        push_entity(entity, parent, geometry, visibility);
+    }
+    let mut q_ellipses = world.query::<(Entity, &SketchEllipse, Option<&Parent>, Option<&Visibility>)>();
+    for (entity, ellipse, parent, visibility) in q_ellipses.iter(world) {
+        let geometry = SketchGeometryData::Ellipse {
+            center: ellipse.center.to_array(),
+            major: ellipse.major.to_array(),
+            minor_radius: ellipse.minor_radius,
+        };
+        push_entity(entity, parent, geometry, visibility);
+    }
+
+    let mut q_construction = world.query_filtered::<Entity, With<Construction>>();
+    for entity in q_construction.iter(world) {
+        if let Some(&(sketch, i)) = entity_index.get(&entity) {
+            sketches[sketch].entities[i].construction = true;
+        }
+    }
+
+    // 同じスケッチ内のエンティティだけをインデックスに置き換えられる
+    let local_index = |entity: Entity, sketch: usize| match entity_index.get(&entity) {
+        Some(&(owner, i)) if owner == sketch => Some(i),
+        _ => None,
+    };
+    let mut q_copies = world.query::<(Entity, &SketchCopy)>();
+    for (entity, copy) in q_copies.iter(world) {
+        let Some(&(index, i)) = entity_index.get(&entity) else {
+            continue;
+        };
+        let source = local_index(copy.source, index);
+        if let (Some(source), Some(transform)) = (source, copy.transform.map(|entity| local_index(entity, index))) {
+            sketches[index].entities[i].copy = Some(CopyData { source, transform });
+        }
+    }
+    let mut q_constraints = world.query::<(&SketchConstraint, &Parent)>();
+    for (constraint, parent) in q_constraints.iter(world) {
+        let Some(&index) = sketch_index.get(&parent.get()) else {
+            continue;
+        };
+        if let Some(kind) = constraint.0.map(|entity| local_index(entity, index)) {
+            sketches[index].constraints.push(kind);
+        }
+    }
+    let mut q_dimensions = world.query::<(&SketchDimension, &Parent)>();
+    for (dimension, parent) in q_dimensions.iter(world) {
+        let Some(&index) = sketch_index.get(&parent.get()) else {
+            continue;
+        };
+        if let Some(kind) = dimension.kind.map(|entity| local_index(entity, index)) {
+            sketches[index].dimensions.push(DimensionData { kind, value: dimension.value });
+        }
+    }
+
+    let profile_data = |profiles: &[ProfileRef], sketch: usize| -> Vec<ProfileRefData> {
+        profiles
+            .iter()
+            .filter_map(|profile| match profile {
+                ProfileRef::Region(seed) => Some(ProfileRefData::Region(seed.to_array())),
+                ProfileRef::Entity(entity) => local_index(*entity, sketch).map(ProfileRefData::Entity),
+            })
+            .collect()
+    };
+    let tree = world.resource::<FeatureTree>();
+    let features = tree
+        .features
+        .iter()
+        .filter_map(|feature| {
+            let kind = match &feature.kind {
+                FeatureKind::Sketch(sketch) => FeatureKindData::Sketch { sketch: *sketch_index.get(sketch)? },
+                FeatureKind::Extrude { sketch, profiles, distance, operation } => {
+                    let index = *sketch_index.get(sketch)?;
+                    let profiles = profile_data(profiles, index);
+                    FeatureKindData::Extrude { sketch: index, profiles, distance: *distance, operation: *operation }
+                }
+                FeatureKind::Revolve { sketch, profiles, axis, angle, operation } => {
+                    let index = *sketch_index.get(sketch)?;
+                    let profiles = profile_data(profiles, index);
+                    let axis = local_index(*axis, index)?;
+                    FeatureKindData::Revolve { sketch: index, profiles, axis, angle: *angle, operation: *operation }
+                }
+                FeatureKind::Sweep { sketch, profiles, path, path_sketch, orientation, twist, operation } => {
+                    let index = *sketch_index.get(sketch)?;
+                    let path_index = *sketch_index.get(path_sketch)?;
+                    FeatureKindData::Sweep {
+                        sketch: index,
+                        profiles: profile_data(profiles, index),
+                        path: path.iter().filter_map(|entity| local_index(*entity, path_index)).collect(),
+                        path_sketch: (path_index != index).then_some(path_index),
+                        orientation: *orientation,
+                        twist: *twist,
+                        operation: *operation,
+                    }
+                }
+                FeatureKind::Loft { sections, auto_align, operation } => {
+                    let sections = sections
+                        .iter()
+                        .map(|section| {
+                            let index = *sketch_index.get(&section.sketch)?;
+                            let profile = profile_data(std::slice::from_ref(&section.profile), index).pop()?;
+                            Some(LoftSectionData { sketch: index, profile, start: section.start })
+                        })
+                        .collect::<Option<_>>()?;
+                    FeatureKindData::Loft { sections, auto_align: *auto_align, operation: *operation }
+                }
+                FeatureKind::Combine { target, tool, op } => FeatureKindData::Combine {
+                    target: target.feature,
+                    target_index: target.index,
+                    tool: tool.feature,
+                    tool_index: tool.index,
+                    op: *op,
+                },
+                FeatureKind::BaseBody(brep) => FeatureKindData::BaseBody { mesh: mesh_to_data(&brep.tessellate()) },
+            };
+            Some(FeatureData { id: feature.id, name: feature.name.clone(), kind, suppressed: feature.suppressed })
+        })
+        .collect();
+
+    let project = ProjectFile { version: CURRENT_VERSION, sketches, features, rollback: Some(tree.rollback) };
+    (project, document_entities)
+}
+
+/// 現在のスケッチとボディを破棄し、プロジェクトファイルの内容で置き換える。作ったスケッチと図形のエンティティを返す
+pub fn restore_document(world: &mut World, project: &ProjectFile) -> DocumentEntities {
+    let mut existing = Vec::new();
+    existing.extend(world.query_filtered::<Entity, With<Sketch>>().iter(world));
+    for entity in existing {
+        world.entity_mut(entity).despawn_recursive();
+    }
+
+    let mut sketches = Vec::new();
+    let mut sketch_entities = Vec::new();
+    for sketch_data in &project.sketches {
+        let sketch = world.spawn(Sketch { name: sketch_data.name.clone(), plane: sketch_data.plane }).id();
+        let mut entities = Vec::new();
+        for data in &sketch_data.entities {
+            let mut entity = match data.geometry {
+                SketchGeometryData::Line { p1, p2 } => {
+                    world.spawn(SketchLine { p1: Vec3::from_array(p1), p2: Vec3::from_array(p2) })
+                }
+                SketchGeometryData::Circle { center, radius } => {
+                    world.spawn(SketchCircle { center: Vec3::from_array(center), radius })
+                }
+                SketchGeometryData::Rectangle { p1, p2, angle } => {
+                    world.spawn(SketchRectangle { p1: Vec3::from_array(p1), p2: Vec3::from_array(p2), angle })
+                }
+                SketchGeometryData::Arc { center, start, end } => world.spawn(SketchArc {
+                    center: Vec3::from_array(center),
+                    start: Vec3::from_array(start),
+                    end: Vec3::from_array(end),
+                }),
+                SketchGeometryData::Spline { ref points, fit, ref knots } => {
+                    let points = points.iter().map(|&p| Vec3::from_array(p)).collect();
+                    world.spawn(SketchSpline { points, fit, knots: knots.clone() })
+                }
+                SketchGeometryData::Polygon { center, point, sides, inscribed } => world.spawn(SketchPolygon {
+                    center: Vec3::from_array(center),
+                    point: Vec3::from_array(point),
+                    sides,
+                    inscribed,
+                }),
+                SketchGeometryData::Slot { a, b, radius } => {
+                    world.spawn(SketchSlot { a: Vec3::from_array(a), b: Vec3::from_array(b), radius })
+                }
+                SketchGeometryData::Ellipse { center, major, minor_radius } => world.spawn(SketchEllipse {
+                    center: Vec3::from_array(center),
+                    major: Vec3::from_array(major),
+                    minor_radius,
+                }),
+            };
+            entity.set_parent(sketch);
+            if data.hidden {
+                entity.insert(Visibility::Hidden);
+            }
+            if data.construction {
+                entity.insert(Construction);
+            }
+            entities.push(entity.id());
+        }
+        for (data, &entity) in sketch_data.entities.iter().zip(&entities) {
+            let Some(copy) = &data.copy else {
+                continue;
+            };
+            let transform = copy.transform.map(|i| entities.get(i).copied());
+            if let (Some(&source), Some(transform)) = (entities.get(copy.source), transform) {
+                world.entity_mut(entity).insert(SketchCopy { source, transform });
+            }
+        }
+        for kind in &sketch_data.constraints {
+            if let Some(kind) = kind.map(|i| entities.get(i).copied()) {
+                world.spawn(SketchConstraint(kind)).set_parent(sketch);
+            }
+        }
+        for dimension in &sketch_data.dimensions {
+            if let Some(kind) = dimension.kind.map(|i| entities.get(i).copied()) {
+                world.spawn(SketchDimension { kind, value: dimension.value }).set_parent(sketch);
+            }
+        }
+        sketches.push(sketch);
+        sketch_entities.push(entities);
+    }
+
+    let profile_refs = |profiles: &[ProfileRefData], entities: &[Entity]| -> Vec<ProfileRef> {
+        profiles
+            .iter()
+            .filter_map(|profile| match profile {
+                ProfileRefData::Region(seed) => Some(ProfileRef::Region(Vec2::from_array(*seed))),
+                ProfileRefData::Entity(i) => entities.get(*i).copied().map(ProfileRef::Entity),
+            })
+            .collect()
+    };
+    let features: Vec<Feature> = project
+        .features
+        .iter()
+        .filter_map(|data| {
+            let kind = match &data.kind {
+                FeatureKindData::Sketch { sketch } => FeatureKind::Sketch(*sketches.get(*sketch)?),
+                FeatureKindData::Extrude { sketch, profiles, distance, operation } => FeatureKind::Extrude {
+                    sketch: *sketches.get(*sketch)?,
+                    profiles: profile_refs(profiles, sketch_entities.get(*sketch)?),
+                    distance: *distance,
+                    operation: *operation,
+                },
+                FeatureKindData::Revolve { sketch, profiles, axis, angle, operation } => FeatureKind::Revolve {
+                    sketch: *sketches.get(*sketch)?,
+                    profiles: profile_refs(profiles, sketch_entities.get(*sketch)?),
+                    axis: *sketch_entities.get(*sketch)?.get(*axis)?,
+                    angle: *angle,
+                    operation: *operation,
+                },
+                FeatureKindData::Sweep { sketch, profiles, path, path_sketch, orientation, twist, operation } => {
+                    let path_sketch = path_sketch.unwrap_or(*sketch);
+                    let path_entities = sketch_entities.get(path_sketch)?;
+                    FeatureKind::Sweep {
+                        sketch: *sketches.get(*sketch)?,
+                        profiles: profile_refs(profiles, sketch_entities.get(*sketch)?),
+                        path: path.iter().filter_map(|i| path_entities.get(*i).copied()).collect(),
+                        path_sketch: *sketches.get(path_sketch)?,
+                        orientation: *orientation,
+                        twist: *twist,
+                        operation: *operation,
+                    }
+                }
+                FeatureKindData::Loft { sections, auto_align, operation } => {
+                    let sections = sections
+                        .iter()
+                        .map(|section| {
+                            let entities = sketch_entities.get(section.sketch)?;
+                            Some(LoftSection {
+                                sketch: *sketches.get(section.sketch)?,
+                                profile: profile_refs(std::slice::from_ref(&section.profile), entities).pop()?,
+                                start: section.start,
+                            })
+                        })
+                        .collect::<Option<_>>()?;
+                    FeatureKind::Loft { sections, auto_align: *auto_align, operation: *operation }
+                }
+                FeatureKindData::Combine { target, target_index, tool, tool_index, op } => FeatureKind::Combine {
+                    target: BodyRef { feature: *target, index: *target_index },
+                    tool: BodyRef { feature: *tool, index: *tool_index },
+                    op: *op,
+                },
+                // 解析的な形状は保存していないので、三角形ごとの面で復元する
+                FeatureKindData::BaseBody { mesh } => FeatureKind::BaseBody(Brep::from_mesh(&data_to_mesh(mesh))),
+            };
+            Some(Feature {
+                id: data.id,
+                name: data.name.clone(),
+                kind,
+                suppressed: data.suppressed,
+                error: None,
+                targets: Vec::new(),
+            })
+        })
+        .collect();
+    let rollback = project.rollback.unwrap_or(features.len());
+    // ボディは次の再生成で作られる
+    world.resource_mut::<FeatureTree>().replace(features, rollback);
+    DocumentEntities { sketches, entities: sketch_entities }
+}
+
+fn is_hidden(visibility: Option<&Visibility>) -> bool {
+    matches!(visibility, Some(Visibility::Hidden))
+}
+
+/// Bevyのメッシュから保存用のデータを取り出す
+pub fn mesh_to_data(mesh: &Mesh) -> MeshData {
+    let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(VertexAttributeValues::Float32x3(values)) => values.clone(),
+        _ => Vec::new(),
+    };
+    let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+        Some(VertexAttributeValues::Float32x3(values)) => values.clone(),
+        _ => Vec::new(),
+    };
+    let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+        Some(VertexAttributeValues::Float32x2(values)) => values.clone(),
+        _ => Vec::new(),
+    };
+    let indices = match mesh.indices() {
+        Some(indices) => indices.iter().map(|i| i as u32).collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    MeshData { positions, normals, uvs, indices }
+}
+
+/// 保存用のデータからBevyのメッシュを組み立てる
+pub fn data_to_mesh(data: &MeshData) -> Mesh {
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default());
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, data.positions.clone());
+    if data.normals.len() == data.positions.len() {
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, data.normals.clone());
+    }
+    if data.uvs.len() == data.positions.len() {
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, data.uvs.clone());
+    }
+    mesh.insert_indices(Indices::U32(data.indices.clone()));
+    if data.normals.len() != data.positions.len() {
+        mesh.duplicate_vertices();
+        mesh.compute_flat_normals();
+    }
+    mesh
+}
+
+/// UIから要求されたファイル操作を処理するシステム
+pub fn document_io_system(world: &mut World) {
+    let Some(action) = world.resource_mut::<DocumentState>().pending.take() else {
+        return;
+    };
+
+    let result = match action {
+        FileAction::Save | FileAction::SaveAs(_) => {
+            let path = match action {
+                FileAction::SaveAs(path) => with_default_extension(path),
+                _ => match world.resource::<DocumentState>().path.clone() {
+                    Some(path) => path,
+                    None => with_default_extension(PathBuf::from(
+                        world.resource::<DocumentState>().path_input.trim(),
+                    )),
+                },
+            };
+            let project = capture_document(world);
+            save_to_path(&project, &path).map(|_| (path, "保存しました"))
+        }
+        FileAction::Open(path) => load_from_path(&path).map(|project| {
+            restore_document(world, &project);
+            // 読み込む前の状態には戻せず、ロフトの断面やスイープのパスも読み込む前のスケッチを指している
+            world.resource_mut::<UndoHistory>().clear();
+            world.resource_mut::<LoftSections>().0.clear();
+            world.resource_mut::<SweepPath>().0 = None;
+            // 読み込んだドキュメントは表示モードから編集を始める
+            world.resource_mut::<ActiveSketch>().0 = None;
+            world.resource_mut::<NextState<AppState>>().set(AppState::Viewing);
+            (path, "開きました")
+        }),
+    };
+
+    let mut document = world.resource_mut::<DocumentState>();
+    match result {
+        Ok((path, message)) => {
+            println!("{}: {}", message, path.display());
+            document.status = format!("{}: {}", message, path.display());
+            document.path_input = path.display().to_string();
+            document.path = Some(path);
+        }
+        Err(e) => {
+            println!("{e}");
+            document.status = e.to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1: &str = r#"(
+        version: 1,
+        sketch_entities: [
+            (geometry: Line(p1: (0.0, 0.0, 0.0), p2: (1.0, 0.0, 0.0))),
+            (geometry: Circle(center: (0.0, 0.0, 0.0), radius: 0.5), hidden: true),
+            (
+                plane: (origin: (0.0, 1.0, 0.0), normal: (0.0, 1.0, 0.0)),
+                geometry: Rectangle(p1: (0.0, 1.0, 0.0), p2: (1.0, 1.0, -1.0)),
+            ),
+        ],
+        bodies: [
+            (
+                extrude_distance: 1.0,
+                transform: (translation: (1.0, 0.0, 0.0), rotation: (0.0, 0.0, 0.0, 1.0), scale: (2.0, 1.0, 1.0)),
+                mesh: (
+                    positions: [(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0)],
+                    normals: [(0.0, 0.0, 1.0), (0.0, 0.0, 1.0), (0.0, 0.0, 1.0)],
+                    indices: [0, 1, 2],
+                ),
+            ),
+        ],
+    )"#;
+
+    const V2: &str = r#"(
+        version: 2,
+        sketches: [
+            (
+                name: "スケッチ1",
+                entities: [
+                    (geometry: Line(p1: (0.0, 0.0, 0.0), p2: (0.0, 0.0, -1.0))),
+                    (geometry: Circle(center: (0.0, 0.0, 0.0), radius: 1.0)),
+                ],
+                constraints: [Vertical(0)],
+                dimensions: [(kind: Radius(1), value: 1.0)],
+            ),
+        ],
+        bodies: [],
+    )"#;
+
+    /// 書き出したテキストを読み直して書き出すと同じテキストになることを確かめる
+    fn assert_round_trip(project: &ProjectFile) {
+        let text = serialize_project(project).unwrap();
+        let reparsed = parse_project(&text).unwrap();
+        assert_eq!(reparsed.version, CURRENT_VERSION);
+        assert_eq!(serialize_project(&reparsed).unwrap(), text);
+    }
+
+    #[test]
+    fn migrates_v1_to_current() {
+        let project = parse_project(V1).unwrap();
+        assert_eq!(project.version, CURRENT_VERSION);
+        // 平面ごとにスケッチにまとまる
+        assert_eq!(project.sketches.len(), 2);
+        assert_eq!(project.sketches[0].entities.len(), 2);
+        assert!(project.sketches[0].entities[1].hidden);
+        assert_eq!(project.sketches[1].plane.origin, [0.0, 1.0, 0.0]);
+        // スケッチごとのスケッチフィーチャーと、変換を焼き込んだボディ
+        assert_eq!(project.features.len(), 3);
+        let FeatureKindData::BaseBody { mesh } = &project.features[2].kind else {
+            panic!("ボディは履歴なしのボディになる");
+        };
+        assert_eq!(mesh.positions[1], [3.0, 0.0, 0.0]);
+        assert_round_trip(&project);
+    }
+
+    #[test]
+    fn migrates_v2_to_current() {
+        let project = parse_project(V2).unwrap();
+        assert_eq!(project.version, CURRENT_VERSION);
+        assert_eq!(project.sketches.len(), 1);
+        assert_eq!(project.sketches[0].entities.len(), 2);
+        assert!(matches!(project.sketches[0].constraints[..], [ConstraintKind::Vertical(0)]));
+        assert!(matches!(project.sketches[0].dimensions[..], [DimensionData { kind: DimensionKind::Radius(1), .. }]));
+        assert!(matches!(project.features[..], [FeatureData { kind: FeatureKindData::Sketch { sketch: 0 }, .. }]));
+        assert_eq!(project.rollback, None);
+        assert_round_trip(&project);
+    }
+
+    #[test]
+    fn keeps_sketch_planes_through_world() {
+        let mut world = World::new();
+        world.init_resource::<FeatureTree>();
+        let project = parse_project(V1).unwrap();
+        restore_document(&mut world, &project);
+        let captured = capture_document(&mut world);
+        let mut planes: Vec<[f32; 3]> = captured.sketches.iter().map(|sketch| sketch.plane.origin).collect();
+        planes.sort_by(|a, b| a[1].total_cmp(&b[1]));
+        assert_eq!(planes, [[0.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+    }
+
+    #[test]
+    fn rejects_newer_version() {
+        let text = format!("(version: {})", CURRENT_VERSION + 1);
+        assert!(matches!(parse_project(&text), Err(DocumentError::UnsupportedVersion(_))));
+    }
+}
@@ -0,0 +1,996 @@
+//! フィーチャー履歴ツリーと、履歴からのボディの再生成
+//!
+//! スケッチ・押し出し・結合などの操作をパラメータ付きのフィーチャーとして順に記録し、
+//! スケッチやパラメータが変わるたびに、変わったフィーチャーから後ろを実行し直してボディを作り直す。
+
+use std::collections::{HashMap, HashSet};
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_egui::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::bodies::BodySelection;
+use crate::brep::Brep;
+use crate::csg::{self, BooleanOp, Solid};
+use crate::geometry::{Curve2d, SketchFrame};
+use crate::history::UndoHistory;
+use crate::profile::{self, SketchProfiles, SketchShape, SketchShapeChanges};
+use crate::{Body, Construction, Sketch};
+
+/// フィーチャーの識別子。並べ替えても変わらない
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FeatureId(pub u32);
+
+/// ボディの参照。ボディを作ったフィーチャーと、そのフィーチャーが作ったボディの中の番号で識別する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BodyRef {
+    pub feature: FeatureId,
+    pub index: usize,
+}
+
+/// 押し出し・回転・スイープ・ロフトしたソリッドと既存のボディとの演算
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ExtrudeOperation {
+    /// 新しいボディを作る
+    #[default]
+    NewBody,
+    /// 重なるボディと結合する
+    Join,
+    /// 重なるボディから取り除く
+    Cut,
+    /// 重なるボディとの共通部分だけを残す
+    Intersect,
+}
+
+impl ExtrudeOperation {
+    pub const ALL: [ExtrudeOperation; 4] =
+        [ExtrudeOperation::NewBody, ExtrudeOperation::Join, ExtrudeOperation::Cut, ExtrudeOperation::Intersect];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ExtrudeOperation::NewBody => "新規ボディ",
+            ExtrudeOperation::Join => "結合",
+            ExtrudeOperation::Cut => "カット",
+            ExtrudeOperation::Intersect => "交差",
+        }
+    }
+}
+
+/// スイープで断面をパスに対してどう向けるか
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SweepOrientation {
+    /// パスの向きに合わせて断面を回す
+    #[default]
+    FollowPath,
+    /// 断面の向きを始点のまま変えない
+    Fixed,
+}
+
+impl SweepOrientation {
+    pub const ALL: [SweepOrientation; 2] = [SweepOrientation::FollowPath, SweepOrientation::Fixed];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SweepOrientation::FollowPath => "パスに垂直",
+            SweepOrientation::Fixed => "向きを固定",
+        }
+    }
+}
+
+/// 押し出すプロファイルの参照
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProfileRef {
+    /// 閉じた領域。ジオメトリが変わっても追従できるよう、領域内の点で参照する
+    Region(Vec2),
+    /// スケッチエンティティそのもの (円・四角形はソリッド、直線は面になる)
+    Entity(Entity),
+}
+
+/// ロフトの断面。断面はスケッチの平面に置き、平面どうしの間隔は平面の原点を法線方向に測った位置の差で決める。
+/// スケッチはすべてXZ平面と平行に描くので、傾いた平面の断面は作れない
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoftSection {
+    pub sketch: Entity,
+    pub profile: ProfileRef,
+    /// 外周の始点からの周長の割合で表した開始位置
+    pub start: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeatureKind {
+    Sketch(Entity),
+    Extrude { sketch: Entity, profiles: Vec<ProfileRef>, distance: f32, operation: ExtrudeOperation },
+    /// `axis` の直線を軸にした回転。角度は度
+    Revolve { sketch: Entity, profiles: Vec<ProfileRef>, axis: Entity, angle: f32, operation: ExtrudeOperation },
+    /// `path` のつながった直線・円弧・スプラインに沿ったスイープ。パスは `path_sketch` の図形で、
+    /// 断面と別のスケッチでもよい。ねじれは度
+    Sweep {
+        sketch: Entity,
+        profiles: Vec<ProfileRef>,
+        path: Vec<Entity>,
+        path_sketch: Entity,
+        orientation: SweepOrientation,
+        twist: f32,
+        operation: ExtrudeOperation,
+    },
+    /// 断面を順につないだロフト。`auto_align` でねじれが最小になるよう始点を合わせる
+    Loft { sections: Vec<LoftSection>, auto_align: bool, operation: ExtrudeOperation },
+    /// 2つのボディの演算。結果は新しいボディになり、2つのボディは取り除かれる
+    Combine { target: BodyRef, tool: BodyRef, op: BooleanOp },
+    /// 履歴を持たない読み込み済みのボディ
+    BaseBody(Brep),
+}
+
+#[derive(Debug, Clone)]
+pub struct Feature {
+    pub id: FeatureId,
+    pub name: String,
+    pub kind: FeatureKind,
+    pub suppressed: bool,
+    /// 最後の再生成で発生したエラー
+    pub error: Option<String>,
+    /// 最後の再生成で演算の対象にした既存のボディを作ったフィーチャー。カット・結合・交差で暗黙に参照する
+    pub targets: Vec<FeatureId>,
+}
+
+/// フィーチャーの履歴
+#[derive(Resource, Default)]
+pub struct FeatureTree {
+    pub features: Vec<Feature>,
+    /// ロールバックバーの位置。これより後のフィーチャーは実行しない
+    pub rollback: usize,
+    pub selected: Option<FeatureId>,
+    pub message: String,
+    next_id: u32,
+    /// 次のフレームで再生成する
+    dirty: bool,
+    /// 前回の再生成で各フィーチャーを実行した結果
+    steps: Vec<Step>,
+    /// 前回の再生成で使ったスケッチの曲線
+    sketches: HashMap<Entity, SketchGeometry>,
+}
+
+/// 再生成で1つのフィーチャーを実行した結果。入力が変わらなければ次の再生成で使い回す
+struct Step {
+    id: FeatureId,
+    kind: FeatureKind,
+    active: bool,
+    /// 実行した後のボディ
+    bodies: Bodies,
+}
+
+impl FeatureTree {
+    /// ロールバックバーの位置にフィーチャーを追加し、バーをその後ろへ進める
+    pub fn insert(&mut self, name: String, kind: FeatureKind) -> FeatureId {
+        let id = FeatureId(self.next_id);
+        self.next_id += 1;
+        let feature = Feature { id, name, kind, suppressed: false, error: None, targets: Vec::new() };
+        self.features.insert(self.rollback, feature);
+        self.rollback += 1;
+        id
+    }
+
+    /// 読み込んだ履歴で置き換える
+    pub fn replace(&mut self, features: Vec<Feature>, rollback: usize) {
+        self.next_id = features.iter().map(|feature| feature.id.0 + 1).max().unwrap_or(0);
+        self.rollback = rollback.min(features.len());
+        self.features = features;
+        self.selected = None;
+        self.message.clear();
+    }
+
+    pub fn index_of(&self, id: FeatureId) -> Option<usize> {
+        self.features.iter().position(|feature| feature.id == id)
+    }
+
+    /// 同じ種類のフィーチャーに付ける連番の名前
+    pub fn next_name(&self, prefix: &str) -> String {
+        (1..).map(|n| format!("{prefix}{n}")).find(|name| self.features.iter().all(|f| &f.name != name)).unwrap()
+    }
+
+    /// 指定したスケッチのフィーチャー
+    pub fn sketch_feature(&self, sketch: Entity) -> Option<FeatureId> {
+        self.features.iter().find(|f| matches!(f.kind, FeatureKind::Sketch(s) if s == sketch)).map(|f| f.id)
+    }
+
+    /// フィーチャーが入力として参照しているフィーチャー。演算したボディを作ったフィーチャーも含む
+    pub fn dependencies(&self, feature: &Feature) -> Vec<FeatureId> {
+        let mut dependencies = match &feature.kind {
+            FeatureKind::Sketch(_) | FeatureKind::BaseBody(_) => Vec::new(),
+            FeatureKind::Combine { target, tool, .. } => vec![target.feature, tool.feature],
+            kind => input_sketches(kind).into_iter().filter_map(|sketch| self.sketch_feature(sketch)).collect(),
+        };
+        dependencies.extend(feature.targets.iter().filter(|id| **id != feature.id));
+        dependencies
+    }
+
+    /// 前回の再生成から入力が変わった最初のフィーチャーの位置。変わっていなければフィーチャーの数
+    fn first_changed(&self, sketches: &HashMap<Entity, SketchGeometry>) -> usize {
+        let changed = |i: usize, feature: &Feature| {
+            let Some(step) = self.steps.get(i) else {
+                return true;
+            };
+            step.id != feature.id
+                || step.active != (i < self.rollback && !feature.suppressed)
+                || step.kind != feature.kind
+                || input_sketches(&feature.kind).iter().any(|sketch| sketches.get(sketch) != self.sketches.get(sketch))
+        };
+        self.features.iter().enumerate().position(|(i, feature)| changed(i, feature)).unwrap_or(self.features.len())
+    }
+
+    fn depends_on(&self, index: usize, other: usize) -> bool {
+        self.dependencies(&self.features[index]).contains(&self.features[other].id)
+    }
+
+    /// フィーチャーを1つ前 (`up`) または後ろへ移動する。入力より前には移動できない
+    pub fn move_feature(&mut self, index: usize, up: bool) -> Result<(), String> {
+        let (first, second) = if up { (index.checked_sub(1), Some(index)) } else { (Some(index), Some(index + 1)) };
+        let (Some(first), Some(second)) = (first, second) else {
+            return Ok(());
+        };
+        if second >= self.features.len() {
+            return Ok(());
+        }
+        if self.depends_on(second, first) {
+            return Err(format!(
+                "{} は {} を参照しているため前に移動できません",
+                self.features[second].name, self.features[first].name
+            ));
+        }
+        self.features.swap(first, second);
+        Ok(())
+    }
+
+    /// フィーチャーを削除する。参照されているフィーチャーは削除できない
+    pub fn remove(&mut self, index: usize) -> Result<Feature, String> {
+        let id = self.features[index].id;
+        if let Some(user) = self.features.iter().find(|f| self.dependencies(f).contains(&id)) {
+            return Err(format!("{} が参照しているため削除できません", user.name));
+        }
+        if index < self.rollback {
+            self.rollback -= 1;
+        }
+        Ok(self.features.remove(index))
+    }
+}
+
+/// スケッチ1つ分の再生成の入力
+#[derive(Default, PartialEq)]
+struct SketchGeometry {
+    /// スケッチ平面の原点を法線方向に測った位置
+    offset: f32,
+    curves: Vec<Curve2d>,
+    by_entity: HashMap<Entity, Vec<Curve2d>>,
+    /// 補助線のエンティティ。軸やパスには使えるが、プロファイルにはならない
+    construction: HashSet<Entity>,
+}
+
+/// すべてのスケッチの曲線を集める
+fn gather_sketches(world: &mut World) -> HashMap<Entity, SketchGeometry> {
+    let frame = SketchFrame::default();
+    let mut sketches: HashMap<Entity, SketchGeometry> = world
+        .query::<(Entity, &Sketch)>()
+        .iter(world)
+        .map(|(e, sketch)| (e, SketchGeometry { offset: sketch.plane.offset(), ..default() }))
+        .collect();
+    let mut q_geometry = world.query::<(Entity, SketchShape, &Parent, Has<Construction>)>();
+    for (entity, shape, parent, construction) in q_geometry.iter(world) {
+        let Some(sketch) = sketches.get_mut(&parent.get()) else {
+            continue;
+        };
+        let curves = profile::entity_curves(&frame, shape);
+        if curves.is_empty() {
+            continue;
+        }
+        if construction {
+            sketch.construction.insert(entity);
+        } else {
+            sketch.curves.extend_from_slice(&curves);
+        }
+        sketch.by_entity.insert(entity, curves);
+    }
+    sketches
+}
+
+/// 再生成中のボディ
+type Bodies = Vec<(BodyRef, Brep)>;
+
+/// `feature` が作った次のボディとして加える
+fn push_body(bodies: &mut Bodies, feature: FeatureId, brep: Brep) {
+    let index = bodies.iter().filter(|(body, _)| body.feature == feature).map(|(body, _)| body.index + 1).max();
+    bodies.push((BodyRef { feature, index: index.unwrap_or(0) }, brep));
+}
+
+fn to_solid(brep: &Brep) -> Solid {
+    Solid::from_mesh(&brep.tessellate(), &Transform::IDENTITY)
+}
+
+/// フィーチャーが曲線を読むスケッチ
+fn input_sketches(kind: &FeatureKind) -> Vec<Entity> {
+    match kind {
+        FeatureKind::Sketch(sketch) | FeatureKind::Extrude { sketch, .. } | FeatureKind::Revolve { sketch, .. } => {
+            vec![*sketch]
+        }
+        FeatureKind::Sweep { sketch, path_sketch, .. } if path_sketch != sketch => vec![*sketch, *path_sketch],
+        FeatureKind::Sweep { sketch, .. } => vec![*sketch],
+        FeatureKind::Loft { sections, .. } => sections.iter().map(|section| section.sketch).collect(),
+        FeatureKind::Combine { .. } | FeatureKind::BaseBody(_) => Vec::new(),
+    }
+}
+
+/// フィーチャーを1つ実行する。演算の対象にした既存のボディを作ったフィーチャーを返す
+fn apply_feature(
+    feature: &Feature,
+    sketches: &HashMap<Entity, SketchGeometry>,
+    bodies: &mut Bodies,
+) -> Result<Vec<FeatureId>, String> {
+    let targets = match &feature.kind {
+        FeatureKind::Sketch(sketch) => {
+            if !sketches.contains_key(sketch) {
+                return Err("スケッチが見つかりません".to_string());
+            }
+            Vec::new()
+        }
+        FeatureKind::Extrude { sketch, profiles, distance, operation } => {
+            let geometry = sketches.get(sketch).ok_or("スケッチが見つかりません")?;
+            if *distance == 0.0 {
+                return Err("押し出し距離が0です".to_string());
+            }
+            let frame = SketchFrame::default();
+            let mut tools = Vec::new();
+            for profile in resolve_profiles(profiles, geometry)? {
+                match (&profile.0[..], profile.1.is_empty()) {
+                    // 直線は閉じたソリッドにならないので、演算の種類に関係なく新しいボディにする
+                    (&[Curve2d::Line { a, b }], true) => {
+                        push_body(bodies, feature.id, Brep::extrude_sheet(a, b, &frame, *distance));
+                    }
+                    ([curve], true) if !curve.is_closed() => return Err("開いた曲線は押し出せません".to_string()),
+                    _ => tools.push(Brep::extrude(&profile.0, &profile.1, &frame, *distance)),
+                }
+            }
+            apply_solids(feature.id, *operation, tools, bodies)?
+        }
+        FeatureKind::Revolve { sketch, profiles, axis, angle, operation } => {
+            let geometry = sketches.get(sketch).ok_or("スケッチが見つかりません")?;
+            if *angle <= 0.0 {
+                return Err("回転角度が0です".to_string());
+            }
+            let Some(&[Curve2d::Line { a, b }]) = geometry.by_entity.get(axis).map(Vec::as_slice) else {
+                return Err("回転軸の直線が見つかりません".to_string());
+            };
+            let frame = SketchFrame::default();
+            let mut tools = Vec::new();
+            for (outer, holes) in resolve_profiles(profiles, geometry)? {
+                if holes.is_empty() && matches!(&outer[..], [curve] if !curve.is_closed()) {
+                    return Err("開いた図形は回転できません".to_string());
+                }
+                tools.push(Brep::revolve(&outer, &holes, &frame, (a, b), angle.to_radians())?);
+            }
+            apply_solids(feature.id, *operation, tools, bodies)?
+        }
+        FeatureKind::Sweep { sketch, profiles, path, path_sketch, orientation, twist, operation } => {
+            let geometry = sketches.get(sketch).ok_or("スケッチが見つかりません")?;
+            // スケッチ平面はまだすべて `SketchFrame::default()` なので、別のスケッチのパスも断面と同じ平面上にあり、
+            // 座標をそのまま使える。スケッチごとに平面を持たせる時は、パスのスケッチの平面から断面の平面へ写す
+            let path_geometry = sketches.get(path_sketch).ok_or("パスのスケッチが見つかりません")?;
+            let mut path_curves = Vec::new();
+            for entity in path {
+                let curves = path_geometry.by_entity.get(entity).ok_or("パスの図形が見つかりません")?;
+                path_curves.extend_from_slice(curves);
+            }
+            let chain = profile::chain_path(&path_curves, profile::TOLERANCE).ok_or("パスがつながっていません")?;
+            let mut points = Vec::new();
+            for curve in &chain {
+                let segment = curve.tessellate();
+                points.extend_from_slice(&segment[usize::from(!points.is_empty())..]);
+            }
+            let frame = SketchFrame::default();
+            let follow_path = *orientation == SweepOrientation::FollowPath;
+            let mut tools = Vec::new();
+            for (outer, holes) in resolve_profiles(profiles, geometry)? {
+                if holes.is_empty() && matches!(&outer[..], [curve] if !curve.is_closed()) {
+                    return Err("開いた図形はスイープできません".to_string());
+                }
+                // パスは断面に近い側の端から始める
+                let outline: Vec<Vec2> = outer.iter().flat_map(Curve2d::tessellate).collect();
+                let center = outline.iter().sum::<Vec2>() / outline.len() as f32;
+                let mut points = points.clone();
+                if center.distance(points[points.len() - 1]) < center.distance(points[0]) {
+                    points.reverse();
+                }
+                tools.push(Brep::sweep(&outer, &holes, &frame, &points, follow_path, twist.to_radians())?);
+            }
+            apply_solids(feature.id, *operation, tools, bodies)?
+        }
+        FeatureKind::Loft { sections, auto_align, operation } => {
+            let mut outlines = Vec::new();
+            for section in sections {
+                let geometry = sketches.get(&section.sketch).ok_or("スケッチが見つかりません")?;
+                let (outer, holes) = resolve_profiles(std::slice::from_ref(&section.profile), geometry)?.remove(0);
+                if !holes.is_empty() {
+                    return Err("穴のあるプロファイルはロフトできません".to_string());
+                }
+                if matches!(&outer[..], [curve] if !curve.is_closed()) {
+                    return Err("開いた図形はロフトできません".to_string());
+                }
+                outlines.push((outer, geometry.offset, section.start));
+            }
+            let loft = Brep::loft(&outlines, &SketchFrame::default(), *auto_align)?;
+            apply_solids(feature.id, *operation, vec![loft], bodies)?
+        }
+        FeatureKind::Combine { target, tool, op } => {
+            let find = |body: &BodyRef| bodies.iter().position(|(other, _)| other == body);
+            let (Some(a), Some(b)) = (find(target), find(tool)) else {
+                return Err("対象のボディが見つかりません".to_string());
+            };
+            if a == b || !bodies[a].1.is_closed() || !bodies[b].1.is_closed() {
+                return Err("閉じた2つのボディが必要です".to_string());
+            }
+            let (mesh_a, mesh_b) = (bodies[a].1.tessellate(), bodies[b].1.tessellate());
+            // 結果が空になる演算は失敗として、2つのボディを残す
+            let mesh = csg::boolean((&mesh_a, &Transform::IDENTITY), (&mesh_b, &Transform::IDENTITY), *op)
+                .ok_or_else(|| format!("{}の結果が空になります", op.label()))?;
+            let brep = Brep::from_boolean(&mesh, &[&bodies[a].1, &bodies[b].1]);
+            bodies.retain(|(body, _)| body != target && body != tool);
+            push_body(bodies, feature.id, brep);
+            Vec::new()
+        }
+        FeatureKind::BaseBody(brep) => {
+            push_body(bodies, feature.id, brep.clone());
+            Vec::new()
+        }
+    };
+    Ok(targets)
+}
+
+/// プロファイルの参照を外周と穴の曲線に解決する。直線のエンティティは1本の直線の外周になる
+fn resolve_profiles(
+    profiles: &[ProfileRef],
+    geometry: &SketchGeometry,
+) -> Result<Vec<(Vec<Curve2d>, Vec<Vec<Curve2d>>)>, String> {
+    let loops = profile::detect_loops(&geometry.curves, profile::TOLERANCE);
+    let regions = profile::build_regions(&loops);
+    let detected = SketchProfiles { loops, regions, selected: None };
+    profiles
+        .iter()
+        .map(|profile| match profile {
+            ProfileRef::Region(seed) => {
+                let region = detected.region_at(*seed).ok_or("プロファイルが見つかりません")?;
+                Ok(detected.region_curves(&detected.regions[region]))
+            }
+            ProfileRef::Entity(entity) => match geometry.by_entity.get(entity) {
+                Some(_) if geometry.construction.contains(entity) => Err("補助線はプロファイルにできません".to_string()),
+                Some(curves) => Ok((curves.clone(), Vec::new())),
+                None => Err("スケッチの図形が見つかりません".to_string()),
+            },
+        })
+        .collect()
+}
+
+/// 押し出し・回転・スイープ・ロフトしたソリッドを、外接箱が重なる既存の閉じたボディと演算する。
+/// 演算したボディを作ったフィーチャーを返す
+fn apply_solids(
+    id: FeatureId,
+    operation: ExtrudeOperation,
+    tools: Vec<Brep>,
+    bodies: &mut Bodies,
+) -> Result<Vec<FeatureId>, String> {
+    if operation == ExtrudeOperation::NewBody {
+        for brep in tools {
+            push_body(bodies, id, brep);
+        }
+        return Ok(Vec::new());
+    }
+    let Some(tool) = tools.iter().map(to_solid).reduce(|a, b| a.union(&b)) else {
+        return Ok(Vec::new());
+    };
+    let targets: Vec<(usize, Solid)> = bodies
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, brep))| brep.is_closed())
+        .map(|(i, (_, brep))| (i, to_solid(brep)))
+        .filter(|(_, solid)| !solid.is_empty() && solid.overlaps(&tool))
+        .collect();
+    let mut owners: Vec<FeatureId> = Vec::new();
+    for (i, _) in &targets {
+        if !owners.contains(&bodies[*i].0.feature) {
+            owners.push(bodies[*i].0.feature);
+        }
+    }
+
+    if operation == ExtrudeOperation::Join {
+        let mut joined = tool;
+        for (_, solid) in &targets {
+            joined = solid.union(&joined);
+        }
+        let sources: Vec<&Brep> = tools.iter().chain(targets.iter().map(|(i, _)| &bodies[*i].1)).collect();
+        let brep = Brep::from_boolean(&joined.to_mesh(), &sources);
+        for (i, _) in targets.iter().rev() {
+            bodies.remove(*i);
+        }
+        push_body(bodies, id, brep);
+        return Ok(owners);
+    }
+
+    if targets.is_empty() {
+        return Err("対象のボディがありません".to_string());
+    }
+    let op = match operation {
+        ExtrudeOperation::Cut => BooleanOp::Difference,
+        _ => BooleanOp::Intersection,
+    };
+    for (i, solid) in targets.into_iter().rev() {
+        let result = solid.apply(&tool, op);
+        if result.is_empty() {
+            bodies.remove(i);
+        } else {
+            let sources: Vec<&Brep> = tools.iter().chain([&bodies[i].1]).collect();
+            bodies[i].1 = Brep::from_boolean(&result.to_mesh(), &sources);
+        }
+    }
+    Ok(owners)
+}
+
+/// スケッチの形状や平面、履歴が変わったら再生成を予約するシステム
+pub fn mark_dirty_system(
+    mut tree: ResMut<FeatureTree>,
+    mut changes: SketchShapeChanges,
+    q_planes: Query<(), Changed<Sketch>>,
+) {
+    if changes.any() || !q_planes.is_empty() || tree.is_changed() {
+        tree.bypass_change_detection().dirty = true;
+    }
+}
+
+/// 入力が変わった最初のフィーチャーから後ろを実行し直してボディを作り直すシステム
+pub fn regenerate_system(world: &mut World) {
+    if !world.resource::<FeatureTree>().dirty {
+        return;
+    }
+    let sketches = gather_sketches(world);
+    let mut tree = world.resource_mut::<FeatureTree>();
+    let tree = tree.bypass_change_detection();
+    let start = tree.first_changed(&sketches);
+    tree.steps.truncate(start);
+    let mut bodies = tree.steps.last().map(|step| step.bodies.clone()).unwrap_or_default();
+    for i in start..tree.features.len() {
+        let active = i < tree.rollback && !tree.features[i].suppressed;
+        let feature = &mut tree.features[i];
+        feature.error = None;
+        if active {
+            match apply_feature(feature, &sketches, &mut bodies) {
+                Ok(targets) => feature.targets = targets,
+                Err(error) => feature.error = Some(error),
+            }
+        }
+        let step = Step { id: feature.id, kind: feature.kind.clone(), active, bodies: bodies.clone() };
+        tree.steps.push(step);
+    }
+    tree.sketches = sketches;
+    tree.dirty = false;
+
+    // 選択中のボディは、作り直した後も同じ参照のボディを選択する
+    let selected: Vec<BodyRef> = world
+        .resource::<BodySelection>()
+        .entities
+        .iter()
+        .filter_map(|&entity| world.get::<Body>(entity).map(|body| body.body))
+        .collect();
+    let existing: Vec<Entity> = world.query_filtered::<Entity, With<Body>>().iter(world).collect();
+    for entity in existing {
+        world.entity_mut(entity).despawn_recursive();
+    }
+    let mut spawned = HashMap::new();
+    for (body, brep) in bodies {
+        let material = world.resource_mut::<Assets<StandardMaterial>>().add(Color::rgb(0.7, 0.7, 0.7));
+        spawned.insert(body, world.spawn((PbrBundle { material, ..default() }, Body { body }, brep)).id());
+    }
+    world.resource_mut::<BodySelection>().entities = selected.iter().filter_map(|body| spawned.get(body).copied()).collect();
+}
+
+/// フィーチャーツリーの操作でUIの外側に反映が必要なもの
+pub enum FeatureAction {
+    EditSketch(Entity),
+    DeleteSketch(Entity),
+}
+
+/// フィーチャーツリーのブラウザUI
+#[derive(SystemParam)]
+pub struct FeaturePanel<'w> {
+    tree: ResMut<'w, FeatureTree>,
+}
+
+impl FeaturePanel<'_> {
+    /// `sketch_names` はスケッチのエンティティと表示名
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        sketch_names: &HashMap<Entity, String>,
+        history: &mut UndoHistory,
+    ) -> Option<FeatureAction> {
+        let mut action = None;
+        let mut request: Option<(usize, PanelRequest)> = None;
+        ui.label("フィーチャー");
+        egui::ScrollArea::vertical().id_source("feature_tree").max_height(240.0).show(ui, |ui| {
+            let tree = &*self.tree;
+            for (i, feature) in tree.features.iter().enumerate() {
+                if i == tree.rollback {
+                    rollback_bar(ui, i, &mut request);
+                }
+                ui.horizontal(|ui| {
+                    let name = match feature.kind {
+                        FeatureKind::Sketch(sketch) => sketch_names.get(&sketch).unwrap_or(&feature.name),
+                        _ => &feature.name,
+                    };
+                    let mut text = egui::RichText::new(name);
+                    if i >= tree.rollback || feature.suppressed {
+                        text = text.weak();
+                    }
+                    if feature.error.is_some() {
+                        text = text.color(egui::Color32::RED);
+                    }
+                    let response = ui.selectable_label(tree.selected == Some(feature.id), text);
+                    if response.clicked() {
+                        request = Some((i, PanelRequest::Select));
+                    }
+                    if let Some(error) = &feature.error {
+                        response.on_hover_text(error);
+                    }
+                    if ui.small_button("↑").clicked() {
+                        request = Some((i, PanelRequest::Move(true)));
+                    }
+                    if ui.small_button("↓").clicked() {
+                        request = Some((i, PanelRequest::Move(false)));
+                    }
+                    let mut suppressed = feature.suppressed;
+                    if ui.checkbox(&mut suppressed, "抑制").changed() {
+                        request = Some((i, PanelRequest::Suppress(suppressed)));
+                    }
+                    if ui.small_button("×").clicked() {
+                        request = Some((i, PanelRequest::Delete));
+                    }
+                });
+            }
+            if tree.rollback == tree.features.len() {
+                rollback_bar(ui, tree.features.len(), &mut request);
+            }
+        });
+
+        // 選択とメッセージは履歴の変更ではないので、再生成しないよう変更検出を迂回する
+        if let Some((i, request)) = request {
+            self.tree.bypass_change_detection().message.clear();
+            let name = self.tree.features.get(i).map(|f| f.name.clone()).unwrap_or_default();
+            let result = match request {
+                PanelRequest::Select => {
+                    let tree = self.tree.bypass_change_detection();
+                    tree.selected = Some(tree.features[i].id);
+                    Ok(None)
+                }
+                PanelRequest::Move(up) => self.tree.move_feature(i, up).map(|_| Some(format!("{name}を移動"))),
+                PanelRequest::Suppress(suppressed) => {
+                    self.tree.features[i].suppressed = suppressed;
+                    Ok(Some(format!("{name}を{}", if suppressed { "抑制" } else { "抑制解除" })))
+                }
+                PanelRequest::Delete => self.tree.remove(i).map(|feature| {
+                    if let FeatureKind::Sketch(sketch) = feature.kind {
+                        action = Some(FeatureAction::DeleteSketch(sketch));
+                    }
+                    Some(format!("{name}を削除"))
+                }),
+                PanelRequest::Rollback(position) => {
+                    self.tree.rollback = position.min(self.tree.features.len());
+                    Ok(Some("ロールバック".to_string()))
+                }
+            };
+            match result {
+                Ok(Some(label)) => history.record(label),
+                Ok(None) => {}
+                Err(e) => self.tree.bypass_change_detection().message = e,
+            }
+        }
+        if !self.tree.message.is_empty() {
+            ui.colored_label(egui::Color32::YELLOW, &self.tree.message);
+        }
+
+        // 選択中のフィーチャーのパラメータ
+        let Some(index) = self.tree.selected.and_then(|id| self.tree.index_of(id)) else {
+            return action;
+        };
+        ui.separator();
+        let feature = &self.tree.features[index];
+        if let Some(error) = &feature.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        let label = format!("{}を編集", feature.name);
+        let mut kind = match &feature.kind {
+            FeatureKind::Sketch(sketch) => {
+                if ui.button("スケッチを編集").clicked() {
+                    action = Some(FeatureAction::EditSketch(*sketch));
+                }
+                return action;
+            }
+            FeatureKind::BaseBody(_) => {
+                ui.label("履歴を持たないボディ");
+                return action;
+            }
+            kind => kind.clone(),
+        };
+        // 複製したパラメータを編集し、値が変わった時だけ書き換えて再生成する
+        if edit_parameters(ui, &mut kind, history, &label) {
+            self.tree.features[index].kind = kind;
+        }
+        action
+    }
+}
+
+/// フィーチャーのパラメータの入力欄。値が変わったかを返す
+fn edit_parameters(ui: &mut egui::Ui, kind: &mut FeatureKind, history: &mut UndoHistory, label: &str) -> bool {
+    let mut responses = Vec::new();
+    match kind {
+        FeatureKind::Extrude { distance, operation, .. } => {
+            responses.push(labeled(ui, "距離", egui::DragValue::new(distance).speed(0.1).suffix("m")));
+            responses.extend(selector(ui, operation, ExtrudeOperation::ALL.map(|op| (op, op.label()))));
+        }
+        FeatureKind::Revolve { angle, operation, .. } => {
+            let angle = egui::DragValue::new(angle).speed(1.0).clamp_range(0.0..=360.0).suffix("°");
+            responses.push(labeled(ui, "角度", angle));
+            responses.extend(selector(ui, operation, ExtrudeOperation::ALL.map(|op| (op, op.label()))));
+        }
+        FeatureKind::Sweep { orientation, twist, operation, .. } => {
+            responses.extend(selector(ui, orientation, SweepOrientation::ALL.map(|o| (o, o.label()))));
+            responses.push(labeled(ui, "ねじれ", egui::DragValue::new(twist).speed(1.0).suffix("°")));
+            responses.extend(selector(ui, operation, ExtrudeOperation::ALL.map(|op| (op, op.label()))));
+        }
+        FeatureKind::Loft { sections, auto_align, operation } => {
+            for (i, section) in sections.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("断面{}", i + 1));
+                    ui.label("開始位置");
+                    responses.push(ui.add(egui::DragValue::new(&mut section.start).speed(0.005).clamp_range(0.0..=1.0)));
+                });
+            }
+            responses.push(ui.checkbox(auto_align, "始点を自動で合わせる"));
+            responses.extend(selector(ui, operation, ExtrudeOperation::ALL.map(|op| (op, op.label()))));
+        }
+        FeatureKind::Combine { op, .. } => {
+            responses.extend(selector(ui, op, BooleanOp::ALL.map(|op| (op, op.label()))));
+        }
+        FeatureKind::Sketch(_) | FeatureKind::BaseBody(_) => {}
+    }
+    for response in &responses {
+        history.track_drag(response, label);
+    }
+    responses.iter().any(|response| response.changed())
+}
+
+/// 見出し付きの入力欄
+fn labeled(ui: &mut egui::Ui, text: &str, widget: impl egui::Widget) -> egui::Response {
+    ui.horizontal(|ui| {
+        ui.label(text);
+        ui.add(widget)
+    })
+    .inner
+}
+
+/// 選択肢を横に並べたボタン
+fn selector<T: PartialEq + Copy>(
+    ui: &mut egui::Ui,
+    value: &mut T,
+    options: impl IntoIterator<Item = (T, &'static str)>,
+) -> Vec<egui::Response> {
+    ui.horizontal(|ui| options.into_iter().map(|(option, text)| ui.selectable_value(value, option, text)).collect())
+        .inner
+}
+
+enum PanelRequest {
+    Select,
+    Move(bool),
+    Suppress(bool),
+    Delete,
+    Rollback(usize),
+}
+
+/// ロールバックバー。ボタンで1つ前・後ろへ移動する
+fn rollback_bar(ui: &mut egui::Ui, position: usize, request: &mut Option<(usize, PanelRequest)>) {
+    ui.horizontal(|ui| {
+        ui.colored_label(egui::Color32::LIGHT_BLUE, "── ロールバック ──");
+        if ui.small_button("▲").clicked() {
+            *request = Some((0, PanelRequest::Rollback(position.saturating_sub(1))));
+        }
+        if ui.small_button("▼").clicked() {
+            *request = Some((0, PanelRequest::Rollback(position + 1)));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::PlaneData;
+    use crate::{SketchCircle, SketchLine, SketchRectangle};
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.init_resource::<Assets<StandardMaterial>>();
+        world.init_resource::<BodySelection>();
+        world.init_resource::<FeatureTree>();
+        world
+    }
+
+    /// XZ平面の `(x0, z0)` から `(x1, z1)` までの四角形
+    fn rectangle(world: &mut World, sketch: Entity, (x0, z0): (f32, f32), (x1, z1): (f32, f32)) -> ProfileRef {
+        let p1 = Vec3::new(x0, 0.0, z0);
+        let p2 = Vec3::new(x1, 0.0, z1);
+        ProfileRef::Entity(world.spawn(SketchRectangle { p1, p2, angle: 0.0 }).set_parent(sketch).id())
+    }
+
+    fn extrude(profiles: Vec<ProfileRef>, sketch: Entity, operation: ExtrudeOperation) -> FeatureKind {
+        FeatureKind::Extrude { sketch, profiles, distance: 1.0, operation }
+    }
+
+    fn regenerate(world: &mut World) -> Vec<BodyRef> {
+        world.resource_mut::<FeatureTree>().dirty = true;
+        regenerate_system(world);
+        let tree = world.resource::<FeatureTree>();
+        assert!(tree.features.iter().all(|feature| feature.error.is_none()));
+        let mut bodies: Vec<BodyRef> = world.query::<&Body>().iter(world).map(|body| body.body).collect();
+        bodies.sort_by_key(|body| (body.feature.0, body.index));
+        bodies
+    }
+
+    #[test]
+    fn combine_removes_only_its_operands() {
+        let mut world = world();
+        let sketch = world.spawn(Sketch { name: "スケッチ1".to_string(), plane: default() }).id();
+        let profiles = vec![
+            rectangle(&mut world, sketch, (0.0, 0.0), (1.0, -1.0)),
+            rectangle(&mut world, sketch, (0.5, 0.0), (1.5, -1.0)),
+            rectangle(&mut world, sketch, (3.0, 0.0), (4.0, -1.0)),
+        ];
+        let mut tree = world.resource_mut::<FeatureTree>();
+        tree.insert("スケッチ1".to_string(), FeatureKind::Sketch(sketch));
+        let boxes = tree.insert("押し出し1".to_string(), extrude(profiles, sketch, ExtrudeOperation::NewBody));
+        let body = |index| BodyRef { feature: boxes, index };
+        let combine = FeatureKind::Combine { target: body(0), tool: body(1), op: BooleanOp::Union };
+        let combined = tree.insert("結合1".to_string(), combine);
+
+        assert_eq!(regenerate(&mut world), vec![body(2), BodyRef { feature: combined, index: 0 }]);
+    }
+
+    #[test]
+    fn failed_combine_reports_error_and_keeps_operands() {
+        let mut world = world();
+        let sketch = world.spawn(Sketch { name: "スケッチ1".to_string(), plane: default() }).id();
+        let profiles = vec![
+            rectangle(&mut world, sketch, (0.0, 0.0), (1.0, -1.0)),
+            rectangle(&mut world, sketch, (3.0, 0.0), (4.0, -1.0)),
+        ];
+        let mut tree = world.resource_mut::<FeatureTree>();
+        tree.insert("スケッチ1".to_string(), FeatureKind::Sketch(sketch));
+        let boxes = tree.insert("押し出し1".to_string(), extrude(profiles, sketch, ExtrudeOperation::NewBody));
+        let body = |index| BodyRef { feature: boxes, index };
+        // 離れた2つの箱の積は空になる
+        let combine = FeatureKind::Combine { target: body(0), tool: body(1), op: BooleanOp::Intersection };
+        tree.insert("結合1".to_string(), combine);
+        tree.dirty = true;
+        regenerate_system(&mut world);
+
+        assert!(world.resource::<FeatureTree>().features[2].error.is_some());
+        let mut bodies: Vec<BodyRef> = world.query::<&Body>().iter(&world).map(|body| body.body).collect();
+        bodies.sort_by_key(|body| body.index);
+        assert_eq!(bodies, vec![body(0), body(1)]);
+    }
+
+    #[test]
+    fn cut_depends_on_the_body_it_modifies() {
+        let mut world = world();
+        let sketch = world.spawn(Sketch { name: "スケッチ1".to_string(), plane: default() }).id();
+        let first = rectangle(&mut world, sketch, (0.0, 0.0), (1.0, -1.0));
+        let second = rectangle(&mut world, sketch, (3.0, 0.0), (4.0, -1.0));
+        let hole = world.spawn(SketchCircle { center: Vec3::new(0.5, 0.0, -0.5), radius: 0.25 }).set_parent(sketch).id();
+        let mut tree = world.resource_mut::<FeatureTree>();
+        tree.insert("スケッチ1".to_string(), FeatureKind::Sketch(sketch));
+        let base = tree.insert("押し出し1".to_string(), extrude(vec![first], sketch, ExtrudeOperation::NewBody));
+        tree.insert("押し出し2".to_string(), extrude(vec![second], sketch, ExtrudeOperation::NewBody));
+        let cut = extrude(vec![ProfileRef::Entity(hole)], sketch, ExtrudeOperation::Cut);
+        tree.insert("押し出し3".to_string(), cut);
+        regenerate(&mut world);
+
+        let mut tree = world.resource_mut::<FeatureTree>();
+        assert_eq!(tree.features[3].targets, vec![base]);
+        // 関係のないボディの前には移動できるが、カットするボディの前には移動できない
+        assert!(tree.move_feature(3, true).is_ok());
+        assert!(tree.move_feature(2, true).is_err());
+        assert!(tree.remove(1).is_err());
+    }
+
+    #[test]
+    fn sweeps_along_a_path_from_another_sketch() {
+        let mut world = world();
+        let profile_sketch = world.spawn(Sketch { name: "スケッチ1".to_string(), plane: default() }).id();
+        let path_sketch = world.spawn(Sketch { name: "スケッチ2".to_string(), plane: default() }).id();
+        let circle = world.spawn(SketchCircle { center: Vec3::ZERO, radius: 0.2 }).set_parent(profile_sketch).id();
+        let line = world.spawn(SketchLine { p1: Vec3::ZERO, p2: Vec3::new(3.0, 0.0, 0.0) }).set_parent(path_sketch).id();
+        let mut tree = world.resource_mut::<FeatureTree>();
+        let first = tree.insert("スケッチ1".to_string(), FeatureKind::Sketch(profile_sketch));
+        let second = tree.insert("スケッチ2".to_string(), FeatureKind::Sketch(path_sketch));
+        tree.insert(
+            "スイープ1".to_string(),
+            FeatureKind::Sweep {
+                sketch: profile_sketch,
+                profiles: vec![ProfileRef::Entity(circle)],
+                path: vec![line],
+                path_sketch,
+                orientation: SweepOrientation::FollowPath,
+                twist: 0.0,
+                operation: ExtrudeOperation::NewBody,
+            },
+        );
+        assert_eq!(regenerate(&mut world).len(), 1);
+        let tree = world.resource::<FeatureTree>();
+        assert_eq!(tree.dependencies(&tree.features[2]), vec![first, second]);
+        let aabb = world.query::<&Brep>().single(&world).tessellate().compute_aabb().unwrap();
+        assert!((aabb.max().x - aabb.min().x - 3.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn lofts_between_sections_on_their_sketch_planes() {
+        let mut world = world();
+        let mut plane = PlaneData::default();
+        let bottom_sketch = world.spawn(Sketch { name: "スケッチ1".to_string(), plane }).id();
+        plane.set_offset(2.0);
+        let top_sketch = world.spawn(Sketch { name: "スケッチ2".to_string(), plane }).id();
+        let bottom = rectangle(&mut world, bottom_sketch, (0.0, 0.0), (1.0, -1.0));
+        let top = rectangle(&mut world, top_sketch, (0.0, 0.0), (1.0, -1.0));
+        let sections = vec![
+            LoftSection { sketch: bottom_sketch, profile: bottom, start: 0.0 },
+            LoftSection { sketch: top_sketch, profile: top, start: 0.0 },
+        ];
+        let mut tree = world.resource_mut::<FeatureTree>();
+        tree.insert("スケッチ1".to_string(), FeatureKind::Sketch(bottom_sketch));
+        tree.insert("スケッチ2".to_string(), FeatureKind::Sketch(top_sketch));
+        let loft = FeatureKind::Loft { sections, auto_align: true, operation: ExtrudeOperation::NewBody };
+        tree.insert("ロフト1".to_string(), loft);
+        assert_eq!(regenerate(&mut world).len(), 1);
+        let aabb = world.query::<&Brep>().single(&world).tessellate().compute_aabb().unwrap();
+        assert!(aabb.min().y.abs() < 1e-5 && (aabb.max().y - 2.0).abs() < 1e-5);
+
+        // スケッチ平面を動かすと、そのスケッチからロフトを作り直す
+        world.get_mut::<Sketch>(top_sketch).unwrap().plane.set_offset(3.0);
+        let sketches = gather_sketches(&mut world);
+        assert_eq!(world.resource::<FeatureTree>().first_changed(&sketches), 1);
+        regenerate(&mut world);
+        let aabb = world.query::<&Brep>().single(&world).tessellate().compute_aabb().unwrap();
+        assert!((aabb.max().y - 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn regenerates_from_the_changed_feature() {
+        let mut world = world();
+        let first_sketch = world.spawn(Sketch { name: "スケッチ1".to_string(), plane: default() }).id();
+        let second_sketch = world.spawn(Sketch { name: "スケッチ2".to_string(), plane: default() }).id();
+        let first = rectangle(&mut world, first_sketch, (0.0, 0.0), (1.0, -1.0));
+        let second = rectangle(&mut world, second_sketch, (3.0, 0.0), (4.0, -1.0));
+        let mut tree = world.resource_mut::<FeatureTree>();
+        tree.insert("スケッチ1".to_string(), FeatureKind::Sketch(first_sketch));
+        tree.insert("押し出し1".to_string(), extrude(vec![first], first_sketch, ExtrudeOperation::NewBody));
+        tree.insert("スケッチ2".to_string(), FeatureKind::Sketch(second_sketch));
+        tree.insert("押し出し2".to_string(), extrude(vec![second.clone()], second_sketch, ExtrudeOperation::NewBody));
+        regenerate(&mut world);
+
+        let sketches = gather_sketches(&mut world);
+        assert_eq!(world.resource::<FeatureTree>().first_changed(&sketches), 4);
+        // 2つ目のスケッチを変えると、そのスケッチのフィーチャーから実行し直す
+        let ProfileRef::Entity(entity) = second else { unreachable!() };
+        world.get_mut::<SketchRectangle>(entity).unwrap().p2.x = 5.0;
+        let sketches = gather_sketches(&mut world);
+        assert_eq!(world.resource::<FeatureTree>().first_changed(&sketches), 2);
+        // パラメータを変えたフィーチャーから実行し直す
+        if let FeatureKind::Extrude { distance, .. } = &mut world.resource_mut::<FeatureTree>().features[1].kind {
+            *distance = 2.0;
+        }
+        assert_eq!(world.resource::<FeatureTree>().first_changed(&sketches), 1);
+        assert_eq!(regenerate(&mut world).len(), 2);
+    }
+}
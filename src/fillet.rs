@@ -0,0 +1,431 @@
+//! スケッチの角の丸め (フィレット) と面取り
+//!
+//! 2本の直線の端点が重なる角をクリックすると、両方の直線を角から縮め、間を円弧か直線でつなぐ。
+//! 四角形の角は、四角形を拘束付きの4本の直線に分解してから処理する。角の点はなくなるので、
+//! 角の点を参照していた拘束と寸法は削除する。
+
+use std::f32::consts::PI;
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_egui::EguiContexts;
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::constraints::{ConstraintKind, PointKind, PointRef, SketchConstraint};
+use crate::dimensions::SketchDimension;
+use crate::geometry::{arc_from_points, Curve2d, SketchFrame};
+use crate::history::UndoHistory;
+use crate::pattern::SketchCopy;
+use crate::profile::{self, SketchShape};
+use crate::trim::{remap_point_refs, replace_entity, Piece};
+use crate::{screen_to_world, ActiveSketch, ActiveSketchTool, Construction, SketchArc, SketchData, SketchLine};
+
+/// 角を選ぶ許容範囲
+const PICK_TOLERANCE: f32 = 0.15;
+/// 端点が重なっているとみなす距離
+const POINT_TOLERANCE: f32 = 1e-3;
+
+/// 面取りの寸法の指定方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChamferMode {
+    /// 角から両方の直線に沿って測った距離
+    #[default]
+    DistanceDistance,
+    /// 1本目の直線に沿った距離と、1本目の直線から測った角度
+    DistanceAngle,
+}
+
+/// クリックした角
+struct Corner {
+    point: Vec2,
+    /// 角から出る2本の直線の反対側の端点。1本目はクリックした位置に近い方
+    others: [Vec2; 2],
+    source: CornerSource,
+}
+
+enum CornerSource {
+    /// 2本の直線の角の側の端点
+    Lines([PointRef; 2]),
+    /// 四角形と角のインデックス
+    Rectangle(Entity, usize),
+}
+
+/// 角を置き換える円弧か直線と、縮めた2本の直線の新しい端点
+struct CornerCut {
+    ends: [Vec2; 2],
+    bridge: Curve2d,
+}
+
+/// フィレット・面取りツールの入力を処理するシステム
+pub fn corner_edit_system(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut gizmos: Gizmos,
+    mut sketch_data: ResMut<SketchData>,
+    active_tool: Res<ActiveSketchTool>,
+    active_sketch: Res<ActiveSketch>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    q_shapes: Query<(Entity, SketchShape, &Parent, Has<Construction>)>,
+    q_constraints: Query<(Entity, &SketchConstraint)>,
+    q_dimensions: Query<(Entity, &SketchDimension)>,
+    mut history: ResMut<UndoHistory>,
+) {
+    if contexts.ctx_mut().is_using_pointer() {
+        return;
+    }
+    let Some(sketch) = active_sketch.0 else {
+        return;
+    };
+    let window = q_window.single();
+    let (camera, camera_transform) = q_camera.single();
+    let Some(world_pos) = screen_to_world(window, camera, camera_transform) else {
+        return;
+    };
+    let frame = SketchFrame::default();
+    let mouse = frame.to_local(world_pos);
+    let clicked = mouse_buttons.just_pressed(MouseButton::Left);
+
+    let mut lines = Vec::new();
+    let mut rectangles = Vec::new();
+    for (entity, shape, parent, _) in q_shapes.iter() {
+        if parent.get() != sketch {
+            continue;
+        }
+        if let Some(line) = shape.0 {
+            lines.push((entity, frame.to_local(line.p1), frame.to_local(line.p2)));
+        } else if shape.2.is_some() {
+            rectangles.push((entity, profile::entity_curves(&frame, shape)));
+        }
+    }
+
+    let Some(corner) = find_corner(&lines, &rectangles, mouse) else {
+        if clicked {
+            sketch_data.message = "2本の直線が接する角をクリックしてください".to_string();
+        }
+        return;
+    };
+    let cut = if *active_tool == ActiveSketchTool::Fillet {
+        fillet(&corner, sketch_data.fillet_radius)
+    } else {
+        let distance = sketch_data.chamfer_distance;
+        match sketch_data.chamfer_mode {
+            ChamferMode::DistanceDistance => chamfer(&corner, distance, sketch_data.chamfer_distance2),
+            ChamferMode::DistanceAngle => chamfer_angle(&corner, distance, sketch_data.chamfer_angle.to_radians()),
+        }
+    };
+
+    gizmos.circle(frame.to_world(corner.point), Direction3d::Y, 0.06, Color::YELLOW);
+    if let Ok(cut) = &cut {
+        gizmos.linestrip(cut.bridge.tessellate().into_iter().map(|p| frame.to_world(p)), Color::YELLOW);
+    }
+    if !clicked {
+        return;
+    }
+    let cut = match cut {
+        Ok(cut) => cut,
+        Err(message) => {
+            sketch_data.message = message.to_string();
+            return;
+        }
+    };
+    sketch_data.message.clear();
+
+    // 角で接する2本の直線の (エンティティ, 角の側の端点, 直線の両端)
+    let construction = |entity| q_shapes.get(entity).is_ok_and(|(.., construction)| construction);
+    let (ends, rectangle) = match corner.source {
+        CornerSource::Lines(refs) => {
+            let end = |p: PointRef| {
+                let &(_, a, b) = lines.iter().find(|(entity, ..)| *entity == p.entity).expect("角の直線");
+                (p, a, b)
+            };
+            ([end(refs[0]), end(refs[1])], None)
+        }
+        CornerSource::Rectangle(entity, k) => {
+            let Some((_, curves)) = rectangles.iter().find(|(e, _)| *e == entity) else {
+                return;
+            };
+            let edges = explode_rectangle(&mut commands, &frame, sketch, (entity, k), construction(entity), curves, &q_shapes);
+            let line_end = |edge: usize, kind| {
+                let Curve2d::Line { a, b } = curves[edge] else {
+                    unreachable!("四角形の辺は直線");
+                };
+                (PointRef { entity: edges[edge], kind }, a, b)
+            };
+            let incoming = line_end((k + 3) % 4, PointKind::End);
+            let outgoing = line_end(k, PointKind::Start);
+            // 1本目はクリックした位置に近い方の辺
+            let ends = if corner.others[0] == incoming.1 { [incoming, outgoing] } else { [outgoing, incoming] };
+            (ends, Some((entity, edges)))
+        }
+    };
+
+    for ((point, a, b), new_end) in ends.iter().zip(cut.ends) {
+        let (a, b) = if point.kind == PointKind::Start { (new_end, *b) } else { (*a, new_end) };
+        let line = SketchLine { p1: frame.to_world(a), p2: frame.to_world(b) };
+        commands.entity(point.entity).insert(line).remove::<SketchCopy>();
+    }
+
+    // 角の点はなくなる。四角形の他の角は分解した辺の始点に付け替える
+    let corner_refs = [ends[0].0, ends[1].0];
+    remap_point_refs(&mut commands, &q_constraints, &q_dimensions, |p| {
+        if corner_refs.contains(&p) {
+            return None;
+        }
+        match (rectangle.as_ref(), p.kind) {
+            (Some((entity, edges)), PointKind::Corner(j)) if p.entity == *entity => {
+                Some(PointRef { entity: edges[j as usize], kind: PointKind::Start })
+            }
+            _ => Some(p),
+        }
+    });
+
+    // 円弧は反時計回りに向きをそろえるので、始点がどちらの直線の側になるかは作った後の位置で決める
+    let mut bridge = commands.spawn_empty();
+    bridge.set_parent(sketch);
+    let mut bridge_start = cut.ends[0];
+    match cut.bridge {
+        Curve2d::Line { a, b } => {
+            bridge.insert(SketchLine { p1: frame.to_world(a), p2: frame.to_world(b) });
+        }
+        ref arc => {
+            if let Some(arc) = SketchArc::from_curve(&frame, arc) {
+                bridge_start = frame.to_local(arc.start);
+                bridge.insert(arc);
+            }
+        }
+    }
+    if ends.iter().all(|(point, ..)| construction(point.entity)) || rectangle.is_some_and(|(entity, _)| construction(entity)) {
+        bridge.insert(Construction);
+    }
+    let bridge = bridge.id();
+
+    // 縮めた直線の端とつなぐ図形の端を一致させ、フィレットでは直線と円弧を接させる
+    let reversed = bridge_start.distance(cut.ends[1]) < bridge_start.distance(cut.ends[0]);
+    let bridge_ends = if reversed { [PointKind::End, PointKind::Start] } else { [PointKind::Start, PointKind::End] };
+    let mut constraints: Vec<ConstraintKind> = ends
+        .iter()
+        .zip(bridge_ends)
+        .map(|((point, ..), kind)| ConstraintKind::Coincident(*point, PointRef { entity: bridge, kind }))
+        .collect();
+    if *active_tool == ActiveSketchTool::Fillet {
+        constraints.extend(ends.iter().map(|(point, ..)| ConstraintKind::Tangent(point.entity, bridge)));
+    }
+    for kind in constraints {
+        commands.spawn(SketchConstraint(kind)).set_parent(sketch);
+    }
+    history.record(if *active_tool == ActiveSketchTool::Fillet { "フィレット" } else { "面取り" });
+}
+
+/// マウスに最も近い、2本の直線の端点が重なる角か四角形の角
+fn find_corner(lines: &[(Entity, Vec2, Vec2)], rectangles: &[(Entity, Vec<Curve2d>)], mouse: Vec2) -> Option<Corner> {
+    let line_corner = lines
+        .iter()
+        .flat_map(|&(entity, a, b)| [(entity, a), (entity, b)])
+        .map(|(_, p)| p)
+        .filter(|p| p.distance(mouse) < PICK_TOLERANCE)
+        .min_by(|p, q| p.distance(mouse).total_cmp(&q.distance(mouse)));
+    let rectangle_corner = rectangles
+        .iter()
+        .flat_map(|(entity, curves)| curves.iter().enumerate().map(move |(k, curve)| (*entity, k, curve.start())))
+        .filter(|(.., p)| p.distance(mouse) < PICK_TOLERANCE)
+        .min_by(|x, y| x.2.distance(mouse).total_cmp(&y.2.distance(mouse)));
+
+    let order = |first: Vec2, second: Vec2, point: Vec2| {
+        let distance = |other: Vec2| Curve2d::Line { a: point, b: other }.distance(mouse);
+        if distance(first) <= distance(second) { [first, second] } else { [second, first] }
+    };
+
+    match (line_corner, rectangle_corner) {
+        (_, Some((entity, k, point))) if !line_corner.is_some_and(|p| p.distance(mouse) < point.distance(mouse)) => {
+            let curves = &rectangles.iter().find(|(e, _)| *e == entity)?.1;
+            let (previous, next) = (curves[(k + 3) % 4].start(), curves[k].end());
+            Some(Corner { point, others: order(previous, next, point), source: CornerSource::Rectangle(entity, k) })
+        }
+        (Some(point), _) => {
+            let ends: Vec<(PointRef, Vec2)> = lines
+                .iter()
+                .flat_map(|&(entity, a, b)| {
+                    [(PointRef { entity, kind: PointKind::Start }, a, b), (PointRef { entity, kind: PointKind::End }, b, a)]
+                })
+                .filter(|(_, p, _)| p.distance(point) < POINT_TOLERANCE)
+                .map(|(p, _, other)| (p, other))
+                .collect();
+            let [(ref_a, other_a), (ref_b, other_b)] = ends[..] else {
+                return None;
+            };
+            if ref_a.entity == ref_b.entity {
+                return None;
+            }
+            let others = order(other_a, other_b, point);
+            let refs = if others[0] == other_a { [ref_a, ref_b] } else { [ref_b, ref_a] };
+            Some(Corner { point, others, source: CornerSource::Lines(refs) })
+        }
+        _ => None,
+    }
+}
+
+/// 角から2本の直線に沿った単位ベクトルと、2本の直線のなす角。直線が重なっていれば `None`
+fn corner_directions(corner: &Corner) -> Option<([Vec2; 2], f32)> {
+    let directions = corner.others.map(|other| (other - corner.point).normalize_or_zero());
+    let angle = directions[0].dot(directions[1]).clamp(-1.0, 1.0).acos();
+    (directions.iter().all(|d| *d != Vec2::ZERO) && angle > 1e-3 && angle < PI - 1e-3).then_some((directions, angle))
+}
+
+/// 直線を角から `distances` だけ縮めた点。直線より長ければ `None`
+fn trimmed_ends(corner: &Corner, directions: [Vec2; 2], distances: [f32; 2]) -> Option<[Vec2; 2]> {
+    let fits = (0..2).all(|i| distances[i] <= corner.point.distance(corner.others[i]) + POINT_TOLERANCE);
+    fits.then(|| [0, 1].map(|i| corner.point + directions[i] * distances[i]))
+}
+
+/// 半径 `radius` の円弧で角を丸める
+fn fillet(corner: &Corner, radius: f32) -> Result<CornerCut, &'static str> {
+    if radius <= 0.0 {
+        return Err("半径は正の値にしてください");
+    }
+    let (directions, angle) = corner_directions(corner).ok_or("一直線に並んだ直線の角は丸められません")?;
+    let distance = radius / (angle / 2.0).tan();
+    let ends = trimmed_ends(corner, directions, [distance; 2]).ok_or("半径が大きすぎます")?;
+    let center = corner.point + (directions[0] + directions[1]).normalize() * radius / (angle / 2.0).sin();
+    // 短い方の円弧でつなぐ
+    let mut arc = arc_from_points(center, ends[0], ends[1]);
+    if let Curve2d::Arc { sweep, .. } = arc {
+        if sweep > PI {
+            arc = arc_from_points(center, ends[1], ends[0]);
+        }
+    }
+    Ok(CornerCut { ends, bridge: arc })
+}
+
+/// 角から2本の直線に沿ってそれぞれ `distance1` と `distance2` の点を結んで面取りする
+fn chamfer(corner: &Corner, distance1: f32, distance2: f32) -> Result<CornerCut, &'static str> {
+    if distance1 <= 0.0 || distance2 <= 0.0 {
+        return Err("面取りの距離は正の値にしてください");
+    }
+    let (directions, _) = corner_directions(corner).ok_or("一直線に並んだ直線の角は面取りできません")?;
+    let ends = trimmed_ends(corner, directions, [distance1, distance2]).ok_or("面取りの距離が大きすぎます")?;
+    Ok(CornerCut { ends, bridge: Curve2d::Line { a: ends[0], b: ends[1] } })
+}
+
+/// 1本目の直線に沿った `distance` の点から、1本目の直線と `angle` (ラジアン) をなす直線で面取りする
+fn chamfer_angle(corner: &Corner, distance: f32, angle: f32) -> Result<CornerCut, &'static str> {
+    let (_, corner_angle) = corner_directions(corner).ok_or("一直線に並んだ直線の角は面取りできません")?;
+    // 角・1本目の点・2本目の点の三角形に正弦定理を使う
+    if angle <= 0.0 || angle + corner_angle >= PI {
+        return Err("面取りの角度が範囲外です");
+    }
+    chamfer(corner, distance, distance * angle.sin() / (angle + corner_angle).sin())
+}
+
+/// 四角形を4本の直線に分解し、形を保つ拘束を加える。辺のエンティティを `curves` の順に返す。
+/// 分解しても角の一致と辺の向きの拘束で四角形のまま動く。丸める角 `open_corner` には一致の拘束を加えない
+fn explode_rectangle(
+    commands: &mut Commands,
+    frame: &SketchFrame,
+    sketch: Entity,
+    (entity, open_corner): (Entity, usize),
+    construction: bool,
+    curves: &[Curve2d],
+    q_shapes: &Query<(Entity, SketchShape, &Parent, Has<Construction>)>,
+) -> Vec<Entity> {
+    let pieces: Vec<Piece> = (0..curves.len()).map(|curve| Piece { curve, t0: 0.0, t1: 1.0 }).collect();
+    let edges = replace_entity(commands, frame, sketch, entity, construction, curves, &pieces);
+    let axis_aligned = q_shapes.get(entity).ok().and_then(|(_, shape, ..)| shape.2).is_some_and(|rect| rect.angle == 0.0);
+
+    let mut constraints: Vec<ConstraintKind> = (0..4)
+        .filter(|&k| k != open_corner)
+        .map(|k| {
+            ConstraintKind::Coincident(
+                PointRef { entity: edges[(k + 3) % 4], kind: PointKind::End },
+                PointRef { entity: edges[k], kind: PointKind::Start },
+            )
+        })
+        .collect();
+    if axis_aligned {
+        // 最初の辺はZ軸に沿う
+        constraints.extend([
+            ConstraintKind::Vertical(edges[0]),
+            ConstraintKind::Horizontal(edges[1]),
+            ConstraintKind::Vertical(edges[2]),
+            ConstraintKind::Horizontal(edges[3]),
+        ]);
+    } else {
+        constraints.extend([
+            ConstraintKind::Perpendicular(edges[0], edges[1]),
+            ConstraintKind::Parallel(edges[0], edges[2]),
+            ConstraintKind::Parallel(edges[1], edges[3]),
+        ]);
+    }
+    for kind in constraints {
+        commands.spawn(SketchConstraint(kind)).set_parent(sketch);
+    }
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (0, 0) から (4, 2) までの四角形の辺
+    fn rectangle() -> Vec<Curve2d> {
+        let corners = [Vec2::ZERO, Vec2::new(4.0, 0.0), Vec2::new(4.0, 2.0), Vec2::new(0.0, 2.0)];
+        (0..4).map(|i| Curve2d::Line { a: corners[i], b: corners[(i + 1) % 4] }).collect()
+    }
+
+    /// 下の辺の近くをクリックした、原点の角
+    fn rectangle_corner() -> Corner {
+        let entity = Entity::from_raw(1);
+        let corner = find_corner(&[], &[(entity, rectangle())], Vec2::new(0.05, 0.01)).unwrap();
+        assert!(matches!(corner.source, CornerSource::Rectangle(e, 0) if e == entity));
+        assert_eq!(corner.point, Vec2::ZERO);
+        assert_eq!(corner.others, [Vec2::new(4.0, 0.0), Vec2::new(0.0, 2.0)]);
+        corner
+    }
+
+    #[test]
+    fn fillets_rectangle_corner() {
+        let corner = rectangle_corner();
+        let cut = fillet(&corner, 1.0).unwrap();
+        assert!(cut.ends[0].distance(Vec2::new(1.0, 0.0)) < 1e-5 && cut.ends[1].distance(Vec2::new(0.0, 1.0)) < 1e-5);
+        let Curve2d::Arc { center, radius, sweep, .. } = cut.bridge else {
+            panic!("円弧になっていない");
+        };
+        assert!(center.distance(Vec2::ONE) < 1e-5 && (radius - 1.0).abs() < 1e-5);
+        assert!((sweep.abs() - PI / 2.0).abs() < 1e-4);
+        // 円弧の端は縮めた直線の端につながる
+        let arc_ends = [cut.bridge.start(), cut.bridge.end()];
+        assert!(cut.ends.iter().all(|end| arc_ends.iter().any(|p| p.distance(*end) < 1e-4)));
+
+        // 短い辺 (長さ2) より大きい半径では丸められない
+        assert!(matches!(fillet(&corner, 2.5), Err("半径が大きすぎます")));
+        assert!(fillet(&corner, 0.0).is_err());
+    }
+
+    #[test]
+    fn chamfers_rectangle_corner() {
+        let corner = rectangle_corner();
+        let cut = chamfer(&corner, 1.0, 0.5).unwrap();
+        assert!(cut.ends[0].distance(Vec2::new(1.0, 0.0)) < 1e-5 && cut.ends[1].distance(Vec2::new(0.0, 0.5)) < 1e-5);
+        assert!(matches!(cut.bridge, Curve2d::Line { a, b } if a == cut.ends[0] && b == cut.ends[1]));
+        assert!(chamfer(&corner, 1.0, 3.0).is_err());
+
+        // 直角の角を45°で面取りすると両側の距離が等しくなる
+        let cut = chamfer_angle(&corner, 1.0, PI / 4.0).unwrap();
+        assert!(cut.ends[1].distance(Vec2::new(0.0, 1.0)) < 1e-5);
+        assert!(chamfer_angle(&corner, 1.0, PI / 2.0).is_err());
+    }
+
+    #[test]
+    fn finds_corner_between_two_lines() {
+        let (a, b) = (Entity::from_raw(1), Entity::from_raw(2));
+        let lines = [(a, Vec2::new(3.0, 0.0), Vec2::ZERO), (b, Vec2::ZERO, Vec2::new(0.0, 3.0))];
+        let corner = find_corner(&lines, &[], Vec2::new(0.01, 0.05)).unwrap();
+        assert_eq!(corner.others, [Vec2::new(0.0, 3.0), Vec2::new(3.0, 0.0)]);
+        let CornerSource::Lines(refs) = corner.source else {
+            panic!("直線の角になっていない");
+        };
+        assert_eq!(refs, [PointRef { entity: b, kind: PointKind::Start }, PointRef { entity: a, kind: PointKind::End }]);
+        // 離れた端点は角にならない
+        assert!(find_corner(&lines, &[], Vec2::new(1.0, 1.0)).is_none());
+    }
+}
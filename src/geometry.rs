@@ -0,0 +1,666 @@
+//! スケッチ平面上の2D幾何の共通処理
+
+use std::f32::consts::TAU;
+use std::ops::{Add, Mul, Sub};
+
+use bevy::prelude::*;
+
+use crate::solver;
+
+/// スケッチ平面の座標系。ワールド座標とスケッチ上の2D座標を相互に変換する
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SketchFrame {
+    pub origin: Vec3,
+    /// スケッチのx軸方向
+    pub u: Vec3,
+    /// スケッチのy軸方向
+    pub v: Vec3,
+}
+
+impl SketchFrame {
+    /// 真上から見たXZ平面。スケッチのx軸はワールドのX、y軸は画面上方向の-Z
+    pub const XZ: Self = Self { origin: Vec3::ZERO, u: Vec3::X, v: Vec3::NEG_Z };
+
+    pub fn to_local(self, p: Vec3) -> Vec2 {
+        let d = p - self.origin;
+        Vec2::new(d.dot(self.u), d.dot(self.v))
+    }
+
+    pub fn to_world(self, p: Vec2) -> Vec3 {
+        self.origin + self.u * p.x + self.v * p.y
+    }
+}
+
+impl Default for SketchFrame {
+    fn default() -> Self {
+        Self::XZ
+    }
+}
+
+/// スケッチ平面上の相似変換。`mirror` なら先にx軸で反転し、`scale` 倍して `angle` (ラジアン) だけ
+/// 反時計回りに回してから `translation` だけ動かす
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Similarity {
+    pub mirror: bool,
+    pub scale: f32,
+    pub angle: f32,
+    pub translation: Vec2,
+}
+
+impl Similarity {
+    pub const IDENTITY: Self = Self { mirror: false, scale: 1.0, angle: 0.0, translation: Vec2::ZERO };
+
+    pub fn translation(offset: Vec2) -> Self {
+        Self { translation: offset, ..Self::IDENTITY }
+    }
+
+    /// `center` のまわりに `angle` だけ反時計回りに回す
+    pub fn rotation(center: Vec2, angle: f32) -> Self {
+        Self { angle, translation: center - Vec2::from_angle(angle).rotate(center), ..Self::IDENTITY }
+    }
+
+    /// `center` を中心に `scale` 倍する
+    pub fn scaling(center: Vec2, scale: f32) -> Self {
+        Self { scale, translation: center - center * scale, ..Self::IDENTITY }
+    }
+
+    /// `a` と `b` を通る直線を軸に反転する
+    pub fn reflection(a: Vec2, b: Vec2) -> Self {
+        let angle = 2.0 * (b - a).to_angle();
+        let reflected = Self { mirror: true, angle, ..Self::IDENTITY };
+        Self { translation: a - reflected.point(a), ..reflected }
+    }
+
+    pub fn point(&self, p: Vec2) -> Vec2 {
+        let p = if self.mirror { Vec2::new(p.x, -p.y) } else { p };
+        Vec2::from_angle(self.angle).rotate(p * self.scale) + self.translation
+    }
+
+    /// x軸から反時計回りに測った向きの角度を変換する
+    pub fn direction_angle(&self, angle: f32) -> f32 {
+        if self.mirror { self.angle - angle } else { self.angle + angle }
+    }
+}
+
+/// 円の分割数
+const SEGMENTS_PER_TURN: usize = 64;
+
+/// スプラインの1区間 (ノットの間) の分割数
+const SEGMENTS_PER_SPAN: usize = 16;
+
+/// 同じ位置とみなすノットの差
+const KNOT_TOLERANCE: f32 = 1e-6;
+
+/// スプラインの制御点に使える点の型
+pub trait SplinePoint: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self> {}
+
+impl<T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>> SplinePoint for T {}
+
+/// Bスプライン曲線。パラメータは0から1で、両端のノットを次数+1個重ねて曲線が最初と最後の制御点を通るようにする
+#[derive(Debug, Clone, PartialEq)]
+pub struct BSpline<P = Vec2> {
+    pub control_points: Vec<P>,
+    pub knots: Vec<f32>,
+}
+
+impl<P: SplinePoint> BSpline<P> {
+    /// 制御点から等間隔のノットで作る。次数は3で、制御点が少なければそれに合わせて下げる
+    pub fn clamped(control_points: Vec<P>) -> Self {
+        let n = control_points.len();
+        let degree = n.saturating_sub(1).min(3);
+        let spans = n - degree;
+        let knots = (0..=degree)
+            .map(|_| 0.0)
+            .chain((1..spans).map(|i| i as f32 / spans as f32))
+            .chain((0..=degree).map(|_| 1.0))
+            .collect();
+        Self { control_points, knots }
+    }
+
+    pub fn degree(&self) -> usize {
+        self.knots.len() - self.control_points.len() - 1
+    }
+
+    /// `t` を含むノット区間の番号
+    fn span(&self, t: f32) -> usize {
+        let (n, p) = (self.control_points.len(), self.degree());
+        (p..n).rev().find(|&i| self.knots[i] <= t && self.knots[i] < self.knots[i + 1]).unwrap_or(p)
+    }
+
+    /// `t` を含むノット区間と、その区間で0でない基底関数の値 (The NURBS Book A2.2)
+    fn basis(&self, t: f32) -> (usize, Vec<f32>) {
+        let (n, p) = (self.control_points.len(), self.degree());
+        let t = t.clamp(self.knots[p], self.knots[n]);
+        let span = self.span(t);
+        let mut values = vec![0.0; p + 1];
+        let (mut left, mut right) = (vec![0.0; p + 1], vec![0.0; p + 1]);
+        values[0] = 1.0;
+        for j in 1..=p {
+            left[j] = t - self.knots[span + 1 - j];
+            right[j] = self.knots[span + j] - t;
+            let mut saved = 0.0;
+            for r in 0..j {
+                let temp = values[r] / (right[r + 1] + left[j - r]);
+                values[r] = saved + right[r + 1] * temp;
+                saved = left[j - r] * temp;
+            }
+            values[j] = saved;
+        }
+        (span, values)
+    }
+
+    pub fn point_at(&self, t: f32) -> P {
+        let (span, values) = self.basis(t);
+        let p = self.degree();
+        let first = self.control_points[span - p] * values[0];
+        (1..=p).fold(first, |sum, j| sum + self.control_points[span - p + j] * values[j])
+    }
+
+    /// 導関数の曲線。次数が0の場合は常に0になる曲線
+    pub fn derivative(&self) -> Self {
+        let p = self.degree();
+        if p == 0 {
+            return Self { control_points: vec![self.control_points[0] * 0.0], knots: vec![0.0, 1.0] };
+        }
+        let control_points = (0..self.control_points.len() - 1)
+            .map(|i| {
+                let span = self.knots[i + p + 1] - self.knots[i + 1];
+                let scale = if span > 0.0 { p as f32 / span } else { 0.0 };
+                (self.control_points[i + 1] - self.control_points[i]) * scale
+            })
+            .collect();
+        Self { control_points, knots: self.knots[1..self.knots.len() - 1].to_vec() }
+    }
+
+    /// 制御点を変換した曲線
+    pub fn map<Q: SplinePoint>(&self, f: impl FnMut(P) -> Q) -> BSpline<Q> {
+        BSpline { control_points: self.control_points.iter().copied().map(f).collect(), knots: self.knots.clone() }
+    }
+
+    /// 向きを反転した曲線
+    pub fn reversed(&self) -> Self {
+        Self {
+            control_points: self.control_points.iter().rev().copied().collect(),
+            knots: self.knots.iter().rev().map(|k| 1.0 - k).collect(),
+        }
+    }
+
+    /// ノット `t` を1つ挿入した曲線。形は変わらず、制御点が1つ増える (The NURBS Book A5.1)
+    fn insert_knot(&self, t: f32) -> Self {
+        let (span, p) = (self.span(t), self.degree());
+        let control_points = (0..=self.control_points.len())
+            .map(|i| {
+                if i + p <= span {
+                    self.control_points[i]
+                } else if i > span {
+                    self.control_points[i - 1]
+                } else {
+                    let a = (t - self.knots[i]) / (self.knots[i + p] - self.knots[i]);
+                    self.control_points[i - 1] * (1.0 - a) + self.control_points[i] * a
+                }
+            })
+            .collect();
+        let mut knots = self.knots.clone();
+        knots.insert(span + 1, t);
+        Self { control_points, knots }
+    }
+
+    /// 0と1の間のパラメータ `t` で2つに分ける。`t` のノットを次数と同じ数まで重ねて分け目を制御点にするので、
+    /// どちらも元の曲線と正確に一致する。パラメータはそれぞれ0から1に取り直す
+    pub fn split(&self, t: f32) -> (Self, Self) {
+        // 既にあるノットのごく近くなら、そのノットで分けて極端に短い区間を作らない
+        let t = self.knots.iter().copied().find(|k| (k - t).abs() < KNOT_TOLERANCE).unwrap_or(t);
+        let multiplicity = self.knots.iter().filter(|&&k| k == t).count();
+        let spline = (multiplicity..self.degree()).fold(self.clone(), |spline, _| spline.insert_knot(t));
+        let first = spline.knots.iter().position(|&k| k == t).unwrap_or(0);
+        let p = self.degree();
+        let before = Self {
+            control_points: spline.control_points[..first].to_vec(),
+            knots: spline.knots[..first + p].iter().chain([&t]).map(|k| k / t).collect(),
+        };
+        let after = Self {
+            control_points: spline.control_points[first - 1..].to_vec(),
+            knots: [t].iter().chain(&spline.knots[first..]).map(|k| (k - t) / (1.0 - t)).collect(),
+        };
+        (before, after)
+    }
+
+    /// パラメータ `t0` から `span` だけ進んだ部分を、元の曲線と一致する曲線として取り出す。
+    /// 1を超えた分は始点に戻って続ける (閉じた曲線で始点をまたぐ区間)
+    pub fn segment(&self, t0: f32, span: f32) -> Self {
+        let t1 = t0 + span;
+        if t1 > 1.0 + KNOT_TOLERANCE {
+            let (tail, head) = (1.0 - t0, t1 - 1.0);
+            if tail < KNOT_TOLERANCE {
+                return self.segment(0.0, head);
+            }
+            return self.segment(t0, tail).joined(&self.segment(0.0, head), tail / span);
+        }
+        let (rest, t1) =
+            if t0 > KNOT_TOLERANCE { (self.split(t0).1, (t1 - t0) / (1.0 - t0)) } else { (self.clone(), t1) };
+        if t1 < 1.0 - KNOT_TOLERANCE { rest.split(t1).0 } else { rest }
+    }
+
+    /// 終点が `next` の始点と一致する同じ次数の曲線をつなぐ。`share` はつないだ曲線のうちこの曲線が受け持つパラメータの割合
+    fn joined(&self, next: &Self, share: f32) -> Self {
+        let p = self.degree();
+        let control_points = self.control_points.iter().chain(&next.control_points[1..]).copied().collect();
+        let knots = self.knots[..self.knots.len() - 1]
+            .iter()
+            .map(|k| k * share)
+            .chain(next.knots[p + 1..].iter().map(|k| share + k * (1.0 - share)))
+            .collect();
+        Self { control_points, knots }
+    }
+
+    /// 各ノット区間を等分した点のパラメータ。ノットの位置を必ず含む
+    fn sample_params(&self) -> Vec<f32> {
+        let mut params = vec![0.0];
+        for pair in self.knots.windows(2).filter(|pair| pair[1] > pair[0]) {
+            params.extend((1..=SEGMENTS_PER_SPAN).map(|i| pair[0] + (pair[1] - pair[0]) * i as f32 / SEGMENTS_PER_SPAN as f32));
+        }
+        params
+    }
+
+    /// 折れ線で近似する。始点と終点を含む
+    pub fn tessellate(&self) -> Vec<P> {
+        self.sample_params().into_iter().map(|t| self.point_at(t)).collect()
+    }
+}
+
+impl BSpline<Vec2> {
+    /// 通過点をすべて通る曲線を作る。パラメータは弦長に比例させ、ノットはその平均で決める (The NURBS Book 9.2.1)。
+    /// 通過点が2つ未満か、すべて同じ位置にある場合は `None`
+    pub fn interpolate(fit_points: &[Vec2]) -> Option<Self> {
+        let n = fit_points.len();
+        let total: f32 = fit_points.windows(2).map(|pair| pair[0].distance(pair[1])).sum();
+        if n < 2 || total <= f32::EPSILON {
+            return None;
+        }
+        let degree = (n - 1).min(3);
+        let mut params = vec![0.0];
+        for pair in fit_points.windows(2) {
+            params.push(params[params.len() - 1] + pair[0].distance(pair[1]) / total);
+        }
+        params[n - 1] = 1.0;
+        let knots: Vec<f32> = (0..=degree)
+            .map(|_| 0.0)
+            .chain((1..n - degree).map(|j| params[j..j + degree].iter().sum::<f32>() / degree as f32))
+            .chain((0..=degree).map(|_| 1.0))
+            .collect();
+
+        // 各通過点での基底関数の値を並べた連立方程式を、x座標とy座標で別々に解いて制御点を求める
+        let mut spline = Self { control_points: vec![Vec2::ZERO; n], knots };
+        let mut matrix = vec![vec![0.0; n]; n];
+        for (row, &t) in matrix.iter_mut().zip(&params) {
+            let (span, values) = spline.basis(t);
+            for (j, value) in values.into_iter().enumerate() {
+                row[span - degree + j] = f64::from(value);
+            }
+        }
+        let xs = solver::solve_linear(matrix.clone(), fit_points.iter().map(|p| f64::from(p.x)).collect())?;
+        let ys = solver::solve_linear(matrix, fit_points.iter().map(|p| f64::from(p.y)).collect())?;
+        spline.control_points = xs.into_iter().zip(ys).map(|(x, y)| Vec2::new(x as f32, y as f32)).collect();
+        Some(spline)
+    }
+
+    /// 点に最も近い曲線上の点のパラメータ。折れ線で近い点を探してからニュートン法で詰める
+    pub fn closest_param(&self, p: Vec2) -> f32 {
+        let mut t = self
+            .sample_params()
+            .into_iter()
+            .min_by(|&a, &b| self.point_at(a).distance_squared(p).total_cmp(&self.point_at(b).distance_squared(p)))
+            .unwrap_or(0.0);
+        let derivative = self.derivative();
+        for _ in 0..8 {
+            let d = derivative.point_at(t);
+            if d.length_squared() <= f32::EPSILON {
+                break;
+            }
+            let next = (t - (self.point_at(t) - p).dot(d) / d.length_squared()).clamp(0.0, 1.0);
+            if (next - t).abs() < 1e-7 {
+                break;
+            }
+            t = next;
+        }
+        t
+    }
+}
+
+/// スケッチ平面上の曲線
+#[derive(Debug, Clone, PartialEq)]
+pub enum Curve2d {
+    Line { a: Vec2, b: Vec2 },
+    Circle { center: Vec2, radius: f32 },
+    /// `start_angle` から `sweep` (正なら反時計回り) だけ回る円弧。角度はラジアン
+    Arc { center: Vec2, radius: f32, start_angle: f32, sweep: f32 },
+    /// 始点と終点が同じものは閉じた曲線になる
+    Spline(BSpline),
+    /// `center + major * cos(t) + minor * sin(t)` で表される楕円。`major` と `minor` は直交し、
+    /// `minor` が `major` の左側にあれば反時計回りに進む
+    Ellipse { center: Vec2, major: Vec2, minor: Vec2 },
+}
+
+impl Curve2d {
+    pub fn start(&self) -> Vec2 {
+        match *self {
+            Curve2d::Line { a, .. } => a,
+            Curve2d::Circle { center, radius } => center + Vec2::X * radius,
+            Curve2d::Arc { center, radius, start_angle, .. } => center + Vec2::from_angle(start_angle) * radius,
+            Curve2d::Spline(ref spline) => spline.control_points[0],
+            Curve2d::Ellipse { center, major, .. } => center + major,
+        }
+    }
+
+    pub fn end(&self) -> Vec2 {
+        match *self {
+            Curve2d::Line { b, .. } => b,
+            Curve2d::Circle { .. } | Curve2d::Ellipse { .. } => self.start(),
+            Curve2d::Arc { center, radius, start_angle, sweep } => center + Vec2::from_angle(start_angle + sweep) * radius,
+            Curve2d::Spline(ref spline) => spline.control_points[spline.control_points.len() - 1],
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        match self {
+            Curve2d::Circle { .. } | Curve2d::Ellipse { .. } => true,
+            Curve2d::Spline(spline) => spline.control_points.len() > 2 && self.start().distance(self.end()) < 1e-6,
+            _ => false,
+        }
+    }
+
+    /// 向きを反転した曲線
+    pub fn reversed(&self) -> Curve2d {
+        match *self {
+            Curve2d::Line { a, b } => Curve2d::Line { a: b, b: a },
+            Curve2d::Circle { center, radius } => Curve2d::Circle { center, radius },
+            Curve2d::Arc { center, radius, start_angle, sweep } => {
+                Curve2d::Arc { center, radius, start_angle: start_angle + sweep, sweep: -sweep }
+            }
+            Curve2d::Spline(ref spline) => Curve2d::Spline(spline.reversed()),
+            Curve2d::Ellipse { center, major, minor } => Curve2d::Ellipse { center, major, minor: -minor },
+        }
+    }
+
+    /// 始点での進行方向 (単位ベクトル)
+    pub fn start_tangent(&self) -> Vec2 {
+        match *self {
+            Curve2d::Line { a, b } => (b - a).normalize_or_zero(),
+            Curve2d::Circle { .. } => Vec2::Y,
+            Curve2d::Arc { start_angle, sweep, .. } => Vec2::from_angle(start_angle).perp() * sweep.signum(),
+            Curve2d::Spline(ref spline) => spline.derivative().point_at(0.0).normalize_or_zero(),
+            Curve2d::Ellipse { minor, .. } => minor.normalize_or_zero(),
+        }
+    }
+
+    /// 終点での進行方向 (単位ベクトル)
+    pub fn end_tangent(&self) -> Vec2 {
+        match *self {
+            Curve2d::Arc { start_angle, sweep, .. } => Vec2::from_angle(start_angle + sweep).perp() * sweep.signum(),
+            Curve2d::Spline(ref spline) => spline.derivative().point_at(1.0).normalize_or_zero(),
+            _ => self.start_tangent(),
+        }
+    }
+
+    /// 折れ線で近似する。始点と終点を含む
+    pub fn tessellate(&self) -> Vec<Vec2> {
+        match *self {
+            Curve2d::Line { a, b } => vec![a, b],
+            Curve2d::Circle { center, radius } => {
+                let segments = SEGMENTS_PER_TURN;
+                (0..=segments)
+                    .map(|i| center + Vec2::from_angle(std::f32::consts::TAU * i as f32 / segments as f32) * radius)
+                    .collect()
+            }
+            Curve2d::Arc { center, radius, start_angle, sweep } => {
+                let segments = (sweep.abs() / TAU * SEGMENTS_PER_TURN as f32).ceil().max(1.0) as usize;
+                (0..=segments)
+                    .map(|i| center + Vec2::from_angle(start_angle + sweep * i as f32 / segments as f32) * radius)
+                    .collect()
+            }
+            Curve2d::Spline(ref spline) => spline.tessellate(),
+            Curve2d::Ellipse { center, major, minor } => (0..=SEGMENTS_PER_TURN)
+                .map(|i| {
+                    let angle = TAU * i as f32 / SEGMENTS_PER_TURN as f32;
+                    center + major * angle.cos() + minor * angle.sin()
+                })
+                .collect(),
+        }
+    }
+
+    /// パラメータ `t` (0から1) の位置の点。円と楕円は始点から一周する
+    pub fn point_at(&self, t: f32) -> Vec2 {
+        match *self {
+            Curve2d::Line { a, b } => a.lerp(b, t),
+            Curve2d::Circle { center, radius } => center + Vec2::from_angle(TAU * t) * radius,
+            Curve2d::Arc { center, radius, start_angle, sweep } => center + Vec2::from_angle(start_angle + sweep * t) * radius,
+            Curve2d::Spline(ref spline) => spline.point_at(t),
+            Curve2d::Ellipse { center, major, minor } => center + major * (TAU * t).cos() + minor * (TAU * t).sin(),
+        }
+    }
+
+    /// 点に最も近い曲線上の点のパラメータ
+    pub fn closest_param(&self, p: Vec2) -> f32 {
+        match *self {
+            Curve2d::Line { a, b } => {
+                let ab = b - a;
+                ((p - a).dot(ab) / ab.length_squared().max(f32::EPSILON)).clamp(0.0, 1.0)
+            }
+            Curve2d::Circle { center, .. } => (p - center).to_angle().rem_euclid(TAU) / TAU,
+            Curve2d::Arc { center, start_angle, sweep, .. } => {
+                // 円弧の範囲内の方向なら円周上、範囲外なら近い方の端点
+                let turned = (((p - center).to_angle() - start_angle) * sweep.signum()).rem_euclid(TAU);
+                if turned <= sweep.abs() {
+                    turned / sweep.abs()
+                } else if p.distance(self.start()) <= p.distance(self.end()) {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            Curve2d::Spline(ref spline) => spline.closest_param(p),
+            Curve2d::Ellipse { center, major, minor } => {
+                // 分割した点で近い角度を探してからニュートン法で詰める
+                let at = |t: f32| center + major * t.cos() + minor * t.sin();
+                let mut t = (0..SEGMENTS_PER_TURN)
+                    .map(|i| TAU * i as f32 / SEGMENTS_PER_TURN as f32)
+                    .min_by(|&a, &b| at(a).distance_squared(p).total_cmp(&at(b).distance_squared(p)))
+                    .unwrap_or(0.0);
+                for _ in 0..8 {
+                    let d = at(t) - p;
+                    let d1 = minor * t.cos() - major * t.sin();
+                    let d2 = center - at(t);
+                    let slope = d1.length_squared() + d.dot(d2);
+                    if slope.abs() <= f32::EPSILON {
+                        break;
+                    }
+                    t -= d.dot(d1) / slope;
+                }
+                t.rem_euclid(TAU) / TAU
+            }
+        }
+    }
+
+    /// 点から曲線までの最短距離
+    pub fn distance(&self, p: Vec2) -> f32 {
+        match *self {
+            Curve2d::Circle { center, radius } => (p.distance(center) - radius).abs(),
+            _ => p.distance(self.point_at(self.closest_param(p))),
+        }
+    }
+
+    /// パラメータ `t0` から `t1` まで進む幅。閉じた曲線は始点をまたいで進み、同じ値なら一周する
+    pub fn param_span(&self, t0: f32, t1: f32) -> f32 {
+        if !self.is_closed() {
+            return t1 - t0;
+        }
+        let span = (t1 - t0).rem_euclid(1.0);
+        if span <= f32::EPSILON { 1.0 } else { span }
+    }
+
+    /// パラメータ `t0` から `t1` までの部分。閉じた曲線は `t1` が `t0` より前なら始点をまたいで進み、
+    /// 同じなら一周する。楕円の一部は表せないので `None`
+    pub fn segment(&self, t0: f32, t1: f32) -> Option<Curve2d> {
+        let span = self.param_span(t0, t1);
+        match *self {
+            Curve2d::Line { .. } => Some(Curve2d::Line { a: self.point_at(t0), b: self.point_at(t1) }),
+            Curve2d::Circle { center, radius } => {
+                Some(Curve2d::Arc { center, radius, start_angle: TAU * t0, sweep: TAU * span })
+            }
+            Curve2d::Arc { center, radius, start_angle, sweep } => {
+                Some(Curve2d::Arc { center, radius, start_angle: start_angle + sweep * t0, sweep: sweep * span })
+            }
+            Curve2d::Spline(ref spline) => Some(Curve2d::Spline(spline.segment(t0, span))),
+            Curve2d::Ellipse { .. } => None,
+        }
+    }
+}
+
+/// 始点から反時計回りに終点まで回る円弧。終点は円周上に射影する
+pub fn arc_from_points(center: Vec2, start: Vec2, end: Vec2) -> Curve2d {
+    let start_angle = (start - center).to_angle();
+    let sweep = ((end - center).to_angle() - start_angle).rem_euclid(TAU);
+    Curve2d::Arc { center, radius: start.distance(center), start_angle, sweep: if sweep < 1e-6 { TAU } else { sweep } }
+}
+
+/// 始点から通過点を経て終点へ向かう円弧。3点が一直線上にある場合は `None`
+pub fn arc_through(start: Vec2, through: Vec2, end: Vec2) -> Option<Curve2d> {
+    let center = circumcenter(start, through, end)?;
+    let start_angle = (start - center).to_angle();
+    let to_end = ((end - center).to_angle() - start_angle).rem_euclid(TAU);
+    let to_through = ((through - center).to_angle() - start_angle).rem_euclid(TAU);
+    let sweep = if to_through < to_end { to_end } else { to_end - TAU };
+    Some(Curve2d::Arc { center, radius: start.distance(center), start_angle, sweep })
+}
+
+/// 始点で `tangent` の向きに接し、終点へ向かう円弧。終点が接線の延長上にある場合は `None`
+pub fn tangent_arc(start: Vec2, tangent: Vec2, end: Vec2) -> Option<Curve2d> {
+    let normal = tangent.normalize_or_zero().perp();
+    let chord = end - start;
+    let denominator = 2.0 * chord.dot(normal);
+    if denominator.abs() < 1e-6 {
+        return None;
+    }
+    // 符号付きの半径。正なら中心が進行方向の左にあり、反時計回りに回る
+    let radius = chord.length_squared() / denominator;
+    let center = start + normal * radius;
+    let start_angle = (start - center).to_angle();
+    let end_angle = (end - center).to_angle();
+    let sweep = if radius > 0.0 {
+        (end_angle - start_angle).rem_euclid(TAU)
+    } else {
+        -(start_angle - end_angle).rem_euclid(TAU)
+    };
+    Some(Curve2d::Arc { center, radius: radius.abs(), start_angle, sweep })
+}
+
+/// 3点を通る円の中心。3点が一直線上にある場合は `None`
+fn circumcenter(a: Vec2, b: Vec2, c: Vec2) -> Option<Vec2> {
+    let (ab, ac) = (b - a, c - a);
+    let d = 2.0 * ab.perp_dot(ac);
+    if d.abs() < 1e-9 {
+        return None;
+    }
+    let offset = (ab.perp() * ac.length_squared() - ac.perp() * ab.length_squared()) / d;
+    Some(a + offset)
+}
+
+/// 多角形の符号付き面積 (反時計回りなら正)
+pub fn signed_area(polygon: &[Vec2]) -> f32 {
+    let n = polygon.len();
+    (0..n).map(|i| polygon[i].perp_dot(polygon[(i + 1) % n])).sum::<f32>() / 2.0
+}
+
+/// 点が多角形の内側にあるか (偶奇規則)
+pub fn point_in_polygon(p: Vec2, polygon: &[Vec2]) -> bool {
+    let n = polygon.len();
+    let mut inside = false;
+    for i in 0..n {
+        let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+        if (a.y > p.y) != (b.y > p.y) {
+            let x = a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x);
+            if p.x < x {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2つの曲線を0から1まで同じパラメータで比べた時の最大の差
+    fn max_distance(a: impl Fn(f32) -> Vec2, b: impl Fn(f32) -> Vec2) -> f32 {
+        (0..=50).map(|i| i as f32 / 50.0).map(|t| a(t).distance(b(t))).fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn interpolated_spline_passes_through_fit_points() {
+        // 次数が下がる2点・3点の場合と、間隔が不揃いな場合
+        let cases = [
+            vec![Vec2::ZERO, Vec2::new(2.0, 1.0)],
+            vec![Vec2::ZERO, Vec2::new(1.0, 1.0), Vec2::new(2.0, 0.0)],
+            vec![Vec2::ZERO, Vec2::new(0.5, 2.0), Vec2::new(3.0, 2.5), Vec2::new(3.2, -1.0), Vec2::new(6.0, 0.0)],
+        ];
+        for fit in cases {
+            let spline = BSpline::interpolate(&fit).unwrap();
+            assert_eq!(spline.control_points.len(), fit.len());
+            for &point in &fit {
+                assert!(spline.point_at(spline.closest_param(point)).distance(point) < 1e-4);
+            }
+            assert!(spline.point_at(0.0).distance(fit[0]) < 1e-5);
+            assert!(spline.point_at(1.0).distance(fit[fit.len() - 1]) < 1e-5);
+        }
+        // 点が足りない・すべて同じ位置の場合は曲線にならない
+        assert!(BSpline::interpolate(&[Vec2::ONE]).is_none());
+        assert!(BSpline::interpolate(&[Vec2::ONE, Vec2::ONE]).is_none());
+    }
+
+    #[test]
+    fn clamped_spline_starts_and_ends_at_end_control_points() {
+        let points = vec![Vec2::ZERO, Vec2::new(1.0, 3.0), Vec2::new(4.0, 3.0), Vec2::new(5.0, -1.0), Vec2::X * 7.0];
+        let spline = BSpline::clamped(points.clone());
+        assert_eq!(spline.degree(), 3);
+        assert_eq!(spline.knots.len(), points.len() + 4);
+        assert!(spline.point_at(0.0).distance(points[0]) < 1e-6);
+        assert!(spline.point_at(1.0).distance(points[4]) < 1e-6);
+        // 端での接線は最初と最後の制御点の辺の向き
+        let derivative = spline.derivative();
+        assert!(derivative.point_at(0.0).normalize().distance((points[1] - points[0]).normalize()) < 1e-5);
+        assert!(derivative.point_at(1.0).normalize().distance((points[4] - points[3]).normalize()) < 1e-5);
+
+        // 制御点が2つなら直線になる
+        let line = BSpline::clamped(vec![Vec2::ZERO, Vec2::new(2.0, 2.0)]);
+        assert_eq!(line.degree(), 1);
+        assert!(line.point_at(0.5).distance(Vec2::ONE) < 1e-6);
+    }
+
+    #[test]
+    fn splits_spline_exactly_without_growing_control_points() {
+        let fit = [Vec2::ZERO, Vec2::new(1.0, 2.0), Vec2::new(3.0, -1.0), Vec2::new(4.0, 1.0), Vec2::new(6.0, 0.0)];
+        let spline = BSpline::interpolate(&fit).unwrap();
+        let (before, after) = spline.split(0.37);
+        assert!(max_distance(|t| before.point_at(t), |t| spline.point_at(0.37 * t)) < 1e-4);
+        assert!(max_distance(|t| after.point_at(t), |t| spline.point_at(0.37 + 0.63 * t)) < 1e-4);
+        // 分けた曲線の制御点は合わせても元の数と次数の分しか増えない
+        let degree = spline.degree();
+        assert_eq!(before.control_points.len() + after.control_points.len(), fit.len() + degree + 1);
+
+        // 切り出しを繰り返しても形が変わらず、制御点も増え続けない
+        let piece = spline.segment(0.2, 0.6).segment(0.1, 0.8);
+        assert!(max_distance(|t| piece.point_at(t), |t| spline.point_at(0.26 + 0.48 * t)) < 1e-4);
+        assert!(piece.control_points.len() <= fit.len() + degree);
+    }
+
+    #[test]
+    fn segment_of_closed_spline_wraps_over_start() {
+        let fit = [Vec2::ZERO, Vec2::new(2.0, 0.0), Vec2::new(2.0, 2.0), Vec2::new(0.0, 2.0), Vec2::ZERO];
+        let curve = Curve2d::Spline(BSpline::interpolate(&fit).unwrap());
+        let Some(Curve2d::Spline(piece)) = curve.segment(0.8, 0.2) else {
+            panic!("スプラインになっていない");
+        };
+        assert!(max_distance(|t| piece.point_at(t), |t| curve.point_at((0.8 + 0.4 * t).rem_euclid(1.0))) < 1e-4);
+    }
+}
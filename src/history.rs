@@ -0,0 +1,375 @@
+//! 元に戻す・やり直しの操作履歴
+//!
+//! 操作を行ったシステムは `UndoHistory::record` で操作名を登録する。フレームの最後に
+//! ドキュメント全体のスナップショットを取り、その操作の直後の状態として履歴に積む。
+//! 元に戻す時はスナップショットをドキュメントの読み込みと同じ手順で復元する。復元するとエンティティが
+//! 作り直されるので、選択・ロフトの断面・スイープのパスの参照はスケッチの名前と図形の形状で新しいエンティティへ付け替える。
+//!
+//! 操作ごとに逆操作を持つコマンドにはせず、スナップショットで履歴を持つ。図形を編集するシステムが多く、
+//! 拘束・寸法・フィーチャーにも影響が広がるので、それぞれに逆操作を書かなくてもすべての操作を同じ手順で
+//! 戻せるようにするため。代わりに、履歴の外からエンティティを参照するものは復元の時に付け替える必要がある。
+//! 同じフレームで操作の登録と履歴の移動が要求された場合は、先に操作を履歴に積み、移動は次のフレームで行う。
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::document::{self, ProjectFile, SketchGeometryData};
+use crate::features::{LoftSection, ProfileRef};
+use crate::{ActiveSketch, AppState, LoftSections, Selected, Sketch, SweepPath};
+
+/// 保持する履歴の最大数。超えた分は古いものから捨てる
+const MAX_ENTRIES: usize = 100;
+
+/// ある操作の直後のドキュメントの状態
+struct HistoryEntry {
+    label: String,
+    /// RON形式のプロジェクトファイル。変化がなかった操作を比較で取り除くため文字列で持つ
+    project: String,
+    /// 編集中のスケッチの `sketches` 内のインデックス
+    active_sketch: Option<usize>,
+}
+
+/// 履歴の移動の要求
+#[derive(Debug, Clone, Copy)]
+enum HistoryRequest {
+    Undo,
+    Redo,
+    /// 指定した操作の直後の状態へ移動する
+    Goto(usize),
+}
+
+/// 元に戻す・やり直しの履歴
+#[derive(Resource, Default)]
+pub struct UndoHistory {
+    entries: Vec<HistoryEntry>,
+    /// 現在の状態を表すエントリ。これより後はやり直しの対象
+    position: usize,
+    /// このフレームで行われた操作の名前
+    pending_record: Option<String>,
+    pending_request: Option<HistoryRequest>,
+    /// まとめて1つの操作にする操作の名前と入れ子の深さ
+    group: Option<(String, usize)>,
+}
+
+impl UndoHistory {
+    /// 操作を履歴に登録する。スナップショットはフレームの最後に取る
+    pub fn record(&mut self, label: impl Into<String>) {
+        if self.group.is_none() {
+            self.pending_record = Some(label.into());
+        }
+    }
+
+    /// 複数のフレームにまたがる操作 (ドラッグなど) を1つにまとめ始める
+    pub fn begin_group(&mut self, label: impl Into<String>) {
+        match &mut self.group {
+            Some((_, depth)) => *depth += 1,
+            None => self.group = Some((label.into(), 1)),
+        }
+    }
+
+    /// `begin_group` でまとめた操作を1つの操作として登録する
+    pub fn end_group(&mut self) {
+        let Some((label, depth)) = self.group.take() else {
+            return;
+        };
+        if depth > 1 {
+            self.group = Some((label, depth - 1));
+        } else {
+            self.pending_record = Some(label);
+        }
+    }
+
+    /// ドラッグで値を変える入力欄の操作を登録する。ドラッグ中の変更は1つの操作にまとめる
+    pub fn track_drag(&mut self, response: &egui::Response, label: &str) {
+        if response.drag_started() {
+            self.begin_group(label);
+        }
+        if response.changed() {
+            self.record(label);
+        }
+        if response.drag_stopped() {
+            self.end_group();
+        }
+    }
+
+    /// 履歴を消す。ファイルを開いた時など、元に戻せない変更の後に呼ぶ
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.position > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.position + 1 < self.entries.len()
+    }
+
+    pub fn undo(&mut self) {
+        self.pending_request = Some(HistoryRequest::Undo);
+    }
+
+    pub fn redo(&mut self) {
+        self.pending_request = Some(HistoryRequest::Redo);
+    }
+
+    /// サイドパネルに表示する履歴の一覧。クリックした操作の直後の状態へ戻る
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        ui.label("履歴");
+        ui.horizontal(|ui| {
+            if ui.add_enabled(self.can_undo(), egui::Button::new("元に戻す")).clicked() {
+                self.undo();
+            }
+            if ui.add_enabled(self.can_redo(), egui::Button::new("やり直し")).clicked() {
+                self.redo();
+            }
+        });
+        let mut goto = None;
+        egui::ScrollArea::vertical().id_source("undo_history").max_height(150.0).stick_to_bottom(true).show(ui, |ui| {
+            for (i, entry) in self.entries.iter().enumerate() {
+                let mut text = egui::RichText::new(&entry.label);
+                if i > self.position {
+                    text = text.weak();
+                }
+                if ui.selectable_label(i == self.position, text).clicked() {
+                    goto = Some(i);
+                }
+            }
+        });
+        if let Some(i) = goto {
+            self.pending_request = Some(HistoryRequest::Goto(i));
+        }
+    }
+}
+
+/// Ctrl+Zで元に戻し、Ctrl+YまたはCtrl+Shift+Zでやり直すシステム
+pub fn undo_shortcut_system(
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut history: ResMut<UndoHistory>,
+) {
+    // テキスト入力中はegui側のショートカットを優先する
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if keys.just_pressed(KeyCode::KeyY) || (shift && keys.just_pressed(KeyCode::KeyZ)) {
+        history.redo();
+    } else if keys.just_pressed(KeyCode::KeyZ) {
+        history.undo();
+    }
+}
+
+/// 登録された操作のスナップショットを取り、元に戻す・やり直しを実行するシステム
+pub fn history_system(world: &mut World) {
+    let mut history = world.resource_mut::<UndoHistory>();
+    let record = history.pending_record.take();
+    let in_group = history.group.is_some();
+    let is_empty = history.entries.is_empty();
+
+    // 履歴の移動の要求は残しておき、次のフレームで操作を積んだ後の履歴に対して行う
+    if is_empty || record.is_some() {
+        let label = record.unwrap_or_else(|| "開始".to_string());
+        let entry = snapshot(world, label);
+        let mut history = world.resource_mut::<UndoHistory>();
+        // 何も変わらなかった操作は履歴に残さない
+        if history.entries.get(history.position).is_some_and(|current| current.project == entry.project) {
+            return;
+        }
+        let position = history.position;
+        history.entries.truncate(position + 1);
+        history.entries.push(entry);
+        if history.entries.len() > MAX_ENTRIES {
+            history.entries.remove(0);
+        }
+        history.position = history.entries.len() - 1;
+        return;
+    }
+
+    // 操作の途中では履歴を移動しない
+    let Some(request) = world.resource_mut::<UndoHistory>().pending_request.take().filter(|_| !in_group) else {
+        return;
+    };
+    let history = world.resource::<UndoHistory>();
+    let target = match request {
+        HistoryRequest::Undo => history.position.checked_sub(1),
+        HistoryRequest::Redo => Some(history.position + 1),
+        HistoryRequest::Goto(i) => Some(i),
+    };
+    let Some(target) = target.filter(|&i| i < history.entries.len() && i != history.position) else {
+        return;
+    };
+    let entry = &history.entries[target];
+    let entry_sketch = entry.active_sketch;
+    let project = match document::parse_project(&entry.project) {
+        Ok(project) => project,
+        Err(e) => {
+            println!("履歴の復元に失敗しました: {e}");
+            return;
+        }
+    };
+    let active_sketch = entry_sketch.or_else(|| active_sketch_index(world));
+    world.resource_mut::<UndoHistory>().position = target;
+    restore(world, &project, active_sketch);
+}
+
+/// 編集中のスケッチが `capture_document` で何番目に書き出されるか
+fn active_sketch_index(world: &mut World) -> Option<usize> {
+    let active = world.resource::<ActiveSketch>().0?;
+    world.query_filtered::<Entity, With<Sketch>>().iter(world).position(|e| e == active)
+}
+
+/// 現在のドキュメントのスナップショットを取る
+fn snapshot(world: &mut World, label: String) -> HistoryEntry {
+    let project = document::capture_document(world);
+    let active_sketch = active_sketch_index(world);
+    let project = document::serialize_project(&project).unwrap_or_default();
+    HistoryEntry { label, project, active_sketch }
+}
+
+/// 復元の前後で同じ図形とみなすための、スケッチの名前と図形の形状
+type ShapeKey = (String, SketchGeometryData);
+
+/// スナップショットを復元する。選択中の図形・ロフトの断面・スイープのパスは、同じ名前のスケッチにある同じ形状の図形へ付け替え、
+/// 見つからなければ外す。スケッチ編集中に編集中のスケッチがなくなった場合は表示モードに戻る
+fn restore(world: &mut World, project: &ProjectFile, active_sketch: Option<usize>) {
+    let (current, entities) = document::capture_document_entities(world);
+    let mut sketch_names = HashMap::new();
+    let mut shape_keys: HashMap<Entity, ShapeKey> = HashMap::new();
+    for (i, sketch) in current.sketches.iter().enumerate() {
+        sketch_names.insert(entities.sketches[i], sketch.name.clone());
+        for (&entity, data) in entities.entities[i].iter().zip(&sketch.entities) {
+            shape_keys.insert(entity, (sketch.name.clone(), data.geometry.clone()));
+        }
+    }
+    let selected: Vec<ShapeKey> = world
+        .query_filtered::<Entity, With<Selected>>()
+        .iter(world)
+        .filter_map(|entity| shape_keys.get(&entity).cloned())
+        .collect();
+
+    let restored = document::restore_document(world, project);
+    let find_sketch = |name: &str| project.sketches.iter().position(|sketch| sketch.name == name);
+    let find_shape = |(name, geometry): &ShapeKey| {
+        let i = find_sketch(name)?;
+        let k = project.sketches[i].entities.iter().position(|data| data.geometry == *geometry)?;
+        Some(restored.entities[i][k])
+    };
+    for key in &selected {
+        if let Some(entity) = find_shape(key) {
+            world.entity_mut(entity).insert(Selected);
+        }
+    }
+    let mut loft_sections = world.resource_mut::<LoftSections>();
+    loft_sections.0 = std::mem::take(&mut loft_sections.0)
+        .into_iter()
+        .filter_map(|section| {
+            let sketch = restored.sketches[find_sketch(sketch_names.get(&section.sketch)?)?];
+            let profile = match section.profile {
+                ProfileRef::Region(seed) => ProfileRef::Region(seed),
+                ProfileRef::Entity(entity) => ProfileRef::Entity(find_shape(shape_keys.get(&entity)?)?),
+            };
+            Some(LoftSection { sketch, profile, ..section })
+        })
+        .collect();
+    let mut sweep_path = world.resource_mut::<SweepPath>();
+    sweep_path.0 = sweep_path.0.take().and_then(|(sketch, path)| {
+        let sketch = restored.sketches[find_sketch(sketch_names.get(&sketch)?)?];
+        let path = path.iter().map(|entity| find_shape(shape_keys.get(entity)?)).collect::<Option<_>>()?;
+        Some((sketch, path))
+    });
+
+    if *world.resource::<State<AppState>>().get() != AppState::Sketching {
+        return;
+    }
+    let active = active_sketch.and_then(|i| restored.sketches.get(i).copied());
+    world.resource_mut::<ActiveSketch>().0 = active;
+    if active.is_none() {
+        world.resource_mut::<NextState<AppState>>().set(AppState::Viewing);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::FeatureTree;
+    use crate::{SketchCircle, SketchLine};
+
+    #[test]
+    fn undo_requested_with_record_runs_after_recording() {
+        let mut world = World::new();
+        world.insert_resource(State::new(AppState::Viewing));
+        world.init_resource::<FeatureTree>();
+        world.init_resource::<LoftSections>();
+        world.init_resource::<SweepPath>();
+        world.init_resource::<ActiveSketch>();
+        world.init_resource::<UndoHistory>();
+        let sketch = world.spawn(Sketch { name: "スケッチ1".to_string(), plane: default() }).id();
+        world.spawn(SketchLine { p1: Vec3::ZERO, p2: Vec3::X }).set_parent(sketch);
+        history_system(&mut world);
+
+        let line = world.query_filtered::<Entity, With<SketchLine>>().single(&world);
+        world.get_mut::<SketchLine>(line).unwrap().p2 = Vec3::Z;
+        let mut history = world.resource_mut::<UndoHistory>();
+        history.record("直線を移動");
+        history.undo();
+        history_system(&mut world);
+        let history = world.resource::<UndoHistory>();
+        assert_eq!((history.entries.len(), history.position), (2, 1));
+
+        history_system(&mut world);
+        assert_eq!(world.resource::<UndoHistory>().position, 0);
+        let line = world.query::<&SketchLine>().single(&world);
+        assert_eq!(line.p2, Vec3::X);
+    }
+
+    #[test]
+    fn restore_moves_selection_and_loft_sections_to_new_entities() {
+        let mut world = World::new();
+        world.insert_resource(State::new(AppState::Viewing));
+        world.init_resource::<FeatureTree>();
+        world.init_resource::<LoftSections>();
+        world.init_resource::<SweepPath>();
+        let sketch = world.spawn(Sketch { name: "スケッチ1".to_string(), plane: default() }).id();
+        let line = SketchLine { p1: Vec3::ZERO, p2: Vec3::X };
+        let line = world.spawn((line, Selected)).set_parent(sketch).id();
+        let circle = SketchCircle { center: Vec3::new(0.0, 0.0, -2.0), radius: 0.5 };
+        let circle = world.spawn(circle).set_parent(sketch).id();
+        world.resource_mut::<LoftSections>().0.push(LoftSection {
+            sketch,
+            profile: ProfileRef::Entity(circle),
+            start: 0.0,
+        });
+        let project = document::capture_document(&mut world);
+        // 円を動かした後に元の状態へ戻す
+        world.get_mut::<SketchCircle>(circle).unwrap().radius = 1.0;
+        restore(&mut world, &project, None);
+
+        assert!(world.get_entity(line).is_none());
+        let selected: Vec<Entity> = world.query_filtered::<Entity, With<Selected>>().iter(&world).collect();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(world.get::<SketchLine>(selected[0]).unwrap().p2, Vec3::X);
+        // 動かした円は元の状態にはないので断面から外れる
+        assert!(world.resource::<LoftSections>().0.is_empty());
+
+        // 形状が同じなら新しいスケッチと円を参照する
+        let (sketch, circle) = {
+            let mut q_circles = world.query::<(Entity, &Parent, &SketchCircle)>();
+            let (circle, parent, _) = q_circles.single(&world);
+            (parent.get(), circle)
+        };
+        let section = LoftSection { sketch, profile: ProfileRef::Entity(circle), start: 0.0 };
+        world.resource_mut::<LoftSections>().0.push(section);
+        restore(&mut world, &project, None);
+        let section = &world.resource::<LoftSections>().0[0];
+        let ProfileRef::Entity(restored) = section.profile else { unreachable!() };
+        assert_ne!(restored, circle);
+        assert_eq!(world.get::<Parent>(restored).unwrap().get(), section.sketch);
+        assert_eq!(world.get::<SketchCircle>(restored).unwrap().radius, 0.5);
+    }
+}
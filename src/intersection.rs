@@ -0,0 +1,310 @@
+//! 2次元の曲線同士の交点
+//!
+//! 直線・円・円弧の組み合わせは、元になる無限直線や円の交点を式で求めてから両方の曲線の範囲内に
+//! あるものを残す。スプラインや楕円を含む組み合わせは、折れ線同士の交点を初期値にしてニュートン法で
+//! 曲線上の交点に詰める。交点は各曲線上のパラメータ (`Curve2d::point_at` の引数) とともに返すので、
+//! トリムや分割のほか、交点への吸着やプロファイルの分割にも使える。
+
+use bevy::prelude::*;
+
+use crate::geometry::Curve2d;
+
+/// 曲線上にあるとみなす距離
+const TOLERANCE: f32 = 1e-4;
+/// ニュートン法の反復回数の上限
+const MAX_ITERATIONS: usize = 16;
+/// 2つの交点の間で曲線が離れていないかを調べる点の数
+const TOUCH_SAMPLES: usize = 8;
+
+/// 2つの曲線の交点
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Intersection {
+    pub point: Vec2,
+    /// 1つ目の曲線上のパラメータ
+    pub t: f32,
+    /// 2つ目の曲線上のパラメータ
+    pub s: f32,
+}
+
+/// 2つの曲線の交点をすべて求める。重なっている区間の交点は求めない
+pub fn intersect(a: &Curve2d, b: &Curve2d) -> Vec<Intersection> {
+    let (Some(carrier_a), Some(carrier_b)) = (Carrier::of(a), Carrier::of(b)) else {
+        return refined_intersections(a, b);
+    };
+    let mut result = Vec::new();
+    for point in carrier_a.intersect(&carrier_b) {
+        if a.distance(point) < TOLERANCE && b.distance(point) < TOLERANCE {
+            push_unique(&mut result, Intersection { point, t: a.closest_param(point), s: b.closest_param(point) });
+        }
+    }
+    result
+}
+
+/// 曲線と他の曲線との交点の、曲線上のパラメータを小さい順に並べる。
+/// 開いた曲線の端点にある交点は曲線を分けないので含めない
+pub fn split_params(curve: &Curve2d, others: &[Curve2d]) -> Vec<f32> {
+    let closed = curve.is_closed();
+    let mut params: Vec<f32> = others
+        .iter()
+        .flat_map(|other| intersect(curve, other))
+        .filter(|hit| closed || (hit.point.distance(curve.start()) > TOLERANCE && hit.point.distance(curve.end()) > TOLERANCE))
+        .map(|hit| hit.t)
+        .collect();
+    params.sort_by(f32::total_cmp);
+    params.dedup_by(|a, b| curve.point_at(*a).distance(curve.point_at(*b)) < TOLERANCE);
+    // 閉じた曲線では始点をまたいで同じ交点が両端に並ぶことがある
+    if closed && params.len() > 1 && curve.point_at(params[0]).distance(curve.point_at(params[params.len() - 1])) < TOLERANCE {
+        params.pop();
+    }
+    params
+}
+
+/// 直線・円・円弧の元になる無限直線か円
+#[derive(Clone, Copy)]
+enum Carrier {
+    /// 通る点と方向
+    Line(Vec2, Vec2),
+    Circle(Vec2, f32),
+}
+
+impl Carrier {
+    fn of(curve: &Curve2d) -> Option<Self> {
+        match *curve {
+            Curve2d::Line { a, b } if a != b => Some(Carrier::Line(a, b - a)),
+            Curve2d::Circle { center, radius } | Curve2d::Arc { center, radius, .. } => Some(Carrier::Circle(center, radius)),
+            _ => None,
+        }
+    }
+
+    fn intersect(&self, other: &Carrier) -> Vec<Vec2> {
+        match (*self, *other) {
+            (Carrier::Line(p, d), Carrier::Line(q, e)) => {
+                let denominator = d.perp_dot(e);
+                if denominator.abs() <= f32::EPSILON * d.length() * e.length() {
+                    return Vec::new();
+                }
+                vec![p + d * ((q - p).perp_dot(e) / denominator)]
+            }
+            (Carrier::Line(p, d), Carrier::Circle(center, radius))
+            | (Carrier::Circle(center, radius), Carrier::Line(p, d)) => {
+                let direction = d.normalize();
+                let foot = p + direction * (center - p).dot(direction);
+                let height = foot.distance(center);
+                if height > radius + TOLERANCE {
+                    return Vec::new();
+                }
+                // 接する場合は1点にまとめる
+                let half = (radius * radius - height * height).max(0.0).sqrt();
+                if half < TOLERANCE {
+                    vec![foot]
+                } else {
+                    vec![foot - direction * half, foot + direction * half]
+                }
+            }
+            (Carrier::Circle(c1, r1), Carrier::Circle(c2, r2)) => {
+                let distance = c1.distance(c2);
+                if distance <= f32::EPSILON || distance > r1 + r2 + TOLERANCE || distance < (r1 - r2).abs() - TOLERANCE {
+                    return Vec::new();
+                }
+                let direction = (c2 - c1) / distance;
+                let along = (distance * distance + r1 * r1 - r2 * r2) / (2.0 * distance);
+                let base = c1 + direction * along;
+                let half = (r1 * r1 - along * along).max(0.0).sqrt();
+                if half < TOLERANCE {
+                    vec![base]
+                } else {
+                    vec![base - direction.perp() * half, base + direction.perp() * half]
+                }
+            }
+        }
+    }
+}
+
+/// 折れ線同士の交点をニュートン法で曲線上の交点に詰める
+fn refined_intersections(a: &Curve2d, b: &Curve2d) -> Vec<Intersection> {
+    let (samples_a, samples_b) = (samples(a), samples(b));
+    let mut result = Vec::new();
+    for pair_a in samples_a.windows(2) {
+        let (p0, p1) = (pair_a[0].1, pair_a[1].1);
+        for pair_b in samples_b.windows(2) {
+            let (q0, q1) = (pair_b[0].1, pair_b[1].1);
+            let Some((u, v)) = segment_intersection(p0, p1, q0, q1) else {
+                continue;
+            };
+            let t = pair_a[0].0 + (pair_a[1].0 - pair_a[0].0) * u;
+            let s = pair_b[0].0 + (pair_b[1].0 - pair_b[0].0) * v;
+            if let Some(hit) = refine(a, b, t, s) {
+                push_unique(&mut result, hit);
+            }
+        }
+    }
+    merge_touching(a, b, result)
+}
+
+/// 接するように交わる箇所では、折れ線の交点ごとにわずかに違う点へ収束する。
+/// 間で曲線が離れない交点の並びは1つの接点とみなし、並びの中央の交点だけを残す
+fn merge_touching(a: &Curve2d, b: &Curve2d, mut hits: Vec<Intersection>) -> Vec<Intersection> {
+    hits.sort_by(|x, y| x.t.total_cmp(&y.t));
+    let touching = |x: &Intersection, y: &Intersection| {
+        (1..TOUCH_SAMPLES).all(|i| b.distance(a.point_at(x.t + (y.t - x.t) * i as f32 / TOUCH_SAMPLES as f32)) < TOLERANCE)
+    };
+    let mut result = Vec::new();
+    let mut group: Vec<Intersection> = Vec::new();
+    for hit in hits {
+        if group.last().is_some_and(|last| !touching(last, &hit)) {
+            result.push(group[group.len() / 2]);
+            group.clear();
+        }
+        group.push(hit);
+    }
+    if !group.is_empty() {
+        result.push(group[group.len() / 2]);
+    }
+    result
+}
+
+/// 曲線を折れ線で近似した点とそのパラメータ
+fn samples(curve: &Curve2d) -> Vec<(f32, Vec2)> {
+    let segments = curve.tessellate().len().saturating_sub(1).max(1);
+    (0..=segments).map(|i| i as f32 / segments as f32).map(|t| (t, curve.point_at(t))).collect()
+}
+
+/// 2つの線分の交点の、それぞれの線分上の位置 (0から1)。端点のわずかな外側も含める
+fn segment_intersection(p0: Vec2, p1: Vec2, q0: Vec2, q1: Vec2) -> Option<(f32, f32)> {
+    let (d, e) = (p1 - p0, q1 - q0);
+    let denominator = d.perp_dot(e);
+    if denominator.abs() <= f32::EPSILON {
+        return None;
+    }
+    let u = (q0 - p0).perp_dot(e) / denominator;
+    let v = (q0 - p0).perp_dot(d) / denominator;
+    let slack = 1e-3;
+    ((-slack..=1.0 + slack).contains(&u) && (-slack..=1.0 + slack).contains(&v)).then_some((u, v))
+}
+
+/// 2つの曲線上のパラメータ `t` と `s` の点が一致するようにニュートン法で詰める。収束しなければ `None`
+fn refine(a: &Curve2d, b: &Curve2d, mut t: f32, mut s: f32) -> Option<Intersection> {
+    for _ in 0..MAX_ITERATIONS {
+        let residual = a.point_at(t) - b.point_at(s);
+        if residual.length() < TOLERANCE * 1e-2 {
+            break;
+        }
+        let (da, db) = (derivative(a, t), -derivative(b, s));
+        let determinant = da.perp_dot(db);
+        if determinant.abs() <= f32::EPSILON {
+            break;
+        }
+        t = wrap_param(a, t - residual.perp_dot(db) / determinant);
+        s = wrap_param(b, s - da.perp_dot(residual) / determinant);
+    }
+    let point = a.point_at(t);
+    (point.distance(b.point_at(s)) < TOLERANCE).then_some(Intersection { point, t, s })
+}
+
+/// パラメータに対する曲線の微分を中心差分で求める
+fn derivative(curve: &Curve2d, t: f32) -> Vec2 {
+    let step = 1e-3;
+    let (t0, t1) = if curve.is_closed() { (t - step, t + step) } else { ((t - step).max(0.0), (t + step).min(1.0)) };
+    (curve.point_at(wrap_param(curve, t1)) - curve.point_at(wrap_param(curve, t0))) / (t1 - t0)
+}
+
+/// 閉じた曲線はパラメータを0から1に巻き戻し、開いた曲線は範囲内に収める
+fn wrap_param(curve: &Curve2d, t: f32) -> f32 {
+    if curve.is_closed() {
+        t.rem_euclid(1.0)
+    } else {
+        t.clamp(0.0, 1.0)
+    }
+}
+
+fn push_unique(result: &mut Vec<Intersection>, hit: Intersection) {
+    if result.iter().all(|other| other.point.distance(hit.point) >= TOLERANCE) {
+        result.push(hit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::geometry::BSpline;
+
+    fn line(a: (f32, f32), b: (f32, f32)) -> Curve2d {
+        Curve2d::Line { a: Vec2::new(a.0, a.1), b: Vec2::new(b.0, b.1) }
+    }
+
+    /// 交点が `expected` の点とそれぞれ一致し、パラメータが両方の曲線上の同じ点を指すか
+    fn assert_hits(a: &Curve2d, b: &Curve2d, expected: &[Vec2]) {
+        let hits = intersect(a, b);
+        assert_eq!(hits.len(), expected.len(), "{hits:?}");
+        for point in expected {
+            let hit = hits.iter().find(|hit| hit.point.distance(*point) < 1e-3).expect("交点がない");
+            assert!(a.point_at(hit.t).distance(b.point_at(hit.s)) < 1e-3);
+        }
+    }
+
+    #[test]
+    fn intersects_lines_and_arcs() {
+        assert_hits(&line((0.0, 0.0), (2.0, 2.0)), &line((0.0, 2.0), (2.0, 0.0)), &[Vec2::ONE]);
+        // 線分の範囲外の交点は含めない
+        assert_hits(&line((0.0, 0.0), (1.0, 0.0)), &line((2.0, -1.0), (2.0, 1.0)), &[]);
+
+        let circle = Curve2d::Circle { center: Vec2::ZERO, radius: 1.0 };
+        assert_hits(&line((-2.0, 0.0), (2.0, 0.0)), &circle, &[Vec2::X, -Vec2::X]);
+        // 上半分の円弧とは上側の交点だけ
+        let arc = Curve2d::Arc { center: Vec2::ZERO, radius: 1.0, start_angle: 0.0, sweep: PI };
+        let expected = Vec2::new(0.0, 1.0);
+        assert_hits(&line((0.0, -2.0), (0.0, 2.0)), &arc, &[expected]);
+        // 接する直線は1点
+        assert_hits(&line((-2.0, 1.0), (2.0, 1.0)), &circle, &[expected]);
+
+        let other = Curve2d::Circle { center: Vec2::new(1.0, 0.0), radius: 1.0 };
+        let half = 3.0f32.sqrt() / 2.0;
+        assert_hits(&circle, &other, &[Vec2::new(0.5, -half), Vec2::new(0.5, half)]);
+    }
+
+    #[test]
+    fn intersects_splines_and_ellipses() {
+        let spline = Curve2d::Spline(
+            BSpline::interpolate(&[Vec2::new(-2.0, -1.0), Vec2::new(0.0, 1.0), Vec2::new(2.0, -1.0)]).unwrap(),
+        );
+        let hits = intersect(&spline, &line((-3.0, 0.0), (3.0, 0.0)));
+        assert_eq!(hits.len(), 2);
+        for hit in &hits {
+            assert!(hit.point.y.abs() < 1e-3 && spline.point_at(hit.t).distance(hit.point) < 1e-3);
+        }
+
+        let ellipse = Curve2d::Ellipse { center: Vec2::ZERO, major: Vec2::X * 2.0, minor: Vec2::Y };
+        let x = 3.0f32.sqrt();
+        assert_hits(&line((-3.0, 0.5), (3.0, 0.5)), &ellipse, &[Vec2::new(-x, 0.5), Vec2::new(x, 0.5)]);
+        // x²/4 + y² = 1 と x² + y² = 2.25 の4点
+        let (x, y) = ((5.0f32 / 3.0).sqrt(), (2.25f32 - 5.0 / 3.0).sqrt());
+        let expected = [Vec2::new(x, y), Vec2::new(-x, y), Vec2::new(-x, -y), Vec2::new(x, -y)];
+        assert_hits(&ellipse, &Curve2d::Circle { center: Vec2::ZERO, radius: 1.5 }, &expected);
+    }
+
+    #[test]
+    fn touching_spline_and_circle_meet_once() {
+        // 頂点の曲率が円と同じ放物線を円の頂上に接するように置くと、接点の付近で折れ線同士が何度も交わる
+        let points: Vec<Vec2> = (0..7)
+            .map(|i| {
+                let x = -1.5 + i as f32 * 0.5;
+                Vec2::new(x, 1.0 - 0.5 * x * x)
+            })
+            .collect();
+        let spline = Curve2d::Spline(BSpline::interpolate(&points).unwrap());
+        let circle = Curve2d::Circle { center: Vec2::ZERO, radius: 1.0 };
+        let hits = intersect(&spline, &circle);
+        assert_eq!(hits.iter().filter(|hit| hit.point.distance(Vec2::Y) < 0.1).count(), 1, "{hits:?}");
+    }
+
+    #[test]
+    fn split_params_skip_open_curve_ends() {
+        let curve = line((0.0, 0.0), (4.0, 0.0));
+        let others = [line((0.0, -1.0), (0.0, 1.0)), line((3.0, -1.0), (3.0, 1.0)), line((1.0, -1.0), (1.0, 1.0))];
+        let params = split_params(&curve, &others);
+        assert_eq!(params.len(), 2);
+        assert!((params[0] - 0.25).abs() < 1e-4 && (params[1] - 0.75).abs() < 1e-4);
+    }
+}
@@ -1,7 +1,43 @@
-use bevy::{prelude::*, window::PrimaryWindow};
+use bevy::{ecs::system::SystemParam, prelude::*, window::PrimaryWindow};
 use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
 use bevy_egui::{egui, EguiContexts, EguiPlugin};
 
+mod bodies;
+mod brep;
+mod constraints;
+mod csg;
+mod dimensions;
+mod document;
+mod features;
+mod fillet;
+mod geometry;
+mod history;
+mod intersection;
+mod mesh_builder;
+mod offset;
+mod pattern;
+mod profile;
+mod solver;
+mod transform;
+mod trim;
+
+use bodies::{BodySelection, CombineEvent, CombinePanel};
+use constraints::{
+    AddConstraintEvent, ConstraintKind, ConstraintPanel, GeometryQuery, PointKind, PointRef, SketchConstraint, SolverReport,
+};
+use dimensions::{AddDimensionEvent, DimensionPanel, SketchDimension};
+use document::{DocumentState, FileAction, PlaneData};
+use fillet::ChamferMode;
+use features::{
+    BodyRef, ExtrudeOperation, FeatureAction, FeatureKind, FeaturePanel, FeatureTree, LoftSection, ProfileRef,
+    SweepOrientation,
+};
+use geometry::{arc_from_points, arc_through, tangent_arc, BSpline, Curve2d, Similarity, SketchFrame};
+use history::UndoHistory;
+use offset::OffsetJoin;
+use pattern::SketchCopy;
+use profile::{SketchProfiles, SketchShape};
+
 /// アプリケーション全体の状態
 #[derive(States, Debug, Clone, Eq, PartialEq, Hash, Default)]
 enum AppState {
@@ -11,20 +47,84 @@ enum AppState {
 }
 
 /// 現在選択されているスケッチツール
-#[derive(Resource, Debug, Clone, Eq, PartialEq, Default)]
+#[derive(Resource, Debug, Clone, Copy, Eq, PartialEq, Default)]
 enum ActiveSketchTool {
     #[default]
     Line,
+    /// クリックした点を順に結ぶ連続した直線。最初の点をクリックすると閉じる
+    Polyline,
     Circle,
     Rectangle,
+    /// 中心と角の1つで描く四角形
+    CenterRectangle,
+    /// 1辺の両端と反対側の辺を通る点で描く、傾けられる四角形
+    ThreePointRectangle,
+    /// 始点・終点・通過点の3点で描く円弧
+    ThreePointArc,
+    /// 中心・始点・終点で描く反時計回りの円弧
+    CenterArc,
+    /// 直前の直線や円弧の終点から接線方向に続ける円弧
+    TangentArc,
+    /// クリックした点を通過点か制御点とするスプライン。右クリックで確定し、最初の点をクリックすると閉じる
+    Spline,
+    /// 中心と頂点 (外接では辺の中点) で描く正多角形
+    Polygon,
+    /// 2つの中心と幅を決める点で描く長穴
+    Slot,
+    /// 中心・長軸の端点・短軸の長さを決める点で描く楕円
+    Ellipse,
+    /// クリックした区間を交点まで切り取る
+    Trim,
+    /// 直線をクリックした側に次の図形まで伸ばす
+    Extend,
+    /// クリックした位置で図形を分ける
+    Split,
+    /// 2本の直線の角を円弧で丸める
+    Fillet,
+    /// 2本の直線の角を直線で切り落とす
+    Chamfer,
+    /// 選択した図形をクリックした直線を軸に反転したコピーを作る
+    Mirror,
+    /// 選択した図形をクリックした点のまわりに並べたコピーを作る
+    CircularPattern,
+    /// 選択した図形を基準点から移動先へ動かす
+    Move,
+    /// 選択した図形をクリックした点のまわりに回す
+    Rotate,
+    /// 選択した図形をクリックした点を中心に拡大縮小する
+    Scale,
     Select,
 }
 
+impl ActiveSketchTool {
+    /// 既存の図形を編集するツールか
+    fn is_edit_tool(self) -> bool {
+        matches!(
+            self,
+            ActiveSketchTool::Trim
+                | ActiveSketchTool::Extend
+                | ActiveSketchTool::Split
+                | ActiveSketchTool::Fillet
+                | ActiveSketchTool::Chamfer
+                | ActiveSketchTool::Mirror
+                | ActiveSketchTool::CircularPattern
+                | ActiveSketchTool::Move
+                | ActiveSketchTool::Rotate
+                | ActiveSketchTool::Scale
+        )
+    }
+}
+
 // ActiveSketchToolリソースの値に基づいてシステムを実行するためのカスタム条件
 fn is_active_tool(tool: ActiveSketchTool) -> impl Fn(Res<ActiveSketchTool>) -> bool + Clone {
     move |active_tool: Res<ActiveSketchTool>| *active_tool == tool
 }
 
+/// 図形を編集するツールが選ばれているか
+fn is_edit_tool_active(active_tool: Res<ActiveSketchTool>) -> bool {
+    active_tool.is_edit_tool()
+}
+
 /// スケッチモードで非表示にするメインの立方体
 #[derive(Component)]
 struct MainCube;
@@ -33,6 +133,14 @@ struct MainCube;
 #[derive(Component)]
 struct SketchPlane;
 
+/// 名前付きのスケッチ。直線・円・四角形などのジオメトリを子エンティティとして持つ
+#[derive(Component, Debug)]
+struct Sketch {
+    name: String,
+    /// スケッチ平面。描画はまだXZ平面だけを扱い、ロフトは断面をこの平面の法線方向の位置に置く
+    plane: PlaneData,
+}
+
 /// 直線スケッチのコンポーネント
 #[derive(Component, Debug)]
 struct SketchLine {
@@ -47,50 +155,405 @@ struct SketchCircle {
     radius: f32,
 }
 
-/// 四角形スケッチのコンポーネント
+/// 円弧スケッチのコンポーネント。上から見て中心まわりに始点から終点まで反時計回りに回る
+#[derive(Component, Debug)]
+struct SketchArc {
+    center: Vec3,
+    start: Vec3,
+    end: Vec3,
+}
+
+impl SketchArc {
+    /// スケッチ上の円弧から作る。時計回りの円弧は向きを反転して持つ
+    fn from_curve(frame: &SketchFrame, curve: &Curve2d) -> Option<Self> {
+        let Curve2d::Arc { center, sweep, .. } = *curve else {
+            return None;
+        };
+        let curve = if sweep < 0.0 { curve.reversed() } else { curve.clone() };
+        Some(Self { center: frame.to_world(center), start: frame.to_world(curve.start()), end: frame.to_world(curve.end()) })
+    }
+}
+
+/// スプラインスケッチのコンポーネント
+#[derive(Component, Debug)]
+struct SketchSpline {
+    points: Vec<Vec3>,
+    /// `points` を曲線が通る通過点として扱うか。偽なら制御点として扱う
+    fit: bool,
+    /// 制御点として扱う場合のノット。空なら等間隔のノットにする
+    knots: Vec<f32>,
+}
+
+impl SketchSpline {
+    /// スケッチ上の曲線。点が2つ未満などで曲線にならない場合は `None`
+    fn curve(&self, frame: &SketchFrame) -> Option<Curve2d> {
+        let points: Vec<Vec2> = self.points.iter().map(|&p| frame.to_local(p)).collect();
+        if points.len() < 2 {
+            return None;
+        }
+        let spline = if self.fit {
+            BSpline::interpolate(&points)?
+        } else if self.knots.len() >= points.len() + 2 {
+            BSpline { control_points: points, knots: self.knots.clone() }
+        } else {
+            BSpline::clamped(points)
+        };
+        Some(Curve2d::Spline(spline))
+    }
+}
+
+/// ドラッグで動かしているスプラインの点 (エンティティ, 点の番号)
+#[derive(Resource, Default)]
+struct SplineHandleDrag(Option<(Entity, usize)>);
+
+/// 四角形スケッチのコンポーネント。`p1` と `p2` は対角の角
 #[derive(Component, Debug)]
 struct SketchRectangle {
     p1: Vec3,
     p2: Vec3,
+    /// 辺をX軸・Z軸からY軸まわりに回した角度 (ラジアン)
+    angle: f32,
+}
+
+impl SketchRectangle {
+    /// `a` から `b` への辺と、反対側の辺が通る点 `c` で決まる四角形
+    fn three_point(a: Vec3, b: Vec3, c: Vec3) -> Self {
+        let edge = b - a;
+        let angle = (-edge.z).atan2(edge.x);
+        let side = Quat::from_rotation_y(angle) * Vec3::Z;
+        Self { p1: a, p2: b + side * (c - b).dot(side), angle }
+    }
+
+    /// 四隅の座標 (p1から順に周回する)
+    fn corners(&self) -> [Vec3; 4] {
+        let rotation = Quat::from_rotation_y(self.angle);
+        let (x_axis, z_axis) = (rotation * Vec3::X, rotation * Vec3::Z);
+        let diagonal = self.p2 - self.p1;
+        [self.p1, self.p1 + z_axis * diagonal.dot(z_axis), self.p2, self.p1 + x_axis * diagonal.dot(x_axis)]
+    }
+}
+
+/// 正多角形スケッチのコンポーネント
+#[derive(Component, Debug)]
+struct SketchPolygon {
+    center: Vec3,
+    /// 内接なら頂点、外接なら辺の中点
+    point: Vec3,
+    sides: u32,
+    /// 真なら `point` を通る円に内接し、偽なら外接する
+    inscribed: bool,
+}
+
+impl SketchPolygon {
+    /// 頂点の座標 (中心まわりに順に周回する)
+    fn corners(&self) -> Vec<Vec3> {
+        let sides = self.sides.max(3);
+        let half = std::f32::consts::PI / sides as f32;
+        let first = if self.inscribed {
+            self.point - self.center
+        } else {
+            // 辺の中点から隣の頂点へ回し、外接円の半径まで伸ばす
+            Quat::from_rotation_y(half) * (self.point - self.center) / half.cos()
+        };
+        (0..sides).map(|i| self.center + Quat::from_rotation_y(2.0 * half * i as f32) * first).collect()
+    }
+}
+
+/// 長穴スケッチのコンポーネント。2つの中心を結ぶ直線の両側を半円で閉じる
+#[derive(Component, Debug)]
+struct SketchSlot {
+    a: Vec3,
+    b: Vec3,
+    radius: f32,
+}
+
+impl SketchSlot {
+    /// 外形を反時計回りに周回する曲線。中心が重なる場合は円になる
+    fn curves(&self, frame: &SketchFrame) -> Vec<Curve2d> {
+        let (a, b) = (frame.to_local(self.a), frame.to_local(self.b));
+        let Some(dir) = (b - a).try_normalize() else {
+            return vec![Curve2d::Circle { center: a, radius: self.radius }];
+        };
+        let n = dir.perp() * self.radius;
+        vec![
+            Curve2d::Line { a: a - n, b: b - n },
+            arc_from_points(b, b - n, b + n),
+            Curve2d::Line { a: b + n, b: a + n },
+            arc_from_points(a, a + n, a - n),
+        ]
+    }
+}
+
+/// 楕円スケッチのコンポーネント
+#[derive(Component, Debug)]
+struct SketchEllipse {
+    center: Vec3,
+    /// 長軸の端点
+    major: Vec3,
+    minor_radius: f32,
+}
+
+impl SketchEllipse {
+    fn curve(&self, frame: &SketchFrame) -> Curve2d {
+        let center = frame.to_local(self.center);
+        let major = frame.to_local(self.major) - center;
+        Curve2d::Ellipse { center, major, minor: major.perp().normalize_or_zero() * self.minor_radius }
+    }
+}
+
+/// フィーチャーの再生成で生成されたボディ
+#[derive(Component, Debug)]
+struct Body {
+    /// ボディを作ったフィーチャーと、その中での番号
+    body: BodyRef,
 }
 
 /// スケッチが選択されていることを示すマーカーコンポーネント
 #[derive(Component, Default)]
 struct Selected;
 
-/// スケッチデータを保持するリソース
+/// 補助線を示すマーカーコンポーネント。拘束には使えるが、プロファイルにはならない
+#[derive(Component, Default)]
+struct Construction;
+
+/// ロフトに使う断面。スケッチを切り替えても残るので、別のスケッチの断面も加えられる
 #[derive(Resource, Default)]
+struct LoftSections(Vec<LoftSection>);
+
+/// 別のスケッチの断面もスイープできるよう設定したパスのスケッチと図形。スケッチを切り替えても残る
+#[derive(Resource, Default)]
+struct SweepPath(Option<(Entity, Vec<Entity>)>);
+
+/// 押し出し・回転・スイープ・ロフトの操作のUIで使うもの
+#[derive(SystemParam)]
+struct SolidFeatureInput<'w> {
+    events: EventWriter<'w, SolidFeatureEvent>,
+    loft_sections: ResMut<'w, LoftSections>,
+    sweep_path: ResMut<'w, SweepPath>,
+}
+
+/// スケッチデータを保持するリソース
+#[derive(Resource)]
 struct SketchData {
     start_point: Option<Vec3>,
+    /// 円弧の2点目 (3点円弧では終点、中心円弧では始点)
+    second_point: Option<Vec3>,
+    /// 接線円弧を続ける点と接線方向。直線や円弧を描くたびにその終点で更新する
+    tangent_start: Option<(Vec3, Vec3)>,
+    /// 描画中のポリライン
+    polyline: Option<PolylineDraft>,
+    /// 描画中のスプラインの点
+    spline_points: Vec<Vec3>,
+    /// スプラインの点を通過点にするか (偽なら制御点)
+    spline_fit: bool,
+    /// 正多角形の辺の数
+    polygon_sides: u32,
+    /// 正多角形を円に内接させるか (偽なら外接)
+    polygon_inscribed: bool,
+    fillet_radius: f32,
+    chamfer_mode: ChamferMode,
+    /// 面取りの1本目の直線に沿った距離
+    chamfer_distance: f32,
+    /// 面取りの2本目の直線に沿った距離
+    chamfer_distance2: f32,
+    /// 面取りの1本目の直線からの角度 (度)
+    chamfer_angle: f32,
+    /// オフセットの距離。閉じた図形では正で外側、開いた図形では進行方向の左側にずらす
+    offset_distance: f32,
+    offset_join: OffsetJoin,
+    /// 直線パターンの方向1と方向2の個数。元の図形を含む
+    pattern_count: [u32; 2],
+    /// 直線パターンの方向1と方向2の間隔
+    pattern_spacing: [f32; 2],
+    /// 直線パターンの方向1と方向2の、スケッチのx軸から反時計回りに測った角度 (度)
+    pattern_angle: [f32; 2],
+    /// 円形パターンの個数。元の図形を含む
+    circular_count: u32,
+    /// 円形パターンを並べる範囲の角度 (度)
+    circular_angle: f32,
+    /// 数値で移動する時の移動量 (スケッチのx, y)
+    move_offset: [f32; 2],
+    /// 回転ツールの角度 (度)
+    rotate_angle: f32,
+    /// 拡大縮小ツールの倍率
+    scale_factor: f32,
+    /// 移動・回転・拡大縮小で元の図形を残すか
+    transform_copy: bool,
     extrude_distance: f32,
+    /// 回転角度 (度)
+    revolve_angle: f32,
+    sweep_orientation: SweepOrientation,
+    /// スイープのねじれ (度)
+    sweep_twist: f32,
+    extrude_operation: ExtrudeOperation,
+    /// 押し出し・回転・スイープ・ロフトできなかった理由
+    message: String,
 }
 
-/// 押し出し処理をトリガーするイベント
-#[derive(Event)]
-struct ExtrudeEvent;
+impl Default for SketchData {
+    fn default() -> Self {
+        Self {
+            start_point: None,
+            second_point: None,
+            tangent_start: None,
+            polyline: None,
+            spline_points: Vec::new(),
+            spline_fit: true,
+            polygon_sides: 6,
+            polygon_inscribed: true,
+            fillet_radius: 0.2,
+            chamfer_mode: ChamferMode::default(),
+            chamfer_distance: 0.2,
+            chamfer_distance2: 0.2,
+            chamfer_angle: 45.0,
+            offset_distance: 0.2,
+            offset_join: OffsetJoin::default(),
+            pattern_count: [3, 1],
+            pattern_spacing: [1.0, 1.0],
+            pattern_angle: [0.0, 90.0],
+            circular_count: 6,
+            circular_angle: 360.0,
+            move_offset: [1.0, 0.0],
+            rotate_angle: 90.0,
+            scale_factor: 2.0,
+            transform_copy: false,
+            extrude_distance: 0.0,
+            revolve_angle: 360.0,
+            sweep_orientation: SweepOrientation::default(),
+            sweep_twist: 0.0,
+            extrude_operation: ExtrudeOperation::default(),
+            message: String::new(),
+        }
+    }
+}
+
+/// 描画中のポリラインの最初の点と、最初と直前の直線
+#[derive(Clone, Copy)]
+struct PolylineDraft {
+    first_point: Vec3,
+    first_line: Entity,
+    last_line: Entity,
+}
+
+/// 編集中のスケッチ
+#[derive(Resource, Default)]
+struct ActiveSketch(Option<Entity>);
+
+/// スケッチからソリッドを作るフィーチャーの追加をトリガーするイベント
+#[derive(Event, Clone, Copy, PartialEq, Eq)]
+enum SolidFeatureEvent {
+    Extrude,
+    /// 選択した直線を軸に回転する
+    Revolve,
+    /// 選択した直線・円弧・スプラインか、設定したパスに沿ってスイープする
+    Sweep,
+    /// 選択した直線・円弧・スプラインを、別のスケッチでも使えるスイープのパスにする
+    SetSweepPath,
+    /// 選択したプロファイルをロフトの断面に加える
+    AddLoftSection,
+    /// 加えた断面をつないでロフトする
+    Loft,
+}
+
+/// 選択したスケッチのエンティティを編集するイベント
+#[derive(Event, Clone, Copy, PartialEq, Eq)]
+enum SketchEditEvent {
+    /// 補助線と通常の線を切り替える
+    ToggleConstruction,
+    /// 選択した直線・円弧のつながりを平行にずらしたコピーを作る
+    Offset,
+    /// 選択した図形をスケッチのx軸とy軸の方向に並べたコピーを作る
+    LinearPattern,
+    /// 選択した図形を数値で指定した量だけ動かす
+    Move,
+}
 
 fn main() {
     App::new()
         .init_state::<AppState>()
         .init_resource::<SketchData>()
+        .init_resource::<LoftSections>()
+        .init_resource::<SweepPath>()
         .init_resource::<ActiveSketchTool>()
-        .add_event::<ExtrudeEvent>() // ExtrudeEventを登録
+        .init_resource::<DocumentState>()
+        .init_resource::<ActiveSketch>()
+        .init_resource::<SolverReport>()
+        .init_resource::<SketchProfiles>()
+        .init_resource::<BodySelection>()
+        .init_resource::<FeatureTree>()
+        .init_resource::<UndoHistory>()
+        .init_resource::<SplineHandleDrag>()
+        .add_event::<SolidFeatureEvent>()
+        .add_event::<SketchEditEvent>()
+        .add_event::<AddConstraintEvent>()
+        .add_event::<AddDimensionEvent>()
+        .add_event::<CombineEvent>()
         .add_plugins(DefaultPlugins)
         .add_plugins(PanOrbitCameraPlugin)
         .add_plugins(EguiPlugin)
         .add_systems(Startup, (setup, configure_fonts))
-        .add_systems(Update, ui_system)
+        .add_systems(Update, (ui_system, history::undo_shortcut_system))
+        .add_systems(
+            Update,
+            (bodies::body_picking_system, bodies::combine_system)
+                .chain()
+                .run_if(in_state(AppState::Viewing)),
+        )
+        .add_systems(PostUpdate, bodies::highlight_bodies_system)
+        .add_systems(
+            PostUpdate,
+            (
+                document::document_io_system,
+                history::history_system,
+                features::mark_dirty_system,
+                features::regenerate_system,
+                brep::tessellate_bodies_system,
+            )
+                .chain(),
+        )
         .add_systems(OnEnter(AppState::Sketching), on_sketch_enter)
         .add_systems(
             Update,
             (
-                sketching_system.run_if(is_active_tool(ActiveSketchTool::Line)),
-                sketching_system.run_if(is_active_tool(ActiveSketchTool::Circle)),
-                sketching_system.run_if(is_active_tool(ActiveSketchTool::Rectangle)),
+                // ツールを切り替えたら、前のツールで描きかけの点を使わないよう先に片付ける
+                clear_drafts_on_tool_change
+                    .after(ui_system)
+                    .before(sketching_system)
+                    .before(transform::transform_tool_system),
+                // 選択と編集以外のツールはすべて作図のツール
+                sketching_system.run_if(not(is_active_tool(ActiveSketchTool::Select)).and_then(not(is_edit_tool_active))),
+                trim::curve_edit_system.run_if(
+                    is_active_tool(ActiveSketchTool::Trim)
+                        .or_else(is_active_tool(ActiveSketchTool::Extend))
+                        .or_else(is_active_tool(ActiveSketchTool::Split)),
+                ),
+                fillet::corner_edit_system
+                    .run_if(is_active_tool(ActiveSketchTool::Fillet).or_else(is_active_tool(ActiveSketchTool::Chamfer))),
+                pattern::pattern_tool_system.run_if(
+                    is_active_tool(ActiveSketchTool::Mirror).or_else(is_active_tool(ActiveSketchTool::CircularPattern)),
+                ),
+                transform::transform_tool_system.run_if(
+                    is_active_tool(ActiveSketchTool::Move)
+                        .or_else(is_active_tool(ActiveSketchTool::Rotate))
+                        .or_else(is_active_tool(ActiveSketchTool::Scale)),
+                ),
+                spline_handle_system.before(selection_system).run_if(is_active_tool(ActiveSketchTool::Select)),
                 selection_system.run_if(is_active_tool(ActiveSketchTool::Select)),
                 draw_sketch_gizmos,
                 draw_grid,
-                extrude_system, // 押し出しシステムを追加
+                profile::detect_profiles_system,
+                profile::draw_profile_gizmos.after(profile::detect_profiles_system),
+                solid_feature_system,
+                sketch_edit_system,
+                (
+                    constraints::add_constraint_system,
+                    dimensions::add_dimension_system,
+                    constraints::solve_constraints_system,
+                    pattern::update_copies_system,
+                )
+                    .chain(),
+                constraints::draw_constraint_gizmos,
+                dimensions::dimension_label_system,
             )
             .run_if(in_state(AppState::Sketching)),
         )
@@ -100,48 +563,318 @@ fn main() {
 
 /// UIを描画するシステム
 fn ui_system(
+    mut commands: Commands,
     mut contexts: EguiContexts,
     current_state: Res<State<AppState>>,
     mut next_state: ResMut<NextState<AppState>>,
     mut active_tool: ResMut<ActiveSketchTool>,
     mut sketch_data: ResMut<SketchData>,
-    mut extrude_events: EventWriter<ExtrudeEvent>,
+    mut solid_input: SolidFeatureInput,
+    mut edit_events: EventWriter<SketchEditEvent>,
+    mut document: ResMut<DocumentState>,
+    mut active_sketch: ResMut<ActiveSketch>,
+    mut q_sketches: Query<(Entity, &mut Sketch)>,
+    mut constraint_panel: ConstraintPanel,
+    mut dimension_panel: DimensionPanel,
+    mut combine_panel: CombinePanel,
+    mut feature_panel: FeaturePanel,
+    mut history: ResMut<UndoHistory>,
 ) {
     egui::SidePanel::left("side_panel").show(contexts.ctx_mut(), |ui| {
         ui.heading("QuillCAD");
         ui.separator();
 
+        ui.label("ファイル");
+        ui.text_edit_singleline(&mut document.path_input);
+        ui.horizontal(|ui| {
+            if ui.button("保存").clicked() {
+                document.pending = Some(FileAction::Save);
+            }
+            if ui.button("名前を付けて保存").clicked() {
+                let path = document.path_input.trim().into();
+                document.pending = Some(FileAction::SaveAs(path));
+            }
+            if ui.button("開く").clicked() {
+                let path = document.path_input.trim().into();
+                document.pending = Some(FileAction::Open(path));
+            }
+        });
+        if !document.status.is_empty() {
+            ui.label(&document.status);
+        }
+        ui.separator();
+
         match current_state.get() {
             AppState::Viewing => {
                 if ui.button("スケッチ開始").clicked() {
+                    active_sketch.0 = None; // 新しいスケッチを作成する
                     next_state.set(AppState::Sketching);
                 }
+
+                ui.separator();
+
+                let sketch_names = q_sketches.iter().map(|(entity, sketch)| (entity, sketch.name.clone())).collect();
+                match feature_panel.show(ui, &sketch_names, &mut history) {
+                    Some(FeatureAction::EditSketch(entity)) => {
+                        active_sketch.0 = Some(entity);
+                        next_state.set(AppState::Sketching);
+                    }
+                    Some(FeatureAction::DeleteSketch(entity)) => {
+                        commands.entity(entity).despawn_recursive();
+                        solid_input.loft_sections.0.retain(|section| section.sketch != entity);
+                        if solid_input.sweep_path.0.as_ref().is_some_and(|(sketch, _)| *sketch == entity) {
+                            solid_input.sweep_path.0 = None;
+                        }
+                    }
+                    None => {}
+                }
+
+                ui.separator();
+
+                combine_panel.show(ui);
             }
             AppState::Sketching => {
                 ui.label("スケッチモード");
+                if let Some(Ok((_, mut sketch))) = active_sketch.0.map(|e| q_sketches.get_mut(e)) {
+                    let mut name = sketch.name.clone();
+                    let response = ui.text_edit_singleline(&mut name);
+                    if response.changed() {
+                        sketch.name = name;
+                    }
+                    if response.lost_focus() {
+                        history.record("スケッチ名を変更");
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("平面の高さ");
+                        let mut offset = sketch.plane.offset();
+                        let response = ui.add(egui::DragValue::new(&mut offset).speed(0.1).suffix("m"));
+                        if response.changed() {
+                            sketch.plane.set_offset(offset);
+                        }
+                        history.track_drag(&response, "スケッチ平面を移動");
+                    });
+                }
                 ui.separator();
 
                 ui.label("ツール選択");
                 let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Line, "直線");
+                let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Polyline, "ポリライン");
                 let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Circle, "円");
-                let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Rectangle, "四角形");
+                ui.horizontal(|ui| {
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Rectangle, "四角形");
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::CenterRectangle, "中心四角形");
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::ThreePointRectangle, "3点四角形");
+                });
+                ui.horizontal(|ui| {
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::ThreePointArc, "3点円弧");
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::CenterArc, "中心円弧");
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::TangentArc, "接線円弧");
+                });
+                ui.horizontal(|ui| {
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Spline, "スプライン");
+                    if *active_tool == ActiveSketchTool::Spline {
+                        ui.radio_value(&mut sketch_data.spline_fit, true, "通過点");
+                        ui.radio_value(&mut sketch_data.spline_fit, false, "制御点");
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Polygon, "多角形");
+                    if *active_tool == ActiveSketchTool::Polygon {
+                        ui.add(egui::DragValue::new(&mut sketch_data.polygon_sides).clamp_range(3..=64).suffix("辺"));
+                        ui.radio_value(&mut sketch_data.polygon_inscribed, true, "内接");
+                        ui.radio_value(&mut sketch_data.polygon_inscribed, false, "外接");
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Slot, "長穴");
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Ellipse, "楕円");
+                });
+                ui.horizontal(|ui| {
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Trim, "トリム");
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Extend, "延長");
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Split, "分割");
+                });
+                ui.horizontal(|ui| {
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Fillet, "フィレット");
+                    if *active_tool == ActiveSketchTool::Fillet {
+                        ui.label("半径:");
+                        ui.add(egui::DragValue::new(&mut sketch_data.fillet_radius).speed(0.01).clamp_range(0.0..=f32::MAX).suffix("m"));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Chamfer, "面取り");
+                    if *active_tool == ActiveSketchTool::Chamfer {
+                        ui.radio_value(&mut sketch_data.chamfer_mode, ChamferMode::DistanceDistance, "距離-距離");
+                        ui.radio_value(&mut sketch_data.chamfer_mode, ChamferMode::DistanceAngle, "距離-角度");
+                    }
+                });
+                if *active_tool == ActiveSketchTool::Chamfer {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut sketch_data.chamfer_distance).speed(0.01).clamp_range(0.0..=f32::MAX).suffix("m"));
+                        match sketch_data.chamfer_mode {
+                            ChamferMode::DistanceDistance => {
+                                ui.add(egui::DragValue::new(&mut sketch_data.chamfer_distance2).speed(0.01).clamp_range(0.0..=f32::MAX).suffix("m"));
+                            }
+                            ChamferMode::DistanceAngle => {
+                                ui.add(egui::DragValue::new(&mut sketch_data.chamfer_angle).speed(1.0).clamp_range(0.0..=180.0).suffix("°"));
+                            }
+                        }
+                    });
+                }
                 let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Select, "選択");
+                if ui.button("補助線の切り替え").on_hover_text("選択した図形を補助線と通常の線で切り替えます").clicked() {
+                    edit_events.send(SketchEditEvent::ToggleConstruction);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("オフセット").on_hover_text("選択した直線・円弧のつながりを平行にずらしたコピーを作ります").clicked() {
+                        edit_events.send(SketchEditEvent::Offset);
+                    }
+                    ui.add(egui::DragValue::new(&mut sketch_data.offset_distance).speed(0.01).suffix("m"));
+                    ui.radio_value(&mut sketch_data.offset_join, OffsetJoin::Round, "円弧");
+                    ui.radio_value(&mut sketch_data.offset_join, OffsetJoin::Miter, "留め継ぎ");
+                });
+                let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Mirror, "ミラー")
+                    .on_hover_text("選択した図形を、クリックした直線を軸に反転したコピーを作ります");
+                ui.horizontal(|ui| {
+                    if ui.button("直線パターン").on_hover_text("選択した図形を2つの方向に並べたコピーを作ります").clicked() {
+                        edit_events.send(SketchEditEvent::LinearPattern);
+                    }
+                });
+                for axis in 0..2 {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("方向{}:", axis + 1));
+                        ui.add(egui::DragValue::new(&mut sketch_data.pattern_count[axis]).clamp_range(1..=100).suffix("個"));
+                        ui.add(egui::DragValue::new(&mut sketch_data.pattern_spacing[axis]).speed(0.01).suffix("m"));
+                        let angle = egui::DragValue::new(&mut sketch_data.pattern_angle[axis]).speed(1.0).clamp_range(-360.0..=360.0);
+                        ui.add(angle.suffix("°")).on_hover_text("スケッチのx軸から反時計回りに測った方向");
+                    });
+                }
+                ui.horizontal(|ui| {
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::CircularPattern, "円形パターン")
+                        .on_hover_text("選択した図形を、クリックした点のまわりに並べたコピーを作ります");
+                    if *active_tool == ActiveSketchTool::CircularPattern {
+                        ui.add(egui::DragValue::new(&mut sketch_data.circular_count).clamp_range(2..=360).suffix("個"));
+                        ui.add(egui::DragValue::new(&mut sketch_data.circular_angle).speed(1.0).clamp_range(-360.0..=360.0).suffix("°"));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Move, "移動")
+                        .on_hover_text("基準点と移動先をクリックして選択した図形を動かします");
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Rotate, "回転")
+                        .on_hover_text("クリックした点のまわりに選択した図形を回します");
+                    let _ = ui.selectable_value(active_tool.as_mut(), ActiveSketchTool::Scale, "拡大縮小")
+                        .on_hover_text("クリックした点を中心に選択した図形を拡大縮小します");
+                    ui.checkbox(&mut sketch_data.transform_copy, "コピー");
+                });
+                match *active_tool {
+                    ActiveSketchTool::Move => {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut sketch_data.move_offset[0]).speed(0.01).prefix("x: ").suffix("m"));
+                            ui.add(egui::DragValue::new(&mut sketch_data.move_offset[1]).speed(0.01).prefix("y: ").suffix("m"));
+                            if ui.button("数値で移動").clicked() {
+                                edit_events.send(SketchEditEvent::Move);
+                            }
+                        });
+                    }
+                    ActiveSketchTool::Rotate => {
+                        ui.horizontal(|ui| {
+                            ui.label("角度:");
+                            ui.add(egui::DragValue::new(&mut sketch_data.rotate_angle).speed(1.0).clamp_range(-360.0..=360.0).suffix("°"));
+                        });
+                    }
+                    ActiveSketchTool::Scale => {
+                        ui.horizontal(|ui| {
+                            ui.label("倍率:");
+                            ui.add(egui::DragValue::new(&mut sketch_data.scale_factor).speed(0.01).clamp_range(0.01..=100.0));
+                        });
+                    }
+                    _ => {}
+                }
 
                 ui.separator();
 
-                ui.label("押し出し");
-                ui.add(egui::DragValue::new(&mut sketch_data.extrude_distance).speed(0.1).suffix("m"));
-                if ui.button("押し出し").clicked() {
-                    extrude_events.send(ExtrudeEvent);
+                ui.label("押し出し・回転・スイープ・ロフト");
+                ui.horizontal(|ui| {
+                    for operation in ExtrudeOperation::ALL {
+                        ui.selectable_value(&mut sketch_data.extrude_operation, operation, operation.label());
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut sketch_data.extrude_distance).speed(0.1).suffix("m"));
+                    if ui.button("押し出し").clicked() {
+                        solid_input.events.send(SolidFeatureEvent::Extrude);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut sketch_data.revolve_angle)
+                            .speed(1.0)
+                            .clamp_range(0.0..=360.0)
+                            .suffix("°"),
+                    );
+                    if ui.button("回転").on_hover_text("選択した直線を軸に回転します").clicked() {
+                        solid_input.events.send(SolidFeatureEvent::Revolve);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    for orientation in SweepOrientation::ALL {
+                        ui.selectable_value(&mut sketch_data.sweep_orientation, orientation, orientation.label());
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("ねじれ");
+                    ui.add(egui::DragValue::new(&mut sketch_data.sweep_twist).speed(1.0).suffix("°"));
+                    if ui.button("スイープ").on_hover_text("選択したつながった直線・円弧・スプラインに沿ってスイープします").clicked() {
+                        solid_input.events.send(SolidFeatureEvent::Sweep);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let hover = "選択した直線・円弧・スプラインを、パスを選択していない時のスイープのパスにします。別のスケッチでも使えます";
+                    if ui.button("パスに設定").on_hover_text(hover).clicked() {
+                        solid_input.events.send(SolidFeatureEvent::SetSweepPath);
+                    }
+                    if let Some((_, path)) = &solid_input.sweep_path.0 {
+                        ui.label(format!("パス {}本", path.len()));
+                        if ui.button("解除").clicked() {
+                            solid_input.sweep_path.0 = None;
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("断面に追加").on_hover_text("スケッチ平面の高さに断面を置きます").clicked() {
+                        solid_input.events.send(SolidFeatureEvent::AddLoftSection);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label(format!("断面 {}", solid_input.loft_sections.0.len()));
+                    if ui.button("ロフト").clicked() {
+                        solid_input.events.send(SolidFeatureEvent::Loft);
+                    }
+                    if ui.button("クリア").clicked() {
+                        solid_input.loft_sections.0.clear();
+                    }
+                });
+                if !sketch_data.message.is_empty() {
+                    ui.colored_label(egui::Color32::YELLOW, &sketch_data.message);
                 }
 
                 ui.separator();
 
+                if let Some(sketch) = active_sketch.0 {
+                    constraint_panel.show(ui, sketch, &mut history);
+                    ui.separator();
+                    dimension_panel.show(ui, sketch, &mut history);
+                    ui.separator();
+                }
+
                 if ui.button("スケッチ完了").clicked() {
                     next_state.set(AppState::Viewing);
                 }
             }
         }
+
+        ui.separator();
+        history.show(ui);
     });
 }
 
@@ -152,24 +885,29 @@ fn on_sketch_enter(
     mut cube_query: Query<&mut Visibility, (With<MainCube>, Without<SketchPlane>)>,
     mut plane_query: Query<&mut Visibility, (With<SketchPlane>, Without<MainCube>)>,
     mut camera_query: Query<(&mut Transform, &mut PanOrbitCamera), With<Camera3d>>,
-    // 既存のスケッチエンティティを削除するためのクエリ
-    q_lines: Query<Entity, With<SketchLine>>,
-    q_circles: Query<Entity, With<SketchCircle>>,
-    q_rectangles: Query<Entity, With<SketchRectangle>>,
+    mut active_sketch: ResMut<ActiveSketch>,
+    q_sketches: Query<&Sketch>,
+    mut tree: ResMut<FeatureTree>,
+    mut history: ResMut<UndoHistory>,
 ) {
     println!("スケッチモードに入りました.");
     *sketch_data = SketchData::default();
 
-    // 既存のスケッチエンティティをすべて削除
-    for entity in q_lines.iter() {
-        commands.entity(entity).despawn();
-    }
-    for entity in q_circles.iter() {
-        commands.entity(entity).despawn();
-    }
-    for entity in q_rectangles.iter() {
-        commands.entity(entity).despawn();
-    }
+    // 既存のスケッチを編集するか、新しいスケッチを作成する
+    let sketch = match active_sketch.0 {
+        Some(entity) if q_sketches.contains(entity) => entity,
+        _ => {
+            let name = (1..)
+                .map(|n| format!("スケッチ{n}"))
+                .find(|name| q_sketches.iter().all(|sketch| &sketch.name != name))
+                .unwrap();
+            let sketch = commands.spawn(Sketch { name: name.clone(), plane: default() }).id();
+            tree.insert(name, FeatureKind::Sketch(sketch));
+            history.record("スケッチを作成");
+            sketch
+        }
+    };
+    active_sketch.0 = Some(sketch);
 
     let mut cube_visibility = cube_query.single_mut();
     *cube_visibility = Visibility::Hidden;
@@ -182,15 +920,32 @@ fn on_sketch_enter(
     pan_orbit.button_orbit = MouseButton::Middle;
 }
 
+/// ツールが切り替わった時に、描きかけの点・ポリライン・スプラインと移動の基準点を消す。
+/// ツールのボタンは毎フレーム `ActiveSketchTool` を書き換えるので、前のフレームのツールと比べる
+fn clear_drafts_on_tool_change(
+    active_tool: Res<ActiveSketchTool>,
+    mut sketch_data: ResMut<SketchData>,
+    mut previous: Local<Option<ActiveSketchTool>>,
+) {
+    if previous.replace(*active_tool).is_some_and(|tool| tool != *active_tool) {
+        sketch_data.start_point = None;
+        sketch_data.second_point = None;
+        sketch_data.polyline = None;
+        sketch_data.spline_points.clear();
+    }
+}
+
 /// Sketching状態から出る時に呼ばれる関数
 fn on_sketch_exit(
     mut sketch_data: ResMut<SketchData>,
     mut cube_query: Query<&mut Visibility, (With<MainCube>, Without<SketchPlane>)>,
     mut plane_query: Query<&mut Visibility, (With<SketchPlane>, Without<MainCube>)>,
     mut camera_query: Query<&mut PanOrbitCamera>,
+    mut active_sketch: ResMut<ActiveSketch>,
 ) {
     println!("表示モードに戻ります.");
     *sketch_data = SketchData::default(); // start_pointをクリア
+    active_sketch.0 = None;
 
     let mut cube_visibility = cube_query.single_mut();
     *cube_visibility = Visibility::Visible;
@@ -225,222 +980,662 @@ fn sketching_system(
     mut contexts: EguiContexts,
     mut sketch_data: ResMut<SketchData>,
     active_tool: Res<ActiveSketchTool>,
+    active_sketch: Res<ActiveSketch>,
     mouse_buttons: Res<ButtonInput<MouseButton>>,
     q_window: Query<&Window, With<PrimaryWindow>>,
     q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    q_lines: Query<(&SketchLine, &Parent)>,
+    q_shapes: Query<(SketchShape, &Parent)>,
+    mut history: ResMut<UndoHistory>,
 ) {
     if contexts.ctx_mut().is_using_pointer() {
         return;
     }
+    let Some(sketch) = active_sketch.0 else {
+        return;
+    };
 
     let window = q_window.single();
     let (camera, camera_transform) = q_camera.single();
+    let frame = SketchFrame::default();
 
     if let Some(world_pos) = screen_to_world(window, camera, camera_transform) {
+        // 補助線を含む図形の端点や中心の近くをクリックしたらその点に吸着する
+        let shapes = q_shapes.iter().filter(|(_, parent)| parent.get() == sketch).map(|(shape, _)| shape);
+        let world_pos = frame.to_world(profile::snap_point(&frame, shapes, frame.to_local(world_pos)));
         if mouse_buttons.just_pressed(MouseButton::Left) {
-            if let Some(start_pos) = sketch_data.start_point {
-                match *active_tool {
-                    ActiveSketchTool::Line => {
-                        commands.spawn(SketchLine { p1: start_pos, p2: world_pos });
+            match (*active_tool, sketch_data.start_point, sketch_data.second_point) {
+                (ActiveSketchTool::Line, Some(start_pos), _) => {
+                    commands.spawn(SketchLine { p1: start_pos, p2: world_pos }).set_parent(sketch);
+                    sketch_data.tangent_start = Some((world_pos, (world_pos - start_pos).normalize_or_zero()));
+                    history.record("直線を追加");
+                    sketch_data.start_point = None;
+                }
+                (ActiveSketchTool::Polyline, Some(start_pos), _) => {
+                    add_polyline_segment(&mut commands, &mut sketch_data, &mut history, &q_lines, sketch, start_pos, world_pos);
+                }
+                (ActiveSketchTool::Circle, Some(start_pos), _) => {
+                    let radius = start_pos.distance(world_pos);
+                    commands.spawn(SketchCircle { center: start_pos, radius }).set_parent(sketch);
+                    history.record("円を追加");
+                    sketch_data.start_point = None;
+                }
+                (ActiveSketchTool::Rectangle, Some(start_pos), _) => {
+                    commands.spawn(SketchRectangle { p1: start_pos, p2: world_pos, angle: 0.0 }).set_parent(sketch);
+                    history.record("四角形を追加");
+                    sketch_data.start_point = None;
+                }
+                (ActiveSketchTool::CenterRectangle, Some(center), _) => {
+                    commands.spawn(SketchRectangle { p1: 2.0 * center - world_pos, p2: world_pos, angle: 0.0 }).set_parent(sketch);
+                    history.record("四角形を追加");
+                    sketch_data.start_point = None;
+                }
+                // 円弧は3回目のクリックで確定する
+                (ActiveSketchTool::ThreePointArc, Some(start_pos), Some(end_pos)) => {
+                    let curve = arc_through(frame.to_local(start_pos), frame.to_local(world_pos), frame.to_local(end_pos));
+                    add_arc(&mut commands, &mut sketch_data, &mut history, sketch, curve);
+                }
+                (ActiveSketchTool::CenterArc, Some(center), Some(start_pos)) => {
+                    let (center, start_pos) = (frame.to_local(center), frame.to_local(start_pos));
+                    let curve = (center != start_pos).then(|| arc_from_points(center, start_pos, frame.to_local(world_pos)));
+                    add_arc(&mut commands, &mut sketch_data, &mut history, sketch, curve);
+                }
+                (ActiveSketchTool::Polygon, Some(center), _) => {
+                    if center != world_pos {
+                        let (sides, inscribed) = (sketch_data.polygon_sides, sketch_data.polygon_inscribed);
+                        commands.spawn(SketchPolygon { center, point: world_pos, sides, inscribed }).set_parent(sketch);
+                        history.record("多角形を追加");
                     }
-                    ActiveSketchTool::Circle => {
-                        let radius = start_pos.distance(world_pos);
-                        commands.spawn(SketchCircle { center: start_pos, radius });
+                    sketch_data.start_point = None;
+                }
+                // 3点四角形・長穴・楕円は3回目のクリックで幅を決めて確定する
+                (ActiveSketchTool::ThreePointRectangle, Some(a), Some(b)) => {
+                    if a != b {
+                        commands.spawn(SketchRectangle::three_point(a, b, world_pos)).set_parent(sketch);
+                        history.record("四角形を追加");
                     }
-                    ActiveSketchTool::Rectangle => {
-                        commands.spawn(SketchRectangle { p1: start_pos, p2: world_pos });
+                    sketch_data.start_point = None;
+                    sketch_data.second_point = None;
+                }
+                (ActiveSketchTool::Slot, Some(a), Some(b)) => {
+                    let radius = point_line_segment_distance_sq(world_pos, a, b).sqrt();
+                    if radius > 0.0 {
+                        commands.spawn(SketchSlot { a, b, radius }).set_parent(sketch);
+                        history.record("長穴を追加");
                     }
-                    _ => {},
+                    sketch_data.start_point = None;
+                    sketch_data.second_point = None;
+                }
+                (ActiveSketchTool::Ellipse, Some(center), Some(major)) => {
+                    let minor_radius = ellipse_minor_radius(center, major, world_pos);
+                    if center != major && minor_radius > 0.0 {
+                        commands.spawn(SketchEllipse { center, major, minor_radius }).set_parent(sketch);
+                        history.record("楕円を追加");
+                    }
+                    sketch_data.start_point = None;
+                    sketch_data.second_point = None;
+                }
+                (
+                    ActiveSketchTool::ThreePointArc
+                    | ActiveSketchTool::CenterArc
+                    | ActiveSketchTool::ThreePointRectangle
+                    | ActiveSketchTool::Slot
+                    | ActiveSketchTool::Ellipse,
+                    Some(_),
+                    None,
+                ) => {
+                    sketch_data.second_point = Some(world_pos);
+                }
+                (ActiveSketchTool::TangentArc, _, _) => match sketch_data.tangent_start {
+                    Some((start_pos, tangent)) => {
+                        let curve = tangent_arc(
+                            frame.to_local(start_pos),
+                            frame.to_local(start_pos + tangent) - frame.to_local(start_pos),
+                            frame.to_local(world_pos),
+                        );
+                        add_arc(&mut commands, &mut sketch_data, &mut history, sketch, curve);
+                    }
+                    // 続ける図形がなければ、クリックした直線の端点から外向きに続ける
+                    None => {
+                        sketch_data.tangent_start = q_lines
+                            .iter()
+                            .filter(|(_, parent)| parent.get() == sketch)
+                            .flat_map(|(line, _)| [(line.p2, line.p2 - line.p1), (line.p1, line.p1 - line.p2)])
+                            .filter(|(end, _)| end.distance(world_pos) < 0.1)
+                            .min_by(|a, b| a.0.distance(world_pos).total_cmp(&b.0.distance(world_pos)))
+                            .map(|(end, direction)| (end, direction.normalize_or_zero()));
+                    }
+                },
+                // スプラインは点を足していき、最初の点をクリックすると閉じて確定する
+                (ActiveSketchTool::Spline, _, _) => {
+                    let points = &mut sketch_data.spline_points;
+                    if points.len() >= 3 && points[0].distance(world_pos) < 0.1 {
+                        points.push(points[0]);
+                        add_spline(&mut commands, &mut sketch_data, &mut history, sketch);
+                    } else if points.last() != Some(&world_pos) {
+                        points.push(world_pos);
+                    }
+                }
+                // 選択と編集のツールは別のシステムで扱う
+                (
+                    ActiveSketchTool::Select
+                    | ActiveSketchTool::Trim
+                    | ActiveSketchTool::Extend
+                    | ActiveSketchTool::Split
+                    | ActiveSketchTool::Fillet
+                    | ActiveSketchTool::Chamfer
+                    | ActiveSketchTool::Mirror
+                    | ActiveSketchTool::CircularPattern
+                    | ActiveSketchTool::Move
+                    | ActiveSketchTool::Rotate
+                    | ActiveSketchTool::Scale,
+                    _,
+                    _,
+                ) => {}
+                (_, None, _) => {
+                    sketch_data.start_point = Some(world_pos);
+                    sketch_data.polyline = None;
                 }
-                sketch_data.start_point = None;
-            } else {
-                sketch_data.start_point = Some(world_pos);
             }
         }
 
         if mouse_buttons.just_pressed(MouseButton::Right) {
+            if *active_tool == ActiveSketchTool::Spline {
+                add_spline(&mut commands, &mut sketch_data, &mut history, sketch);
+            }
+            sketch_data.start_point = None;
+            sketch_data.second_point = None;
+            sketch_data.tangent_start = None;
+            sketch_data.polyline = None;
+        }
+    }
+}
+
+/// ポリラインの次の直線を追加する。前の直線の終点とは一致拘束でつなぎ、最初の点をクリックした時は閉じて終える
+fn add_polyline_segment(
+    commands: &mut Commands,
+    sketch_data: &mut SketchData,
+    history: &mut UndoHistory,
+    q_lines: &Query<(&SketchLine, &Parent)>,
+    sketch: Entity,
+    start_pos: Vec3,
+    world_pos: Vec3,
+) {
+    // 元に戻すなどで消えた直線にはつながない
+    let draft = sketch_data.polyline.filter(|draft| q_lines.contains(draft.last_line));
+    let closing = draft.filter(|draft| draft.first_line != draft.last_line && draft.first_point.distance(world_pos) < 0.1);
+    let end_pos = closing.map_or(world_pos, |draft| draft.first_point);
+    if end_pos == start_pos {
+        return;
+    }
+    let line = commands.spawn(SketchLine { p1: start_pos, p2: end_pos }).set_parent(sketch).id();
+    let coincident = |a: Entity, a_kind: PointKind, b: Entity, b_kind: PointKind| {
+        SketchConstraint(ConstraintKind::Coincident(PointRef { entity: a, kind: a_kind }, PointRef { entity: b, kind: b_kind }))
+    };
+    if let Some(draft) = draft {
+        commands.spawn(coincident(draft.last_line, PointKind::End, line, PointKind::Start)).set_parent(sketch);
+    }
+    sketch_data.tangent_start = Some((end_pos, (end_pos - start_pos).normalize_or_zero()));
+    match closing {
+        Some(draft) => {
+            if q_lines.contains(draft.first_line) {
+                commands.spawn(coincident(line, PointKind::End, draft.first_line, PointKind::Start)).set_parent(sketch);
+            }
             sketch_data.start_point = None;
+            sketch_data.polyline = None;
+            history.record("ポリラインを閉じる");
+        }
+        None => {
+            sketch_data.start_point = Some(end_pos);
+            sketch_data.polyline = Some(PolylineDraft {
+                first_point: draft.map_or(start_pos, |draft| draft.first_point),
+                first_line: draft.map_or(line, |draft| draft.first_line),
+                last_line: line,
+            });
+            history.record("直線を追加");
         }
     }
 }
 
+/// 描いた円弧を追加し、その終点から接線円弧を続けられるようにする。円弧にならない入力は無視する
+fn add_arc(
+    commands: &mut Commands,
+    sketch_data: &mut SketchData,
+    history: &mut UndoHistory,
+    sketch: Entity,
+    curve: Option<Curve2d>,
+) {
+    sketch_data.start_point = None;
+    sketch_data.second_point = None;
+    let frame = SketchFrame::default();
+    let Some((arc, curve)) = curve.and_then(|curve| Some((SketchArc::from_curve(&frame, &curve)?, curve))) else {
+        return;
+    };
+    let tangent = frame.to_world(curve.end_tangent()) - frame.origin;
+    sketch_data.tangent_start = Some((frame.to_world(curve.end()), tangent));
+    commands.spawn(arc).set_parent(sketch);
+    history.record("円弧を追加");
+}
+
+/// 描画中の点からスプラインを追加し、その終点から接線円弧を続けられるようにする。曲線にならない場合は破棄する
+fn add_spline(commands: &mut Commands, sketch_data: &mut SketchData, history: &mut UndoHistory, sketch: Entity) {
+    let points = std::mem::take(&mut sketch_data.spline_points);
+    let spline = SketchSpline { points, fit: sketch_data.spline_fit, knots: Vec::new() };
+    let frame = SketchFrame::default();
+    let Some(curve) = spline.curve(&frame) else {
+        return;
+    };
+    let tangent = frame.to_world(curve.end_tangent()) - frame.origin;
+    sketch_data.tangent_start = Some((frame.to_world(curve.end()), tangent));
+    commands.spawn(spline).set_parent(sketch);
+    history.record("スプラインを追加");
+}
+
+/// 選択中のスプラインの点 (通過点または制御点) をドラッグで動かすシステム
+fn spline_handle_system(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    active_sketch: Res<ActiveSketch>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    mut q_splines: Query<(Entity, &mut SketchSpline, &Parent), With<Selected>>,
+    mut drag: ResMut<SplineHandleDrag>,
+    mut history: ResMut<UndoHistory>,
+) {
+    if drag.0.is_some() && !mouse_buttons.pressed(MouseButton::Left) {
+        drag.0 = None;
+        history.end_group();
+        return;
+    }
+    let window = q_window.single();
+    let (camera, camera_transform) = q_camera.single();
+    let Some(world_pos) = screen_to_world(window, camera, camera_transform) else {
+        return;
+    };
+
+    if mouse_buttons.just_pressed(MouseButton::Left) && !contexts.ctx_mut().is_using_pointer() {
+        drag.0 = q_splines
+            .iter()
+            .filter(|(_, _, parent)| in_sketch(Some(*parent), active_sketch.0))
+            .flat_map(|(entity, spline, _)| spline.points.iter().enumerate().map(move |(i, &p)| (entity, i, p)))
+            .filter(|(_, _, p)| p.distance(world_pos) < 0.1)
+            .min_by(|a, b| a.2.distance(world_pos).total_cmp(&b.2.distance(world_pos)))
+            .map(|(entity, i, _)| (entity, i));
+        if let Some((entity, _)) = drag.0 {
+            // 点を動かしたコピーは元の図形から切り離す
+            commands.entity(entity).remove::<SketchCopy>();
+            history.begin_group("スプラインの点を移動");
+        }
+    }
+
+    let Some((entity, index)) = drag.0 else {
+        return;
+    };
+    let Ok((_, mut spline, _)) = q_splines.get_mut(entity) else {
+        drag.0 = None;
+        history.end_group();
+        return;
+    };
+    if spline.points[index] != world_pos {
+        // 閉じたスプラインは始点と終点を一緒に動かす
+        let last = spline.points.len() - 1;
+        let closed = last > 1 && spline.points[0] == spline.points[last];
+        spline.points[index] = world_pos;
+        if closed && (index == 0 || index == last) {
+            spline.points[last - index] = world_pos;
+        }
+        history.record("スプラインの点を移動");
+    }
+}
+
 /// スケッチの選択を処理するシステム
 fn selection_system(
     mut commands: Commands,
     mut contexts: EguiContexts,
     mouse_buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    active_sketch: Res<ActiveSketch>,
     q_window: Query<&Window, With<PrimaryWindow>>,
     q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
-    q_lines: Query<(Entity, &SketchLine, Option<&Selected>)>,
-    q_circles: Query<(Entity, &SketchCircle, Option<&Selected>)>,
-    q_rectangles: Query<(Entity, &SketchRectangle, Option<&Selected>)>,
+    q_shapes: Query<(Entity, SketchShape, Has<Selected>, Option<&Parent>)>,
+    handle_drag: Res<SplineHandleDrag>,
+    mut profiles: ResMut<SketchProfiles>,
+    mut history: ResMut<UndoHistory>,
+    q_constraints: Query<(Entity, &SketchConstraint)>,
+    q_dimensions: Query<(Entity, &SketchDimension)>,
+    q_copies: Query<(Entity, &SketchCopy)>,
 ) {
     if contexts.ctx_mut().is_using_pointer() {
         return;
     }
 
+    // Deleteキーで選択中の図形を、それを参照する拘束と寸法ごと削除する
+    if keys.just_pressed(KeyCode::Delete) && !contexts.ctx_mut().wants_keyboard_input() {
+        let selected: Vec<Entity> = q_shapes
+            .iter()
+            .filter(|(_, _, selected, parent)| *selected && in_sketch(*parent, active_sketch.0))
+            .map(|(entity, ..)| entity)
+            .collect();
+        if !selected.is_empty() {
+            trim::remove_entity_refs(&mut commands, &q_constraints, &q_dimensions, &q_copies, &selected);
+            for &entity in &selected {
+                commands.entity(entity).despawn_recursive();
+            }
+            history.record("図形を削除");
+        }
+    }
+
     let window = q_window.single();
     let (camera, camera_transform) = q_camera.single();
 
-    if mouse_buttons.just_pressed(MouseButton::Left) {
+    // スプラインの点のドラッグを始めたクリックでは選択を変えない
+    if mouse_buttons.just_pressed(MouseButton::Left) && handle_drag.0.is_none() {
         if let Some(world_mouse_pos) = screen_to_world(window, camera, camera_transform) {
-            let mut closest_entity: Option<Entity> = None;
-            let mut min_distance_sq = f32::MAX;
-            let tolerance_sq = 0.1 * 0.1; // 選択の許容範囲の二乗
-
-            // 直線の選択判定
-            for (entity, line, _) in q_lines.iter() {
-                let dist_sq = point_line_segment_distance_sq(world_mouse_pos, line.p1, line.p2);
-                if dist_sq < tolerance_sq && dist_sq < min_distance_sq {
-                    min_distance_sq = dist_sq;
-                    closest_entity = Some(entity);
+            let frame = SketchFrame::default();
+            let tolerance = 0.1; // 選択の許容範囲
+            let mouse = frame.to_local(world_mouse_pos);
+
+            // 図形を構成する曲線までの距離が最も近いものを選ぶ
+            let closest_entity = q_shapes
+                .iter()
+                .filter(|(.., parent)| in_sketch(*parent, active_sketch.0))
+                .filter_map(|(entity, shape, ..)| {
+                    let distance = profile::entity_curves(&frame, shape)
+                        .iter()
+                        .map(|curve| curve.distance(mouse))
+                        .fold(f32::MAX, f32::min);
+                    (distance < tolerance).then_some((entity, distance))
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(entity, _)| entity);
+
+            // Shiftキーを押している間は選択を追加・解除する
+            if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+                if let Some(entity) = closest_entity {
+                    let is_selected = q_shapes.get(entity).map(|q| q.2).unwrap_or(false);
+                    if is_selected {
+                        commands.entity(entity).remove::<Selected>();
+                    } else {
+                        commands.entity(entity).insert(Selected);
+                    }
                 }
+                return;
             }
 
-            // 円の選択判定
-            for (entity, circle, _) in q_circles.iter() {
-                let dist_sq = world_mouse_pos.distance_squared(circle.center);
-                // 円周からの距離を考慮
-                let dist_from_circumference_sq = (dist_sq.sqrt() - circle.radius).powi(2);
-                if dist_from_circumference_sq < tolerance_sq && dist_from_circumference_sq < min_distance_sq {
-                    min_distance_sq = dist_from_circumference_sq;
-                    closest_entity = Some(entity);
+            // 既存の選択をすべて解除
+            for (entity, _, selected, _) in q_shapes.iter() {
+                if selected {
+                    commands.entity(entity).remove::<Selected>();
                 }
             }
 
-            // 四角形の選択判定
-            for (entity, rect, _) in q_rectangles.iter() {
-                // 四角形の境界ボックス内にあるか、または境界線に近いか
-                let min_x = rect.p1.x.min(rect.p2.x);
-                let max_x = rect.p1.x.max(rect.p2.x);
-                let min_z = rect.p1.z.min(rect.p2.z);
-                let max_z = rect.p1.z.max(rect.p2.z);
-
-                let is_inside_x = world_mouse_pos.x >= min_x && world_mouse_pos.x <= max_x;
-                let is_inside_z = world_mouse_pos.z >= min_z && world_mouse_pos.z <= max_z;
+            // 新しい選択を適用。図形に当たらなければクリックした位置の領域を選択する
+            if let Some(entity) = closest_entity {
+                commands.entity(entity).insert(Selected);
+                profiles.selected = None;
+            } else {
+                profiles.selected = profiles.region_at(mouse).map(|_| mouse);
+            }
+        }
+    }
+}
 
-                // 簡易的な境界線判定（より正確には各線分との距離を測るべきだが、今回は簡易化）
-                let is_near_border = (world_mouse_pos.x - min_x).abs() < tolerance_sq.sqrt() ||
-                                     (world_mouse_pos.x - max_x).abs() < tolerance_sq.sqrt() ||
-                                     (world_mouse_pos.z - min_z).abs() < tolerance_sq.sqrt() ||
-                                     (world_mouse_pos.z - max_z).abs() < tolerance_sq.sqrt();
+/// 押し出し・回転・スイープ・ロフトフィーチャーを履歴に追加するシステム。ボディはフィーチャーの再生成で作られる
+fn solid_feature_system(
+    mut commands: Commands,
+    mut sketch_data: ResMut<SketchData>,
+    mut loft_sections: ResMut<LoftSections>,
+    mut sweep_path: ResMut<SweepPath>,
+    mut events: EventReader<SolidFeatureEvent>,
+    active_sketch: Res<ActiveSketch>,
+    q_selected: Query<(Entity, SketchShape, Option<&Parent>, Has<Construction>), With<Selected>>,
+    profiles: Res<SketchProfiles>,
+    mut tree: ResMut<FeatureTree>,
+    mut history: ResMut<UndoHistory>,
+) {
+    for event in events.read() {
+        let Some(sketch) = active_sketch.0 else {
+            continue;
+        };
+        // (エンティティ, 直線か, 円弧かスプラインか)
+        let mut selected: Vec<(Entity, bool, bool)> = q_selected
+            .iter()
+            .filter(|(_, _, parent, _)| in_sketch(*parent, active_sketch.0))
+            .map(|(entity, shape, ..)| (entity, shape.0.is_some(), shape.3.is_some() || shape.4.is_some()))
+            .collect();
+
+        // 回転では選択した直線を軸に、スイープでは直線・円弧・スプラインをパスにし、プロファイルからは除く
+        let lines: Vec<Entity> = selected.iter().filter(|(_, is_line, _)| *is_line).map(|(e, ..)| *e).collect();
+        let path: Vec<Entity> =
+            selected.iter().filter(|(_, is_line, is_arc)| *is_line || *is_arc).map(|(e, ..)| *e).collect();
+        match event {
+            SolidFeatureEvent::Extrude => {}
+            SolidFeatureEvent::Revolve if lines.len() != 1 => {
+                sketch_data.message = "回転軸にする直線を1本選択してください".to_string();
+                continue;
+            }
+            SolidFeatureEvent::Sweep | SolidFeatureEvent::SetSweepPath
+                if path.is_empty() && (sweep_path.0.is_none() || *event == SolidFeatureEvent::SetSweepPath) =>
+            {
+                sketch_data.message = "パスにする直線・円弧・スプラインを選択してください".to_string();
+                continue;
+            }
+            SolidFeatureEvent::SetSweepPath => {
+                sweep_path.0 = Some((sketch, path));
+                sketch_data.message.clear();
+                continue;
+            }
+            SolidFeatureEvent::Sweep => selected.retain(|(_, is_line, is_arc)| !*is_line && !*is_arc),
+            SolidFeatureEvent::Revolve | SolidFeatureEvent::AddLoftSection => {
+                selected.retain(|(_, is_line, _)| !*is_line)
+            }
+            // ロフトは追加済みの断面から作る
+            SolidFeatureEvent::Loft if loft_sections.0.len() < 2 => {
+                sketch_data.message = "ロフトには断面が2つ以上必要です".to_string();
+                continue;
+            }
+            SolidFeatureEvent::Loft => selected.clear(),
+        }
+        // 補助線は軸やパスには使えるが、プロファイルにはしない
+        selected.retain(|(entity, ..)| q_selected.get(*entity).is_ok_and(|(.., construction)| !construction));
+
+        // 選択された領域は内部の点で、選択された図形はエンティティで参照する
+        let mut references: Vec<ProfileRef> = profiles.selected.map(ProfileRef::Region).into_iter().collect();
+        for (entity, ..) in selected {
+            references.push(ProfileRef::Entity(entity));
+            // 元のスケッチを非表示。ロフトの断面は他の断面と合わせて見られるよう残す
+            if *event != SolidFeatureEvent::AddLoftSection {
+                commands.entity(entity).insert(Visibility::Hidden);
+            }
+        }
+        if references.is_empty() && *event != SolidFeatureEvent::Loft {
+            sketch_data.message = "プロファイルを選択してください".to_string();
+            continue;
+        }
+        sketch_data.message.clear();
+
+        let (name, kind) = match event {
+            SolidFeatureEvent::Extrude => (
+                tree.next_name("押し出し"),
+                FeatureKind::Extrude {
+                    sketch,
+                    profiles: references,
+                    distance: sketch_data.extrude_distance,
+                    operation: sketch_data.extrude_operation,
+                },
+            ),
+            SolidFeatureEvent::Revolve => (
+                tree.next_name("回転"),
+                FeatureKind::Revolve {
+                    sketch,
+                    profiles: references,
+                    axis: lines[0],
+                    angle: sketch_data.revolve_angle,
+                    operation: sketch_data.extrude_operation,
+                },
+            ),
+            SolidFeatureEvent::Sweep => {
+                // 編集中のスケッチでパスを選択していなければ、設定したパスを使う
+                let (path_sketch, path) = match &sweep_path.0 {
+                    Some(stored) if path.is_empty() => stored.clone(),
+                    _ => (sketch, path),
+                };
+                (
+                    tree.next_name("スイープ"),
+                    FeatureKind::Sweep {
+                        sketch,
+                        profiles: references,
+                        path,
+                        path_sketch,
+                        orientation: sketch_data.sweep_orientation,
+                        twist: sketch_data.sweep_twist,
+                        operation: sketch_data.extrude_operation,
+                    },
+                )
+            }
+            // パスの設定は選択を調べた時に済ませている
+            SolidFeatureEvent::SetSweepPath => continue,
+            SolidFeatureEvent::AddLoftSection => {
+                let [profile] = &references[..] else {
+                    sketch_data.message = "断面にするプロファイルを1つ選択してください".to_string();
+                    continue;
+                };
+                let section = LoftSection { sketch, profile: profile.clone(), start: 0.0 };
+                loft_sections.0.push(section);
+                continue;
+            }
+            SolidFeatureEvent::Loft => (
+                tree.next_name("ロフト"),
+                FeatureKind::Loft {
+                    sections: std::mem::take(&mut loft_sections.0),
+                    auto_align: true,
+                    operation: sketch_data.extrude_operation,
+                },
+            ),
+        };
+        history.record(format!("{name}を追加"));
+        tree.insert(name, kind);
+    }
+}
 
-                if (is_inside_x && is_inside_z) || is_near_border {
-                    // 四角形の場合、距離計算が複雑なので、一旦ヒットしたものを選択対象とする
-                    // より正確な距離計算が必要であれば、各辺との距離を計算する
-                    if 0.0 < min_distance_sq { // 既に他の図形がヒットしている場合は、そちらを優先しない
-                        min_distance_sq = 0.0; // ヒットしたとみなす
-                        closest_entity = Some(entity);
+/// 選択したスケッチのエンティティを編集するシステム
+fn sketch_edit_system(
+    mut commands: Commands,
+    mut sketch_data: ResMut<SketchData>,
+    mut events: EventReader<SketchEditEvent>,
+    active_sketch: Res<ActiveSketch>,
+    q_selected: Query<(Entity, SketchShape, Option<&Parent>, Has<Construction>), With<Selected>>,
+    mut history: ResMut<UndoHistory>,
+) {
+    let frame = SketchFrame::default();
+    for event in events.read() {
+        let Some(sketch) = active_sketch.0 else {
+            continue;
+        };
+        let selected: Vec<(Entity, bool)> = q_selected
+            .iter()
+            .filter(|(_, _, parent, _)| in_sketch(*parent, active_sketch.0))
+            .map(|(entity, _, _, construction)| (entity, construction))
+            .collect();
+        if selected.is_empty() {
+            continue;
+        }
+        match event {
+            // 通常の線が混ざっていればすべて補助線にし、すべて補助線なら通常の線に戻す
+            SketchEditEvent::ToggleConstruction => {
+                if selected.iter().all(|(_, construction)| *construction) {
+                    for (entity, _) in selected {
+                        commands.entity(entity).remove::<Construction>();
                     }
+                    history.record("通常の線に変更");
+                } else {
+                    for (entity, _) in selected {
+                        commands.entity(entity).insert(Construction);
+                    }
+                    history.record("補助線に変更");
                 }
             }
-
-            // 既存の選択をすべて解除
-            for (entity, _, selected) in q_lines.iter() {
-                if selected.is_some() {
-                    commands.entity(entity).remove::<Selected>();
+            SketchEditEvent::Offset => {
+                let curves: Vec<Curve2d> = q_selected
+                    .iter()
+                    .filter(|(_, _, parent, _)| in_sketch(*parent, active_sketch.0))
+                    .flat_map(|(_, shape, ..)| profile::entity_curves(&frame, shape))
+                    .collect();
+                match offset::offset_chain(&curves, sketch_data.offset_distance, sketch_data.offset_join) {
+                    Ok(result) => {
+                        for curve in &result {
+                            spawn_curve(&mut commands, &frame, sketch, curve);
+                        }
+                        sketch_data.message.clear();
+                        history.record("オフセット");
+                    }
+                    Err(message) => sketch_data.message = message.to_string(),
                 }
             }
-            for (entity, _, selected) in q_circles.iter() {
-                if selected.is_some() {
-                    commands.entity(entity).remove::<Selected>();
+            SketchEditEvent::LinearPattern => {
+                let angles = sketch_data.pattern_angle.map(f32::to_radians);
+                if sketch_data.pattern_count.iter().all(|&count| count > 1) && (angles[1] - angles[0]).sin().abs() < 1e-3 {
+                    sketch_data.message = "直線パターンの2つの方向が平行です".to_string();
+                    continue;
                 }
-            }
-            for (entity, _, selected) in q_rectangles.iter() {
-                if selected.is_some() {
-                    commands.entity(entity).remove::<Selected>();
+                let copies = pattern::linear_pattern(sketch_data.pattern_count, sketch_data.pattern_spacing, angles);
+                if copies.is_empty() {
+                    sketch_data.message = "パターンの個数を2個以上にしてください".to_string();
+                    continue;
+                }
+                let sources = q_selected.iter().filter(|(_, _, parent, _)| in_sketch(*parent, active_sketch.0));
+                for (entity, shape, _, construction) in sources {
+                    for (copy, transform) in &copies {
+                        pattern::spawn_copy(&mut commands, &frame, sketch, (entity, shape, construction), *copy, transform);
+                    }
                 }
+                sketch_data.message.clear();
+                history.record("直線パターン");
             }
-
-            // 新しい選択を適用
-            if let Some(entity) = closest_entity {
-                commands.entity(entity).insert(Selected);
+            SketchEditEvent::Move => {
+                let transform = Similarity::translation(Vec2::from_array(sketch_data.move_offset));
+                let shapes = q_selected
+                    .iter()
+                    .filter(|(_, _, parent, _)| in_sketch(*parent, active_sketch.0))
+                    .map(|(entity, shape, _, construction)| (entity, shape, construction));
+                transform::transform_shapes(&mut commands, &frame, sketch, shapes, &transform, sketch_data.transform_copy);
+                sketch_data.message.clear();
+                history.record(if sketch_data.transform_copy { "図形を移動してコピー" } else { "図形を移動" });
             }
         }
     }
 }
 
-/// 押し出し処理を行うシステム
-fn extrude_system(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    sketch_data: Res<SketchData>,
-    mut extrude_events: EventReader<ExtrudeEvent>,
-    q_selected_lines: Query<(Entity, &SketchLine), With<Selected>>,
-    q_selected_circles: Query<(Entity, &SketchCircle), With<Selected>>,
-    q_selected_rectangles: Query<(Entity, &SketchRectangle), With<Selected>>,
-) {
-    use bevy::render::render_asset::RenderAssetUsages;
-
-    for _event in extrude_events.read() {
-        println!("押し出しイベントを受信しました。");
-
-        let extrude_distance = sketch_data.extrude_distance;
-
-        // 選択された直線からの押し出し（面を生成）
-        for (entity, line) in q_selected_lines.iter() {
-            println!("直線から押し出し: {:?}", line);
-            // 直線を押し出すと面になる。頂点とインデックスを直接定義
-            let p1 = line.p1;
-            let p2 = line.p2;
-            let normal = Vec3::Y; // XZ平面からの押し出し
-
-            let v1 = p1;
-            let v2 = p2;
-            let v3 = p2 + normal * extrude_distance;
-            let v4 = p1 + normal * extrude_distance;
-
-            let mut mesh = Mesh::new(bevy::render::render_resource::PrimitiveTopology::TriangleList, RenderAssetUsages::default());
-            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![v1.to_array(), v2.to_array(), v3.to_array(), v4.to_array()]);
-            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 1.0, 0.0], [0.0, 1.0, 0.0], [0.0, 1.0, 0.0], [0.0, 1.0, 0.0]]);
-            mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]);
-            mesh.insert_indices(bevy::render::mesh::Indices::U32(vec![0, 1, 2, 0, 2, 3]));
-
-            commands.spawn(PbrBundle {
-                mesh: meshes.add(mesh),
-                material: materials.add(Color::rgb(0.7, 0.7, 0.7)),
-                ..default()
-            });
-            commands.entity(entity).insert(Visibility::Hidden); // 元のスケッチを非表示
+/// スケッチ上の直線・円弧・円をスケッチのエンティティとして追加する
+fn spawn_curve(commands: &mut Commands, frame: &SketchFrame, sketch: Entity, curve: &Curve2d) {
+    match *curve {
+        Curve2d::Line { a, b } => {
+            commands.spawn(SketchLine { p1: frame.to_world(a), p2: frame.to_world(b) }).set_parent(sketch);
         }
-
-        // 選択された円からの押し出し（円柱を生成）
-        for (entity, circle) in q_selected_circles.iter() {
-            println!("円から押し出し: {:?}", circle);
-            commands.spawn(PbrBundle {
-                mesh: meshes.add(Cylinder::new(circle.radius, extrude_distance).mesh()),
-                material: materials.add(Color::rgb(0.7, 0.7, 0.7)),
-                transform: Transform::from_xyz(circle.center.x, extrude_distance / 2.0, circle.center.z),
-                ..default()
-            });
-            commands.entity(entity).insert(Visibility::Hidden); // 元のスケッチを非表示
-        }
-
-        // 選択された四角形からの押し出し（直方体を生成）
-        for (entity, rect) in q_selected_rectangles.iter() {
-            println!("四角形から押し出し: {:?}", rect);
-            let min_x = rect.p1.x.min(rect.p2.x);
-            let max_x = rect.p1.x.max(rect.p2.x);
-            let min_z = rect.p1.z.min(rect.p2.z);
-            let max_z = rect.p1.z.max(rect.p2.z);
-
-            let width = max_x - min_x;
-            let depth = max_z - min_z;
-            let center_x = (min_x + max_x) / 2.0;
-            let center_z = (min_z + max_z) / 2.0;
-
-            commands.spawn(PbrBundle {
-                mesh: meshes.add(Cuboid::new(width, extrude_distance, depth).mesh()),
-                material: materials.add(Color::rgb(0.7, 0.7, 0.7)),
-                transform: Transform::from_xyz(center_x, extrude_distance / 2.0, center_z),
-                ..default()
-            });
-            commands.entity(entity).insert(Visibility::Hidden); // 元のスケッチを非表示
+        Curve2d::Circle { center, radius } => {
+            commands.spawn(SketchCircle { center: frame.to_world(center), radius }).set_parent(sketch);
+        }
+        _ => {
+            if let Some(arc) = SketchArc::from_curve(frame, curve) {
+                commands.spawn(arc).set_parent(sketch);
+            }
         }
     }
 }
 
+/// エンティティが指定したスケッチに属しているかを判定するヘルパー関数
+fn in_sketch(parent: Option<&Parent>, sketch: Option<Entity>) -> bool {
+    matches!((parent, sketch), (Some(parent), Some(sketch)) if parent.get() == sketch)
+}
+
+/// 楕円の中心と長軸の端点から、短軸の長さを決める点までの長軸からの距離
+fn ellipse_minor_radius(center: Vec3, major: Vec3, point: Vec3) -> f32 {
+    let axis = (major - center).normalize_or_zero();
+    let offset = point - center;
+    (offset - axis * offset.dot(axis)).length()
+}
+
 /// 点と線分の最短距離の二乗を計算するヘルパー関数
 fn point_line_segment_distance_sq(p: Vec3, a: Vec3, b: Vec3) -> f32 {
     let ap = p - a;
@@ -467,57 +1662,221 @@ fn draw_sketch_gizmos(
     active_tool: Res<ActiveSketchTool>,
     q_window: Query<&Window, With<PrimaryWindow>>,
     q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
-    // 新しいクエリ
-    q_lines: Query<(&SketchLine, Option<&Selected>)>,
-    q_circles: Query<(&SketchCircle, Option<&Selected>)>,
-    q_rectangles: Query<(&SketchRectangle, Option<&Selected>)>,
+    active_sketch: Res<ActiveSketch>,
+    q_shapes: Query<(SketchShape, Option<&Selected>, Option<&Parent>, Has<Construction>)>,
+    q_dimensions: Query<(Entity, &SketchDimension, &Parent)>,
+    q_geometry: GeometryQuery,
+    report: Res<SolverReport>,
 ) {
-    // 完成した線を描画
-    for (line, selected) in q_lines.iter() {
-        let color = if selected.is_some() { Color::BLUE } else { Color::WHITE };
-        gizmos.line(line.p1, line.p2, color);
-    }
-    // 完成した円を描画
-    for (circle, selected) in q_circles.iter() {
-        let color = if selected.is_some() { Color::BLUE } else { Color::WHITE };
-        gizmos.circle(circle.center, Direction3d::Y, circle.radius, color);
+    // 編集中でないスケッチは参照用に暗く描画する
+    let entity_color = |selected: Option<&Selected>, parent: Option<&Parent>, construction: bool| {
+        if !in_sketch(parent, active_sketch.0) {
+            Color::DARK_GRAY
+        } else if selected.is_some() {
+            Color::BLUE
+        } else if construction {
+            Color::ORANGE
+        } else {
+            Color::WHITE
+        }
+    };
+
+    // 完成した図形を描画。補助線は破線にし、選択中のスプラインは点をドラッグできるよう示す
+    let frame = SketchFrame::default();
+    for (shape, selected, parent, construction) in q_shapes.iter() {
+        let color = entity_color(selected, parent, construction);
+        for curve in profile::entity_curves(&frame, shape) {
+            let points: Vec<Vec3> = curve.tessellate().into_iter().map(|p| frame.to_world(p)).collect();
+            if construction {
+                draw_dashed(&mut gizmos, &points, color);
+            } else {
+                gizmos.linestrip(points, color);
+            }
+        }
+        if let (Some(spline), Some(_)) = (shape.4, selected) {
+            if in_sketch(parent, active_sketch.0) {
+                draw_spline_points(&mut gizmos, &spline.points, spline.fit, Color::BLUE);
+            }
+        }
     }
-    // 完成した四角形を描画
-    for (rect, selected) in q_rectangles.iter() {
-        let color = if selected.is_some() { Color::BLUE } else { Color::WHITE };
-        draw_rectangle(&mut gizmos, rect.p1, rect.p2, color);
+    // 寸法線と補助線を描画 (値のラベルは dimension_label_system が表示する)
+    for (entity, dimension, parent) in q_dimensions.iter() {
+        if !in_sketch(Some(parent), active_sketch.0) {
+            continue;
+        }
+        if let Some(layout) = dimensions::layout(&dimension.kind, &q_geometry, &frame) {
+            let status = report.statuses.get(&entity).copied().unwrap_or_default();
+            let color = dimensions::dimension_color(status);
+            for (a, b) in layout.lines {
+                gizmos.line(a, b, color);
+            }
+        }
     }
 
     // 描画中のプレビューを描画
-    if let Some(start_point) = sketch_data.start_point {
-        let window = q_window.single();
-        let (camera, camera_transform) = q_camera.single();
-        if let Some(world_pos) = screen_to_world(window, camera, camera_transform) {
-            match *active_tool {
-                ActiveSketchTool::Line => {
-                    gizmos.line(start_point, world_pos, Color::YELLOW);
-                }
-                ActiveSketchTool::Circle => {
-                    let radius = start_point.distance(world_pos);
-                    gizmos.circle(start_point, Direction3d::Y, radius, Color::YELLOW);
-                }
-                ActiveSketchTool::Rectangle => {
-                    draw_rectangle(&mut gizmos, start_point, world_pos, Color::YELLOW);
+    let window = q_window.single();
+    let (camera, camera_transform) = q_camera.single();
+    let Some(world_pos) = screen_to_world(window, camera, camera_transform) else {
+        return;
+    };
+    // 描画ツールではクリックした時と同じ点に吸着させて示す
+    let world_pos = if active_tool.is_edit_tool() || *active_tool == ActiveSketchTool::Select {
+        world_pos
+    } else {
+        let shapes = q_shapes.iter().filter(|(_, _, parent, _)| in_sketch(*parent, active_sketch.0));
+        let snapped = frame.to_world(profile::snap_point(&frame, shapes.map(|(shape, ..)| shape), frame.to_local(world_pos)));
+        if snapped != world_pos {
+            gizmos.circle(snapped, Direction3d::Y, 0.06, Color::YELLOW);
+        }
+        snapped
+    };
+    let draw_curve = |gizmos: &mut Gizmos, curve: Option<Curve2d>| {
+        if let Some(curve) = curve {
+            gizmos.linestrip(curve.tessellate().into_iter().map(|p| frame.to_world(p)), Color::YELLOW);
+        }
+    };
+    match (*active_tool, sketch_data.start_point, sketch_data.second_point) {
+        (ActiveSketchTool::Line, Some(start_point), _) => {
+            gizmos.line(start_point, world_pos, Color::YELLOW);
+        }
+        // 最初の点に近づいたら閉じる位置に吸着して示す
+        (ActiveSketchTool::Polyline, Some(start_point), _) => {
+            let first_point = sketch_data
+                .polyline
+                .filter(|draft| draft.first_line != draft.last_line)
+                .map(|draft| draft.first_point)
+                .filter(|first_point| first_point.distance(world_pos) < 0.1);
+            gizmos.line(start_point, first_point.unwrap_or(world_pos), Color::YELLOW);
+            if let Some(first_point) = first_point {
+                gizmos.circle(first_point, Direction3d::Y, 0.1, Color::YELLOW);
+            }
+        }
+        (ActiveSketchTool::Circle, Some(start_point), _) => {
+            let radius = start_point.distance(world_pos);
+            gizmos.circle(start_point, Direction3d::Y, radius, Color::YELLOW);
+        }
+        (ActiveSketchTool::Rectangle, Some(start_point), _) => {
+            draw_rectangle(&mut gizmos, &SketchRectangle { p1: start_point, p2: world_pos, angle: 0.0 }, Color::YELLOW);
+        }
+        (ActiveSketchTool::CenterRectangle, Some(center), _) => {
+            let rect = SketchRectangle { p1: 2.0 * center - world_pos, p2: world_pos, angle: 0.0 };
+            draw_rectangle(&mut gizmos, &rect, Color::YELLOW);
+        }
+        (ActiveSketchTool::ThreePointRectangle, Some(a), Some(b)) => {
+            draw_rectangle(&mut gizmos, &SketchRectangle::three_point(a, b, world_pos), Color::YELLOW);
+        }
+        (ActiveSketchTool::Polygon, Some(center), _) => {
+            let (sides, inscribed) = (sketch_data.polygon_sides, sketch_data.polygon_inscribed);
+            let corners = SketchPolygon { center, point: world_pos, sides, inscribed }.corners();
+            gizmos.linestrip(corners.iter().chain(corners.first()).copied(), Color::YELLOW);
+        }
+        // 円弧は2点目までは弦 (中心円弧では半径) を、その後は円弧を描く。
+        // 3点四角形・長穴・楕円も2点目までは最初の辺か中心線 (楕円では長軸の半分) を描く
+        (
+            ActiveSketchTool::ThreePointArc
+            | ActiveSketchTool::CenterArc
+            | ActiveSketchTool::ThreePointRectangle
+            | ActiveSketchTool::Slot
+            | ActiveSketchTool::Ellipse,
+            Some(start_point),
+            None,
+        ) => {
+            gizmos.line(start_point, world_pos, Color::YELLOW);
+        }
+        (ActiveSketchTool::Slot, Some(a), Some(b)) => {
+            let radius = point_line_segment_distance_sq(world_pos, a, b).sqrt();
+            gizmos.line(a, b, Color::GRAY);
+            for curve in (SketchSlot { a, b, radius }).curves(&frame) {
+                draw_curve(&mut gizmos, Some(curve));
+            }
+        }
+        (ActiveSketchTool::Ellipse, Some(center), Some(major)) => {
+            gizmos.line(center, major, Color::GRAY);
+            let minor_radius = ellipse_minor_radius(center, major, world_pos);
+            draw_curve(&mut gizmos, Some(SketchEllipse { center, major, minor_radius }.curve(&frame)));
+        }
+        (ActiveSketchTool::ThreePointArc, Some(start_point), Some(end_point)) => {
+            let curve = arc_through(frame.to_local(start_point), frame.to_local(world_pos), frame.to_local(end_point));
+            draw_curve(&mut gizmos, curve);
+        }
+        (ActiveSketchTool::CenterArc, Some(center), Some(start_point)) => {
+            gizmos.line(center, start_point, Color::YELLOW);
+            let curve = arc_from_points(frame.to_local(center), frame.to_local(start_point), frame.to_local(world_pos));
+            draw_curve(&mut gizmos, Some(curve));
+        }
+        (ActiveSketchTool::TangentArc, _, _) => {
+            if let Some((start_point, tangent)) = sketch_data.tangent_start {
+                let start = frame.to_local(start_point);
+                let curve = tangent_arc(start, frame.to_local(start_point + tangent) - start, frame.to_local(world_pos));
+                draw_curve(&mut gizmos, curve);
+            }
+        }
+        (ActiveSketchTool::Spline, _, _) if !sketch_data.spline_points.is_empty() => {
+            let points = &sketch_data.spline_points;
+            let closing = points.len() >= 3 && points[0].distance(world_pos) < 0.1;
+            let mut points = points.clone();
+            points.push(if closing { points[0] } else { world_pos });
+            let spline = SketchSpline { points, fit: sketch_data.spline_fit, knots: Vec::new() };
+            draw_curve(&mut gizmos, spline.curve(&frame));
+            draw_spline_points(&mut gizmos, &spline.points, spline.fit, Color::YELLOW);
+        }
+        // 選択中の図形を変換した位置に描く。移動は基準点を決めてから
+        (ActiveSketchTool::Move | ActiveSketchTool::Rotate | ActiveSketchTool::Scale, base, _) => {
+            let shapes = || q_shapes.iter().filter(|(_, _, parent, _)| in_sketch(*parent, active_sketch.0));
+            let point = profile::snap_point(&frame, shapes().map(|(shape, ..)| shape), frame.to_local(world_pos));
+            gizmos.circle(frame.to_world(point), Direction3d::Y, 0.06, Color::YELLOW);
+            if let Some(base) = base {
+                gizmos.line(base, frame.to_world(point), Color::YELLOW);
+            }
+            let base = base.map(|p| frame.to_local(p));
+            if let Some(transform) = transform::tool_transform(*active_tool, &sketch_data, base, point) {
+                for (shape, ..) in shapes().filter(|(_, selected, ..)| selected.is_some()) {
+                    for curve in profile::entity_curves(&frame, shape) {
+                        let points = curve.tessellate().into_iter().map(|p| frame.to_world(transform.point(p)));
+                        gizmos.linestrip(points, Color::YELLOW);
+                    }
                 }
-                _ => {},
             }
         }
+        _ => {}
+    }
+}
+
+/// スプラインの点を描画するヘルパー関数。制御点の場合は制御点をつなぐ折れ線も描く
+fn draw_spline_points(gizmos: &mut Gizmos, points: &[Vec3], fit: bool, color: Color) {
+    for &point in points {
+        gizmos.circle(point, Direction3d::Y, 0.05, color);
+    }
+    if !fit {
+        gizmos.linestrip(points.iter().copied(), Color::GRAY);
+    }
+}
+
+/// 折れ線を破線で描画するヘルパー関数
+fn draw_dashed(gizmos: &mut Gizmos, points: &[Vec3], color: Color) {
+    const DASH: f32 = 0.08;
+    // 折れ線の始点から測った長さで線と隙間を交互にする
+    let mut travelled = 0.0;
+    for segment in points.windows(2) {
+        let (a, b) = (segment[0], segment[1]);
+        let length = a.distance(b);
+        let mut t = 0.0;
+        while t < length {
+            let dash = ((travelled + t) / DASH).floor();
+            let next = ((dash + 1.0) * DASH - travelled).max(t + 1e-4).min(length);
+            if dash as i64 % 2 == 0 {
+                gizmos.line(a.lerp(b, t / length), a.lerp(b, next / length), color);
+            }
+            t = next;
+        }
+        travelled += length;
     }
 }
 
-/// 2つの対角点から四角形をGizmosで描画するヘルパー関数
-fn draw_rectangle(gizmos: &mut Gizmos, p1: Vec3, p2: Vec3, color: Color) {
-    let corner2 = Vec3::new(p1.x, 0.0, p2.z);
-    let corner4 = Vec3::new(p2.x, 0.0, p1.z);
-    gizmos.line(p1, corner2, color);
-    gizmos.line(corner2, p2, color);
-    gizmos.line(p2, corner4, color);
-    gizmos.line(corner4, p1, color);
+/// 四角形をGizmosで描画するヘルパー関数
+fn draw_rectangle(gizmos: &mut Gizmos, rect: &SketchRectangle, color: Color) {
+    let corners = rect.corners();
+    gizmos.linestrip(corners.into_iter().chain([rect.p1]), color);
 }
 
 /// スケッチ平面にグリッドを描画するシステム
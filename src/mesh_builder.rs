@@ -0,0 +1,190 @@
+//! 多角形の三角形分割と、描画用メッシュの組み立て
+
+use bevy::prelude::*;
+use bevy::render::{mesh::Indices, render_asset::RenderAssetUsages, render_resource::PrimitiveTopology};
+
+use crate::geometry::signed_area;
+
+/// 単純多角形を耳刈り法で三角形分割する。入力は反時計回り、出力も反時計回り
+pub fn triangulate(polygon: &[Vec2]) -> Vec<[usize; 3]> {
+    let mut indices: Vec<usize> = (0..polygon.len()).collect();
+    if signed_area(polygon) < 0.0 {
+        indices.reverse();
+    }
+    let mut triangles = Vec::new();
+    let mut guard = 0;
+    while indices.len() > 3 && guard < polygon.len() * polygon.len() {
+        guard += 1;
+        let n = indices.len();
+        let ear = (0..n).find(|&i| {
+            let (prev, cur, next) = (indices[(i + n - 1) % n], indices[i], indices[(i + 1) % n]);
+            let (a, b, c) = (polygon[prev], polygon[cur], polygon[next]);
+            // 凸頂点で、他の頂点を内側に含まないものが耳
+            (b - a).perp_dot(c - b) > 1e-9
+                && indices
+                    .iter()
+                    .map(|&j| polygon[j])
+                    // 穴をつなぐブリッジで重複した頂点は三角形の頂点とみなす
+                    .filter(|&p| p != a && p != b && p != c)
+                    .all(|p| !point_in_triangle(p, a, b, c))
+        });
+        match ear {
+            Some(i) => {
+                triangles.push([indices[(i + n - 1) % n], indices[i], indices[(i + 1) % n]]);
+                indices.remove(i);
+            }
+            // 数値誤差で耳が見つからない場合は退化した頂点を取り除いて続ける
+            None => {
+                indices.remove(0);
+            }
+        }
+    }
+    if indices.len() == 3 {
+        triangles.push([indices[0], indices[1], indices[2]]);
+    }
+    triangles
+}
+
+/// 穴を外周にブリッジでつないだ1つの多角形にする。外周は反時計回り、穴は時計回りに揃える
+pub fn merge_holes(outer: &[Vec2], holes: &[Vec<Vec2>]) -> Vec<Vec2> {
+    let mut polygon = outer.to_vec();
+    if signed_area(&polygon) < 0.0 {
+        polygon.reverse();
+    }
+    let mut holes: Vec<Vec<Vec2>> = holes
+        .iter()
+        .filter(|hole| hole.len() >= 3)
+        .map(|hole| {
+            let mut hole = hole.clone();
+            if signed_area(&hole) > 0.0 {
+                hole.reverse();
+            }
+            hole
+        })
+        .collect();
+    // 右端の穴からつなぐと、後の穴のブリッジが先のブリッジと交差しにくい
+    let max_x = |hole: &Vec<Vec2>| hole.iter().fold(f32::MIN, |m, p| m.max(p.x));
+    holes.sort_by(|a, b| max_x(b).total_cmp(&max_x(a)));
+
+    for (k, hole) in holes.iter().enumerate() {
+        let m = (0..hole.len()).max_by(|&a, &b| hole[a].x.total_cmp(&hole[b].x)).unwrap_or(0);
+        let from = hole[m];
+        // まだつないでいない穴の辺もブリッジの障害物になる
+        let obstacles: Vec<(Vec2, Vec2)> = std::iter::once(&polygon)
+            .chain(holes[k..].iter())
+            .flat_map(|ring| (0..ring.len()).map(move |i| (ring[i], ring[(i + 1) % ring.len()])))
+            .collect();
+        let mut candidates: Vec<usize> = (0..polygon.len()).collect();
+        candidates.sort_by(|&a, &b| polygon[a].distance_squared(from).total_cmp(&polygon[b].distance_squared(from)));
+        let Some(target) = candidates.into_iter().find(|&i| {
+            let to = polygon[i];
+            obstacles
+                .iter()
+                .filter(|(a, b)| *a != to && *b != to && *a != from && *b != from)
+                .all(|(a, b)| !segments_cross(from, to, *a, *b))
+        }) else {
+            continue;
+        };
+
+        let mut merged = polygon[..=target].to_vec();
+        merged.extend((0..=hole.len()).map(|i| hole[(m + i) % hole.len()]));
+        merged.extend_from_slice(&polygon[target..]);
+        polygon = merged;
+    }
+    polygon
+}
+
+/// 2つの線分が交差するか (端点での接触を除く)
+fn segments_cross(a: Vec2, b: Vec2, c: Vec2, d: Vec2) -> bool {
+    let d1 = (b - a).perp_dot(c - a);
+    let d2 = (b - a).perp_dot(d - a);
+    let d3 = (d - c).perp_dot(a - c);
+    let d4 = (d - c).perp_dot(b - c);
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+fn point_in_triangle(p: Vec2, a: Vec2, b: Vec2, c: Vec2) -> bool {
+    let d1 = (b - a).perp_dot(p - a);
+    let d2 = (c - b).perp_dot(p - b);
+    let d3 = (a - c).perp_dot(p - c);
+    d1 >= 0.0 && d2 >= 0.0 && d3 >= 0.0
+}
+
+/// 頂点と法線を面ごとに持つ三角形メッシュの組み立て用バッファ
+#[derive(Default)]
+pub struct MeshBuffers {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuffers {
+    pub fn triangle(&mut self, a: Vec3, b: Vec3, c: Vec3, normal: Vec3) {
+        let base = self.positions.len() as u32;
+        for p in [a, b, c] {
+            self.positions.push(p.to_array());
+            self.normals.push(normal.to_array());
+        }
+        self.indices.extend([base, base + 1, base + 2]);
+    }
+
+    /// 頂点ごとに法線を指定した三角形
+    pub fn smooth_triangle(&mut self, positions: [Vec3; 3], normals: [Vec3; 3]) {
+        let base = self.positions.len() as u32;
+        for (p, n) in positions.into_iter().zip(normals) {
+            self.positions.push(p.to_array());
+            self.normals.push(n.to_array());
+        }
+        self.indices.extend([base, base + 1, base + 2]);
+    }
+
+    pub fn build(self) -> Mesh {
+        let uvs = vec![[0.0, 0.0]; self.positions.len()];
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.insert_indices(Indices::U32(self.indices));
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(min: Vec2, size: f32) -> Vec<Vec2> {
+        vec![min, min + Vec2::X * size, min + Vec2::splat(size), min + Vec2::Y * size]
+    }
+
+    fn triangle_area(polygon: &[Vec2], [i, j, k]: [usize; 3]) -> f32 {
+        (polygon[j] - polygon[i]).perp_dot(polygon[k] - polygon[i]) / 2.0
+    }
+
+    #[test]
+    fn triangulates_rectangle_with_hole() {
+        let outer = square(Vec2::ZERO, 4.0);
+        let hole = square(Vec2::ONE, 2.0);
+        let polygon = merge_holes(&outer, std::slice::from_ref(&hole));
+        let triangles = triangulate(&polygon);
+
+        // すべて反時計回りで、面積の合計は外周から穴を引いたもの
+        assert!(triangles.iter().all(|&t| triangle_area(&polygon, t) > 0.0));
+        let area: f32 = triangles.iter().map(|&t| triangle_area(&polygon, t)).sum();
+        assert!((area - 12.0).abs() < 1e-4, "area = {area}");
+        // 穴の内側を覆う三角形はない
+        for [i, j, k] in triangles {
+            let centroid = (polygon[i] + polygon[j] + polygon[k]) / 3.0;
+            assert!(!crate::geometry::point_in_polygon(centroid, &hole));
+        }
+    }
+
+    #[test]
+    fn accepts_clockwise_outer_loop() {
+        let mut outer = square(Vec2::ZERO, 1.0);
+        outer.reverse();
+        let polygon = merge_holes(&outer, &[]);
+        assert!(signed_area(&polygon) > 0.0);
+        assert_eq!(triangulate(&polygon).len(), 2);
+    }
+}
@@ -0,0 +1,325 @@
+//! 直線と円弧のチェーンのオフセット
+//!
+//! 端点でつながった直線と円弧を1本のチェーンに並べ、各曲線を同じ距離だけ平行にずらす。
+//! ずらした曲線の間に隙間ができる角は円弧か留め継ぎ (直線の延長) でつなぎ、重なる角は交点で
+//! 切りそろえる。最後にチェーン自身との交点で曲線を区切り、元のチェーンにオフセットの距離より近づいた区間と、
+//! 閉じたチェーンでは元の図形の反対側に抜けた区間を取り除く。
+
+use bevy::prelude::*;
+
+use crate::geometry::{arc_from_points, point_in_polygon, signed_area, Curve2d};
+use crate::intersection::{intersect, split_params};
+
+/// 端点がつながっているとみなす距離
+const CHAIN_TOLERANCE: f32 = 1e-3;
+/// 長さがないとみなす距離
+const TOLERANCE: f32 = 1e-4;
+/// 留め継ぎの角の先端が角からオフセット距離の何倍まで離れてよいか。超えると円弧でつなぐ
+const MITER_LIMIT: f32 = 4.0;
+
+/// オフセットで隙間ができる角のつなぎ方
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OffsetJoin {
+    /// 元の角を中心とする円弧
+    #[default]
+    Round,
+    /// 両側の曲線を接線方向に延ばして交わらせる
+    Miter,
+}
+
+/// 曲線をつないだチェーンを `distance` だけずらした曲線。閉じたチェーンは正の距離で外側に、
+/// 開いたチェーンは進行方向の左側にずらす。円は単独の場合だけオフセットできる
+pub fn offset_chain(curves: &[Curve2d], distance: f32, join: OffsetJoin) -> Result<Vec<Curve2d>, &'static str> {
+    if distance == 0.0 {
+        return Err("オフセットの距離を指定してください");
+    }
+    if curves.iter().any(|curve| matches!(curve, Curve2d::Spline(_) | Curve2d::Ellipse { .. })) {
+        return Err("オフセットできるのは直線・円弧・円だけです");
+    }
+    if let [Curve2d::Circle { center, radius }] = *curves {
+        let radius = radius + distance;
+        return if radius > TOLERANCE {
+            Ok(vec![Curve2d::Circle { center, radius }])
+        } else {
+            Err("オフセットすると図形がなくなります")
+        };
+    }
+    let (mut chain, closed) = build_chain(curves).ok_or("選択した図形が1本につながっていません")?;
+
+    // 閉じたチェーンは反時計回りにそろえ、外側 (右側) にずらす
+    let mut amount = distance;
+    if closed {
+        if signed_area(&outline(&chain)) < 0.0 {
+            chain = chain.iter().rev().map(Curve2d::reversed).collect();
+        }
+        amount = -distance;
+    }
+
+    // 半径がなくなった円弧は取り除き、前後の曲線を直接つなぐ
+    let mut shifted: Vec<(usize, Curve2d)> =
+        chain.iter().enumerate().filter_map(|(i, curve)| shift_left(curve, amount).map(|c| (i, c))).collect();
+    if shifted.is_empty() {
+        return Err("オフセットすると図形がなくなります");
+    }
+
+    let n = shifted.len();
+    let pairs = if closed { n } else { n - 1 };
+    let mut connectors = vec![Vec::new(); n];
+    for k in 0..pairs {
+        let next = (k + 1) % n;
+        let (i, j) = (shifted[k].0, shifted[next].0);
+        // 間の円弧が消えていなければ元の角を中心に円弧でつなげる
+        let vertex = (j == (i + 1) % chain.len()).then(|| chain[i].end());
+        let turn = chain[i].end_tangent().perp_dot(chain[j].start_tangent());
+        let (mut a, mut b) = (shifted[k].1.clone(), shifted[next].1.clone());
+        connectors[k] = join_corner(&mut a, &mut b, vertex, turn * amount > 0.0, amount.abs(), join);
+        shifted[k].1 = a;
+        shifted[next].1 = b;
+    }
+    let joined: Vec<Curve2d> = shifted
+        .into_iter()
+        .zip(connectors)
+        .flat_map(|((_, curve), connectors)| std::iter::once(curve).chain(connectors))
+        .filter(|curve| !is_degenerate(curve))
+        .collect();
+    let result = remove_loops(&joined, &chain, closed, distance);
+    if result.is_empty() {
+        return Err("オフセットすると図形がなくなります");
+    }
+    Ok(result)
+}
+
+/// 端点でつながった曲線を1本のチェーンに並べる。必要なら曲線の向きを反転する。
+/// 1本につながらなければ `None`。閉じているかどうかも返す
+pub fn build_chain(curves: &[Curve2d]) -> Option<(Vec<Curve2d>, bool)> {
+    let (first, rest) = curves.split_first()?;
+    let mut chain = vec![first.clone()];
+    let mut remaining: Vec<Curve2d> = rest.to_vec();
+    while !remaining.is_empty() {
+        let (head, tail) = (chain[0].start(), chain[chain.len() - 1].end());
+        let near = |p: Vec2, q: Vec2| p.distance(q) < CHAIN_TOLERANCE;
+        if let Some(index) = remaining.iter().position(|c| near(c.start(), tail) || near(c.end(), tail)) {
+            let curve = remaining.swap_remove(index);
+            chain.push(if near(curve.start(), tail) { curve } else { curve.reversed() });
+        } else if let Some(index) = remaining.iter().position(|c| near(c.end(), head) || near(c.start(), head)) {
+            let curve = remaining.swap_remove(index);
+            chain.insert(0, if near(curve.end(), head) { curve } else { curve.reversed() });
+        } else {
+            return None;
+        }
+    }
+    let closed = chain.len() > 1 && chain[0].start().distance(chain[chain.len() - 1].end()) < CHAIN_TOLERANCE;
+    Some((chain, closed))
+}
+
+/// チェーンを折れ線にした点。閉じたチェーンの向きを調べるのに使う
+fn outline(chain: &[Curve2d]) -> Vec<Vec2> {
+    chain
+        .iter()
+        .flat_map(|curve| {
+            let mut points = curve.tessellate();
+            points.pop();
+            points
+        })
+        .collect()
+}
+
+/// 曲線を進行方向の左側に `amount` だけずらす。円弧の半径がなくなれば `None`
+fn shift_left(curve: &Curve2d, amount: f32) -> Option<Curve2d> {
+    match *curve {
+        Curve2d::Line { a, b } => {
+            let normal = (b - a).normalize_or_zero().perp() * amount;
+            Some(Curve2d::Line { a: a + normal, b: b + normal })
+        }
+        // 反時計回りの円弧の左側は中心の側
+        Curve2d::Arc { center, radius, start_angle, sweep } => {
+            let radius = radius - amount * sweep.signum();
+            (radius > TOLERANCE).then_some(Curve2d::Arc { center, radius, start_angle, sweep })
+        }
+        _ => None,
+    }
+}
+
+/// ずらした2つの曲線 `a` と `b` の間の角をつなぎ、間に入れる曲線を返す。`inner` なら曲線同士が
+/// 重なっているので交点で切りそろえる。`vertex` は円弧でつなぐ時の中心 (元の角)
+fn join_corner(
+    a: &mut Curve2d,
+    b: &mut Curve2d,
+    vertex: Option<Vec2>,
+    inner: bool,
+    radius: f32,
+    join: OffsetJoin,
+) -> Vec<Curve2d> {
+    let (p, q) = (a.end(), b.start());
+    if p.distance(q) < TOLERANCE {
+        return Vec::new();
+    }
+    if inner {
+        // 角に近い (aの終わり寄りでbの始め寄りの) 交点で切る。交わらなければ直線でつなぎ、後でループとして取り除く
+        let hit = intersect(a, b).into_iter().min_by(|x, y| ((1.0 - x.t) + x.s).total_cmp(&((1.0 - y.t) + y.s)));
+        if let Some((trimmed_a, trimmed_b)) = hit.and_then(|hit| Some((a.segment(0.0, hit.t)?, b.segment(hit.s, 1.0)?))) {
+            *a = trimmed_a;
+            *b = trimmed_b;
+            return Vec::new();
+        }
+        return vec![Curve2d::Line { a: p, b: q }];
+    }
+    let Some(vertex) = vertex else {
+        return vec![Curve2d::Line { a: p, b: q }];
+    };
+    if join == OffsetJoin::Miter {
+        if let Some(miter) = miter_point(p, a.end_tangent(), q, b.start_tangent()) {
+            if miter.distance(vertex) <= MITER_LIMIT * radius {
+                let mut connectors = Vec::new();
+                match *a {
+                    Curve2d::Line { a: start, .. } => *a = Curve2d::Line { a: start, b: miter },
+                    _ => connectors.push(Curve2d::Line { a: p, b: miter }),
+                }
+                match *b {
+                    Curve2d::Line { b: end, .. } => *b = Curve2d::Line { a: miter, b: end },
+                    _ => connectors.push(Curve2d::Line { a: miter, b: q }),
+                }
+                return connectors;
+            }
+        }
+    }
+    // 元の角のまわりを、チェーンの曲がる向きと同じ向きに回る
+    let ccw = a.end_tangent().perp_dot(b.start_tangent()) > 0.0;
+    vec![if ccw { arc_from_points(vertex, p, q) } else { arc_from_points(vertex, q, p).reversed() }]
+}
+
+/// `p` から `tangent_p` の向きに延ばした半直線と、`q` から `tangent_q` の逆向きに延ばした半直線の交点
+fn miter_point(p: Vec2, tangent_p: Vec2, q: Vec2, tangent_q: Vec2) -> Option<Vec2> {
+    let denominator = tangent_p.perp_dot(tangent_q);
+    if denominator.abs() <= f32::EPSILON {
+        return None;
+    }
+    let u = (q - p).perp_dot(tangent_q) / denominator;
+    let w = tangent_p.perp_dot(q - p) / denominator;
+    (u >= 0.0 && w >= 0.0).then_some(p + tangent_p * u)
+}
+
+/// ずらした曲線 `curves` を互いの交点と、重なった曲線の端点で区切り、オフセットした図形の境界になる区間を残す。
+/// 境界の区間は元のチェーン `chain` から `distance` 以上 (留め継ぎの角ではそれより遠く) 離れていて、
+/// 閉じたチェーンなら元の図形の外側 (負の距離では内側) にある。交点の間で境界かどうかは変わらないので、区間の中点で調べる
+fn remove_loops(curves: &[Curve2d], chain: &[Curve2d], closed: bool, distance: f32) -> Vec<Curve2d> {
+    let source = outline(chain);
+    let on_boundary = |curve: &Curve2d| {
+        let middle = curve.point_at(0.5);
+        let clearance = chain.iter().map(|original| original.distance(middle)).fold(f32::MAX, f32::min);
+        let inside = point_in_polygon(middle, &source);
+        clearance > distance.abs() - CHAIN_TOLERANCE && (!closed || inside == (distance < 0.0))
+    };
+    let mut result: Vec<Curve2d> = Vec::new();
+    for (i, curve) in curves.iter().enumerate() {
+        let others: Vec<Curve2d> =
+            curves.iter().enumerate().filter(|&(j, _)| j != i).map(|(_, other)| other.clone()).collect();
+        // 重なった曲線同士は交点を持たないので、相手の端点でも区切って同じ区間にそろえる
+        let touching = others
+            .iter()
+            .flat_map(|other| [other.start(), other.end()])
+            .filter(|&p| curve.distance(p) < TOLERANCE)
+            .filter(|&p| p.distance(curve.start()) > TOLERANCE && p.distance(curve.end()) > TOLERANCE)
+            .map(|p| curve.closest_param(p));
+        let mut params: Vec<f32> = split_params(curve, &others).into_iter().chain(touching).collect();
+        params.sort_by(f32::total_cmp);
+        params.dedup_by(|a, b| curve.point_at(*a).distance(curve.point_at(*b)) < TOLERANCE);
+        let bounds: Vec<f32> = std::iter::once(0.0).chain(params).chain(std::iter::once(1.0)).collect();
+        for pair in bounds.windows(2) {
+            let Some(piece) = curve.segment(pair[0], pair[1]) else {
+                continue;
+            };
+            // 重なった区間は1つだけ残す
+            let duplicate = result.iter().any(|kept| kept.distance(piece.point_at(0.5)) < TOLERANCE);
+            if !is_degenerate(&piece) && !duplicate && on_boundary(&piece) {
+                result.push(piece);
+            }
+        }
+    }
+    result
+}
+
+/// 長さがほとんどない曲線か
+fn is_degenerate(curve: &Curve2d) -> bool {
+    match *curve {
+        Curve2d::Line { a, b } => a.distance(b) < TOLERANCE,
+        Curve2d::Arc { radius, sweep, .. } => (radius * sweep).abs() < TOLERANCE,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    fn polyline(points: &[(f32, f32)], closed: bool) -> Vec<Curve2d> {
+        let points: Vec<Vec2> = points.iter().map(|&(x, y)| Vec2::new(x, y)).collect();
+        let n = if closed { points.len() } else { points.len() - 1 };
+        (0..n).map(|i| Curve2d::Line { a: points[i], b: points[(i + 1) % points.len()] }).collect()
+    }
+
+    /// 曲線を折れ線にした点の範囲
+    fn bounds(curves: &[Curve2d]) -> (Vec2, Vec2) {
+        let points = curves.iter().flat_map(Curve2d::tessellate);
+        points.fold((Vec2::MAX, Vec2::MIN), |(min, max), p| (min.min(p), max.max(p)))
+    }
+
+    #[test]
+    fn offset_closes_narrow_notch() {
+        // 上の辺から幅0.4、深さ3の切り欠きがある 4×4 の四角形
+        let notch = [(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (2.2, 4.0), (2.2, 1.0), (1.8, 1.0), (1.8, 4.0), (0.0, 4.0)];
+        for join in [OffsetJoin::Miter, OffsetJoin::Round] {
+            let result = offset_chain(&polyline(&notch, true), 0.3, join).unwrap();
+            let (chain, closed) = build_chain(&result).expect("1本につながっていない");
+            assert!(closed, "{join:?}");
+            let (min, max) = bounds(&chain);
+            assert!(min.distance(Vec2::splat(-0.3)) < 1e-3 && max.distance(Vec2::splat(4.3)) < 1e-3, "{join:?}");
+            // 切り欠きはふさがり、丸める場合も両側の角の円弧が交わる所までしかへこまない
+            let deepest = chain
+                .iter()
+                .flat_map(Curve2d::tessellate)
+                .filter(|p| (1.85..=2.15).contains(&p.x))
+                .map(|p| p.y)
+                .fold(f32::MAX, f32::min);
+            assert!(deepest > 4.2, "{join:?}: {deepest}");
+        }
+    }
+
+    #[test]
+    fn offset_u_shape_trims_inner_corners_and_rounds_outer_ones() {
+        let u = polyline(&[(0.0, 4.0), (0.0, 0.0), (4.0, 0.0), (4.0, 4.0)], false);
+        // 進行方向の左側 (Uの内側) では角で切りそろえる
+        let inner = offset_chain(&u, 1.0, OffsetJoin::Round).unwrap();
+        assert_eq!(inner, polyline(&[(1.0, 4.0), (1.0, 1.0), (3.0, 1.0), (3.0, 4.0)], false));
+        // 外側では元の角を中心とする円弧でつなぐ
+        let outer = offset_chain(&u, -1.0, OffsetJoin::Round).unwrap();
+        assert_eq!(outer.len(), 5);
+        for (curve, corner) in [(&outer[1], Vec2::ZERO), (&outer[3], Vec2::new(4.0, 0.0))] {
+            assert!(matches!(*curve, Curve2d::Arc { center, radius, .. } if center == corner && radius == 1.0));
+        }
+        // 内側の幅より大きくずらすと何も残らない
+        assert!(offset_chain(&u, 2.5, OffsetJoin::Round).is_err());
+    }
+
+    #[test]
+    fn offset_slot_keeps_or_drops_end_arcs() {
+        // 両端が半円の長穴
+        let slot = vec![
+            Curve2d::Line { a: Vec2::ZERO, b: Vec2::new(4.0, 0.0) },
+            Curve2d::Arc { center: Vec2::new(4.0, 1.0), radius: 1.0, start_angle: -PI / 2.0, sweep: PI },
+            Curve2d::Line { a: Vec2::new(4.0, 2.0), b: Vec2::new(0.0, 2.0) },
+            Curve2d::Arc { center: Vec2::new(0.0, 1.0), radius: 1.0, start_angle: PI / 2.0, sweep: PI },
+        ];
+        let outer = offset_chain(&slot, 0.5, OffsetJoin::Miter).unwrap();
+        assert_eq!(outer.len(), 4);
+        let (min, max) = bounds(&outer);
+        assert!(min.distance(Vec2::new(-1.5, -0.5)) < 1e-3 && max.distance(Vec2::new(5.5, 2.5)) < 1e-3);
+        let inner = offset_chain(&slot, -0.5, OffsetJoin::Round).unwrap();
+        let arcs = inner.iter().filter(|curve| matches!(curve, Curve2d::Arc { radius, .. } if (radius - 0.5).abs() < 1e-5));
+        assert_eq!(arcs.count(), 2);
+        // 半径より大きく内側にずらすと円弧が消え、両側の直線が入れ替わるので残らない
+        assert!(offset_chain(&slot, -1.2, OffsetJoin::Round).is_err());
+    }
+}
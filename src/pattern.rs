@@ -0,0 +1,295 @@
+//! スケッチのミラー・直線パターン・円形パターン
+//!
+//! コピーは元の図形と同じ種類のコンポーネントを持ち、`SketchCopy` で元の図形と変換を覚えておく。
+//! 元の図形 (ミラーでは対称軸の直線も) が変わると `update_copies_system` がコピーを作り直す。
+//! 元の図形や対称軸が削除されると、コピーは関連のない普通の図形になる。
+
+use std::f32::consts::TAU;
+
+use bevy::{ecs::system::EntityCommands, prelude::*, window::PrimaryWindow};
+use bevy_egui::EguiContexts;
+use bevy_panorbit_camera::PanOrbitCamera;
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::{Similarity, SketchFrame};
+use crate::history::UndoHistory;
+use crate::profile::{self, SketchShape, SketchShapeBundle, SketchShapeChanges, SketchShapeItem};
+use crate::{
+    screen_to_world, ActiveSketch, ActiveSketchTool, Construction, SketchArc, SketchCircle, SketchData, SketchEllipse,
+    SketchLine, SketchPolygon, SketchRectangle, SketchSlot, SketchSpline, Selected,
+};
+
+/// 対称軸を選ぶ許容範囲
+const PICK_TOLERANCE: f32 = 0.15;
+
+/// コピーを作る変換。ファイル保存時は `E` をスケッチ内のインデックスに置き換える
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum CopyTransform<E = Entity> {
+    /// 直線エンティティを軸に反転する
+    Mirror(E),
+    /// スケッチ座標で平行移動する
+    Translate([f32; 2]),
+    /// スケッチ座標の点のまわりに反時計回りに回す (ラジアン)
+    Rotate { center: [f32; 2], angle: f32 },
+}
+
+impl<E: Copy> CopyTransform<E> {
+    /// 参照先のエンティティを置き換える。置き換えられなければ `None`
+    pub fn map<T: Copy>(&self, mut f: impl FnMut(E) -> Option<T>) -> Option<CopyTransform<T>> {
+        Some(match *self {
+            CopyTransform::Mirror(axis) => CopyTransform::Mirror(f(axis)?),
+            CopyTransform::Translate(offset) => CopyTransform::Translate(offset),
+            CopyTransform::Rotate { center, angle } => CopyTransform::Rotate { center, angle },
+        })
+    }
+}
+
+/// 別のエンティティを変換して作ったコピーであることを示すコンポーネント
+#[derive(Component, Debug, Clone, Copy)]
+pub struct SketchCopy {
+    pub source: Entity,
+    pub transform: CopyTransform,
+}
+
+/// コピーの変換を相似変換にする。対称軸が直線でなくなっていれば `None`
+fn resolve(transform: &CopyTransform, frame: &SketchFrame, q_shapes: &Query<SketchShape>) -> Option<Similarity> {
+    match *transform {
+        CopyTransform::Mirror(axis) => {
+            let line = q_shapes.get(axis).ok()?.0?;
+            let (a, b) = (frame.to_local(line.p1), frame.to_local(line.p2));
+            (a != b).then(|| Similarity::reflection(a, b))
+        }
+        CopyTransform::Translate(offset) => Some(Similarity::translation(Vec2::from_array(offset))),
+        CopyTransform::Rotate { center, angle } => Some(Similarity::rotation(Vec2::from_array(center), angle)),
+    }
+}
+
+/// 図形を変換したコンポーネントをエンティティに挿入する。別の種類の形を持っていれば取り除く
+pub fn insert_transformed(
+    entity_commands: &mut EntityCommands,
+    frame: &SketchFrame,
+    (line, circle, rect, arc, spline, polygon, slot, ellipse): SketchShapeItem,
+    transform: &Similarity,
+) {
+    let point = |p: Vec3| frame.to_world(transform.point(frame.to_local(p)));
+    entity_commands.remove::<SketchShapeBundle>();
+    if let Some(line) = line {
+        entity_commands.insert(SketchLine { p1: point(line.p1), p2: point(line.p2) });
+    } else if let Some(circle) = circle {
+        entity_commands.insert(SketchCircle { center: point(circle.center), radius: circle.radius * transform.scale });
+    } else if let Some(rect) = rect {
+        let angle = transform.direction_angle(rect.angle);
+        entity_commands.insert(SketchRectangle { p1: point(rect.p1), p2: point(rect.p2), angle });
+    } else if let Some(arc) = arc {
+        // 反転すると回る向きが逆になるので始点と終点を入れ替える
+        let (start, end) = if transform.mirror { (arc.end, arc.start) } else { (arc.start, arc.end) };
+        entity_commands.insert(SketchArc { center: point(arc.center), start: point(start), end: point(end) });
+    } else if let Some(spline) = spline {
+        let points = spline.points.iter().map(|&p| point(p)).collect();
+        entity_commands.insert(SketchSpline { points, fit: spline.fit, knots: spline.knots.clone() });
+    } else if let Some(polygon) = polygon {
+        entity_commands.insert(SketchPolygon {
+            center: point(polygon.center),
+            point: point(polygon.point),
+            sides: polygon.sides,
+            inscribed: polygon.inscribed,
+        });
+    } else if let Some(slot) = slot {
+        entity_commands.insert(SketchSlot { a: point(slot.a), b: point(slot.b), radius: slot.radius * transform.scale });
+    } else if let Some(ellipse) = ellipse {
+        entity_commands.insert(SketchEllipse {
+            center: point(ellipse.center),
+            major: point(ellipse.major),
+            minor_radius: ellipse.minor_radius * transform.scale,
+        });
+    }
+}
+
+/// 図形のコピーをスケッチに加える。`transform` は `copy` を解決した相似変換
+pub fn spawn_copy(
+    commands: &mut Commands,
+    frame: &SketchFrame,
+    sketch: Entity,
+    (source, shape, construction): (Entity, SketchShapeItem, bool),
+    copy: CopyTransform,
+    transform: &Similarity,
+) {
+    let mut entity_commands = commands.spawn(SketchCopy { source, transform: copy });
+    entity_commands.set_parent(sketch);
+    insert_transformed(&mut entity_commands, frame, shape, transform);
+    if construction {
+        entity_commands.insert(Construction);
+    }
+}
+
+/// 直線パターンの変換。元の図形の位置を除き、スケッチのx軸から反時計回りに `angles` (ラジアン) だけ
+/// 傾けた方向1と方向2に並べる
+pub fn linear_pattern(counts: [u32; 2], spacings: [f32; 2], angles: [f32; 2]) -> Vec<(CopyTransform, Similarity)> {
+    let steps = [0, 1].map(|k| Vec2::from_angle(angles[k]) * spacings[k]);
+    (0..counts[1].max(1))
+        .flat_map(|j| (0..counts[0].max(1)).map(move |i| (i, j)))
+        .filter(|&(i, j)| (i, j) != (0, 0))
+        .map(|(i, j)| {
+            let offset = steps[0] * i as f32 + steps[1] * j as f32;
+            (CopyTransform::Translate(offset.to_array()), Similarity::translation(offset))
+        })
+        .collect()
+}
+
+/// 円形パターンの変換。元の図形を含めて `count` 個を `total_angle` (ラジアン) の範囲に等間隔に並べる。
+/// 一周する場合は最後のコピーが元の図形に重ならないように分ける
+pub fn circular_pattern(center: Vec2, count: u32, total_angle: f32) -> Vec<(CopyTransform, Similarity)> {
+    let full_turn = (total_angle.abs() - TAU).abs() < 1e-4;
+    let divisions = if full_turn { count } else { count.saturating_sub(1) };
+    if divisions == 0 {
+        return Vec::new();
+    }
+    (1..count)
+        .map(|k| {
+            let angle = total_angle * k as f32 / divisions as f32;
+            (CopyTransform::Rotate { center: center.to_array(), angle }, Similarity::rotation(center, angle))
+        })
+        .collect()
+}
+
+/// ミラー・円形パターンツールの入力を処理するシステム。クリックした直線を軸に、または点を中心に
+/// 選択中の図形をコピーする
+pub fn pattern_tool_system(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut gizmos: Gizmos,
+    mut sketch_data: ResMut<SketchData>,
+    active_tool: Res<ActiveSketchTool>,
+    active_sketch: Res<ActiveSketch>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    q_shapes: Query<(Entity, SketchShape, &Parent, Has<Construction>, Has<Selected>)>,
+    q_geometry: Query<SketchShape>,
+    mut history: ResMut<UndoHistory>,
+) {
+    if contexts.ctx_mut().is_using_pointer() {
+        return;
+    }
+    let Some(sketch) = active_sketch.0 else {
+        return;
+    };
+    let window = q_window.single();
+    let (camera, camera_transform) = q_camera.single();
+    let Some(world_pos) = screen_to_world(window, camera, camera_transform) else {
+        return;
+    };
+    let frame = SketchFrame::default();
+    let mouse = frame.to_local(world_pos);
+    let clicked = mouse_buttons.just_pressed(MouseButton::Left);
+    let in_sketch: Vec<_> = q_shapes.iter().filter(|(_, _, parent, ..)| parent.get() == sketch).collect();
+
+    let (transforms, axis) = if *active_tool == ActiveSketchTool::Mirror {
+        // 対称軸はマウスに最も近い直線
+        let axis = in_sketch
+            .iter()
+            .filter_map(|&(entity, shape, ..)| {
+                let line = shape.0?;
+                let curve = profile::entity_curves(&frame, shape).pop()?;
+                let distance = curve.distance(mouse);
+                (distance < PICK_TOLERANCE && line.p1 != line.p2).then_some((entity, line, distance))
+            })
+            .min_by(|a, b| a.2.total_cmp(&b.2));
+        let Some((axis, line, _)) = axis else {
+            if clicked {
+                sketch_data.message = "対称軸にする直線をクリックしてください".to_string();
+            }
+            return;
+        };
+        gizmos.line(line.p1, line.p2, Color::YELLOW);
+        let copy = CopyTransform::Mirror(axis);
+        (resolve(&copy, &frame, &q_geometry).map(|transform| (copy, transform)).into_iter().collect(), Some(axis))
+    } else {
+        // 図形の端点や中心の近くならその点を中心にする
+        let center = profile::snap_point(&frame, in_sketch.iter().map(|&(_, shape, ..)| shape), mouse);
+        gizmos.circle(frame.to_world(center), Direction3d::Y, 0.06, Color::YELLOW);
+        (circular_pattern(center, sketch_data.circular_count, sketch_data.circular_angle.to_radians()), None)
+    };
+
+    let selected: Vec<_> = in_sketch
+        .into_iter()
+        .filter(|(entity, _, _, _, selected)| *selected && Some(*entity) != axis)
+        .map(|(entity, shape, _, construction, _)| (entity, shape, construction))
+        .collect();
+
+    // コピーの位置をプレビューする
+    for (_, shape, _) in &selected {
+        for curve in profile::entity_curves(&frame, *shape) {
+            for (_, transform) in &transforms {
+                let points = curve.tessellate().into_iter().map(|p| frame.to_world(transform.point(p)));
+                gizmos.linestrip(points, Color::YELLOW);
+            }
+        }
+    }
+    if !clicked {
+        return;
+    }
+    if selected.is_empty() {
+        sketch_data.message = "コピーする図形を先に選択してください".to_string();
+        return;
+    }
+    for &(entity, shape, construction) in &selected {
+        for (copy, transform) in &transforms {
+            spawn_copy(&mut commands, &frame, sketch, (entity, shape, construction), *copy, transform);
+        }
+    }
+    sketch_data.message.clear();
+    history.record(if *active_tool == ActiveSketchTool::Mirror { "ミラー" } else { "円形パターン" });
+}
+
+/// 元の図形か対称軸が変わったコピーを作り直すシステム。元の図形や対称軸がなくなったコピーは関連を外す
+pub fn update_copies_system(
+    mut commands: Commands,
+    q_copies: Query<(Entity, Ref<SketchCopy>)>,
+    q_shapes: Query<SketchShape>,
+    changes: SketchShapeChanges,
+) {
+    let frame = SketchFrame::default();
+    for (entity, copy) in q_copies.iter() {
+        let axis = match copy.transform {
+            CopyTransform::Mirror(axis) => Some(axis),
+            _ => None,
+        };
+        let changed = copy.is_changed() || changes.contains(copy.source) || axis.is_some_and(|axis| changes.contains(axis));
+        if !changed {
+            continue;
+        }
+        let (Ok(shape), Some(transform)) = (q_shapes.get(copy.source), resolve(&copy.transform, &frame, &q_shapes)) else {
+            commands.entity(entity).remove::<SketchCopy>();
+            continue;
+        };
+        insert_transformed(&mut commands.entity(entity), &frame, shape, &transform);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    #[test]
+    fn linear_pattern_follows_direction_angles() {
+        let offsets = |copies: Vec<(CopyTransform, Similarity)>| -> Vec<Vec2> {
+            copies.into_iter().map(|(_, transform)| transform.point(Vec2::ZERO)).collect()
+        };
+        // 既定の x 方向と y 方向
+        let grid = offsets(linear_pattern([2, 2], [1.0, 2.0], [0.0, FRAC_PI_2]));
+        assert_eq!(grid.len(), 3);
+        for (offset, expected) in grid.iter().zip([Vec2::X, Vec2::new(0.0, 2.0), Vec2::new(1.0, 2.0)]) {
+            assert!(offset.distance(expected) < 1e-5);
+        }
+        // 45° 傾けた1方向
+        let diagonal = offsets(linear_pattern([3, 1], [2.0_f32.sqrt(), 1.0], [FRAC_PI_2 / 2.0, FRAC_PI_2]));
+        assert_eq!(diagonal.len(), 2);
+        assert!(diagonal[0].distance(Vec2::ONE) < 1e-5 && diagonal[1].distance(Vec2::splat(2.0)) < 1e-5);
+        // コピーの変換も同じ移動量を覚える
+        let copies = linear_pattern([2, 1], [1.0, 1.0], [FRAC_PI_2, 0.0]);
+        assert!(matches!(copies[0].0, CopyTransform::Translate([x, y]) if x.abs() < 1e-5 && (y - 1.0).abs() < 1e-5));
+    }
+}
@@ -0,0 +1,534 @@
+//! スケッチの閉じたプロファイル (領域) の検出
+//!
+//! 端点が許容誤差内で一致する曲線をつないだグラフを作り、各頂点で時計回りに
+//! 最も近い辺をたどることで最小の閉ループを求める。ループの包含関係から
+//! 内側のループを穴とする領域を作る。
+
+use std::f32::consts::TAU;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use crate::geometry::{arc_from_points, point_in_polygon, signed_area, Curve2d, SketchFrame};
+use crate::{
+    in_sketch, ActiveSketch, Construction, SketchArc, SketchCircle, SketchEllipse, SketchLine, SketchPolygon, SketchRectangle, SketchSlot,
+    SketchSpline,
+};
+
+/// 端点を同一とみなす距離
+pub const TOLERANCE: f32 = 1e-3;
+/// 点を図形の端点や中心に吸着させる距離
+const SNAP_TOLERANCE: f32 = 0.15;
+
+/// 閉じたループ
+#[derive(Debug, Clone)]
+pub struct ProfileLoop {
+    /// 反時計回りにつながった曲線
+    pub curves: Vec<Curve2d>,
+    /// 折れ線で近似した境界 (反時計回り、始点の重複なし)
+    pub polygon: Vec<Vec2>,
+    pub area: f32,
+}
+
+/// 外周ループと、その内側にある穴ループからなる領域
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub outer: usize,
+    pub holes: Vec<usize>,
+}
+
+/// 編集中のスケッチで検出されたループと領域
+#[derive(Resource, Default)]
+pub struct SketchProfiles {
+    pub loops: Vec<ProfileLoop>,
+    pub regions: Vec<Region>,
+    /// 押し出しに使う領域。ジオメトリが変わっても追従できるよう、領域内の点で覚える
+    pub selected: Option<Vec2>,
+}
+
+impl SketchProfiles {
+    /// 点を含む最も小さい領域
+    pub fn region_at(&self, p: Vec2) -> Option<usize> {
+        self.regions
+            .iter()
+            .enumerate()
+            .filter(|(_, region)| {
+                point_in_polygon(p, &self.loops[region.outer].polygon)
+                    && region.holes.iter().all(|&hole| !point_in_polygon(p, &self.loops[hole].polygon))
+            })
+            .min_by(|(_, a), (_, b)| self.loops[a.outer].area.total_cmp(&self.loops[b.outer].area))
+            .map(|(i, _)| i)
+    }
+
+    pub fn selected_region(&self) -> Option<&Region> {
+        self.selected.and_then(|p| self.region_at(p)).map(|i| &self.regions[i])
+    }
+
+    /// 領域の外周と穴の多角形
+    pub fn region_polygons(&self, region: &Region) -> (Vec<Vec2>, Vec<Vec<Vec2>>) {
+        let outer = self.loops[region.outer].polygon.clone();
+        let holes = region.holes.iter().map(|&hole| self.loops[hole].polygon.clone()).collect();
+        (outer, holes)
+    }
+
+    /// 領域の外周と穴の曲線。どちらも反時計回り
+    pub fn region_curves(&self, region: &Region) -> (Vec<Curve2d>, Vec<Vec<Curve2d>>) {
+        let outer = self.loops[region.outer].curves.clone();
+        let holes = region.holes.iter().map(|&hole| self.loops[hole].curves.clone()).collect();
+        (outer, holes)
+    }
+}
+
+/// 曲線をつないだグラフの有向辺
+struct HalfEdge {
+    from: usize,
+    to: usize,
+    curve: Curve2d,
+    twin: usize,
+}
+
+/// 曲線の集合から閉ループを検出する
+pub fn detect_loops(curves: &[Curve2d], tolerance: f32) -> Vec<ProfileLoop> {
+    let mut loops = Vec::new();
+    let mut vertices: Vec<Vec2> = Vec::new();
+    let mut vertex_of = |p: Vec2| -> usize {
+        match vertices.iter().position(|v| v.distance(p) <= tolerance) {
+            Some(i) => i,
+            None => {
+                vertices.push(p);
+                vertices.len() - 1
+            }
+        }
+    };
+
+    // 円や閉じたスプラインはそれだけで閉ループ、それ以外は端点を頂点として辺にする
+    let mut edges: Vec<(usize, usize, Curve2d)> = Vec::new();
+    for curve in curves {
+        if curve.is_closed() {
+            loops.extend(make_loop(vec![curve.clone()]).or_else(|| make_loop(vec![curve.reversed()])));
+            continue;
+        }
+        let (from, to) = (vertex_of(curve.start()), vertex_of(curve.end()));
+        if from == to {
+            continue;
+        }
+        edges.push((from, to, curve.clone()));
+    }
+
+    // 行き止まりの辺はループにならないので取り除く
+    loop {
+        let mut degree = vec![0; vertices.len()];
+        for (from, to, _) in &edges {
+            degree[*from] += 1;
+            degree[*to] += 1;
+        }
+        let before = edges.len();
+        edges.retain(|(from, to, _)| degree[*from] > 1 && degree[*to] > 1);
+        if edges.len() == before {
+            break;
+        }
+    }
+
+    let mut half_edges = Vec::new();
+    for (from, to, curve) in edges {
+        let i = half_edges.len();
+        let reversed = curve.reversed();
+        half_edges.push(HalfEdge { from, to, curve, twin: i + 1 });
+        half_edges.push(HalfEdge { from: to, to: from, curve: reversed, twin: i });
+    }
+
+    // 各頂点から出る辺を出発方向の角度順に並べる
+    let mut outgoing: Vec<Vec<usize>> = vec![Vec::new(); vertices.len()];
+    for (i, edge) in half_edges.iter().enumerate() {
+        outgoing[edge.from].push(i);
+    }
+    let angle = |i: usize| {
+        let t = half_edges[i].curve.start_tangent();
+        t.y.atan2(t.x).rem_euclid(TAU)
+    };
+    for list in &mut outgoing {
+        list.sort_by(|&a, &b| angle(a).total_cmp(&angle(b)));
+    }
+
+    // 到着した辺の逆向きから時計回りに最も近い辺を選ぶと、左手側の面を一周できる
+    let next = |i: usize| -> usize {
+        let edge = &half_edges[i];
+        let list = &outgoing[edge.to];
+        let twin_pos = list.iter().position(|&e| e == edge.twin).unwrap_or(0);
+        list[(twin_pos + list.len() - 1) % list.len()]
+    };
+
+    let mut used = vec![false; half_edges.len()];
+    for start in 0..half_edges.len() {
+        if used[start] {
+            continue;
+        }
+        let mut face = Vec::new();
+        let mut current = start;
+        while !used[current] && face.len() <= half_edges.len() {
+            used[current] = true;
+            face.push(half_edges[current].curve.clone());
+            current = next(current);
+        }
+        // 外側の面 (時計回り) は make_loop で除外される
+        if current == start {
+            loops.extend(make_loop(face));
+        }
+    }
+    loops
+}
+
+/// 端点でつながった曲線を始点から順に並べ、たどる向きにそろえる。
+/// 枝分かれしている、途中で切れている、または閉じている場合は `None`
+pub fn chain_path(curves: &[Curve2d], tolerance: f32) -> Option<Vec<Curve2d>> {
+    if curves.is_empty() || curves.iter().any(Curve2d::is_closed) {
+        return None;
+    }
+    let touches = |p: Vec2, i: usize| curves[i].start().distance(p) <= tolerance || curves[i].end().distance(p) <= tolerance;
+    let degree = |p: Vec2| (0..curves.len()).filter(|&i| touches(p, i)).count();
+    if curves.iter().any(|curve| degree(curve.start()) > 2 || degree(curve.end()) > 2) {
+        return None;
+    }
+    // 他の曲線とつながっていない端点から始める
+    let first = (0..curves.len()).find_map(|i| {
+        if degree(curves[i].start()) == 1 {
+            Some(curves[i].clone())
+        } else if degree(curves[i].end()) == 1 {
+            Some(curves[i].reversed())
+        } else {
+            None
+        }
+    })?;
+
+    let mut used = vec![false; curves.len()];
+    used[curves.iter().position(|c| c == &first || c.reversed() == first)?] = true;
+    let mut path = vec![first];
+    while path.len() < curves.len() {
+        let end = path[path.len() - 1].end();
+        let i = (0..curves.len()).find(|&i| !used[i] && touches(end, i))?;
+        used[i] = true;
+        let curve = if curves[i].start().distance(end) <= tolerance { curves[i].clone() } else { curves[i].reversed() };
+        path.push(curve);
+    }
+    Some(path)
+}
+
+/// 曲線列からループを作る。反時計回りでない、または面積がないものは `None`
+fn make_loop(curves: Vec<Curve2d>) -> Option<ProfileLoop> {
+    let mut polygon = Vec::new();
+    for curve in &curves {
+        let points = curve.tessellate();
+        polygon.extend_from_slice(&points[..points.len() - 1]);
+    }
+    let area = signed_area(&polygon);
+    (area > TOLERANCE * TOLERANCE).then_some(ProfileLoop { curves, polygon, area })
+}
+
+/// ループの内側にある点を1つ求める
+fn interior_point(profile_loop: &ProfileLoop) -> Vec2 {
+    let polygon = &profile_loop.polygon;
+    let n = polygon.len();
+    let step = (profile_loop.area.sqrt() * 1e-3).max(1e-5);
+    (0..n)
+        .map(|i| {
+            let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+            (a + b) / 2.0 + (b - a).normalize_or_zero().perp() * step
+        })
+        .find(|p| point_in_polygon(*p, polygon))
+        .unwrap_or(polygon[0])
+}
+
+/// ループの包含関係から領域を作る。すべてのループがそれぞれ1つの領域の外周になり、
+/// 直接内側にあるループがその穴になる
+pub fn build_regions(loops: &[ProfileLoop]) -> Vec<Region> {
+    let parents: Vec<Option<usize>> = loops
+        .iter()
+        .enumerate()
+        .map(|(i, inner)| {
+            let p = interior_point(inner);
+            loops
+                .iter()
+                .enumerate()
+                .filter(|(j, outer)| *j != i && outer.area > inner.area && point_in_polygon(p, &outer.polygon))
+                .min_by(|(_, a), (_, b)| a.area.total_cmp(&b.area))
+                .map(|(j, _)| j)
+        })
+        .collect();
+
+    (0..loops.len())
+        .map(|outer| Region {
+            outer,
+            holes: (0..loops.len()).filter(|&i| parents[i] == Some(outer)).collect(),
+        })
+        .collect()
+}
+
+/// 曲線になるスケッチエンティティのコンポーネント。エンティティはこのうち1つだけを持つ
+pub type SketchShape<'a> = AnyOf<(
+    &'a SketchLine,
+    &'a SketchCircle,
+    &'a SketchRectangle,
+    &'a SketchArc,
+    &'a SketchSpline,
+    &'a SketchPolygon,
+    &'a SketchSlot,
+    &'a SketchEllipse,
+)>;
+
+/// `SketchShape` のクエリで得られるコンポーネントの組
+pub type SketchShapeItem<'a> = (
+    Option<&'a SketchLine>,
+    Option<&'a SketchCircle>,
+    Option<&'a SketchRectangle>,
+    Option<&'a SketchArc>,
+    Option<&'a SketchSpline>,
+    Option<&'a SketchPolygon>,
+    Option<&'a SketchSlot>,
+    Option<&'a SketchEllipse>,
+);
+
+/// `SketchShape` のコンポーネントすべて。エンティティの形を別の種類に置き換える時にまとめて取り除く
+pub type SketchShapeBundle =
+    (SketchLine, SketchCircle, SketchRectangle, SketchArc, SketchSpline, SketchPolygon, SketchSlot, SketchEllipse);
+
+/// スケッチのジオメトリの追加・変更・削除と、補助線の切り替えを調べるシステムパラメータ
+#[derive(SystemParam)]
+pub struct SketchShapeChanges<'w, 's> {
+    changed: Query<
+        'w,
+        's,
+        (),
+        Or<(
+            Changed<SketchLine>,
+            Changed<SketchCircle>,
+            Changed<SketchRectangle>,
+            Changed<SketchArc>,
+            Changed<SketchSpline>,
+            Changed<SketchPolygon>,
+            Changed<SketchSlot>,
+            Changed<SketchEllipse>,
+            Changed<Construction>,
+        )>,
+    >,
+    removed_lines: RemovedComponents<'w, 's, SketchLine>,
+    removed_circles: RemovedComponents<'w, 's, SketchCircle>,
+    removed_rectangles: RemovedComponents<'w, 's, SketchRectangle>,
+    removed_arcs: RemovedComponents<'w, 's, SketchArc>,
+    removed_splines: RemovedComponents<'w, 's, SketchSpline>,
+    removed_polygons: RemovedComponents<'w, 's, SketchPolygon>,
+    removed_slots: RemovedComponents<'w, 's, SketchSlot>,
+    removed_ellipses: RemovedComponents<'w, 's, SketchEllipse>,
+    removed_construction: RemovedComponents<'w, 's, Construction>,
+}
+
+impl SketchShapeChanges<'_, '_> {
+    /// 前回呼んでから変化があったか
+    pub fn any(&mut self) -> bool {
+        let removed = self.removed_lines.read().count()
+            + self.removed_circles.read().count()
+            + self.removed_rectangles.read().count()
+            + self.removed_arcs.read().count()
+            + self.removed_splines.read().count()
+            + self.removed_polygons.read().count()
+            + self.removed_slots.read().count()
+            + self.removed_ellipses.read().count()
+            + self.removed_construction.read().count();
+        removed > 0 || !self.changed.is_empty()
+    }
+
+    /// エンティティの形か補助線の設定が前回から変わったか
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.changed.contains(entity)
+    }
+}
+
+/// スケッチエンティティ1つ分の曲線。四角形・多角形は反時計回りとは限らない直線の列、円弧は反時計回りの円弧になる
+pub fn entity_curves(
+    frame: &SketchFrame,
+    (line, circle, rect, arc, spline, polygon, slot, ellipse): SketchShapeItem,
+) -> Vec<Curve2d> {
+    let closed_polyline = |corners: Vec<Vec2>| {
+        let n = corners.len();
+        (0..n).map(|i| Curve2d::Line { a: corners[i], b: corners[(i + 1) % n] }).collect()
+    };
+    if let Some(line) = line {
+        vec![Curve2d::Line { a: frame.to_local(line.p1), b: frame.to_local(line.p2) }]
+    } else if let Some(circle) = circle {
+        vec![Curve2d::Circle { center: frame.to_local(circle.center), radius: circle.radius }]
+    } else if let Some(rect) = rect {
+        closed_polyline(rect.corners().iter().map(|&corner| frame.to_local(corner)).collect())
+    } else if let Some(arc) = arc {
+        vec![arc_from_points(frame.to_local(arc.center), frame.to_local(arc.start), frame.to_local(arc.end))]
+    } else if let Some(spline) = spline {
+        spline.curve(frame).into_iter().collect()
+    } else if let Some(polygon) = polygon {
+        closed_polyline(polygon.corners().into_iter().map(|corner| frame.to_local(corner)).collect())
+    } else if let Some(slot) = slot {
+        slot.curves(frame)
+    } else if let Some(ellipse) = ellipse {
+        vec![ellipse.curve(frame)]
+    } else {
+        Vec::new()
+    }
+}
+
+/// マウスの位置 `mouse` に最も近い図形の端点か円・円弧の中心。近くになければ `mouse` のまま
+pub fn snap_point<'a>(frame: &SketchFrame, shapes: impl IntoIterator<Item = SketchShapeItem<'a>>, mouse: Vec2) -> Vec2 {
+    shapes
+        .into_iter()
+        .flat_map(|shape| {
+            let curves = entity_curves(frame, shape);
+            let centers: Vec<Vec2> = curves
+                .iter()
+                .filter_map(|curve| match *curve {
+                    Curve2d::Circle { center, .. } | Curve2d::Arc { center, .. } => Some(center),
+                    _ => None,
+                })
+                .collect();
+            curves.iter().flat_map(|curve| [curve.start(), curve.end()]).chain(centers).collect::<Vec<_>>()
+        })
+        .filter(|p| p.distance(mouse) < SNAP_TOLERANCE)
+        .min_by(|a, b| a.distance(mouse).total_cmp(&b.distance(mouse)))
+        .unwrap_or(mouse)
+}
+
+/// 編集中のスケッチの閉じた領域を検出するシステム。補助線は使わない
+pub fn detect_profiles_system(
+    active_sketch: Res<ActiveSketch>,
+    mut profiles: ResMut<SketchProfiles>,
+    q_shapes: Query<(SketchShape, &Parent), Without<Construction>>,
+    mut changes: SketchShapeChanges,
+) {
+    // 削除の通知を読み捨てるため、変化の確認は毎回行う
+    if !changes.any() && !active_sketch.is_changed() {
+        return;
+    }
+    if active_sketch.is_changed() {
+        profiles.selected = None;
+    }
+
+    let frame = SketchFrame::default();
+    let curves: Vec<Curve2d> = q_shapes
+        .iter()
+        .filter(|(_, parent)| in_sketch(Some(*parent), active_sketch.0))
+        .flat_map(|(shape, _)| entity_curves(&frame, shape))
+        .collect();
+    profiles.loops = detect_loops(&curves, TOLERANCE);
+    profiles.regions = build_regions(&profiles.loops);
+}
+
+/// 検出した領域の輪郭と、選択中の領域のハッチングを描画するシステム
+pub fn draw_profile_gizmos(mut gizmos: Gizmos, profiles: Res<SketchProfiles>) {
+    let frame = SketchFrame::default();
+    let lift = frame.u.cross(frame.v) * 0.002; // スケッチの線と重ならないよう少し浮かせる
+    for profile_loop in &profiles.loops {
+        let points = profile_loop.polygon.iter().chain(profile_loop.polygon.first());
+        gizmos.linestrip(points.map(|p| frame.to_world(*p) + lift), Color::rgba(0.0, 0.8, 0.8, 0.5));
+    }
+
+    let Some(region) = profiles.selected_region() else {
+        return;
+    };
+    let (outer, holes) = profiles.region_polygons(region);
+    let boundaries: Vec<&Vec<Vec2>> = std::iter::once(&outer).chain(holes.iter()).collect();
+    let (min_y, max_y) = outer.iter().fold((f32::MAX, f32::MIN), |(lo, hi), p| (lo.min(p.y), hi.max(p.y)));
+    let spacing = 0.1;
+    let mut y = (min_y / spacing).ceil() * spacing;
+    while y < max_y {
+        // 水平線と境界の交点を並べ、内側の区間だけを描く
+        let mut xs = Vec::new();
+        for polygon in &boundaries {
+            for i in 0..polygon.len() {
+                let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+                if (a.y > y) != (b.y > y) {
+                    xs.push(a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x));
+                }
+            }
+        }
+        xs.sort_by(|a, b| a.total_cmp(b));
+        for pair in xs.chunks_exact(2) {
+            gizmos.line(
+                frame.to_world(Vec2::new(pair[0], y)) + lift,
+                frame.to_world(Vec2::new(pair[1], y)) + lift,
+                Color::GREEN,
+            );
+        }
+        y += spacing;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polyline(corners: &[Vec2]) -> Vec<Curve2d> {
+        let n = corners.len();
+        (0..n).map(|i| Curve2d::Line { a: corners[i], b: corners[(i + 1) % n] }).collect()
+    }
+
+    fn square(min: Vec2, size: f32) -> Vec<Curve2d> {
+        polyline(&[min, min + Vec2::X * size, min + Vec2::splat(size), min + Vec2::Y * size])
+    }
+
+    #[test]
+    fn inner_square_becomes_hole() {
+        let mut curves = square(Vec2::ZERO, 4.0);
+        curves.extend(square(Vec2::ONE, 1.0));
+        let loops = detect_loops(&curves, TOLERANCE);
+        assert_eq!(loops.len(), 2);
+        assert!(loops.iter().all(|profile_loop| profile_loop.area > 0.0));
+
+        let regions = build_regions(&loops);
+        let outer = regions.iter().find(|region| loops[region.outer].area > 10.0).unwrap();
+        assert_eq!(outer.holes.len(), 1);
+        assert!((loops[outer.holes[0]].area - 1.0).abs() < 1e-4);
+
+        let profiles = SketchProfiles { loops, regions, selected: None };
+        // 穴の中の点は穴そのものの領域、穴の外の点は穴あきの領域になる
+        let inside_hole = profiles.region_at(Vec2::splat(1.5)).unwrap();
+        assert!(profiles.regions[inside_hole].holes.is_empty());
+        let around_hole = profiles.region_at(Vec2::splat(0.5)).unwrap();
+        assert_eq!(profiles.regions[around_hole].holes.len(), 1);
+    }
+
+    #[test]
+    fn ignores_dangling_lines_and_splits_shared_edges() {
+        // 辺を共有する2つの正方形と、角から飛び出した線
+        let p = |x: f32, y: f32| Vec2::new(x, y);
+        let segments = [
+            (p(0.0, 0.0), p(1.0, 0.0)),
+            (p(1.0, 0.0), p(2.0, 0.0)),
+            (p(2.0, 0.0), p(2.0, 1.0)),
+            (p(2.0, 1.0), p(1.0, 1.0)),
+            (p(1.0, 1.0), p(0.0, 1.0)),
+            (p(0.0, 1.0), p(0.0, 0.0)),
+            (p(1.0, 0.0), p(1.0, 1.0)),
+            (p(2.0, 1.0), p(3.0, 2.0)),
+        ];
+        let curves: Vec<Curve2d> = segments.iter().map(|&(a, b)| Curve2d::Line { a, b }).collect();
+
+        let loops = detect_loops(&curves, TOLERANCE);
+        assert_eq!(loops.len(), 2);
+        for profile_loop in &loops {
+            assert!((profile_loop.area - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn open_chain_has_no_loop_and_orders_path() {
+        let curves = vec![
+            Curve2d::Line { a: Vec2::new(2.0, 0.0), b: Vec2::new(1.0, 0.0) },
+            Curve2d::Line { a: Vec2::ZERO, b: Vec2::new(1.0, 0.0) },
+        ];
+        assert!(detect_loops(&curves, TOLERANCE).is_empty());
+        let path = chain_path(&curves, TOLERANCE).unwrap();
+        assert_eq!(path[0].end(), path[1].start());
+    }
+
+    #[test]
+    fn circle_is_a_loop_by_itself() {
+        let loops = detect_loops(&[Curve2d::Circle { center: Vec2::ZERO, radius: 1.0 }], TOLERANCE);
+        assert_eq!(loops.len(), 1);
+        assert!((loops[0].area - std::f32::consts::PI).abs() < 0.01);
+    }
+}
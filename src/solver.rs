@@ -0,0 +1,305 @@
+//! 拘束方程式を解く数値ソルバー
+//!
+//! すべての拘束を「0になるべき残差」の式として登録し、Levenberg-Marquardt法で
+//! 最小ノルムの修正量を繰り返し求める。ヤコビアンは中心差分で数値的に計算する。
+
+use bevy::math::DVec2;
+
+/// 収束とみなす残差の大きさ
+const TOLERANCE: f64 = 1e-7;
+const MAX_ITERATIONS: usize = 100;
+/// 数値微分の刻み幅
+const STEP: f64 = 1e-6;
+
+/// パラメータ配列中の2つの値で表される点
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PointExpr {
+    pub x: usize,
+    pub y: usize,
+}
+
+impl PointExpr {
+    pub fn eval(&self, params: &[f64]) -> DVec2 {
+        DVec2::new(params[self.x], params[self.y])
+    }
+}
+
+type Equation = Box<dyn Fn(&[f64]) -> f64>;
+
+/// 方程式ごとの解析結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EquationStatus {
+    Satisfied,
+    /// 他の方程式から導かれる (冗長) が満たされている
+    Redundant,
+    /// 他の方程式と矛盾しているか、満たすことができない
+    Conflicting,
+}
+
+#[derive(Debug, Clone)]
+pub struct SolveResult {
+    pub converged: bool,
+    /// 残りの自由度
+    pub dof: usize,
+    pub statuses: Vec<EquationStatus>,
+}
+
+/// 未知数と残差方程式の組
+#[derive(Default)]
+pub struct SolverSystem {
+    pub params: Vec<f64>,
+    equations: Vec<Equation>,
+}
+
+impl SolverSystem {
+    /// 未知数を追加し、そのインデックスを返す
+    pub fn add_param(&mut self, value: f64) -> usize {
+        self.params.push(value);
+        self.params.len() - 1
+    }
+
+    pub fn add_point(&mut self, p: DVec2) -> PointExpr {
+        PointExpr { x: self.add_param(p.x), y: self.add_param(p.y) }
+    }
+
+    /// 残差方程式を追加し、そのインデックスを返す
+    pub fn add_equation(&mut self, f: impl Fn(&[f64]) -> f64 + 'static) -> usize {
+        self.equations.push(Box::new(f));
+        self.equations.len() - 1
+    }
+
+    fn residuals(&self, params: &[f64]) -> Vec<f64> {
+        self.equations.iter().map(|f| f(params)).collect()
+    }
+
+    fn jacobian(&self, params: &[f64]) -> Vec<Vec<f64>> {
+        let mut work = params.to_vec();
+        let mut jacobian = vec![vec![0.0; params.len()]; self.equations.len()];
+        for j in 0..params.len() {
+            let original = work[j];
+            work[j] = original + STEP;
+            let plus = self.residuals(&work);
+            work[j] = original - STEP;
+            let minus = self.residuals(&work);
+            work[j] = original;
+            for (i, row) in jacobian.iter_mut().enumerate() {
+                row[j] = (plus[i] - minus[i]) / (2.0 * STEP);
+            }
+        }
+        jacobian
+    }
+
+    /// 方程式を解いて `params` を更新する。収束しなかった場合は元の値に戻す
+    pub fn solve(&mut self) -> SolveResult {
+        let initial = self.params.clone();
+        let mut params = self.params.clone();
+        let mut residuals = self.residuals(&params);
+        let mut error = norm_sq(&residuals);
+        let mut lambda = 1e-3;
+
+        for _ in 0..MAX_ITERATIONS {
+            if max_abs(&residuals) < TOLERANCE {
+                break;
+            }
+            let jacobian = self.jacobian(&params);
+            let mut improved = false;
+            while lambda < 1e12 {
+                let Some(step) = min_norm_step(&jacobian, &residuals, lambda) else {
+                    lambda *= 10.0;
+                    continue;
+                };
+                let candidate: Vec<f64> = params.iter().zip(&step).map(|(p, d)| p - d).collect();
+                let candidate_residuals = self.residuals(&candidate);
+                let candidate_error = norm_sq(&candidate_residuals);
+                if candidate_error.is_finite() && candidate_error < error {
+                    params = candidate;
+                    residuals = candidate_residuals;
+                    error = candidate_error;
+                    lambda = (lambda / 10.0).max(1e-12);
+                    improved = true;
+                    break;
+                }
+                lambda *= 10.0;
+            }
+            if !improved {
+                break;
+            }
+        }
+
+        let converged = max_abs(&residuals) < TOLERANCE;
+        let jacobian = self.jacobian(&params);
+        let independent = independent_rows(&jacobian);
+        let rank = independent.iter().filter(|&&independent| independent).count();
+        let dof = self.params.len().saturating_sub(rank);
+
+        // 収束しなかった場合、従属している行があればそれを矛盾の原因とみなし、
+        // なければ満たせなかった行を矛盾とする
+        let has_dependent = independent.iter().any(|&independent| !independent);
+        let statuses = residuals
+            .iter()
+            .zip(&independent)
+            .map(|(residual, &independent)| match (converged, independent) {
+                (true, true) => EquationStatus::Satisfied,
+                (true, false) => EquationStatus::Redundant,
+                (false, false) => EquationStatus::Conflicting,
+                (false, true) if !has_dependent && residual.abs() >= TOLERANCE => EquationStatus::Conflicting,
+                (false, true) => EquationStatus::Satisfied,
+            })
+            .collect();
+
+        self.params = if converged { params } else { initial };
+        SolveResult { converged, dof, statuses }
+    }
+}
+
+fn norm_sq(v: &[f64]) -> f64 {
+    v.iter().map(|x| x * x).sum()
+}
+
+fn max_abs(v: &[f64]) -> f64 {
+    v.iter().fold(0.0, |acc: f64, x| acc.max(x.abs()))
+}
+
+/// 減衰付きの最小ノルム解 dx = J^T (J J^T + λI)^-1 r を求める
+fn min_norm_step(jacobian: &[Vec<f64>], residuals: &[f64], lambda: f64) -> Option<Vec<f64>> {
+    let m = jacobian.len();
+    let n = jacobian.first().map_or(0, |row| row.len());
+    let mut a = vec![vec![0.0; m]; m];
+    for i in 0..m {
+        for k in 0..m {
+            a[i][k] = jacobian[i].iter().zip(&jacobian[k]).map(|(x, y)| x * y).sum();
+        }
+        a[i][i] += lambda;
+    }
+    let y = solve_linear(a, residuals.to_vec())?;
+    let mut step = vec![0.0; n];
+    for (i, row) in jacobian.iter().enumerate() {
+        for (j, value) in row.iter().enumerate() {
+            step[j] += value * y[i];
+        }
+    }
+    Some(step)
+}
+
+/// 部分ピボット選択付きガウスの消去法。係数行列が特異な場合は `None`
+pub fn solve_linear(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-14 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..n {
+            let factor = a[row][col] / a[col][col];
+            if factor == 0.0 {
+                continue;
+            }
+            let (pivot_rows, rows) = a.split_at_mut(row);
+            for (value, pivot) in rows[0][col..].iter_mut().zip(&pivot_rows[col][col..]) {
+                *value -= factor * pivot;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// ヤコビアンの各行が先行する行と一次独立かどうかをグラム・シュミット法で調べる
+fn independent_rows(jacobian: &[Vec<f64>]) -> Vec<bool> {
+    let scale = jacobian
+        .iter()
+        .map(|row| norm_sq(row).sqrt())
+        .fold(0.0, f64::max)
+        .max(1.0);
+    let mut basis: Vec<Vec<f64>> = Vec::new();
+    jacobian
+        .iter()
+        .map(|row| {
+            let mut v = row.clone();
+            for b in &basis {
+                let dot: f64 = v.iter().zip(b).map(|(x, y)| x * y).sum();
+                for (x, y) in v.iter_mut().zip(b) {
+                    *x -= dot * y;
+                }
+            }
+            let length = norm_sq(&v).sqrt();
+            if length > 1e-6 * scale {
+                basis.push(v.into_iter().map(|x| x / length).collect());
+                true
+            } else {
+                false
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converges_to_target() {
+        let mut system = SolverSystem::default();
+        let a = system.add_point(DVec2::new(0.3, -0.2));
+        let b = system.add_point(DVec2::new(2.0, 1.0));
+        system.add_equation(move |p| p[a.x]);
+        system.add_equation(move |p| p[a.y]);
+        // 2点の距離を1にする
+        system.add_equation(move |p| a.eval(p).distance(b.eval(p)) - 1.0);
+        let result = system.solve();
+        assert!(result.converged);
+        assert_eq!(result.statuses, vec![EquationStatus::Satisfied; 3]);
+        assert!(a.eval(&system.params).length() < 1e-6);
+        assert!((b.eval(&system.params).length() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn counts_degrees_of_freedom() {
+        let mut system = SolverSystem::default();
+        let a = system.add_point(DVec2::new(0.0, 0.0));
+        let b = system.add_point(DVec2::new(1.0, 0.5));
+        assert_eq!(system.solve().dof, 4);
+        system.add_equation(move |p| p[a.y] - p[b.y]);
+        let result = system.solve();
+        assert!(result.converged);
+        assert_eq!(result.dof, 3);
+        system.add_equation(move |p| p[a.x]);
+        system.add_equation(move |p| p[a.y]);
+        system.add_equation(move |p| p[b.x] - 2.0);
+        assert_eq!(system.solve().dof, 0);
+    }
+
+    #[test]
+    fn classifies_redundant_equations() {
+        let mut system = SolverSystem::default();
+        let a = system.add_point(DVec2::new(0.0, 0.0));
+        let b = system.add_point(DVec2::new(1.0, 0.5));
+        system.add_equation(move |p| p[a.y] - p[b.y]);
+        // 同じ条件をもう一度加えても満たされるので冗長
+        system.add_equation(move |p| 2.0 * (p[b.y] - p[a.y]));
+        let result = system.solve();
+        assert!(result.converged);
+        assert_eq!(result.statuses, vec![EquationStatus::Satisfied, EquationStatus::Redundant]);
+        assert_eq!(result.dof, 3);
+    }
+
+    #[test]
+    fn classifies_conflicting_equations() {
+        let mut system = SolverSystem::default();
+        let x = system.add_param(0.5);
+        system.add_equation(move |p| p[x]);
+        system.add_equation(move |p| p[x] - 1.0);
+        let result = system.solve();
+        assert!(!result.converged);
+        assert!(result.statuses.contains(&EquationStatus::Conflicting));
+        // 解けなければ元の値に戻す
+        assert_eq!(system.params, vec![0.5]);
+    }
+}
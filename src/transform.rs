@@ -0,0 +1,114 @@
+//! 選択したスケッチの図形の移動・回転・拡大縮小
+//!
+//! 移動は基準点と移動先の2点をクリックするか、移動量を数値で指定する。回転と拡大縮小は中心を
+//! クリックすると、指定した角度や倍率で変換する。基準点は図形の端点や中心の近くなら吸着する。
+//! コピーを選ぶと元の図形を残し、変換した図形を新しく加える。
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_egui::EguiContexts;
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::geometry::{Similarity, SketchFrame};
+use crate::history::UndoHistory;
+use crate::pattern::{insert_transformed, SketchCopy};
+use crate::profile::{self, SketchShape, SketchShapeItem};
+use crate::{screen_to_world, ActiveSketch, ActiveSketchTool, Construction, SketchData, Selected};
+
+/// ツールで選択中の図形に加える変換。移動では `base` が基準点で `point` が移動先、回転と拡大縮小では
+/// `point` が中心。変換が決まらなければ `None`
+pub fn tool_transform(tool: ActiveSketchTool, sketch_data: &SketchData, base: Option<Vec2>, point: Vec2) -> Option<Similarity> {
+    match tool {
+        ActiveSketchTool::Move => base.map(|base| Similarity::translation(point - base)),
+        ActiveSketchTool::Rotate => Some(Similarity::rotation(point, sketch_data.rotate_angle.to_radians())),
+        ActiveSketchTool::Scale => (sketch_data.scale_factor > 0.0).then(|| Similarity::scaling(point, sketch_data.scale_factor)),
+        _ => None,
+    }
+}
+
+/// 図形を変換する。`copy` なら元の図形を残して変換した図形を加え、そうでなければ図形を置き換える。
+/// 置き換えたコピーは元の図形から切り離す
+pub fn transform_shapes<'a>(
+    commands: &mut Commands,
+    frame: &SketchFrame,
+    sketch: Entity,
+    shapes: impl IntoIterator<Item = (Entity, SketchShapeItem<'a>, bool)>,
+    transform: &Similarity,
+    copy: bool,
+) {
+    for (entity, shape, construction) in shapes {
+        if copy {
+            let mut entity_commands = commands.spawn_empty();
+            entity_commands.set_parent(sketch);
+            insert_transformed(&mut entity_commands, frame, shape, transform);
+            if construction {
+                entity_commands.insert(Construction);
+            }
+        } else {
+            let mut entity_commands = commands.entity(entity);
+            insert_transformed(&mut entity_commands, frame, shape, transform);
+            entity_commands.remove::<SketchCopy>();
+        }
+    }
+}
+
+/// 移動・回転・拡大縮小ツールの入力を処理するシステム。プレビューは `draw_sketch_gizmos` が描く
+pub fn transform_tool_system(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut sketch_data: ResMut<SketchData>,
+    active_tool: Res<ActiveSketchTool>,
+    active_sketch: Res<ActiveSketch>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    q_shapes: Query<(Entity, SketchShape, &Parent, Has<Construction>, Has<Selected>)>,
+    mut history: ResMut<UndoHistory>,
+) {
+    if mouse_buttons.just_pressed(MouseButton::Right) {
+        sketch_data.start_point = None;
+        return;
+    }
+    if contexts.ctx_mut().is_using_pointer() || !mouse_buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(sketch) = active_sketch.0 else {
+        return;
+    };
+    let window = q_window.single();
+    let (camera, camera_transform) = q_camera.single();
+    let Some(world_pos) = screen_to_world(window, camera, camera_transform) else {
+        return;
+    };
+    let frame = SketchFrame::default();
+    let in_sketch = || q_shapes.iter().filter(|(_, _, parent, ..)| parent.get() == sketch);
+    let selected: Vec<_> = in_sketch()
+        .filter(|(.., selected)| *selected)
+        .map(|(entity, shape, _, construction, _)| (entity, shape, construction))
+        .collect();
+    if selected.is_empty() {
+        sketch_data.message = "変換する図形を先に選択してください".to_string();
+        return;
+    }
+    let point = profile::snap_point(&frame, in_sketch().map(|(_, shape, ..)| shape), frame.to_local(world_pos));
+
+    // 移動は1回目のクリックで基準点を決める
+    if *active_tool == ActiveSketchTool::Move && sketch_data.start_point.is_none() {
+        sketch_data.start_point = Some(frame.to_world(point));
+        return;
+    }
+    let base = sketch_data.start_point.map(|p| frame.to_local(p));
+    let Some(transform) = tool_transform(*active_tool, &sketch_data, base, point) else {
+        sketch_data.message = "倍率は正の値にしてください".to_string();
+        return;
+    };
+    let copy = sketch_data.transform_copy;
+    transform_shapes(&mut commands, &frame, sketch, selected, &transform, copy);
+    sketch_data.start_point = None;
+    sketch_data.message.clear();
+    let label = match *active_tool {
+        ActiveSketchTool::Move => "移動",
+        ActiveSketchTool::Rotate => "回転",
+        _ => "拡大縮小",
+    };
+    history.record(if copy { format!("図形を{label}してコピー") } else { format!("図形を{label}") });
+}
@@ -0,0 +1,518 @@
+//! スケッチの曲線のトリム・延長・分割
+//!
+//! クリックした曲線を、他の図形との交点 (`intersection` モジュール) で区切って編集する。
+//! 四角形・多角形・長穴は編集すると直線と円弧に分解し、円は切ると円弧になる。スプラインは
+//! 残した区間を通過点で持ち直す。直線や円弧の端点を参照する拘束と寸法は、その端点が残った
+//! エンティティに付け替え、切り取られた端点を参照するものは削除する。
+
+use bevy::{ecs::system::EntityCommands, prelude::*, window::PrimaryWindow};
+use bevy_egui::EguiContexts;
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::constraints::{ConstraintKind, PointKind, PointRef, SketchConstraint};
+use crate::dimensions::SketchDimension;
+use crate::geometry::{Curve2d, SketchFrame};
+use crate::history::UndoHistory;
+use crate::intersection::{intersect, split_params};
+use crate::pattern::SketchCopy;
+use crate::profile::{self, SketchShape, SketchShapeBundle};
+use crate::{screen_to_world, ActiveSketch, ActiveSketchTool, Construction, SketchArc, SketchData, SketchLine, SketchSpline};
+
+/// 曲線を選ぶ許容範囲
+const PICK_TOLERANCE: f32 = 0.1;
+/// 端点と同じ位置とみなす距離
+const POINT_TOLERANCE: f32 = 1e-3;
+/// 直線を延長する最大の長さ
+const EXTEND_LIMIT: f32 = 1000.0;
+
+/// 残す曲線の区間
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Piece {
+    /// エンティティの曲線のインデックス
+    pub curve: usize,
+    pub t0: f32,
+    pub t1: f32,
+}
+
+/// クリックした位置で行う編集
+enum CurveEdit {
+    /// エンティティの曲線を残す区間で置き換える。区間が空ならエンティティを削除する
+    Replace {
+        pieces: Vec<Piece>,
+        /// 取り除く部分、または分割する位置 (プレビュー用)
+        preview: Vec<Vec2>,
+    },
+    /// 直線の始点か終点を動かす
+    Extend { start: bool, point: Vec2 },
+}
+
+/// トリム・延長・分割ツールの入力を処理するシステム
+pub fn curve_edit_system(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut gizmos: Gizmos,
+    mut sketch_data: ResMut<SketchData>,
+    active_tool: Res<ActiveSketchTool>,
+    active_sketch: Res<ActiveSketch>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<PanOrbitCamera>>,
+    q_shapes: Query<(Entity, SketchShape, &Parent, Has<Construction>)>,
+    q_constraints: Query<(Entity, &SketchConstraint)>,
+    q_dimensions: Query<(Entity, &SketchDimension)>,
+    mut history: ResMut<UndoHistory>,
+) {
+    if contexts.ctx_mut().is_using_pointer() {
+        return;
+    }
+    let Some(sketch) = active_sketch.0 else {
+        return;
+    };
+    let window = q_window.single();
+    let (camera, camera_transform) = q_camera.single();
+    let Some(world_pos) = screen_to_world(window, camera, camera_transform) else {
+        return;
+    };
+    let frame = SketchFrame::default();
+    let mouse = frame.to_local(world_pos);
+    let clicked = mouse_buttons.just_pressed(MouseButton::Left);
+
+    let shapes: Vec<(Entity, Vec<Curve2d>, bool)> = q_shapes
+        .iter()
+        .filter(|(_, _, parent, _)| parent.get() == sketch)
+        .map(|(entity, shape, _, construction)| (entity, profile::entity_curves(&frame, shape), construction))
+        .collect();
+    let Some((index, curve_index)) = pick(&shapes, mouse) else {
+        return;
+    };
+    let (entity, ref curves, construction) = shapes[index];
+    // 同じエンティティの他の辺も区切りになる
+    let others: Vec<Curve2d> = shapes
+        .iter()
+        .enumerate()
+        .flat_map(|(i, (_, curves, _))| {
+            curves.iter().enumerate().filter(move |(j, _)| (i, *j) != (index, curve_index)).map(|(_, curve)| curve.clone())
+        })
+        .collect();
+
+    let edit = match *active_tool {
+        ActiveSketchTool::Trim | ActiveSketchTool::Split if curves.iter().any(|c| matches!(c, Curve2d::Ellipse { .. })) => {
+            Err("楕円はトリム・分割できません")
+        }
+        ActiveSketchTool::Trim => Ok(trim(curves, curve_index, &others, mouse)),
+        ActiveSketchTool::Split => split(curves, curve_index, mouse).ok_or("端点では分割できません"),
+        ActiveSketchTool::Extend => extend(curves, &others, mouse),
+        _ => return,
+    };
+
+    let color = Color::RED;
+    match &edit {
+        Ok(CurveEdit::Replace { preview, .. }) if *active_tool == ActiveSketchTool::Split => {
+            for &point in preview {
+                gizmos.circle(frame.to_world(point), Direction3d::Y, 0.06, color);
+            }
+        }
+        Ok(CurveEdit::Replace { preview, .. }) => {
+            gizmos.linestrip(preview.iter().map(|&p| frame.to_world(p)), color);
+        }
+        Ok(CurveEdit::Extend { start, point }) => {
+            let Curve2d::Line { a, b } = curves[0] else {
+                return;
+            };
+            let from = if *start { a } else { b };
+            gizmos.line(frame.to_world(from), frame.to_world(*point), Color::YELLOW);
+        }
+        Err(_) => {}
+    }
+    if !clicked {
+        return;
+    }
+
+    sketch_data.message.clear();
+    match edit {
+        Err(message) => sketch_data.message = message.to_string(),
+        Ok(CurveEdit::Extend { start, point }) => {
+            let Curve2d::Line { a, b } = curves[0] else {
+                return;
+            };
+            let (a, b) = if start { (point, b) } else { (a, point) };
+            // 形を変えたコピーは元の図形から切り離す
+            let line = SketchLine { p1: frame.to_world(a), p2: frame.to_world(b) };
+            commands.entity(entity).insert(line).remove::<SketchCopy>();
+            history.record("直線を延長");
+        }
+        Ok(CurveEdit::Replace { pieces, .. }) => {
+            let entities = replace_entity(&mut commands, &frame, sketch, entity, construction, curves, &pieces);
+
+            // 開いた1本の曲線なら、端点の参照を端点が残ったエンティティに付け替える
+            let (mut start_owner, mut end_owner) = (None, None);
+            if let [curve] = curves.as_slice() {
+                if !curve.is_closed() {
+                    start_owner = pieces.first().filter(|piece| piece.t0 == 0.0).map(|_| entities[0]);
+                    end_owner = pieces.last().filter(|piece| piece.t1 == 1.0).and_then(|_| entities.last().copied());
+                }
+            }
+            let remap = |p: PointRef| {
+                if p.entity != entity {
+                    return Some(p);
+                }
+                match p.kind {
+                    PointKind::Start => start_owner.map(|owner| PointRef { entity: owner, kind: p.kind }),
+                    PointKind::End => end_owner.map(|owner| PointRef { entity: owner, kind: p.kind }),
+                    PointKind::Center => (!entities.is_empty()).then_some(p),
+                    PointKind::Corner(_) => None,
+                }
+            };
+            remap_point_refs(&mut commands, &q_constraints, &q_dimensions, remap);
+
+            if *active_tool == ActiveSketchTool::Split {
+                // 分割した直線はつながったまま動くように一致拘束でつなぐ
+                if let ([Curve2d::Line { .. }], [first, second]) = (curves.as_slice(), entities.as_slice()) {
+                    let kind = ConstraintKind::Coincident(
+                        PointRef { entity: *first, kind: PointKind::End },
+                        PointRef { entity: *second, kind: PointKind::Start },
+                    );
+                    commands.spawn(SketchConstraint(kind)).set_parent(sketch);
+                }
+                history.record("図形を分割");
+            } else {
+                history.record("図形をトリム");
+            }
+        }
+    }
+}
+
+/// 拘束と寸法の点の参照を置き換える。置き換えられない参照を持つものは削除する
+pub fn remap_point_refs(
+    commands: &mut Commands,
+    q_constraints: &Query<(Entity, &SketchConstraint)>,
+    q_dimensions: &Query<(Entity, &SketchDimension)>,
+    mut remap: impl FnMut(PointRef) -> Option<PointRef>,
+) {
+    for (constraint_entity, constraint) in q_constraints.iter() {
+        match constraint.0.map_points(&mut remap) {
+            None => commands.entity(constraint_entity).despawn_recursive(),
+            Some(kind) if kind != constraint.0 => {
+                commands.entity(constraint_entity).insert(SketchConstraint(kind));
+            }
+            Some(_) => {}
+        }
+    }
+    for (dimension_entity, dimension) in q_dimensions.iter() {
+        match dimension.kind.map_points(&mut remap) {
+            None => commands.entity(dimension_entity).despawn_recursive(),
+            Some(kind) if kind != dimension.kind => {
+                commands.entity(dimension_entity).insert(SketchDimension { kind, value: dimension.value });
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// 削除するエンティティを参照する拘束と寸法を削除する。削除するエンティティを元の図形や対称軸にしている
+/// コピーは元の図形から切り離す
+pub fn remove_entity_refs(
+    commands: &mut Commands,
+    q_constraints: &Query<(Entity, &SketchConstraint)>,
+    q_dimensions: &Query<(Entity, &SketchDimension)>,
+    q_copies: &Query<(Entity, &SketchCopy)>,
+    removed: &[Entity],
+) {
+    let keep = |entity: Entity| (!removed.contains(&entity)).then_some(entity);
+    for (constraint_entity, constraint) in q_constraints.iter() {
+        if constraint.0.map(keep).is_none() {
+            commands.entity(constraint_entity).despawn_recursive();
+        }
+    }
+    for (dimension_entity, dimension) in q_dimensions.iter() {
+        if dimension.kind.map(keep).is_none() {
+            commands.entity(dimension_entity).despawn_recursive();
+        }
+    }
+    for (copy_entity, copy) in q_copies.iter() {
+        if removed.contains(&copy_entity) {
+            continue;
+        }
+        if keep(copy.source).is_none() || copy.transform.map(keep).is_none() {
+            commands.entity(copy_entity).remove::<SketchCopy>();
+        }
+    }
+}
+
+/// マウスに最も近いエンティティとその曲線のインデックス
+fn pick(shapes: &[(Entity, Vec<Curve2d>, bool)], mouse: Vec2) -> Option<(usize, usize)> {
+    shapes
+        .iter()
+        .enumerate()
+        .flat_map(|(i, (_, curves, _))| curves.iter().enumerate().map(move |(j, curve)| ((i, j), curve.distance(mouse))))
+        .filter(|(_, distance)| *distance < PICK_TOLERANCE)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(index, _)| index)
+}
+
+/// クリックした位置を含む、交点で区切られた区間を取り除く。交点がなければ曲線全体を取り除く
+fn trim(curves: &[Curve2d], curve_index: usize, others: &[Curve2d], mouse: Vec2) -> CurveEdit {
+    let curve = &curves[curve_index];
+    let params = split_params(curve, others);
+    let t = curve.closest_param(mouse);
+    let below = params.iter().rev().copied().find(|&p| p < t);
+    let above = params.iter().copied().find(|&p| p > t);
+
+    let (kept, removed) = if curve.is_closed() {
+        // 閉じた曲線は始点をまたいで区切る。交点が2つ未満なら区切れない
+        if params.len() < 2 {
+            (Vec::new(), (0.0, 1.0))
+        } else {
+            let lo = below.unwrap_or(params[params.len() - 1]);
+            let hi = above.unwrap_or(params[0]);
+            (vec![Piece { curve: curve_index, t0: hi, t1: lo }], (lo, hi))
+        }
+    } else {
+        let mut kept = Vec::new();
+        if let Some(lo) = below {
+            kept.push(Piece { curve: curve_index, t0: 0.0, t1: lo });
+        }
+        if let Some(hi) = above {
+            kept.push(Piece { curve: curve_index, t0: hi, t1: 1.0 });
+        }
+        (kept, (below.unwrap_or(0.0), above.unwrap_or(1.0)))
+    };
+
+    CurveEdit::Replace { pieces: with_other_curves(curves, curve_index, kept), preview: sample(curve, removed.0, removed.1) }
+}
+
+/// クリックした位置で曲線を2つに分ける。閉じた曲線はその位置を始点とする一周の曲線にする。
+/// 開いた曲線の端点では分けられないので `None`
+fn split(curves: &[Curve2d], curve_index: usize, mouse: Vec2) -> Option<CurveEdit> {
+    let curve = &curves[curve_index];
+    let t = curve.closest_param(mouse);
+    let point = curve.point_at(t);
+    let pieces = if curve.is_closed() {
+        vec![Piece { curve: curve_index, t0: t, t1: t }]
+    } else {
+        if point.distance(curve.start()) < POINT_TOLERANCE || point.distance(curve.end()) < POINT_TOLERANCE {
+            return None;
+        }
+        vec![Piece { curve: curve_index, t0: 0.0, t1: t }, Piece { curve: curve_index, t0: t, t1: 1.0 }]
+    };
+    Some(CurveEdit::Replace { pieces: with_other_curves(curves, curve_index, pieces), preview: vec![point] })
+}
+
+/// クリックした位置に近い方の直線の端点を、延長した先で最初に当たる図形まで伸ばす
+fn extend(curves: &[Curve2d], others: &[Curve2d], mouse: Vec2) -> Result<CurveEdit, &'static str> {
+    let [Curve2d::Line { a, b }] = *curves else {
+        return Err("延長できるのは直線だけです");
+    };
+    let start = mouse.distance(a) < mouse.distance(b);
+    let (from, to) = if start { (b, a) } else { (a, b) };
+    let direction = (to - from).normalize_or_zero();
+    if direction == Vec2::ZERO {
+        return Err("延長できるのは直線だけです");
+    }
+    let ray = Curve2d::Line { a: to, b: to + direction * EXTEND_LIMIT };
+    others
+        .iter()
+        .flat_map(|other| intersect(&ray, other))
+        .filter(|hit| hit.point.distance(to) > POINT_TOLERANCE)
+        .min_by(|x, y| x.t.total_cmp(&y.t))
+        .map(|hit| CurveEdit::Extend { start, point: hit.point })
+        .ok_or("延長先の図形が見つかりません")
+}
+
+/// 編集した曲線の区間を、エンティティの他の曲線と並べる
+fn with_other_curves(curves: &[Curve2d], curve_index: usize, pieces: Vec<Piece>) -> Vec<Piece> {
+    let mut pieces = Some(pieces);
+    (0..curves.len())
+        .flat_map(|i| {
+            if i == curve_index {
+                pieces.take().unwrap_or_default()
+            } else {
+                vec![Piece { curve: i, t0: 0.0, t1: 1.0 }]
+            }
+        })
+        .collect()
+}
+
+/// 曲線の `t0` から `t1` までの部分を折れ線にした点
+fn sample(curve: &Curve2d, t0: f32, t1: f32) -> Vec<Vec2> {
+    let span = curve.param_span(t0, t1);
+    let segments = 64;
+    (0..=segments)
+        .map(|i| {
+            let t = t0 + span * i as f32 / segments as f32;
+            curve.point_at(if curve.is_closed() { t.rem_euclid(1.0) } else { t })
+        })
+        .collect()
+}
+
+/// エンティティを残す区間で置き換え、区間を持つエンティティを順に返す。最初の区間は元の
+/// エンティティに入れ、残りは同じスケッチに新しく作る。区間がなければエンティティを削除する。
+/// コピーだったエンティティは元の図形から切り離す
+pub fn replace_entity(
+    commands: &mut Commands,
+    frame: &SketchFrame,
+    sketch: Entity,
+    entity: Entity,
+    construction: bool,
+    curves: &[Curve2d],
+    pieces: &[Piece],
+) -> Vec<Entity> {
+    let Some((first, rest)) = pieces.split_first() else {
+        commands.entity(entity).despawn_recursive();
+        return Vec::new();
+    };
+    let mut entity_commands = commands.entity(entity);
+    entity_commands.remove::<(SketchShapeBundle, SketchCopy)>();
+    insert_piece(&mut entity_commands, frame, &curves[first.curve], first);
+
+    let mut entities = vec![entity];
+    for piece in rest {
+        let mut entity_commands = commands.spawn_empty();
+        entity_commands.set_parent(sketch);
+        insert_piece(&mut entity_commands, frame, &curves[piece.curve], piece);
+        if construction {
+            entity_commands.insert(Construction);
+        }
+        entities.push(entity_commands.id());
+    }
+    entities
+}
+
+/// 曲線の区間を直線・円弧・スプラインのコンポーネントにして挿入する。スプラインは元の曲線と一致する
+/// 制御点とノットで持つ
+fn insert_piece(entity_commands: &mut EntityCommands, frame: &SketchFrame, curve: &Curve2d, piece: &Piece) {
+    match curve.segment(piece.t0, piece.t1) {
+        Some(Curve2d::Line { a, b }) => {
+            entity_commands.insert(SketchLine { p1: frame.to_world(a), p2: frame.to_world(b) });
+        }
+        Some(Curve2d::Spline(spline)) => {
+            let points = spline.control_points.iter().map(|&p| frame.to_world(p)).collect();
+            entity_commands.insert(SketchSpline { points, fit: false, knots: spline.knots });
+        }
+        Some(arc) => {
+            if let Some(arc) = SketchArc::from_curve(frame, &arc) {
+                entity_commands.insert(arc);
+            }
+        }
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::dimensions::DimensionKind;
+    use crate::geometry::BSpline;
+
+    #[test]
+    fn removing_entity_drops_its_constraints_dimensions_and_copy_links() {
+        let mut world = World::new();
+        let line = world.spawn(SketchLine { p1: Vec3::ZERO, p2: Vec3::X }).id();
+        let other = world.spawn(SketchLine { p1: Vec3::X, p2: Vec3::Z }).id();
+        let point = |entity, kind| PointRef { entity, kind };
+        let horizontal = world.spawn(SketchConstraint(ConstraintKind::Horizontal(line))).id();
+        let coincident = ConstraintKind::Coincident(point(line, PointKind::End), point(other, PointKind::Start));
+        let coincident = world.spawn(SketchConstraint(coincident)).id();
+        let vertical = world.spawn(SketchConstraint(ConstraintKind::Vertical(other))).id();
+        let angle = world.spawn(SketchDimension { kind: DimensionKind::Angle(line, other), value: 90.0 }).id();
+        let copy = SketchCopy { source: other, transform: crate::pattern::CopyTransform::Mirror(line) };
+        let copy = world.spawn((SketchLine { p1: Vec3::ZERO, p2: Vec3::Z }, copy)).id();
+
+        world.run_system_once(
+            move |mut commands: Commands,
+                  q_constraints: Query<(Entity, &SketchConstraint)>,
+                  q_dimensions: Query<(Entity, &SketchDimension)>,
+                  q_copies: Query<(Entity, &SketchCopy)>| {
+                remove_entity_refs(&mut commands, &q_constraints, &q_dimensions, &q_copies, &[line]);
+            },
+        );
+
+        assert!(world.get_entity(horizontal).is_none());
+        assert!(world.get_entity(coincident).is_none());
+        assert!(world.get_entity(angle).is_none());
+        assert!(world.get_entity(vertical).is_some());
+        assert!(world.get::<SketchCopy>(copy).is_none());
+    }
+
+    fn line(a: (f32, f32), b: (f32, f32)) -> Curve2d {
+        Curve2d::Line { a: Vec2::new(a.0, a.1), b: Vec2::new(b.0, b.1) }
+    }
+
+    /// 置き換えの編集で残る区間
+    fn kept_pieces(edit: CurveEdit) -> Vec<Piece> {
+        match edit {
+            CurveEdit::Replace { pieces, .. } => pieces,
+            CurveEdit::Extend { .. } => panic!("置き換えになっていない"),
+        }
+    }
+
+    /// 延長の編集で動かす端点とその移動先
+    fn extended_end(edit: Result<CurveEdit, &'static str>) -> (bool, Vec2) {
+        match edit {
+            Ok(CurveEdit::Extend { start, point }) => (start, point),
+            _ => panic!("延長になっていない"),
+        }
+    }
+
+    #[test]
+    fn trims_between_crossings_on_lines_circles_and_splines() {
+        let cutters = [line((1.0, -3.0), (1.0, 3.0)), line((-1.0, -3.0), (-1.0, 3.0))];
+
+        // 直線は交点の間が取り除かれ、両側が残る
+        let curves = [line((-2.0, 0.0), (2.0, 0.0))];
+        let pieces = kept_pieces(trim(&curves, 0, &cutters, Vec2::ZERO));
+        assert_eq!(pieces.len(), 2);
+        assert!((pieces[0].t1 - 0.25).abs() < 1e-4 && (pieces[1].t0 - 0.75).abs() < 1e-4);
+        // 交点のない側の端をクリックすると、端から交点までが取り除かれる
+        let pieces = kept_pieces(trim(&curves, 0, &cutters, Vec2::new(1.8, 0.0)));
+        assert_eq!(pieces.len(), 1);
+        assert!(pieces[0].t0 == 0.0 && (pieces[0].t1 - 0.75).abs() < 1e-4);
+
+        // 円は2つの交点の間の右側を取り除くと、左側の円弧が残る
+        let curves = [Curve2d::Circle { center: Vec2::ZERO, radius: 2.0 }];
+        let pieces = kept_pieces(trim(&curves, 0, &cutters[..1], Vec2::new(2.0, 0.0)));
+        assert_eq!(pieces.len(), 1);
+        let middle = curves[0].point_at(pieces[0].t0 + curves[0].param_span(pieces[0].t0, pieces[0].t1) / 2.0);
+        assert!(middle.distance(Vec2::new(-2.0, 0.0)) < 1e-3);
+
+        // スプラインも交点で区切る
+        let spline = BSpline::interpolate(&[Vec2::new(-2.0, -1.0), Vec2::new(0.0, 1.0), Vec2::new(2.0, -1.0)]).unwrap();
+        let curves = [Curve2d::Spline(spline)];
+        let pieces = kept_pieces(trim(&curves, 0, &cutters, Vec2::new(0.0, 1.0)));
+        assert_eq!(pieces.len(), 2);
+        for (piece, x) in pieces.iter().zip([-1.0, 1.0]) {
+            let t = if piece.t0 == 0.0 { piece.t1 } else { piece.t0 };
+            assert!((curves[0].point_at(t).x - x).abs() < 1e-3);
+            // 残す区間は近似し直さず、元の曲線と重なるスプラインになる
+            let Some(Curve2d::Spline(kept)) = curves[0].segment(piece.t0, piece.t1) else {
+                panic!("スプラインになっていない");
+            };
+            let middle = curves[0].point_at((piece.t0 + piece.t1) / 2.0);
+            assert!(kept.point_at(0.5).distance(middle) < 1e-4);
+        }
+    }
+
+    #[test]
+    fn extends_line_to_lines_arcs_splines_and_ellipses() {
+        let curves = [line((0.0, 0.0), (1.0, 0.0))];
+        // 最も近い図形で止まり、クリックに近い方の端点を動かす
+        let others = [line((5.0, -1.0), (5.0, 1.0)), line((3.0, -1.0), (3.0, 1.0))];
+        assert_eq!(extended_end(extend(&curves, &others, Vec2::new(0.9, 0.0))), (false, Vec2::new(3.0, 0.0)));
+        let others = [Curve2d::Arc { center: Vec2::new(-3.0, 0.0), radius: 1.0, start_angle: -1.0, sweep: 2.0 }];
+        assert_eq!(extended_end(extend(&curves, &others, Vec2::new(0.1, 0.0))), (true, Vec2::new(-2.0, 0.0)));
+
+        let spline = BSpline::interpolate(&[Vec2::new(4.0, -1.0), Vec2::new(4.5, 0.0), Vec2::new(4.0, 1.0)]).unwrap();
+        let (_, point) = extended_end(extend(&curves, &[Curve2d::Spline(spline)], Vec2::new(0.9, 0.0)));
+        assert!(point.distance(Vec2::new(4.5, 0.0)) < 1e-3);
+        let ellipse = Curve2d::Ellipse { center: Vec2::new(6.0, 0.0), major: Vec2::X * 2.0, minor: Vec2::Y };
+        let (_, point) = extended_end(extend(&curves, &[ellipse], Vec2::new(0.9, 0.0)));
+        assert!(point.distance(Vec2::new(4.0, 0.0)) < 1e-3);
+
+        // 何もない方向や直線以外は延長できない
+        assert!(extend(&curves, &others, Vec2::new(0.9, 0.0)).is_err());
+        let arc = [Curve2d::Arc { center: Vec2::ZERO, radius: 1.0, start_angle: 0.0, sweep: 1.0 }];
+        assert!(extend(&arc, &[line((5.0, -1.0), (5.0, 1.0))], Vec2::X).is_err());
+    }
+}